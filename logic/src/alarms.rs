@@ -0,0 +1,155 @@
+//! Alarms and their checks
+//!
+//! An alarm rings on the days of its weekday mask, bit 0 for Monday to bit 6
+//! for Sunday, at `minute` from midnight, local time.
+
+use alloc::string::String;
+
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+/// Monday to Sunday
+pub const ALL_DAYS: u8 = 0x7F;
+
+const fn enabled() -> bool {
+    true
+}
+
+/// Alarm without its id, as created or updated
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlarmFields {
+    /// Weekday mask, bit 0 for Monday
+    pub days: u8,
+    /// From midnight
+    pub minute: u16,
+    /// Name of a built-in or custom melody
+    #[serde(default)]
+    pub melody: Option<String>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alarm {
+    pub id: u8,
+    pub days: u8,
+    pub minute: u16,
+    #[serde(default)]
+    pub melody: Option<String>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+impl Alarm {
+    pub fn new(id: u8, fields: AlarmFields) -> Self {
+        Alarm {
+            id,
+            days: fields.days,
+            minute: fields.minute,
+            melody: fields.melody,
+            enabled: fields.enabled,
+        }
+    }
+
+    pub fn rings_on(&self, weekday: Weekday) -> bool {
+        self.days & (1 << weekday.to_monday_zero_offset()) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlarmError {
+    InvalidMinute,
+    InvalidDays,
+    UnknownMelody,
+    TooMany,
+    NotFound,
+    /// The list does not fit its NVS slot
+    TooLarge,
+    /// The slot could not be written, the alarms are unchanged
+    NotSaved,
+}
+
+impl AlarmFields {
+    /// Check the time, the days and, through `is_melody`, the melody name
+    pub fn validate(&self, is_melody: impl Fn(&str) -> bool) -> Result<(), AlarmError> {
+        if self.minute >= 24 * 60 {
+            return Err(AlarmError::InvalidMinute);
+        }
+        if self.days == 0 || self.days & !ALL_DAYS != 0 {
+            return Err(AlarmError::InvalidDays);
+        }
+        match &self.melody {
+            Some(name) if !is_melody(name) => Err(AlarmError::UnknownMelody),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(days: u8, minute: u16, melody: Option<&str>) -> AlarmFields {
+        AlarmFields {
+            days,
+            minute,
+            melody: melody.map(String::from),
+            enabled: true,
+        }
+    }
+
+    fn is_melody(name: &str) -> bool {
+        name == "westminster"
+    }
+
+    #[test]
+    fn minute_is_within_the_day() {
+        assert_eq!(fields(ALL_DAYS, 0, None).validate(is_melody), Ok(()));
+        assert_eq!(fields(ALL_DAYS, 1439, None).validate(is_melody), Ok(()));
+        assert_eq!(
+            fields(ALL_DAYS, 1440, None).validate(is_melody),
+            Err(AlarmError::InvalidMinute)
+        );
+    }
+
+    #[test]
+    fn days_are_a_non_empty_weekday_mask() {
+        assert_eq!(fields(0x01, 420, None).validate(is_melody), Ok(()));
+        assert_eq!(
+            fields(0, 420, None).validate(is_melody),
+            Err(AlarmError::InvalidDays)
+        );
+        assert_eq!(
+            fields(0x80, 420, None).validate(is_melody),
+            Err(AlarmError::InvalidDays)
+        );
+        assert_eq!(
+            fields(0x81, 420, None).validate(is_melody),
+            Err(AlarmError::InvalidDays)
+        );
+    }
+
+    #[test]
+    fn melody_must_exist() {
+        let known = fields(ALL_DAYS, 420, Some("westminster"));
+        assert_eq!(known.validate(is_melody), Ok(()));
+        assert_eq!(
+            fields(ALL_DAYS, 420, Some("nope")).validate(is_melody),
+            Err(AlarmError::UnknownMelody)
+        );
+    }
+
+    #[test]
+    fn rings_on_the_days_of_the_mask() {
+        // Monday to Friday
+        let alarm = Alarm::new(3, fields(0x1F, 420, None));
+        assert_eq!(alarm.id, 3);
+        assert!(alarm.rings_on(Weekday::Monday));
+        assert!(alarm.rings_on(Weekday::Friday));
+        assert!(!alarm.rings_on(Weekday::Saturday));
+
+        let sunday = Alarm::new(0, fields(0x40, 420, None));
+        assert!(sunday.rings_on(Weekday::Sunday));
+        assert!(!sunday.rings_on(Weekday::Monday));
+    }
+}
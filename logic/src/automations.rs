@@ -0,0 +1,160 @@
+//! Scheduled messages and readings
+//!
+//! Each automation runs at `start`, in minutes from midnight, then every
+//! `every` minutes until `end` when set.
+
+use alloc::string::String;
+
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+use crate::{schedule::Days, sensor::Sensor};
+
+/// Longest showing
+pub const MAX_SECONDS: u16 = 300;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Automation {
+    pub days: Days,
+    /// Minutes from midnight of the first run
+    pub start: u16,
+    /// Minutes between runs, once a day when `None`
+    #[serde(default)]
+    pub every: Option<u16>,
+    /// Minutes from midnight of the last possible run, the end of the day
+    /// when `None`
+    #[serde(default)]
+    pub end: Option<u16>,
+    /// Scrolled as is, in the glyphs of the matrix font
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Latest reading scrolled, instead of a text
+    #[serde(default)]
+    pub sensor: Option<Sensor>,
+    /// Scrolled again until they pass, once when 0
+    #[serde(default)]
+    pub seconds: u16,
+}
+
+impl Automation {
+    /// Whether the times are within the day and it shows either a text that
+    /// `fits` the matrix or a sensor
+    pub fn is_valid(&self, fits: impl Fn(&str) -> bool) -> bool {
+        let shows_one = match (&self.text, self.sensor) {
+            (Some(text), None) => fits(text),
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        shows_one
+            && self.start < MINUTES_PER_DAY
+            && self
+                .every
+                .is_none_or(|every| (1..=MINUTES_PER_DAY).contains(&every))
+            && self.end.is_none_or(|end| {
+                self.every.is_some() && (self.start..MINUTES_PER_DAY).contains(&end)
+            })
+            && self.seconds <= MAX_SECONDS
+    }
+
+    /// Whether it runs at `minute` from midnight of `weekday`
+    pub fn runs_at(&self, weekday: Weekday, minute: u16) -> bool {
+        if !self.days.contains(weekday) || minute < self.start {
+            return false;
+        }
+        match self.every {
+            Some(every) => {
+                minute <= self.end.unwrap_or(MINUTES_PER_DAY - 1)
+                    && (minute - self.start) % every == 0
+            }
+            None => minute == self.start,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(days: Days, start: u16, every: Option<u16>, end: Option<u16>) -> Automation {
+        Automation {
+            days,
+            start,
+            every,
+            end,
+            text: Some("TAKE MEDS".into()),
+            sensor: None,
+            seconds: 30,
+        }
+    }
+
+    fn fits(_: &str) -> bool {
+        true
+    }
+
+    #[test]
+    fn once_runs_at_start_only() {
+        let once = text(Days::Workdays, 450, None, None);
+        assert!(once.runs_at(Weekday::Monday, 450));
+        assert!(!once.runs_at(Weekday::Monday, 449));
+        assert!(!once.runs_at(Weekday::Monday, 451));
+        assert!(!once.runs_at(Weekday::Saturday, 450));
+    }
+
+    #[test]
+    fn every_runs_from_start_to_end() {
+        let hourly = text(Days::Every, 540, Some(60), Some(1020));
+        assert!(hourly.runs_at(Weekday::Sunday, 540));
+        assert!(!hourly.runs_at(Weekday::Sunday, 570));
+        assert!(hourly.runs_at(Weekday::Sunday, 600));
+        assert!(hourly.runs_at(Weekday::Sunday, 1020));
+        assert!(!hourly.runs_at(Weekday::Sunday, 1080));
+        assert!(!hourly.runs_at(Weekday::Sunday, 480));
+
+        // Until the end of the day without an end
+        let late = text(Days::Weekend, 1380, Some(30), None);
+        assert!(late.runs_at(Weekday::Saturday, 1410));
+        assert!(!late.runs_at(Weekday::Friday, 1410));
+    }
+
+    #[test]
+    fn shows_exactly_one_thing() {
+        assert!(text(Days::Every, 0, None, None).is_valid(fits));
+        assert!(!text(Days::Every, 0, None, None).is_valid(|_| false));
+
+        let both = Automation {
+            sensor: Some(Sensor::Humidity),
+            ..text(Days::Every, 0, None, None)
+        };
+        assert!(!both.is_valid(fits));
+        let sensor = Automation {
+            text: None,
+            ..both.clone()
+        };
+        assert!(sensor.is_valid(fits));
+        let neither = Automation {
+            sensor: None,
+            ..sensor
+        };
+        assert!(!neither.is_valid(fits));
+    }
+
+    #[test]
+    fn times_are_within_the_day() {
+        assert!(!text(Days::Every, 1440, None, None).is_valid(fits));
+        assert!(!text(Days::Every, 0, Some(0), None).is_valid(fits));
+        assert!(text(Days::Every, 0, Some(1440), None).is_valid(fits));
+        assert!(!text(Days::Every, 0, Some(1441), None).is_valid(fits));
+        // An end needs a repeat, after the start
+        assert!(!text(Days::Every, 600, None, Some(700)).is_valid(fits));
+        assert!(!text(Days::Every, 600, Some(10), Some(599)).is_valid(fits));
+        assert!(!text(Days::Every, 600, Some(10), Some(1440)).is_valid(fits));
+        assert!(text(Days::Every, 600, Some(10), Some(600)).is_valid(fits));
+
+        let long = Automation {
+            seconds: MAX_SECONDS + 1,
+            ..text(Days::Every, 0, None, None)
+        };
+        assert!(!long.is_valid(fits));
+    }
+}
@@ -0,0 +1,80 @@
+//! Week numbers, from the first day of the week
+//!
+//! Weeks starting on Monday are numbered as in ISO 8601, week 1 holding the
+//! first Thursday of the year. Weeks starting on Sunday or Saturday are
+//! numbered from the one holding January 1st, as in North America and the
+//! Middle East.
+
+use jiff::civil::{Date, Weekday};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FirstDay {
+    #[default]
+    Monday,
+    Saturday,
+    Sunday,
+}
+
+impl From<FirstDay> for Weekday {
+    fn from(day: FirstDay) -> Self {
+        match day {
+            FirstDay::Monday => Weekday::Monday,
+            FirstDay::Saturday => Weekday::Saturday,
+            FirstDay::Sunday => Weekday::Sunday,
+        }
+    }
+}
+
+/// Week of the year of `date`, from 1, with weeks starting on `first_day`
+pub fn week_number(date: Date, first_day: FirstDay) -> i8 {
+    match first_day {
+        FirstDay::Monday => date.iso_week_date().week(),
+        first_day => {
+            let offset = date.first_of_year().weekday().since(first_day.into()) as i16;
+            ((date.day_of_year() - 1 + offset) / 7 + 1) as i8
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::civil::date;
+
+    use super::*;
+
+    #[test]
+    fn iso_weeks_cross_the_year() {
+        // 2020 starts on a Wednesday, so it has a week 53
+        assert_eq!(week_number(date(2020, 12, 31), FirstDay::Monday), 53);
+        assert_eq!(week_number(date(2021, 1, 3), FirstDay::Monday), 53);
+        assert_eq!(week_number(date(2021, 1, 4), FirstDay::Monday), 1);
+        // The last days of 2024 are in week 1 of 2025
+        assert_eq!(week_number(date(2024, 12, 29), FirstDay::Monday), 52);
+        assert_eq!(week_number(date(2024, 12, 30), FirstDay::Monday), 1);
+        assert_eq!(week_number(date(2026, 12, 31), FirstDay::Monday), 53);
+    }
+
+    #[test]
+    fn sunday_weeks_start_with_january_1st() {
+        // Saturday, alone in week 1
+        assert_eq!(week_number(date(2022, 1, 1), FirstDay::Sunday), 1);
+        assert_eq!(week_number(date(2022, 1, 2), FirstDay::Sunday), 2);
+        assert_eq!(week_number(date(2022, 12, 31), FirstDay::Sunday), 53);
+        // Sunday, a full week 1
+        assert_eq!(week_number(date(2023, 1, 7), FirstDay::Sunday), 1);
+        assert_eq!(week_number(date(2023, 1, 8), FirstDay::Sunday), 2);
+        // Leap year ending on a Sunday, week 54
+        assert_eq!(week_number(date(2000, 12, 31), FirstDay::Sunday), 54);
+    }
+
+    #[test]
+    fn saturday_weeks_start_with_january_1st() {
+        // Friday, then the week of Saturday 2
+        assert_eq!(week_number(date(2021, 1, 1), FirstDay::Saturday), 1);
+        assert_eq!(week_number(date(2021, 1, 2), FirstDay::Saturday), 2);
+        assert_eq!(week_number(date(2022, 1, 1), FirstDay::Saturday), 1);
+        assert_eq!(week_number(date(2022, 1, 7), FirstDay::Saturday), 1);
+        assert_eq!(week_number(date(2022, 1, 8), FirstDay::Saturday), 2);
+    }
+}
@@ -0,0 +1,95 @@
+//! Words shown on the matrix, per language
+//!
+//! Only the ASCII range of the normal font is available, accents are dropped.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    English,
+    French,
+}
+
+/// Time of day in words, in 5 minute steps
+pub struct TimeWords {
+    /// From midnight, `{h}` in `steps` is replaced by one of them
+    pub hours: [&'static str; 24],
+    /// One per 5 minutes from the full hour
+    pub steps: [&'static str; 12],
+    /// First step counted to the next hour ("TWENTY FIVE TO")
+    pub next_hour_from: usize,
+}
+
+const ENGLISH: TimeWords = TimeWords {
+    hours: [
+        "TWELVE", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE", "TEN",
+        "ELEVEN", "TWELVE", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE",
+        "TEN", "ELEVEN",
+    ],
+    steps: [
+        "{h} O'CLOCK",
+        "FIVE PAST {h}",
+        "TEN PAST {h}",
+        "QUARTER PAST {h}",
+        "TWENTY PAST {h}",
+        "TWENTY FIVE PAST {h}",
+        "HALF PAST {h}",
+        "TWENTY FIVE TO {h}",
+        "TWENTY TO {h}",
+        "QUARTER TO {h}",
+        "TEN TO {h}",
+        "FIVE TO {h}",
+    ],
+    next_hour_from: 7,
+};
+
+const FRENCH: TimeWords = TimeWords {
+    hours: [
+        "MINUIT",
+        "UNE HEURE",
+        "DEUX HEURES",
+        "TROIS HEURES",
+        "QUATRE HEURES",
+        "CINQ HEURES",
+        "SIX HEURES",
+        "SEPT HEURES",
+        "HUIT HEURES",
+        "NEUF HEURES",
+        "DIX HEURES",
+        "ONZE HEURES",
+        "MIDI",
+        "UNE HEURE",
+        "DEUX HEURES",
+        "TROIS HEURES",
+        "QUATRE HEURES",
+        "CINQ HEURES",
+        "SIX HEURES",
+        "SEPT HEURES",
+        "HUIT HEURES",
+        "NEUF HEURES",
+        "DIX HEURES",
+        "ONZE HEURES",
+    ],
+    steps: [
+        "{h}",
+        "{h} CINQ",
+        "{h} DIX",
+        "{h} ET QUART",
+        "{h} VINGT",
+        "{h} VINGT-CINQ",
+        "{h} ET DEMIE",
+        "{h} MOINS VINGT-CINQ",
+        "{h} MOINS VINGT",
+        "{h} MOINS LE QUART",
+        "{h} MOINS DIX",
+        "{h} MOINS CINQ",
+    ],
+    next_hour_from: 7,
+};
+
+impl Language {
+    pub fn time_words(self) -> &'static TimeWords {
+        match self {
+            Language::English => &ENGLISH,
+            Language::French => &FRENCH,
+        }
+    }
+}
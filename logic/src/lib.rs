@@ -0,0 +1,23 @@
+//! Parts of the firmware without hardware or network dependencies
+//!
+//! Built into the firmware like any dependency, and tested on the host from
+//! this directory with `cargo test`: the local `.cargo/config.toml` replaces
+//! the firmware target by the host one.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod alarms;
+pub mod automations;
+pub mod calendar;
+pub mod i18n;
+pub mod morse;
+pub mod schedule;
+pub mod sensor;
+pub mod sha1;
+pub mod sockets;
+pub mod transliteration;
+pub mod units;
+pub mod wifimanager;
+pub mod wordclock;
@@ -0,0 +1,125 @@
+//! Letters, digits and spaces in Morse, as the light of each unit
+
+use alloc::vec::Vec;
+
+/// Longest text
+pub const MAX_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum MorseError {
+    /// Nothing but spaces
+    Empty,
+    TooLong,
+    /// Neither a letter, a digit nor a space
+    Unsupported(char),
+}
+
+/// Dots and dashes of `letter`, either case
+fn code(letter: char) -> Option<&'static str> {
+    let code = match letter.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Light of each unit of `text`: a dot lasts 1 unit, a dash 3, with 1 unit
+/// between the elements of a letter, 3 between letters and 7 between words
+pub fn encode(text: &str) -> Result<Vec<bool>, MorseError> {
+    if text.len() > MAX_LEN {
+        return Err(MorseError::TooLong);
+    }
+
+    let mut units = Vec::new();
+    for (word_idx, word) in text.split_whitespace().enumerate() {
+        if word_idx > 0 {
+            units.extend([false; 7]);
+        }
+        for (letter_idx, letter) in word.chars().enumerate() {
+            let code = code(letter).ok_or(MorseError::Unsupported(letter))?;
+            if letter_idx > 0 {
+                units.extend([false; 3]);
+            }
+            for (element_idx, element) in code.chars().enumerate() {
+                if element_idx > 0 {
+                    units.push(false);
+                }
+                let len = if element == '-' { 3 } else { 1 };
+                units.extend(core::iter::repeat_n(true, len));
+            }
+        }
+    }
+
+    if units.is_empty() {
+        return Err(MorseError::Empty);
+    }
+    Ok(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_follow_the_standard_timing() {
+        // E, then I: dot, gap, dot
+        let units = encode("e i").unwrap();
+        assert_eq!(units.len(), 1 + 7 + 3);
+        assert_eq!(units[..1], [true]);
+        assert!(units[1..8].iter().all(|lit| !lit));
+        assert_eq!(units[8..], [true, false, true]);
+
+        // A then T: dot, gap, dash, letter gap, dash
+        let units = encode("AT").unwrap();
+        assert_eq!(
+            units,
+            [true, false, true, true, true, false, false, false, true, true, true]
+        );
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_sent() {
+        assert!(matches!(encode("   "), Err(MorseError::Empty)));
+        assert!(matches!(
+            encode("ABCDEFGHIJKLMNOPQ"),
+            Err(MorseError::TooLong)
+        ));
+        assert!(matches!(encode("OK!"), Err(MorseError::Unsupported('!'))));
+        assert!(encode("ABCDEFGHIJKLMNOP").is_ok());
+    }
+}
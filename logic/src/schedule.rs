@@ -0,0 +1,39 @@
+//! Days of the weekly schedules of themes, wake-up lights and automations
+
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Days {
+    Every,
+    /// Monday to Friday
+    Workdays,
+    Weekend,
+}
+
+impl Days {
+    pub fn contains(self, weekday: Weekday) -> bool {
+        let weekend = matches!(weekday, Weekday::Saturday | Weekday::Sunday);
+        match self {
+            Days::Every => true,
+            Days::Workdays => !weekend,
+            Days::Weekend => weekend,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekend_is_saturday_and_sunday() {
+        assert!(Days::Workdays.contains(Weekday::Monday));
+        assert!(Days::Workdays.contains(Weekday::Friday));
+        assert!(!Days::Workdays.contains(Weekday::Saturday));
+        assert!(Days::Weekend.contains(Weekday::Saturday));
+        assert!(Days::Weekend.contains(Weekday::Sunday));
+        assert!(!Days::Weekend.contains(Weekday::Monday));
+        assert!(Days::Every.contains(Weekday::Sunday));
+    }
+}
@@ -0,0 +1,13 @@
+//! Readings of the alerts and automations
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sensor {
+    /// °C
+    Temperature,
+    /// %
+    Humidity,
+    /// Charge, %
+    Battery,
+}
@@ -0,0 +1,104 @@
+//! Socket budget of the station stack, the sum of the sockets each use holds
+//! at the same time
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Use {
+    /// DHCP client and DNS resolver, added by the stack itself
+    Stack,
+    Api,
+    Discovery,
+    ShowSync,
+    Mqtt,
+    Ntp,
+    SelfTest,
+    HttpClient,
+    Webhook,
+}
+
+pub const USES: [Use; 9] = [
+    Use::Stack,
+    Use::Api,
+    Use::Discovery,
+    Use::ShowSync,
+    Use::Mqtt,
+    Use::Ntp,
+    Use::SelfTest,
+    Use::HttpClient,
+    Use::Webhook,
+];
+
+impl Use {
+    /// Sockets of this use open at the same time, at most
+    pub const fn max_open(self) -> usize {
+        match self {
+            Use::Stack => 2,
+            // One API task, serving one connection at a time
+            Use::Api => 1,
+            Use::Discovery => 1,
+            Use::ShowSync => 1,
+            Use::Mqtt => 1,
+            Use::Ntp => 1,
+            // The HTTP probe is closed before the NTP one opens
+            Use::SelfTest => 1,
+            // Location lookup, Open-Meteo and Home Assistant, each from its task
+            Use::HttpClient => 3,
+            // Hooks are posted one after the other
+            Use::Webhook => 1,
+        }
+    }
+}
+
+/// Sockets of every use at its peak at once
+pub const fn budget() -> usize {
+    let mut total = 0;
+    let mut idx = 0;
+    while idx < USES.len() {
+        total += USES[idx].max_open();
+        idx += 1;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_use_listed_once() {
+        assert_eq!(USES.len(), Use::Webhook as usize + 1);
+        for (idx, usage) in USES.iter().enumerate() {
+            assert_eq!(*usage as usize, idx);
+        }
+    }
+
+    #[test]
+    fn budget_covers_every_user_at_once() {
+        // Always open: DHCP, DNS, the API, discovery, show sync and MQTT
+        let always = [
+            Use::Stack,
+            Use::Stack,
+            Use::Api,
+            Use::Discovery,
+            Use::ShowSync,
+            Use::Mqtt,
+        ];
+        // Now and then: NTP, the self-test, a webhook and the HTTP clients
+        let occasional = [
+            Use::Ntp,
+            Use::SelfTest,
+            Use::Webhook,
+            Use::HttpClient,
+            Use::HttpClient,
+            Use::HttpClient,
+        ];
+        let users: Vec<Use> = always.into_iter().chain(occasional).collect();
+
+        for usage in USES {
+            let count = users.iter().filter(|&&user| user == usage).count();
+            assert_eq!(usage.max_open(), count, "{usage:?}");
+        }
+        assert_eq!(budget(), users.len());
+    }
+}
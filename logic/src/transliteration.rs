@@ -0,0 +1,154 @@
+//! Text written with the glyphs of the matrix font
+//!
+//! The matrix font only has printable ASCII. Curly quotes, dashes, spaces and
+//! accented Latin letters are replaced by their closest ASCII, any other
+//! character is refused.
+
+use alloc::{string::String, vec::Vec};
+
+#[derive(Debug)]
+pub enum MessageError {
+    /// Nothing but spaces
+    Empty,
+    TooLong,
+    /// Characters without a glyph nor a replacement, once each, in order
+    Unsupported(Vec<char>),
+}
+
+/// `text` written with the glyphs of a font, those for which `has_glyph` is
+/// true, up to `max_len` bytes
+///
+/// `&` and `` ` `` are taken by the temperature units: °C and °F are written
+/// with them, and the characters themselves are replaced.
+pub fn fit(
+    text: &str,
+    has_glyph: impl Fn(char) -> bool,
+    max_len: usize,
+) -> Result<String, MessageError> {
+    let mut fitted = String::with_capacity(text.len());
+    let mut unsupported = Vec::new();
+    let mut letters = text.chars().peekable();
+    while let Some(letter) = letters.next() {
+        match letter {
+            // Their glyphs are the temperature units
+            '&' => fitted.push('+'),
+            '`' => fitted.push('\''),
+            '°' if letters.next_if_eq(&'C').is_some() => fitted.push('&'),
+            '°' if letters.next_if_eq(&'F').is_some() => fitted.push('`'),
+            letter if has_glyph(letter) => fitted.push(letter),
+            letter => match replacement(letter) {
+                Some(ascii) => fitted.push_str(ascii),
+                None if !unsupported.contains(&letter) => unsupported.push(letter),
+                None => {}
+            },
+        }
+    }
+
+    if !unsupported.is_empty() {
+        Err(MessageError::Unsupported(unsupported))
+    } else if fitted.trim().is_empty() {
+        Err(MessageError::Empty)
+    } else if fitted.len() > max_len {
+        Err(MessageError::TooLong)
+    } else {
+        Ok(fitted)
+    }
+}
+
+/// Closest ASCII of common punctuation and Latin-1 letters
+fn replacement(letter: char) -> Option<&'static str> {
+    let ascii = match letter {
+        '\t' | '\n' | '\r' | '\u{a0}' | '\u{2002}'..='\u{200a}' | '\u{202f}' => " ",
+        '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' | '´' => "'",
+        '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{2033}' | '«' | '»' => "\"",
+        '\u{2010}'..='\u{2015}' | '\u{2212}' => "-",
+        '\u{2026}' => "...",
+        '·' | '•' => ".",
+        '×' => "x",
+        '÷' => "/",
+        '€' => "EUR",
+        'À'..='Å' => "A",
+        'Æ' => "AE",
+        'Ç' => "C",
+        'È'..='Ë' => "E",
+        'Ì'..='Ï' => "I",
+        'Ñ' => "N",
+        'Ò'..='Ö' | 'Ø' => "O",
+        'Ù'..='Ü' => "U",
+        'Ý' | 'Ÿ' => "Y",
+        'Œ' => "OE",
+        'ß' => "ss",
+        'à'..='å' => "a",
+        'æ' => "ae",
+        'ç' => "c",
+        'è'..='ë' => "e",
+        'ì'..='ï' => "i",
+        'ñ' => "n",
+        'ò'..='ö' | 'ø' => "o",
+        'ù'..='ü' => "u",
+        'ý' | 'ÿ' => "y",
+        'œ' => "oe",
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+/// `letters` quoted and escaped, for the reports
+pub fn describe(letters: &[char]) -> String {
+    letters
+        .iter()
+        .map(|letter| alloc::format!("{letter:?}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascii(text: &str) -> Result<String, MessageError> {
+        fit(
+            text,
+            |letter| letter == ' ' || letter.is_ascii_graphic(),
+            16,
+        )
+    }
+
+    #[test]
+    fn drops_accents_and_typography() {
+        assert_eq!(ascii("café").unwrap(), "cafe");
+        assert_eq!(ascii("Œuvre à 5€").unwrap(), "OEuvre a 5EUR");
+        assert_eq!(
+            ascii("\u{201c}Hi\u{201d} \u{2014} ok").unwrap(),
+            "\"Hi\" - ok"
+        );
+    }
+
+    #[test]
+    fn temperature_units_take_two_glyphs() {
+        assert_eq!(ascii("21°C").unwrap(), "21&");
+        assert_eq!(ascii("70°F").unwrap(), "70`");
+        assert_eq!(ascii("A & B `x`").unwrap(), "A + B 'x'");
+    }
+
+    #[test]
+    fn reports_each_unsupported_character_once() {
+        let Err(MessageError::Unsupported(letters)) = ascii("日本 ° 日") else {
+            panic!("fitted");
+        };
+        assert_eq!(letters, ['日', '本', '°']);
+        assert_eq!(describe(&letters), "'日' '本' '°'");
+    }
+
+    #[test]
+    fn refuses_empty_and_long_text() {
+        assert!(matches!(ascii(" \t\n"), Err(MessageError::Empty)));
+        assert!(matches!(
+            ascii("0123456789abcdefg"),
+            Err(MessageError::TooLong)
+        ));
+        // Replacements count once written
+        assert!(matches!(ascii("………………"), Err(MessageError::TooLong)));
+        assert_eq!(ascii("0123456789abcdef").unwrap().len(), 16);
+    }
+}
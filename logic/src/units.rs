@@ -0,0 +1,119 @@
+//! Units and number formats of what the faces and widgets show
+//!
+//! Metric, day first and a decimal point by default.
+
+use alloc::string::String;
+
+use jiff::civil::Date;
+use serde::{Deserialize, Serialize};
+
+/// Degree and unit letters, glyphs of the normal font
+const CELSIUS: char = '&';
+const FAHRENHEIT: char = '`';
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DateOrder {
+    /// 16/10, day first
+    #[default]
+    Dmy,
+    /// 10/16, month first
+    Mdy,
+    /// 10-16, year first
+    Ymd,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecimalMark {
+    #[default]
+    Point,
+    Comma,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Units {
+    #[serde(default)]
+    pub temperature: TemperatureUnit,
+    #[serde(default)]
+    pub date_order: DateOrder,
+    #[serde(default)]
+    pub decimal: DecimalMark,
+}
+
+/// Temperature from °C with `decimals` digits and the unit, dashes when
+/// unknown
+pub fn temperature(units: Units, celsius: Option<f32>, decimals: usize) -> String {
+    let unit = match units.temperature {
+        TemperatureUnit::Celsius => CELSIUS,
+        TemperatureUnit::Fahrenheit => FAHRENHEIT,
+    };
+    let value = celsius.map(|celsius| match units.temperature {
+        TemperatureUnit::Celsius => celsius,
+        TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+    });
+    let text = match value {
+        Some(value) => alloc::format!("{value:.decimals$}{unit}"),
+        None if decimals > 0 => alloc::format!("--.{:-<decimals$}{unit}", ""),
+        None => alloc::format!("--{unit}"),
+    };
+    match units.decimal {
+        DecimalMark::Point => text,
+        DecimalMark::Comma => text.replace('.', ","),
+    }
+}
+
+/// Day and month of `date`, in `order`
+pub fn short_date(order: DateOrder, date: Date) -> String {
+    match order {
+        DateOrder::Dmy => alloc::format!("{:02}/{:02}", date.day(), date.month()),
+        DateOrder::Mdy => alloc::format!("{:02}/{:02}", date.month(), date.day()),
+        DateOrder::Ymd => alloc::format!("{:02}-{:02}", date.month(), date.day()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jiff::civil::date;
+
+    use super::*;
+
+    #[test]
+    fn temperatures_follow_the_units() {
+        let metric = Units::default();
+        assert_eq!(temperature(metric, Some(21.46), 1), "21.5&");
+        assert_eq!(temperature(metric, Some(-3.0), 0), "-3&");
+
+        let imperial = Units {
+            temperature: TemperatureUnit::Fahrenheit,
+            decimal: DecimalMark::Comma,
+            ..Units::default()
+        };
+        assert_eq!(temperature(imperial, Some(20.0), 1), "68,0`");
+        assert_eq!(temperature(imperial, Some(-40.0), 0), "-40`");
+    }
+
+    #[test]
+    fn unknown_temperatures_are_dashes() {
+        let comma = Units {
+            decimal: DecimalMark::Comma,
+            ..Units::default()
+        };
+        assert_eq!(temperature(Units::default(), None, 0), "--&");
+        assert_eq!(temperature(Units::default(), None, 2), "--.--&");
+        assert_eq!(temperature(comma, None, 1), "--,-&");
+    }
+
+    #[test]
+    fn dates_follow_the_order() {
+        let day = date(2026, 3, 7);
+        assert_eq!(short_date(DateOrder::Dmy, day), "07/03");
+        assert_eq!(short_date(DateOrder::Mdy, day), "03/07");
+        assert_eq!(short_date(DateOrder::Ymd, day), "03-07");
+    }
+}
@@ -0,0 +1,129 @@
+//! Wildcard DNS of the setup portal
+//!
+//! Every name resolves to the AP, so the connectivity probes of phones reach
+//! the portal web server and make them pop the sign-in window.
+
+/// Size of the DNS header
+const HEADER_LEN: usize = 12;
+/// Answer appended after the question: name pointer, type, class, TTL, length
+const ANSWER_LEN: usize = 16;
+/// Short, the names stop resolving to the AP once setup is done
+const TTL_SECS: u32 = 60;
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+/// Write into `out` the answer to `query` pointing its name to `ip`
+///
+/// Only A and ANY questions get an address, the other types an empty answer.
+/// Returns the length of the answer, `None` when `query` is not a standard
+/// query or `out` is too small.
+pub fn answer(query: &[u8], ip: [u8; 4], out: &mut [u8]) -> Option<usize> {
+    let header = query.get(..HEADER_LEN)?;
+    let is_response = header[2] & 0x80 != 0;
+    let opcode = (header[2] >> 3) & 0x0f;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    if is_response || opcode != 0 || questions == 0 {
+        return None;
+    }
+
+    // First question only: a name of labels up to the empty one, type and class
+    let mut pos = HEADER_LEN;
+    loop {
+        let label = *query.get(pos)? as usize;
+        // Compression is not expected in a question
+        if label & 0xc0 != 0 {
+            return None;
+        }
+        pos += 1 + label;
+        if label == 0 {
+            break;
+        }
+    }
+    let fields = query.get(pos..pos + 4)?;
+    let qtype = u16::from_be_bytes([fields[0], fields[1]]);
+    let qclass = u16::from_be_bytes([fields[2], fields[3]]);
+    let question_end = pos + 4;
+
+    let answers = qclass == CLASS_IN && (qtype == TYPE_A || qtype == TYPE_ANY);
+    let len = question_end + if answers { ANSWER_LEN } else { 0 };
+    let out = out.get_mut(..len)?;
+
+    out[..question_end].copy_from_slice(&query[..question_end]);
+    // Authoritative response, recursion desired copied, no error
+    out[2] = 0x84 | (query[2] & 0x01);
+    out[3] = 0;
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answers as u16).to_be_bytes());
+    out[8..12].fill(0);
+
+    if answers {
+        let answer = &mut out[question_end..];
+        // Pointer to the question name, right after the header
+        answer[..2].copy_from_slice(&[0xc0, HEADER_LEN as u8]);
+        answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&ip);
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP: [u8; 4] = [192, 168, 4, 1];
+
+    fn query(qtype: u16) -> alloc::vec::Vec<u8> {
+        let mut query = alloc::vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in ["captive", "apple", "com"] {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn a_question_resolves_to_the_ap() {
+        let query = query(TYPE_A);
+        let mut out = [0; 512];
+        let len = answer(&query, AP, &mut out).unwrap();
+
+        assert_eq!(len, query.len() + ANSWER_LEN);
+        // Same id and question, one answer
+        assert_eq!(out[..2], query[..2]);
+        assert_eq!(out[2] & 0x80, 0x80);
+        assert_eq!(out[12..query.len()], query[12..]);
+        assert_eq!(out[6..8], [0, 1]);
+        assert_eq!(out[len - 4..len], AP);
+    }
+
+    #[test]
+    fn other_types_get_no_address() {
+        // AAAA
+        let query = query(28);
+        let mut out = [0; 512];
+        let len = answer(&query, AP, &mut out).unwrap();
+
+        assert_eq!(len, query.len());
+        assert_eq!(out[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn ignores_responses_and_truncated_queries() {
+        let mut out = [0; 512];
+        let mut response = query(TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(answer(&response, AP, &mut out), None);
+
+        let query = query(TYPE_A);
+        assert_eq!(answer(&query[..query.len() - 2], AP, &mut out), None);
+        assert_eq!(answer(&query, AP, &mut out[..20]), None);
+    }
+}
@@ -0,0 +1,392 @@
+//! Connection and provisioning decisions, without the radio
+//!
+//! The tasks driving the esp-radio controller feed what happened to these
+//! machines and carry out the command they answer. Times are milliseconds
+//! from any fixed origin, the machines never read a clock.
+
+/// What the setup portal worker reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupInput {
+    /// Nothing new, polled periodically
+    Tick,
+    /// Credentials were submitted through the portal
+    Credentials,
+    /// The connection with the submitted credentials succeeded
+    Connected,
+    /// The connection failed: wrong password, unknown network or timeout
+    ConnectFailed,
+    /// A scan finished
+    Scanned,
+}
+
+/// What the setup portal worker must do next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupCommand {
+    /// Wait for the next input
+    Idle,
+    /// Try the submitted credentials
+    Connect,
+    /// Refresh the scan list shown by the portal
+    Scan,
+    /// Close the portal and keep the credentials
+    Finish,
+    /// Nobody set the device up in time
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetupState {
+    Waiting,
+    Connecting,
+    Done,
+}
+
+/// Setup portal, from its start until credentials work
+#[derive(Clone, Debug)]
+pub struct Provisioning {
+    state: SetupState,
+    started: u64,
+    last_scan: Option<u64>,
+    scan_interval: u64,
+    reset_timeout: Option<u64>,
+    /// Failed attempts, reported in the logs
+    pub failures: u32,
+}
+
+impl Provisioning {
+    pub fn new(now: u64, scan_interval: u64, reset_timeout: Option<u64>) -> Self {
+        Self {
+            state: SetupState::Waiting,
+            started: now,
+            last_scan: None,
+            scan_interval,
+            reset_timeout,
+            failures: 0,
+        }
+    }
+
+    pub fn next(&mut self, input: SetupInput, now: u64) -> SetupCommand {
+        match (self.state, input) {
+            (SetupState::Done, _) => SetupCommand::Idle,
+            (SetupState::Waiting, SetupInput::Credentials) => {
+                self.state = SetupState::Connecting;
+                SetupCommand::Connect
+            }
+            (SetupState::Connecting, SetupInput::Connected) => {
+                self.state = SetupState::Done;
+                SetupCommand::Finish
+            }
+            (SetupState::Connecting, SetupInput::ConnectFailed) => {
+                self.failures += 1;
+                self.state = SetupState::Waiting;
+                SetupCommand::Idle
+            }
+            (SetupState::Connecting, _) => SetupCommand::Idle,
+            (SetupState::Waiting, SetupInput::Scanned) => {
+                self.last_scan = Some(now);
+                SetupCommand::Idle
+            }
+            (SetupState::Waiting, _) => {
+                if self
+                    .reset_timeout
+                    .is_some_and(|timeout| now.saturating_sub(self.started) >= timeout)
+                {
+                    SetupCommand::Reset
+                } else if self
+                    .last_scan
+                    .is_none_or(|last| now.saturating_sub(last) >= self.scan_interval)
+                {
+                    SetupCommand::Scan
+                } else {
+                    SetupCommand::Idle
+                }
+            }
+        }
+    }
+}
+
+/// What the station connection task reports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkInput {
+    Connected,
+    ConnectFailed,
+    Disconnected,
+    /// `WmReturn::stop_radio`
+    Stop,
+    /// `WmReturn::restart_radio`
+    Restart,
+}
+
+/// What the station connection task must do next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkCommand {
+    /// Connect now
+    Connect,
+    /// Connect after the reconnect delay
+    ConnectLater,
+    /// Wait for a disconnection or a stop
+    WaitDisconnect,
+    /// Disconnect and stop the radio
+    StopRadio,
+    /// Start the radio again, then connect after the reconnect delay
+    StartRadio,
+    /// Wait for a restart
+    WaitRestart,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LinkState {
+    Connecting,
+    Connected,
+    Stopped,
+}
+
+/// Station connection kept up after the setup
+#[derive(Clone, Debug)]
+pub struct Reconnect {
+    state: LinkState,
+}
+
+impl Reconnect {
+    pub fn new(connected: bool) -> Self {
+        Self {
+            state: if connected {
+                LinkState::Connected
+            } else {
+                LinkState::Connecting
+            },
+        }
+    }
+
+    /// First command to run
+    pub fn start(&self) -> LinkCommand {
+        match self.state {
+            LinkState::Connected => LinkCommand::WaitDisconnect,
+            LinkState::Connecting => LinkCommand::Connect,
+            LinkState::Stopped => LinkCommand::WaitRestart,
+        }
+    }
+
+    pub fn next(&mut self, input: LinkInput) -> LinkCommand {
+        match (self.state, input) {
+            (LinkState::Stopped, LinkInput::Restart) => {
+                self.state = LinkState::Connecting;
+                LinkCommand::StartRadio
+            }
+            (LinkState::Stopped, _) => LinkCommand::WaitRestart,
+            (_, LinkInput::Stop) => {
+                self.state = LinkState::Stopped;
+                LinkCommand::StopRadio
+            }
+            (_, LinkInput::Connected) => {
+                self.state = LinkState::Connected;
+                LinkCommand::WaitDisconnect
+            }
+            // Already running, nothing to restart
+            (LinkState::Connected, LinkInput::Restart) => LinkCommand::WaitDisconnect,
+            (_, LinkInput::Disconnected | LinkInput::ConnectFailed) => {
+                self.state = LinkState::Connecting;
+                LinkCommand::ConnectLater
+            }
+            (LinkState::Connecting, LinkInput::Restart) => LinkCommand::Connect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCAN_INTERVAL: u64 = 10_000;
+    const RESET_TIMEOUT: u64 = 300_000;
+
+    /// Feed `steps` of (now, input) to `machine`, checking each command
+    fn run_setup(machine: &mut Provisioning, steps: &[(u64, SetupInput, SetupCommand)]) {
+        for (idx, &(now, input, expected)) in steps.iter().enumerate() {
+            assert_eq!(
+                machine.next(input, now),
+                expected,
+                "step {idx}: {input:?} at {now}"
+            );
+        }
+    }
+
+    fn run_link(machine: &mut Reconnect, steps: &[(LinkInput, LinkCommand)]) {
+        for (idx, &(input, expected)) in steps.iter().enumerate() {
+            assert_eq!(machine.next(input), expected, "step {idx}: {input:?}");
+        }
+    }
+
+    #[test]
+    fn scans_periodically_while_waiting() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, None);
+        run_setup(
+            &mut machine,
+            &[
+                (0, SetupInput::Tick, SetupCommand::Scan),
+                (2_000, SetupInput::Scanned, SetupCommand::Idle),
+                (5_000, SetupInput::Tick, SetupCommand::Idle),
+                (11_999, SetupInput::Tick, SetupCommand::Idle),
+                (12_000, SetupInput::Tick, SetupCommand::Scan),
+            ],
+        );
+    }
+
+    #[test]
+    fn credentials_that_work_finish() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, Some(RESET_TIMEOUT));
+        run_setup(
+            &mut machine,
+            &[
+                (1_000, SetupInput::Credentials, SetupCommand::Connect),
+                // No scan nor reset while connecting
+                (RESET_TIMEOUT, SetupInput::Tick, SetupCommand::Idle),
+                (
+                    RESET_TIMEOUT + 1,
+                    SetupInput::Connected,
+                    SetupCommand::Finish,
+                ),
+                (RESET_TIMEOUT + 2, SetupInput::Tick, SetupCommand::Idle),
+                (
+                    RESET_TIMEOUT + 3,
+                    SetupInput::Credentials,
+                    SetupCommand::Idle,
+                ),
+            ],
+        );
+        assert_eq!(machine.failures, 0);
+    }
+
+    #[test]
+    fn wrong_password_goes_back_to_waiting() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, None);
+        run_setup(
+            &mut machine,
+            &[
+                (0, SetupInput::Scanned, SetupCommand::Idle),
+                (1_000, SetupInput::Credentials, SetupCommand::Connect),
+                (6_000, SetupInput::ConnectFailed, SetupCommand::Idle),
+                (7_000, SetupInput::Tick, SetupCommand::Idle),
+                (8_000, SetupInput::Credentials, SetupCommand::Connect),
+                (9_000, SetupInput::ConnectFailed, SetupCommand::Idle),
+                // The portal list is refreshed again between attempts
+                (10_000, SetupInput::Tick, SetupCommand::Scan),
+                (11_000, SetupInput::Credentials, SetupCommand::Connect),
+                (12_000, SetupInput::Connected, SetupCommand::Finish),
+            ],
+        );
+        assert_eq!(machine.failures, 2);
+    }
+
+    #[test]
+    fn connect_timeout_counts_as_failure() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, None);
+        run_setup(
+            &mut machine,
+            &[
+                (0, SetupInput::Credentials, SetupCommand::Connect),
+                // Scans finishing late are ignored while connecting
+                (3_000, SetupInput::Scanned, SetupCommand::Idle),
+                (30_000, SetupInput::ConnectFailed, SetupCommand::Idle),
+                (30_001, SetupInput::Tick, SetupCommand::Scan),
+            ],
+        );
+        assert_eq!(machine.failures, 1);
+    }
+
+    #[test]
+    fn resets_when_nobody_sets_up() {
+        let mut machine = Provisioning::new(1_000, SCAN_INTERVAL, Some(RESET_TIMEOUT));
+        run_setup(
+            &mut machine,
+            &[
+                (1_000, SetupInput::Scanned, SetupCommand::Idle),
+                (RESET_TIMEOUT, SetupInput::Tick, SetupCommand::Scan),
+                (RESET_TIMEOUT + 999, SetupInput::Scanned, SetupCommand::Idle),
+                (RESET_TIMEOUT + 1_000, SetupInput::Tick, SetupCommand::Reset),
+            ],
+        );
+    }
+
+    #[test]
+    fn reset_timeout_spans_failed_attempts() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, Some(RESET_TIMEOUT));
+        run_setup(
+            &mut machine,
+            &[
+                (
+                    RESET_TIMEOUT - 10,
+                    SetupInput::Credentials,
+                    SetupCommand::Connect,
+                ),
+                (
+                    RESET_TIMEOUT + 10,
+                    SetupInput::ConnectFailed,
+                    SetupCommand::Idle,
+                ),
+                (RESET_TIMEOUT + 20, SetupInput::Tick, SetupCommand::Reset),
+            ],
+        );
+    }
+
+    #[test]
+    fn never_resets_without_timeout() {
+        let mut machine = Provisioning::new(0, SCAN_INTERVAL, None);
+        run_setup(
+            &mut machine,
+            &[
+                (0, SetupInput::Scanned, SetupCommand::Idle),
+                (u64::MAX / 2, SetupInput::Tick, SetupCommand::Scan),
+            ],
+        );
+    }
+
+    #[test]
+    fn reconnects_after_drops() {
+        let mut machine = Reconnect::new(false);
+        assert_eq!(machine.start(), LinkCommand::Connect);
+        run_link(
+            &mut machine,
+            &[
+                (LinkInput::ConnectFailed, LinkCommand::ConnectLater),
+                (LinkInput::ConnectFailed, LinkCommand::ConnectLater),
+                (LinkInput::Connected, LinkCommand::WaitDisconnect),
+                (LinkInput::Disconnected, LinkCommand::ConnectLater),
+                (LinkInput::Connected, LinkCommand::WaitDisconnect),
+            ],
+        );
+    }
+
+    #[test]
+    fn starts_connected_after_setup() {
+        let mut machine = Reconnect::new(true);
+        assert_eq!(machine.start(), LinkCommand::WaitDisconnect);
+        run_link(
+            &mut machine,
+            &[
+                (LinkInput::Restart, LinkCommand::WaitDisconnect),
+                (LinkInput::Disconnected, LinkCommand::ConnectLater),
+            ],
+        );
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let mut machine = Reconnect::new(true);
+        run_link(
+            &mut machine,
+            &[
+                (LinkInput::Stop, LinkCommand::StopRadio),
+                // Nothing but a restart leaves the stopped state
+                (LinkInput::Disconnected, LinkCommand::WaitRestart),
+                (LinkInput::Connected, LinkCommand::WaitRestart),
+                (LinkInput::Stop, LinkCommand::WaitRestart),
+                (LinkInput::Restart, LinkCommand::StartRadio),
+                (LinkInput::ConnectFailed, LinkCommand::ConnectLater),
+                (LinkInput::Restart, LinkCommand::Connect),
+                (LinkInput::Stop, LinkCommand::StopRadio),
+            ],
+        );
+        assert_eq!(machine.start(), LinkCommand::WaitRestart);
+    }
+}
@@ -0,0 +1,6 @@
+//! Wifi manager parts tested on the host, the firmware drives them
+
+pub mod captive;
+pub mod machine;
+pub mod radio;
+pub mod setup;
@@ -0,0 +1,144 @@
+//! What the wifimanager needs from the radio
+//!
+//! The firmware implements `RadioControl` over the esp-radio controller,
+//! converting these types to and from the driver ones, and the tests over a
+//! mock radio.
+
+use alloc::{string::String, vec::Vec};
+use core::future::Future;
+
+use serde::{Deserialize, Serialize};
+
+/// Network the station joins
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StationConfig {
+    pub ssid: String,
+    pub password: String,
+}
+
+/// Open network of the setup portal
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessPointConfig {
+    pub ssid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RadioConfig {
+    Station(StationConfig),
+    /// Setup portal, with the station trying the submitted credentials
+    AccessPointStation(StationConfig, AccessPointConfig),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadioError {
+    /// The radio is not started, or not in a mode allowing the operation
+    NotStarted,
+    /// Disconnected, or the access point could not be joined
+    Disconnected,
+    Unsupported,
+    InvalidArguments,
+    /// Error of the driver itself
+    Internal,
+}
+
+/// Access point found by a scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanResult {
+    pub ssid: String,
+    pub channel: u8,
+    /// In dBm
+    pub signal_strength: i8,
+    /// No authentication
+    pub open: bool,
+}
+
+/// Modem sleep of the station
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PowerSave {
+    /// Always awake
+    #[default]
+    None,
+    /// Awake for each DTIM beacon
+    Minimum,
+    /// Awake at the listen interval
+    Maximum,
+}
+
+pub trait RadioControl {
+    fn configure(&mut self, config: &RadioConfig) -> Result<(), RadioError>;
+
+    fn start(&mut self) -> impl Future<Output = Result<(), RadioError>>;
+
+    fn stop(&mut self) -> impl Future<Output = Result<(), RadioError>>;
+
+    /// Connect the station with the current configuration
+    fn connect(&mut self) -> impl Future<Output = Result<(), RadioError>>;
+
+    fn disconnect(&mut self) -> impl Future<Output = Result<(), RadioError>>;
+
+    /// Access points in range
+    fn scan(&mut self) -> impl Future<Output = Result<Vec<ScanResult>, RadioError>>;
+
+    /// Wait for the station to lose its access point
+    fn wait_disconnected(&mut self) -> impl Future<Output = ()>;
+
+    fn is_connected(&self) -> bool;
+
+    /// Signal of the access point in dBm, from its last beacon
+    fn rssi(&self) -> Option<i8>;
+
+    fn set_power_saving(&mut self, mode: PowerSave) -> Result<(), RadioError>;
+}
+
+/// Which access points the portal lists
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanFilter {
+    /// Weaker ones are hidden (in dBm)
+    pub min_rssi: Option<i8>,
+    /// Hide the ones outside the 2.4 GHz band (channels 1-14)
+    pub only_2g4: bool,
+    /// Hide open networks
+    pub skip_open: bool,
+}
+
+impl ScanFilter {
+    pub fn accepts(&self, ap: &ScanResult) -> bool {
+        if self.min_rssi.is_some_and(|min| ap.signal_strength < min) {
+            return false;
+        }
+
+        if self.only_2g4 && !(1..=14).contains(&ap.channel) {
+            return false;
+        }
+
+        !(self.skip_open && ap.open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ap(channel: u8, signal_strength: i8, open: bool) -> ScanResult {
+        ScanResult {
+            ssid: "home".into(),
+            channel,
+            signal_strength,
+            open,
+        }
+    }
+
+    #[test]
+    fn filter_hides_weak_far_and_open() {
+        let filter = ScanFilter {
+            min_rssi: Some(-85),
+            only_2g4: true,
+            skip_open: true,
+        };
+        assert!(filter.accepts(&ap(6, -85, false)));
+        assert!(!filter.accepts(&ap(6, -86, false)));
+        assert!(!filter.accepts(&ap(36, -40, false)));
+        assert!(!filter.accepts(&ap(6, -40, true)));
+        assert!(ScanFilter::default().accepts(&ap(36, -100, true)));
+    }
+}
@@ -0,0 +1,441 @@
+//! Setup portal worker, from the first scan until credentials work
+//!
+//! Drives `Provisioning` with a radio, the portal collecting credentials and
+//! a clock, each behind a trait: the firmware passes the esp-radio
+//! controller, the setup AP signals and embassy time.
+
+use core::future::Future;
+
+use embassy_futures::select::{select, Either};
+
+use super::machine::{Provisioning, SetupCommand, SetupInput};
+use super::radio::{AccessPointConfig, RadioConfig, RadioControl, RadioError, ScanFilter};
+use super::radio::{ScanResult, StationConfig};
+
+/// Pause between two checks for submitted credentials (in ms)
+const IDLE_POLL: u64 = 100;
+
+/// Milliseconds from any fixed origin
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+
+    fn sleep_ms(&self, ms: u64) -> impl Future<Output = ()>;
+}
+
+pub trait SetupPortal {
+    type Credentials;
+
+    /// Credentials were submitted and wait to be tried
+    fn pending(&self) -> bool;
+
+    /// Wait for submitted credentials
+    fn credentials(&mut self) -> impl Future<Output = Self::Credentials>;
+
+    fn station(&self, credentials: &Self::Credentials) -> StationConfig;
+
+    /// Replace the list of access points shown
+    fn show_scan(&mut self, aps: &[ScanResult]) -> impl Future<Output = ()>;
+
+    /// The submitted credentials did not connect
+    fn connect_failed(&mut self, error: ConnectError);
+
+    /// Close the portal, the station is connected
+    fn finish(&mut self) -> impl Future<Output = ()>;
+
+    /// Nobody set the device up in time, the firmware restarts
+    fn reset(&mut self) -> impl Future<Output = ()>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SetupSettings {
+    pub access_point: AccessPointConfig,
+    /// Time between two scans (in ms)
+    pub scan_interval: u64,
+    /// Time after which the device resets, without working credentials (in ms)
+    pub reset_timeout: Option<u64>,
+    /// Time given to each connection (in ms)
+    pub connect_timeout: u64,
+    pub scan_filter: ScanFilter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectError {
+    /// No answer in time
+    Timeout,
+    /// Last error of the radio, retried until the timeout
+    Radio(RadioError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupError {
+    Radio(RadioError),
+    /// The reset timeout passed
+    Reset,
+    /// Finished without a connection
+    NotConnected,
+}
+
+impl From<RadioError> for SetupError {
+    fn from(error: RadioError) -> Self {
+        Self::Radio(error)
+    }
+}
+
+/// Connect the station, retrying failed attempts until `timeout` (in ms)
+pub async fn connect_within(
+    radio: &mut impl RadioControl,
+    clock: &impl Clock,
+    timeout: u64,
+) -> Result<(), ConnectError> {
+    let start = clock.now_ms();
+    let mut error = ConnectError::Timeout;
+    loop {
+        if clock.now_ms().saturating_sub(start) > timeout {
+            return Err(error);
+        }
+
+        match select(radio.connect(), clock.sleep_ms(timeout)).await {
+            Either::First(Ok(())) => return Ok(()),
+            Either::First(Err(e)) => error = ConnectError::Radio(e),
+            Either::Second(()) => return Err(ConnectError::Timeout),
+        }
+    }
+}
+
+/// Run the setup portal until submitted credentials connect, and return them
+///
+/// The radio must be started with the access point of `settings`.
+pub async fn wifi_connection_worker<P: SetupPortal>(
+    radio: &mut impl RadioControl,
+    portal: &mut P,
+    clock: &impl Clock,
+    settings: &SetupSettings,
+) -> Result<P::Credentials, SetupError> {
+    let mut machine = Provisioning::new(
+        clock.now_ms(),
+        settings.scan_interval,
+        settings.reset_timeout,
+    );
+    let mut connected = None;
+    let mut input = SetupInput::Tick;
+    loop {
+        input = match machine.next(input, clock.now_ms()) {
+            SetupCommand::Connect => {
+                let credentials = portal.credentials().await;
+                radio.configure(&RadioConfig::AccessPointStation(
+                    portal.station(&credentials),
+                    settings.access_point.clone(),
+                ))?;
+
+                match connect_within(radio, clock, settings.connect_timeout).await {
+                    Ok(()) => {
+                        connected = Some(credentials);
+                        SetupInput::Connected
+                    }
+                    Err(e) => {
+                        portal.connect_failed(e);
+                        SetupInput::ConnectFailed
+                    }
+                }
+            }
+            SetupCommand::Finish => {
+                portal.finish().await;
+                return connected.ok_or(SetupError::NotConnected);
+            }
+            SetupCommand::Scan => {
+                let mut aps = radio.scan().await.unwrap_or_default();
+                aps.retain(|ap| settings.scan_filter.accepts(ap));
+                portal.show_scan(&aps).await;
+                SetupInput::Scanned
+            }
+            SetupCommand::Reset => {
+                portal.reset().await;
+                return Err(SetupError::Reset);
+            }
+            SetupCommand::Idle => {
+                if portal.pending() {
+                    SetupInput::Credentials
+                } else {
+                    clock.sleep_ms(IDLE_POLL).await;
+                    SetupInput::Tick
+                }
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wifimanager::radio::PowerSave;
+    use alloc::{collections::VecDeque, string::String, vec, vec::Vec};
+    use core::cell::Cell;
+
+    const SSID: &str = "home";
+    const PASSWORD: &str = "secret";
+    /// Time taken by a connection attempt of the mock radio
+    const ATTEMPT: u64 = 1_000;
+
+    fn settings(reset_timeout: Option<u64>) -> SetupSettings {
+        SetupSettings {
+            access_point: AccessPointConfig {
+                ssid: "ESP-1234".into(),
+            },
+            scan_interval: 10_000,
+            reset_timeout,
+            connect_timeout: 5_000,
+            scan_filter: ScanFilter {
+                min_rssi: Some(-85),
+                only_2g4: true,
+                skip_open: false,
+            },
+        }
+    }
+
+    struct MockClock<'a>(&'a Cell<u64>);
+
+    impl Clock for MockClock<'_> {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
+
+        async fn sleep_ms(&self, ms: u64) {
+            self.0.set(self.0.get() + ms);
+        }
+    }
+
+    /// Radio with `SSID` in range, protected by `PASSWORD`
+    struct MockRadio<'a> {
+        now: &'a Cell<u64>,
+        config: Option<RadioConfig>,
+        /// Connections never answer
+        hang: bool,
+        attempts: u32,
+    }
+
+    impl<'a> MockRadio<'a> {
+        fn new(now: &'a Cell<u64>) -> Self {
+            Self {
+                now,
+                config: None,
+                hang: false,
+                attempts: 0,
+            }
+        }
+    }
+
+    impl RadioControl for MockRadio<'_> {
+        fn configure(&mut self, config: &RadioConfig) -> Result<(), RadioError> {
+            self.config = Some(config.clone());
+            Ok(())
+        }
+
+        async fn start(&mut self) -> Result<(), RadioError> {
+            Ok(())
+        }
+
+        async fn stop(&mut self) -> Result<(), RadioError> {
+            Ok(())
+        }
+
+        async fn connect(&mut self) -> Result<(), RadioError> {
+            self.attempts += 1;
+            if self.hang {
+                core::future::pending::<()>().await;
+            }
+            self.now.set(self.now.get() + ATTEMPT);
+            match &self.config {
+                Some(RadioConfig::AccessPointStation(station, _))
+                    if station.ssid == SSID && station.password == PASSWORD =>
+                {
+                    Ok(())
+                }
+                Some(_) => Err(RadioError::Disconnected),
+                None => Err(RadioError::NotStarted),
+            }
+        }
+
+        async fn disconnect(&mut self) -> Result<(), RadioError> {
+            Ok(())
+        }
+
+        async fn scan(&mut self) -> Result<Vec<ScanResult>, RadioError> {
+            let ap = |ssid: &str, channel, signal_strength| ScanResult {
+                ssid: ssid.into(),
+                channel,
+                signal_strength,
+                open: false,
+            };
+            Ok(vec![
+                ap(SSID, 6, -60),
+                ap("far", 11, -90),
+                ap("5ghz", 36, -50),
+            ])
+        }
+
+        async fn wait_disconnected(&mut self) {
+            core::future::pending().await
+        }
+
+        fn is_connected(&self) -> bool {
+            false
+        }
+
+        fn rssi(&self) -> Option<i8> {
+            None
+        }
+
+        fn set_power_saving(&mut self, _mode: PowerSave) -> Result<(), RadioError> {
+            Ok(())
+        }
+    }
+
+    /// Portal receiving the passwords of `submitted` at the given times
+    struct MockPortal<'a> {
+        now: &'a Cell<u64>,
+        submitted: VecDeque<(u64, &'static str)>,
+        shown: Vec<Vec<String>>,
+        failures: Vec<ConnectError>,
+        finished: bool,
+        reset: bool,
+    }
+
+    impl<'a> MockPortal<'a> {
+        fn new(now: &'a Cell<u64>, submitted: &[(u64, &'static str)]) -> Self {
+            Self {
+                now,
+                submitted: submitted.iter().copied().collect(),
+                shown: Vec::new(),
+                failures: Vec::new(),
+                finished: false,
+                reset: false,
+            }
+        }
+    }
+
+    impl SetupPortal for MockPortal<'_> {
+        type Credentials = &'static str;
+
+        fn pending(&self) -> bool {
+            self.submitted
+                .front()
+                .is_some_and(|&(at, _)| at <= self.now.get())
+        }
+
+        async fn credentials(&mut self) -> &'static str {
+            self.submitted.pop_front().expect("credentials pending").1
+        }
+
+        fn station(&self, password: &&'static str) -> StationConfig {
+            StationConfig {
+                ssid: SSID.into(),
+                password: (*password).into(),
+            }
+        }
+
+        async fn show_scan(&mut self, aps: &[ScanResult]) {
+            self.shown
+                .push(aps.iter().map(|ap| ap.ssid.clone()).collect());
+        }
+
+        fn connect_failed(&mut self, error: ConnectError) {
+            self.failures.push(error);
+        }
+
+        async fn finish(&mut self) {
+            self.finished = true;
+        }
+
+        async fn reset(&mut self) {
+            self.reset = true;
+        }
+    }
+
+    #[test]
+    fn lists_accepted_aps_and_connects() {
+        let now = Cell::new(0);
+        let mut radio = MockRadio::new(&now);
+        let mut portal = MockPortal::new(&now, &[(25_000, PASSWORD)]);
+        let result = embassy_futures::block_on(wifi_connection_worker(
+            &mut radio,
+            &mut portal,
+            &MockClock(&now),
+            &settings(None),
+        ));
+
+        assert_eq!(result, Ok(PASSWORD));
+        assert!(portal.finished);
+        // At 0, 10 000 and 20 000, before the credentials
+        assert_eq!(portal.shown, vec![vec![String::from(SSID)]; 3]);
+        assert_eq!(
+            radio.config,
+            Some(RadioConfig::AccessPointStation(
+                StationConfig {
+                    ssid: SSID.into(),
+                    password: PASSWORD.into(),
+                },
+                AccessPointConfig {
+                    ssid: "ESP-1234".into(),
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn wrong_password_retries_until_timeout_then_waits() {
+        let now = Cell::new(0);
+        let mut radio = MockRadio::new(&now);
+        let mut portal = MockPortal::new(&now, &[(1_000, "wrong"), (30_000, PASSWORD)]);
+        let result = embassy_futures::block_on(wifi_connection_worker(
+            &mut radio,
+            &mut portal,
+            &MockClock(&now),
+            &settings(None),
+        ));
+
+        assert_eq!(result, Ok(PASSWORD));
+        assert_eq!(
+            portal.failures,
+            vec![ConnectError::Radio(RadioError::Disconnected)]
+        );
+        // Attempts until past the 5 s timeout, then the right password
+        assert_eq!(radio.attempts, 7);
+    }
+
+    #[test]
+    fn hung_connection_times_out_and_resets() {
+        let now = Cell::new(0);
+        let mut radio = MockRadio::new(&now);
+        radio.hang = true;
+        let mut portal = MockPortal::new(&now, &[(1_000, PASSWORD)]);
+        let result = embassy_futures::block_on(wifi_connection_worker(
+            &mut radio,
+            &mut portal,
+            &MockClock(&now),
+            &settings(Some(60_000)),
+        ));
+
+        assert_eq!(result, Err(SetupError::Reset));
+        assert_eq!(portal.failures, vec![ConnectError::Timeout]);
+        assert!(portal.reset);
+        assert!(!portal.finished);
+        assert_eq!(radio.attempts, 1);
+    }
+
+    #[test]
+    fn connect_within_answers_at_once() {
+        let now = Cell::new(0);
+        let mut radio = MockRadio::new(&now);
+        radio.config = Some(RadioConfig::AccessPointStation(
+            StationConfig {
+                ssid: SSID.into(),
+                password: PASSWORD.into(),
+            },
+            AccessPointConfig::default(),
+        ));
+        let result = embassy_futures::block_on(connect_within(&mut radio, &MockClock(&now), 5_000));
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(radio.attempts, 1);
+    }
+}
@@ -0,0 +1,38 @@
+//! Time in words for the word clock face, rounded down to 5 minutes
+
+use alloc::string::String;
+
+use crate::i18n::Language;
+
+/// Time in words, upper case
+pub fn phrase(language: Language, hour: u8, minute: u8) -> String {
+    let words = language.time_words();
+    let step = (minute as usize / 5).min(words.steps.len() - 1);
+    let hour = if step >= words.next_hour_from {
+        (hour as usize + 1) % 24
+    } else {
+        hour as usize % 24
+    };
+    words.steps[step].replace("{h}", words.hours[hour])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_counts_to_the_next_hour_from_twenty_five_to() {
+        assert_eq!(phrase(Language::English, 3, 0), "THREE O'CLOCK");
+        assert_eq!(phrase(Language::English, 3, 34), "HALF PAST THREE");
+        assert_eq!(phrase(Language::English, 3, 35), "TWENTY FIVE TO FOUR");
+        assert_eq!(phrase(Language::English, 23, 58), "FIVE TO TWELVE");
+    }
+
+    #[test]
+    fn french_names_midnight_and_noon() {
+        assert_eq!(phrase(Language::French, 0, 0), "MINUIT");
+        assert_eq!(phrase(Language::French, 11, 45), "MIDI MOINS LE QUART");
+        assert_eq!(phrase(Language::French, 13, 15), "UNE HEURE ET QUART");
+        assert_eq!(phrase(Language::French, 23, 55), "MINUIT MOINS CINQ");
+    }
+}
@@ -0,0 +1,192 @@
+//! Alarms, created, updated and deleted one at a time through the HTTP API
+//!
+//! An alarm rings on the days of its weekday mask, bit 0 for Monday to bit 6
+//! for Sunday, at `minute` from midnight, local time:
+//!
+//! ```json
+//! {"id":3,"days":31,"minute":420,"melody":"westminster","enabled":true}
+//! ```
+//!
+//! Without a melody it rings with the alarm melody of the melody settings.
+//! Alarms are checked by `b_intime_logic::alarms`.
+//! Alarms ring like the kitchen timer, so they snooze and stop the same way,
+//! and do not disturb silences them.
+//!
+//! The list is saved in two NVS slots, in separate flash sectors, each with a
+//! generation. A save writes the older slot, so a write cut by a reset leaves
+//! the previous list readable.
+
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::Weekday;
+
+use crate::{
+    countdown, dnd, melody,
+    wifimanager::{Nvs, Record},
+};
+
+pub use b_intime_logic::alarms::{Alarm, AlarmError, AlarmFields};
+
+/// Records of the two slots, on different flash sectors
+const SLOTS: [Record; 2] = [Record::Alarms0, Record::Alarms1];
+/// Generation, before the JSON
+const HEADER_LEN: usize = 2;
+
+pub const MAX_ALARMS: usize = 8;
+
+/// Alarms, with the generation and slot of the last save
+static ALARMS: Mutex<CriticalSectionRawMutex, (Vec<Alarm>, u16, usize)> =
+    Mutex::new((Vec::new(), 0, 0));
+/// Copy for the display loop, which cannot wait on the lock
+static CURRENT: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<Alarm>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+/// Day and minute of the last ring, so it rings once
+static RUNG: BlockingMutex<CriticalSectionRawMutex, Cell<Option<(Weekday, u16)>>> =
+    BlockingMutex::new(Cell::new(None));
+
+fn is_melody(name: &str) -> bool {
+    melody::notes(name).is_some()
+}
+
+/// Current alarms
+pub fn list() -> Vec<Alarm> {
+    CURRENT.lock(|current| current.borrow().clone())
+}
+
+/// Read the newest valid slot saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut newest: Option<(Vec<Alarm>, u16, usize)> = None;
+    let mut buf = alloc::vec![0u8; Record::Alarms0.capacity()];
+    for (slot, &record) in SLOTS.iter().enumerate() {
+        let Some(payload) = storage.lock().await.read_record(record, &mut buf) else {
+            continue;
+        };
+        let Some((generation, json)) = payload.split_first_chunk::<HEADER_LEN>() else {
+            continue;
+        };
+        let generation = u16::from_le_bytes(*generation);
+        let Ok((alarms, _)) = serde_json_core::from_slice::<Vec<Alarm>>(json) else {
+            crate::log!("Invalid saved alarms in slot {slot}, ignored");
+            continue;
+        };
+        // Generations wrap, the newer is less than half the range ahead
+        let newer = newest
+            .as_ref()
+            .is_none_or(|(_, newest, _)| (generation.wrapping_sub(*newest) as i16) > 0);
+        if newer {
+            newest = Some((alarms, generation, slot));
+        }
+    }
+
+    if let Some((alarms, generation, slot)) = newest {
+        CURRENT.lock(|current| *current.borrow_mut() = alarms.clone());
+        *ALARMS.lock().await = (alarms, generation, slot);
+    }
+}
+
+/// Apply `change` to the alarms and save them, nothing changes on error
+async fn update<T>(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    change: impl FnOnce(&mut Vec<Alarm>) -> Result<T, AlarmError>,
+) -> Result<T, AlarmError> {
+    let mut state = ALARMS.lock().await;
+    let mut alarms = state.0.clone();
+    let result = change(&mut alarms)?;
+
+    let mut buf = alloc::vec![0u8; Record::Alarms0.capacity()];
+    let len = serde_json_core::to_slice(&alarms, &mut buf[HEADER_LEN..])
+        .map_err(|_| AlarmError::TooLarge)?;
+    let generation = state.1.wrapping_add(1);
+    let slot = (state.2 + 1) % SLOTS.len();
+    buf[..HEADER_LEN].copy_from_slice(&generation.to_le_bytes());
+
+    if let Err(e) = storage
+        .lock()
+        .await
+        .write_record(SLOTS[slot], &buf[..HEADER_LEN + len])
+    {
+        crate::log!("Alarms not saved: {e:?}");
+        return Err(AlarmError::NotSaved);
+    }
+    CURRENT.lock(|current| *current.borrow_mut() = alarms.clone());
+    *state = (alarms, generation, slot);
+    Ok(result)
+}
+
+/// Add an alarm, returns its id
+pub async fn create(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    fields: AlarmFields,
+) -> Result<u8, AlarmError> {
+    fields.validate(is_melody)?;
+    update(storage, |alarms| {
+        if alarms.len() >= MAX_ALARMS {
+            return Err(AlarmError::TooMany);
+        }
+        let id = (0..=u8::MAX)
+            .find(|id| alarms.iter().all(|alarm| alarm.id != *id))
+            .ok_or(AlarmError::TooMany)?;
+        alarms.push(Alarm::new(id, fields));
+        Ok(id)
+    })
+    .await
+}
+
+/// Replace the alarm `id`
+pub async fn replace(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    id: u8,
+    fields: AlarmFields,
+) -> Result<(), AlarmError> {
+    fields.validate(is_melody)?;
+    update(storage, |alarms| {
+        let alarm = alarms
+            .iter_mut()
+            .find(|alarm| alarm.id == id)
+            .ok_or(AlarmError::NotFound)?;
+        *alarm = Alarm::new(id, fields);
+        Ok(())
+    })
+    .await
+}
+
+/// Remove the alarm `id`
+pub async fn delete(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    id: u8,
+) -> Result<(), AlarmError> {
+    update(storage, |alarms| {
+        let idx = alarms
+            .iter()
+            .position(|alarm| alarm.id == id)
+            .ok_or(AlarmError::NotFound)?;
+        alarms.remove(idx);
+        Ok(())
+    })
+    .await
+}
+
+/// Ring the alarms when their time comes, called with the time each second
+pub fn tick(weekday: Weekday, minute: u16) {
+    let alarm = CURRENT.lock(|current| {
+        current
+            .borrow()
+            .iter()
+            .find(|alarm| alarm.enabled && alarm.minute == minute && alarm.rings_on(weekday))
+            .cloned()
+    });
+    let Some(alarm) = alarm else {
+        return;
+    };
+
+    let rung = RUNG.lock(|rung| rung.replace(Some((weekday, minute))));
+    if rung != Some((weekday, minute)) && dnd::allows(dnd::Kind::Alarm) {
+        crate::log!("Alarm {}", alarm.id);
+        countdown::ring_melody(alarm.melody.as_deref().and_then(melody::notes));
+    }
+}
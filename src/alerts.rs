@@ -0,0 +1,195 @@
+//! Threshold alerts on sensor readings
+//!
+//! Rules are JSON, read and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! [{"sensor":"Temperature","comparator":"Above","threshold":28.0,
+//!   "hysteresis":1.0,"notify":"HOT","beep":true}]
+//! ```
+//!
+//! A rule fires when a reading crosses its threshold, and clears once the
+//! reading is back past the threshold by `hysteresis`, so a reading wavering
+//! around the threshold does not fire it again and again.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buzzer::{self, Pattern},
+    dnd,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub use b_intime_logic::sensor::Sensor;
+
+pub const MAX_RULES: usize = 8;
+/// Longest notification text
+pub const MAX_NOTIFY_LEN: usize = 32;
+
+const ALERT: Pattern = &[(200, 100), (200, 100), (200, 0)];
+
+static RULES: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<Armed>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+static NOTIFICATION: Signal<CriticalSectionRawMutex, String> = Signal::new();
+/// Latest reading of each sensor, in `Sensor` order
+static READINGS: BlockingMutex<CriticalSectionRawMutex, Cell<[Option<f32>; 3]>> =
+    BlockingMutex::new(Cell::new([None; 3]));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Comparator {
+    Above,
+    Below,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub sensor: Sensor,
+    pub comparator: Comparator,
+    pub threshold: f32,
+    /// Margin to clear the alert, in the sensor unit
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Text scrolled on the matrix
+    #[serde(default)]
+    pub notify: Option<String>,
+    /// Sound the buzzer
+    #[serde(default)]
+    pub beep: bool,
+}
+
+impl Rule {
+    fn is_valid(&self) -> bool {
+        self.threshold.is_finite()
+            && self.hysteresis.is_finite()
+            && self.hysteresis >= 0.0
+            && self
+                .notify
+                .as_ref()
+                .is_none_or(|text| !text.is_empty() && text.len() <= MAX_NOTIFY_LEN)
+    }
+
+    fn crosses(&self, value: f32) -> bool {
+        match self.comparator {
+            Comparator::Above => value > self.threshold,
+            Comparator::Below => value < self.threshold,
+        }
+    }
+
+    fn clears(&self, value: f32) -> bool {
+        match self.comparator {
+            Comparator::Above => value < self.threshold - self.hysteresis,
+            Comparator::Below => value > self.threshold + self.hysteresis,
+        }
+    }
+}
+
+pub fn is_valid(rules: &[Rule]) -> bool {
+    rules.len() <= MAX_RULES && rules.iter().all(Rule::is_valid)
+}
+
+struct Armed {
+    rule: Rule,
+    fired: bool,
+}
+
+/// Current rules
+pub fn rules() -> Vec<Rule> {
+    RULES.lock(|rules| {
+        rules
+            .borrow()
+            .iter()
+            .map(|armed| armed.rule.clone())
+            .collect()
+    })
+}
+
+fn set_rules(rules: Vec<Rule>) {
+    let armed = rules
+        .into_iter()
+        .map(|rule| Armed { rule, fired: false })
+        .collect();
+    RULES.lock(|current| *current.borrow_mut() = armed);
+}
+
+/// Latest reading of `sensor`, `None` before the first
+pub fn reading(sensor: Sensor) -> Option<f32> {
+    READINGS.lock(|readings| readings.get()[sensor as usize])
+}
+
+/// Run the actions of the rules `value` makes fire
+pub fn evaluate(sensor: Sensor, value: f32) {
+    READINGS.lock(|readings| {
+        let mut latest = readings.get();
+        latest[sensor as usize] = Some(value);
+        readings.set(latest);
+    });
+    RULES.lock(|rules| {
+        for armed in rules.borrow_mut().iter_mut() {
+            let rule = &armed.rule;
+            if rule.sensor != sensor {
+                continue;
+            }
+
+            if armed.fired {
+                armed.fired = !rule.clears(value);
+            } else if rule.crosses(value) {
+                armed.fired = true;
+                crate::log!(
+                    "Alert {:?} {:?} {}: {}",
+                    sensor,
+                    rule.comparator,
+                    rule.threshold,
+                    value
+                );
+                if !dnd::allows(dnd::Kind::Notification) {
+                    continue;
+                }
+                if rule.beep {
+                    buzzer::play(ALERT);
+                }
+                if let Some(text) = rule.notify.as_ref() {
+                    NOTIFICATION.signal(text.clone());
+                }
+            }
+        }
+    });
+}
+
+/// Show `text` like an alert, unless do not disturb silences notifications
+pub fn notify(text: String) {
+    if dnd::allows(dnd::Kind::Notification) {
+        NOTIFICATION.signal(text);
+    }
+}
+
+/// Wait for a notification to show
+pub async fn notification() -> String {
+    NOTIFICATION.wait().await
+}
+
+/// Read the rules saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage.lock().await.read_json::<Vec<Rule>>(Record::Alerts);
+    match saved {
+        Some(Ok(rules)) if is_valid(&rules) => set_rules(rules),
+        Some(_) => crate::log!("Invalid saved alert rules, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `rules`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    rules: Vec<Rule>,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Alerts, &rules)?;
+    set_rules(rules);
+    Ok(())
+}
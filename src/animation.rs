@@ -0,0 +1,233 @@
+//! Frame sequences for the matrix
+//!
+//! Format, all integers little endian:
+//!
+//! | bytes | content                                              |
+//! |-------|------------------------------------------------------|
+//! | 4     | magic `ANI1`                                         |
+//! | 1     | width in pixels, multiple of 8                       |
+//! | 1     | height in pixels                                     |
+//! | 2     | frame count                                          |
+//!
+//! followed by each frame: its delay in ms (2 bytes) then its bitmap, rows
+//! from the top, `width / 8` bytes per row with the leftmost pixel in the MSB.
+//! Trailing bytes are ignored, so a sequence can be stored in a larger file.
+
+use alloc::vec::Vec;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Timer;
+
+use crate::{
+    display::{Canvas, DisplayBackend},
+    profiler::{self, Stage},
+};
+
+const MAGIC: &[u8; 4] = b"ANI1";
+
+/// Largest uploaded sequence, the API request buffer is sized after it
+pub const MAX_LEN: usize = 4 * 1024;
+
+/// Uploaded animation, replaces the boot logo. Must exist on the card, large
+/// enough for the sequence: `truncate -s 4K ANIM.BIN`
+#[cfg(feature = "sdcard")]
+pub const ANIMATION_FILE: &str = "ANIM.BIN";
+/// Part of `ANIMATION_FILE` read, no upload is larger
+#[cfg(feature = "sdcard")]
+pub const MAX_FILE_LEN: usize = MAX_LEN;
+
+const HEADER_LEN: usize = 8;
+
+static REQUEST: Signal<CriticalSectionRawMutex, Vec<u8>> = Signal::new();
+
+#[derive(Debug)]
+pub enum AnimationError {
+    BadMagic,
+    /// Width not a multiple of 8
+    BadSize,
+    /// Fewer bytes than the frames announced by the header
+    Truncated,
+}
+
+#[derive(Clone, Copy)]
+pub struct Animation<'a> {
+    pub width: usize,
+    pub height: usize,
+    frame_count: usize,
+    frames: &'a [u8],
+}
+
+pub struct Frame<'a> {
+    pub delay_ms: u16,
+    bitmap: &'a [u8],
+    row_len: usize,
+}
+
+impl<'a> Animation<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, AnimationError> {
+        if data.len() < HEADER_LEN {
+            return Err(AnimationError::Truncated);
+        }
+        if &data[..4] != MAGIC {
+            return Err(AnimationError::BadMagic);
+        }
+
+        let width = data[4] as usize;
+        let height = data[5] as usize;
+        let frame_count = u16::from_le_bytes([data[6], data[7]]) as usize;
+        if width == 0 || width % 8 != 0 || height == 0 {
+            return Err(AnimationError::BadSize);
+        }
+
+        let animation = Self {
+            width,
+            height,
+            frame_count,
+            frames: &data[HEADER_LEN..],
+        };
+        if animation.frames.len() < frame_count * animation.frame_len() {
+            return Err(AnimationError::Truncated);
+        }
+        Ok(animation)
+    }
+
+    fn frame_len(&self) -> usize {
+        2 + self.width / 8 * self.height
+    }
+
+    /// Serialized size, trailing bytes excluded
+    pub fn len(&self) -> usize {
+        HEADER_LEN + self.frame_count * self.frame_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_count == 0
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = Frame<'a>> {
+        let row_len = self.width / 8;
+        self.frames
+            .chunks_exact(self.frame_len())
+            .take(self.frame_count)
+            .map(move |frame| Frame {
+                delay_ms: u16::from_le_bytes([frame[0], frame[1]]),
+                bitmap: &frame[2..],
+                row_len,
+            })
+    }
+}
+
+impl Frame<'_> {
+    /// Draw at the top left of `canvas`, pixels out of it are dropped
+    pub fn draw<const W: usize, const H: usize>(&self, canvas: &mut Canvas<W, H>) {
+        canvas.clear();
+        for (y, row) in self.bitmap.chunks_exact(self.row_len).enumerate() {
+            for (idx, byte) in row.iter().enumerate() {
+                for bit in 0..8 {
+                    canvas.set_pixel(idx * 8 + bit, y, (byte >> (7 - bit)) & 1 == 1);
+                }
+            }
+        }
+    }
+}
+
+/// Encode canvases into the animation format
+pub struct Builder {
+    data: Vec<u8>,
+    frame_count: u16,
+}
+
+impl Builder {
+    pub fn new(width: u8, height: u8) -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&[width, height, 0, 0]);
+        Self {
+            data,
+            frame_count: 0,
+        }
+    }
+
+    pub fn push<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>, delay_ms: u16) {
+        self.data.extend_from_slice(&delay_ms.to_le_bytes());
+        for y in 0..H {
+            let row = canvas.rows()[y];
+            for x in (0..W).step_by(8) {
+                self.data.push((row << x >> 56) as u8);
+            }
+        }
+        self.frame_count += 1;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data[6..8].copy_from_slice(&self.frame_count.to_le_bytes());
+        self.data
+    }
+}
+
+/// Queue `data` to be played by the display, replacing any pending one
+pub fn play(data: Vec<u8>) {
+    REQUEST.signal(data);
+}
+
+/// Wait for an animation queued with `play`
+pub async fn requested() -> Vec<u8> {
+    REQUEST.wait().await
+}
+
+/// Play every frame of `animation` on `display`
+pub async fn run<D: DisplayBackend, const W: usize, const H: usize>(
+    display: &mut D,
+    canvas: &mut Canvas<W, H>,
+    animation: &Animation<'_>,
+) {
+    for frame in animation.frames() {
+        profiler::measure(Stage::Animation, || frame.draw(canvas));
+        display.draw_async(canvas).await;
+        Timer::after_millis(frame.delay_ms as u64).await;
+    }
+}
+
+const LOGO_FRAMES: usize = 8;
+const LOGO_FRAME_LEN: usize = 2 + 4 * 16;
+
+/// Boot logo: a frame growing from the center to the matrix border
+pub const BOOT_LOGO: [u8; HEADER_LEN + LOGO_FRAMES * LOGO_FRAME_LEN] = boot_logo();
+
+const fn boot_logo() -> [u8; HEADER_LEN + LOGO_FRAMES * LOGO_FRAME_LEN] {
+    let mut data = [0u8; HEADER_LEN + LOGO_FRAMES * LOGO_FRAME_LEN];
+    data[0] = MAGIC[0];
+    data[1] = MAGIC[1];
+    data[2] = MAGIC[2];
+    data[3] = MAGIC[3];
+    data[4] = 32;
+    data[5] = 16;
+    data[6] = LOGO_FRAMES as u8;
+
+    let mut frame = 0;
+    while frame < LOGO_FRAMES {
+        let start = HEADER_LEN + frame * LOGO_FRAME_LEN;
+        // Hold the full frame longer
+        let delay: u16 = if frame == LOGO_FRAMES - 1 { 500 } else { 80 };
+        data[start] = delay.to_le_bytes()[0];
+        data[start + 1] = delay.to_le_bytes()[1];
+
+        // Rectangle from (15 - 2 * frame, 7 - frame) to (16 + 2 * frame, 8 + frame)
+        let (left, right) = (15 - 2 * frame, 16 + 2 * frame);
+        let (top, bottom) = (7 - frame, 8 + frame);
+        let mut y = top;
+        while y <= bottom {
+            let mut x = left;
+            while x <= right {
+                if y == top || y == bottom || x == left || x == right {
+                    data[start + 2 + y * 4 + x / 8] |= 0x80 >> (x % 8);
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+        frame += 1;
+    }
+
+    data
+}
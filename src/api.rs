@@ -0,0 +1,1252 @@
+//! Small HTTP API and settings panel served on the station interface
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Timer};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    alarms::{self, AlarmError, AlarmFields},
+    alerts::{self, Rule},
+    animation::{self, Animation},
+    automations::{self, Automation},
+    battery,
+    burnin::{self, BurnInSettings},
+    calendar::{self, CalendarSettings},
+    capabilities::{self, Capabilities},
+    climate, connectivity, countdown,
+    device::{self, Pairing},
+    dnd, endpoints,
+    face::{self, Face, FaceSettings, Separator},
+    holidays::{self, Holiday},
+    input::{self, Command, InputEvent},
+    location::{self, LocationError, LocationSettings},
+    maintenance::{self, MaintenanceSettings},
+    melody::{self, MelodySettings},
+    message::{self, MessageError},
+    metronome,
+    morse::{self, MorseError, MorseRequest},
+    ntp::{self, NtpSettings},
+    powersave::{self, PowerSaveSettings},
+    profiler, satellite,
+    score::{self, Side},
+    session::{self, LoginError, PasswordError, Sessions},
+    simtime::{self, SimError, SimRequest},
+    snake::{self, Direction},
+    sockets, startup, stats,
+    theme::{self, ThemeSettings},
+    timezone::{self, TimezoneSettings},
+    txpower::{self, TxPowerError, TxPowerSettings},
+    units::{self, Units},
+    wake::{self, WakeSettings},
+    watchdog::{self, Task},
+    webhooks::{self, Hook},
+    wifimanager::{
+        self,
+        http::{parse_http_request, read_request, HttpRequest, Response, TooLarge},
+        NetEventChannel, NetEventSubscriber, Nvs, RecordError,
+    },
+};
+
+/// One task only, every station socket is taken from the same `StackResources`
+const API_TASK_POOL_SIZE: usize = 1;
+/// Headers, and a body up to the largest animation upload
+const HTTP_BUFFER_SIZE: usize = 1024 + animation::MAX_LEN;
+/// Longest body formatted at once, larger documents are sent in parts
+const RESPONSE_BUFFER_SIZE: usize = 1024;
+const API_PORT: u16 = 80;
+
+/// Delay after a wrong pairing code or password, to slow down guessing
+const PAIRING_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+/// Cross-origin access to `/api/*`, for dashboards served from another host
+#[derive(Clone, Copy)]
+pub struct Cors<'a> {
+    /// `Access-Control-Allow-Origin` value, an origin or `*`
+    pub allow_origin: &'a str,
+    /// How long browsers may cache a preflight answer, in seconds
+    pub max_age: u32,
+}
+
+struct Context {
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    pairing: &'static Mutex<CriticalSectionRawMutex, Pairing>,
+    sessions: &'static Mutex<CriticalSectionRawMutex, Sessions>,
+    cors: Option<Cors<'static>>,
+}
+
+#[derive(Deserialize)]
+struct PasswordChange {
+    current: String,
+    new: String,
+}
+
+/// Sync history, JSON unless `?format=csv` is given
+fn ntp_history(query: Option<&str>, out: &mut Response<'_>) {
+    let csv = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .any(|param| param == "format=csv");
+
+    if csv {
+        out.parts("text/csv", ntp_history_csv);
+    } else {
+        out.parts("application/json", ntp_history_json);
+    }
+}
+
+/// Part `idx` of the history in CSV: the header with the first record, then
+/// one record each
+fn ntp_history_csv(idx: usize, out: &mut dyn Write) -> Result<bool, fmt::Error> {
+    if idx == 0 {
+        out.write_str("timestamp,offset_us,delay_us,server\n")?;
+    }
+    let Some(record) = ntp::with_history(|history| history.iter().nth(idx).copied()) else {
+        return Ok(false);
+    };
+    writeln!(
+        out,
+        "{},{},{},{}",
+        record.timestamp, record.offset_us, record.delay_us, record.server
+    )?;
+    Ok(true)
+}
+
+/// Part `idx` of the history in JSON, one record each
+fn ntp_history_json(idx: usize, out: &mut dyn Write) -> Result<bool, fmt::Error> {
+    let Some(record) = ntp::with_history(|history| history.iter().nth(idx).copied()) else {
+        out.write_str(if idx == 0 { "[]" } else { "]" })?;
+        return Ok(false);
+    };
+    write!(
+        out,
+        r#"{}{{"timestamp":{},"offset_us":{},"delay_us":{},"server":"{}"}}"#,
+        if idx == 0 { '[' } else { ',' },
+        record.timestamp,
+        record.offset_us,
+        record.delay_us,
+        record.server
+    )?;
+    Ok(true)
+}
+
+/// Play the uploaded animation, and keep it on the SD card when there is one
+async fn upload_animation(body: &[u8], out: &mut Response<'_>) {
+    let animation = match Animation::parse(body) {
+        Ok(animation) => animation,
+        Err(e) => {
+            return out.text_fmt(
+                "422 Unprocessable Entity",
+                format_args!("invalid animation: {e:?}"),
+            );
+        }
+    };
+    let data = body[..animation.len()].to_vec();
+
+    #[cfg(feature = "sdcard")]
+    if let Err(e) = crate::sdcard::store(animation::ANIMATION_FILE, &data).await {
+        crate::log!("Animation not stored: {e:?}");
+    }
+
+    animation::play(data);
+    out.text("200 OK", ".")
+}
+
+/// Value of the `name` query parameter
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// `?min=` minutes, 5 by default
+fn start_countdown(query: Option<&str>, out: &mut Response<'_>) {
+    let minutes = match query_param(query, "min").map(str::parse::<u32>) {
+        None => 5,
+        Some(Ok(minutes @ 1..=countdown::MAX_MINUTES)) => minutes,
+        Some(_) => {
+            return out.text_fmt(
+                "400 Bad Request",
+                format_args!("min must be 1 to {}", countdown::MAX_MINUTES),
+            );
+        }
+    };
+    countdown::start(minutes);
+    out.text("200 OK", ".")
+}
+
+/// `/api/timer`, seconds left while running, since the end while ringing
+#[derive(Serialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+enum CountdownState {
+    Idle,
+    Running { seconds: u32 },
+    Ringing { seconds: u64, snoozes_left: u8 },
+}
+
+fn countdown_state(out: &mut Response<'_>) {
+    out.json(&match countdown::state() {
+        countdown::State::Idle => CountdownState::Idle,
+        countdown::State::Running(seconds) => CountdownState::Running { seconds },
+        countdown::State::Ringing(since) => CountdownState::Ringing {
+            seconds: since.as_secs(),
+            snoozes_left: countdown::snoozes_left(),
+        },
+    })
+}
+
+/// Replace the snooze time and count
+async fn set_snooze(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<countdown::SnoozeSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => {
+            return out.text_fmt(
+                "422 Unprocessable Entity",
+                format_args!(
+                    "snooze_minutes must be 1 to {}",
+                    countdown::MAX_SNOOZE_MINUTES
+                ),
+            );
+        }
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match countdown::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Current tempo, changed by `?bpm=`
+fn metronome_bpm(query: Option<&str>, out: &mut Response<'_>) {
+    if let Some(bpm) = query_param(query, "bpm") {
+        match bpm.parse::<u16>() {
+            Ok(bpm @ metronome::MIN_BPM..=metronome::MAX_BPM) => metronome::set_bpm(bpm),
+            _ => {
+                return out.text_fmt(
+                    "400 Bad Request",
+                    format_args!(
+                        "bpm must be {} to {}",
+                        metronome::MIN_BPM,
+                        metronome::MAX_BPM
+                    ),
+                );
+            }
+        }
+    }
+    out.json(&Bpm {
+        bpm: metronome::bpm(),
+    })
+}
+
+#[derive(Serialize)]
+struct Bpm {
+    bpm: u16,
+}
+
+/// `/api/dnd/on`, `off`, `toggle` or `auto` (back to the schedule)
+///
+/// Applied here rather than through the input bus, to answer the new state.
+fn switch_dnd(action: &str, out: &mut Response<'_>) {
+    match dnd::Switch::from_name(action) {
+        Some(switch) => switch.apply(),
+        None => return out.text("404 Not Found", "Not Found"),
+    }
+    dnd_state(out)
+}
+
+#[derive(Serialize)]
+struct DndState {
+    active: bool,
+}
+
+fn dnd_state(out: &mut Response<'_>) {
+    out.json(&DndState {
+        active: dnd::is_active(),
+    })
+}
+
+fn snake_input(input: snake::Input, out: &mut Response<'_>) {
+    input::send(InputEvent::Web(Command::Snake(input)));
+    out.text("200 OK", ".")
+}
+
+/// `/api/face/next` or a face name
+fn select_face(name: &str, out: &mut Response<'_>) {
+    let face = match name {
+        "next" => face::next(),
+        name => match Face::from_name(name) {
+            Some(face) if face.is_available() => {
+                face::set(face);
+                face
+            }
+            _ => return out.text("404 Not Found", "Not Found"),
+        },
+    };
+    out.text_fmt("200 OK", format_args!("{face:?}"))
+}
+
+/// `/api/face`: the saved settings, with the face shown and the faces available
+#[derive(Serialize)]
+struct FaceState {
+    current: Face,
+    available: Vec<Face>,
+    face: Option<Face>,
+    carousel: Vec<Face>,
+    separator: Separator,
+}
+
+fn face_state(out: &mut Response<'_>) {
+    let settings = face::settings();
+    out.json(&FaceState {
+        current: face::current(),
+        available: face::FACES
+            .into_iter()
+            .filter(|face| face.is_available())
+            .collect(),
+        face: settings.face,
+        carousel: settings.carousel,
+        separator: settings.separator,
+    })
+}
+
+/// Answer a failed save, nothing was applied
+fn record_error(error: RecordError, out: &mut Response<'_>) {
+    match error {
+        RecordError::TooLarge => out.text("413 Payload Too Large", "too large"),
+        RecordError::Flash => out.text("500 Internal Server Error", "not saved"),
+    }
+}
+
+/// Replace the face shown at boot and the carousel, the face shows now
+async fn set_face_settings(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<FaceSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid faces"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match face::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// `/api/score/left`, `/api/score/right` or `/api/score/reset`
+fn update_score(action: &str, out: &mut Response<'_>) {
+    match (action, Side::from_name(action)) {
+        ("reset", _) => score::reset(),
+        (_, Some(side)) => score::increment(side),
+        _ => return out.text("404 Not Found", "Not Found"),
+    }
+    get_score(out)
+}
+
+#[derive(Serialize)]
+struct Score {
+    left: u8,
+    right: u8,
+}
+
+fn get_score(out: &mut Response<'_>) {
+    let [left, right] = score::score();
+    out.json(&Score { left, right })
+}
+
+/// Id of a created resource
+#[derive(Serialize)]
+struct Created {
+    id: u8,
+}
+
+/// `/api/device`, before pairing
+#[derive(Serialize)]
+struct DeviceInfo<'a> {
+    id: &'a str,
+    name: &'a str,
+}
+
+/// Replace the themes and schedule, applied at the next frame
+async fn set_themes(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<ThemeSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid themes"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match theme::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the NTP servers, used from the next sync
+async fn set_ntp(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<NtpSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid servers"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match ntp::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the units shown on the matrix
+async fn set_units(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<Units>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match units::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+async fn set_calendar(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<CalendarSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match calendar::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Save the location, or go back to the lookup without coordinates
+async fn set_location(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<LocationSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+    match location::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(LocationError::Invalid) => out.text("422 Unprocessable Entity", "invalid location"),
+        Err(LocationError::NotSaved) => out.text("500 Internal Server Error", "not saved"),
+    }
+}
+
+/// Replace the holidays table
+async fn set_holidays(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    match serde_json_core::from_slice::<Vec<Holiday>>(body) {
+        Ok((holidays, _)) if holidays::is_valid(&holidays) => {
+            match holidays::save(ctx.storage, holidays).await {
+                Ok(()) => out.text("200 OK", "."),
+                Err(e) => record_error(e, out),
+            }
+        }
+        Ok(_) => out.text("422 Unprocessable Entity", "invalid holidays"),
+        Err(e) => out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}")),
+    }
+}
+
+/// Replace the anti burn-in, applied at the next frame
+async fn set_burnin(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<BurnInSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+    if !settings.is_valid() {
+        return out.text("422 Unprocessable Entity", "invalid hour");
+    }
+
+    match burnin::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the modem sleep of the station
+async fn set_powersave(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<PowerSaveSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match powersave::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the maximum TX power, applied at once
+async fn set_txpower(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<TxPowerSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match txpower::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(TxPowerError::OutOfRange) => out.text("422 Unprocessable Entity", "dbm out of 2..=20"),
+        Err(TxPowerError::Record(e)) => record_error(e, out),
+    }
+}
+
+/// Run the show time simulated
+fn set_simtime(body: &[u8], out: &mut Response<'_>) {
+    let request = match serde_json_core::from_slice::<SimRequest>(body) {
+        Ok((request, _)) => request,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    let body = match simtime::set(request, ntp::unix_time()) {
+        Ok(()) => return out.text("200 OK", "."),
+        Err(SimError::InvalidRate) => "invalid rate",
+        Err(SimError::InvalidTime) => "invalid time",
+        Err(SimError::NoTime) => "no time yet",
+    };
+    out.text("422 Unprocessable Entity", body)
+}
+
+/// Scroll the text of the body, refused with the characters the matrix cannot
+/// show
+fn show_message(body: &[u8], out: &mut Response<'_>) {
+    let Ok(text) = core::str::from_utf8(body) else {
+        return out.text("400 Bad Request", "invalid UTF-8");
+    };
+
+    match message::show(text) {
+        Ok(()) => out.text("200 OK", "."),
+        Err(MessageError::Unsupported(letters)) => out.text_fmt(
+            "422 Unprocessable Entity",
+            format_args!("unsupported characters: {}", message::describe(&letters)),
+        ),
+        Err(MessageError::Empty) => out.text("422 Unprocessable Entity", "empty message"),
+        Err(MessageError::TooLong) => out.text("413 Payload Too Large", "too large"),
+    }
+}
+
+/// Send a short word in Morse
+fn send_morse(body: &[u8], out: &mut Response<'_>) {
+    let request = match serde_json_core::from_slice::<MorseRequest>(body) {
+        Ok((request, _)) => request,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match morse::send(&request.text, request.buzzer) {
+        Ok(()) => out.text("200 OK", "."),
+        Err(MorseError::TooLong) => out.text("413 Payload Too Large", "too large"),
+        Err(MorseError::Empty) => out.text("422 Unprocessable Entity", "empty text"),
+        Err(MorseError::Unsupported(letter)) => out.text_fmt(
+            "422 Unprocessable Entity",
+            format_args!("unsupported character: {letter:?}"),
+        ),
+    }
+}
+
+/// Replace the POSIX TZ string
+async fn set_timezone(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<TimezoneSettings>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+    if let Err(e) = settings.parse() {
+        return out.text_fmt(
+            "422 Unprocessable Entity",
+            format_args!("invalid TZ string: {e}"),
+        );
+    }
+
+    match timezone::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+#[derive(Deserialize)]
+struct TimezonePreview<'a> {
+    posix: &'a str,
+    /// Unix time, now when missing
+    #[serde(default)]
+    at: Option<i64>,
+}
+
+/// Local time and next transitions under a POSIX TZ string, without saving it
+fn preview_timezone(body: &[u8], out: &mut Response<'_>) {
+    let request = match serde_json_core::from_slice::<TimezonePreview<'_>>(body) {
+        Ok((request, _)) => request,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+    let zone = match jiff::tz::TimeZone::posix(request.posix) {
+        Ok(zone) => zone,
+        Err(e) => {
+            return out.text_fmt(
+                "422 Unprocessable Entity",
+                format_args!("invalid TZ string: {e}"),
+            );
+        }
+    };
+    let Some(at) = request
+        .at
+        .or_else(ntp::unix_time)
+        .and_then(|at| jiff::Timestamp::from_second(at).ok())
+    else {
+        return out.text("422 Unprocessable Entity", "no time yet");
+    };
+
+    out.json(&timezone::preview(&zone, at))
+}
+
+#[derive(Deserialize)]
+struct NameRequest<'a> {
+    name: &'a str,
+}
+
+/// Rename the device, the AP, DHCP hostname and MQTT topics follow at the next boot
+async fn set_name(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let name = match serde_json_core::from_slice::<NameRequest<'_>>(body) {
+        Ok((request, _)) if device::is_valid_name(request.name) => request.name,
+        Ok(_) => {
+            return out.text(
+                "422 Unprocessable Entity",
+                "letters, digits and dashes only, 21 at most",
+            )
+        }
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match device::save_name(ctx.storage, name).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the webhooks, a JSON array
+async fn set_webhooks(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let hooks = match serde_json_core::from_slice::<Vec<Hook>>(body) {
+        Ok((hooks, _)) if webhooks::is_valid(&hooks) => hooks,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid hooks"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match webhooks::save(ctx.storage, hooks).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the maintenance settings
+async fn set_maintenance(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<MaintenanceSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid settings"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match maintenance::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+#[derive(Deserialize)]
+struct BatteryCalibration {
+    millivolts: u16,
+}
+
+/// Correct the battery readings with the voltage measured on the cell
+async fn calibrate_battery(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let millivolts = match serde_json_core::from_slice::<BatteryCalibration>(body) {
+        Ok((calibration, _)) => calibration.millivolts,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    if battery::calibrate(ctx.storage, millivolts).await {
+        out.json(&battery::status())
+    } else {
+        out.text("422 Unprocessable Entity", "no reading, or too far from it")
+    }
+}
+
+/// Enable or disable optional subsystems
+async fn set_capabilities(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<Capabilities>(body) {
+        Ok((settings, _)) => settings,
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match capabilities::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+fn alarm_error(error: AlarmError, out: &mut Response<'_>) {
+    match error {
+        AlarmError::InvalidMinute => out.text("422 Unprocessable Entity", "invalid minute"),
+        AlarmError::InvalidDays => out.text("422 Unprocessable Entity", "invalid days"),
+        AlarmError::UnknownMelody => out.text("422 Unprocessable Entity", "unknown melody"),
+        AlarmError::TooMany => out.text("409 Conflict", "too many alarms"),
+        AlarmError::NotFound => out.text("404 Not Found", "Not Found"),
+        AlarmError::TooLarge => out.text("413 Payload Too Large", "too large"),
+        AlarmError::NotSaved => out.text("500 Internal Server Error", "not saved"),
+    }
+}
+
+/// `None` once answered 400
+fn alarm_fields(body: &[u8], out: &mut Response<'_>) -> Option<AlarmFields> {
+    match serde_json_core::from_slice::<AlarmFields>(body) {
+        Ok((fields, _)) => Some(fields),
+        Err(e) => {
+            out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+            None
+        }
+    }
+}
+
+/// Add an alarm, answers its id
+async fn create_alarm(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let Some(fields) = alarm_fields(body, out) else {
+        return;
+    };
+    match alarms::create(ctx.storage, fields).await {
+        Ok(id) => out.json_with_status("201 Created", &Created { id }),
+        Err(e) => alarm_error(e, out),
+    }
+}
+
+/// Replace or delete the alarm of `/api/alarms/{id}`
+async fn change_alarm(ctx: &Context, method: &str, id: &str, body: &[u8], out: &mut Response<'_>) {
+    let Ok(id) = id.parse::<u8>() else {
+        return alarm_error(AlarmError::NotFound, out);
+    };
+    let result = if method == "PUT" {
+        match alarm_fields(body, out) {
+            Some(fields) => alarms::replace(ctx.storage, id, fields).await,
+            None => return,
+        }
+    } else {
+        alarms::delete(ctx.storage, id).await
+    };
+    match result {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => alarm_error(e, out),
+    }
+}
+
+/// Replace the wake alarm
+async fn set_wake(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<WakeSettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid alarm"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match wake::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the custom melodies and the choice of chime and alarm melodies
+async fn set_melodies(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let settings = match serde_json_core::from_slice::<MelodySettings>(body) {
+        Ok((settings, _)) if settings.is_valid() => settings,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid melodies"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match melody::save(ctx.storage, settings).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Replace the alert rules, a JSON array
+async fn set_alerts(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let rules = match serde_json_core::from_slice::<Vec<Rule>>(body) {
+        Ok((rules, _)) if alerts::is_valid(&rules) => rules,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid rules"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match alerts::save(ctx.storage, rules).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+async fn set_automations(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let automations = match serde_json_core::from_slice::<Vec<Automation>>(body) {
+        Ok((automations, _)) if automations::is_valid(&automations) => automations,
+        Ok(_) => return out.text("422 Unprocessable Entity", "invalid automations"),
+        Err(e) => {
+            return out.text_fmt("400 Bad Request", format_args!("invalid JSON: {e:?}"));
+        }
+    };
+
+    match automations::save(ctx.storage, automations).await {
+        Ok(()) => out.text("200 OK", "."),
+        Err(e) => record_error(e, out),
+    }
+}
+
+/// Exchange the pairing code (body) for the API token, once paired a new
+/// token replacing it needs the current one or a panel session
+async fn bootstrap_token(ctx: &Context, req: &HttpRequest<'_>, out: &mut Response<'_>) {
+    let authorized = is_authorized(ctx, req).await;
+    let mut pairing = ctx.pairing.lock().await;
+    if pairing.is_paired() && !authorized {
+        return out.text("403 Forbidden", "already paired");
+    }
+
+    if !authorized {
+        let code = core::str::from_utf8(req.body).unwrap_or_default();
+        let checked = ctx.sessions.lock().await.guard(|_| pairing.matches(code));
+        match checked {
+            Ok(()) => {}
+            Err(LoginError::Locked(secs)) => return locked_response(secs, out),
+            Err(_) => {
+                Timer::after(PAIRING_FAILURE_DELAY).await;
+                return out.text("403 Forbidden", "invalid pairing code");
+            }
+        }
+    }
+
+    let token = pairing.issue_token(ctx.storage).await;
+    out.text_fmt("200 OK", format_args!("{token}"))
+}
+
+/// Too many wrong passwords or pairing codes, `secs` before the next try
+fn locked_response(secs: u64, out: &mut Response<'_>) {
+    out.header(format_args!("Retry-After: {secs}"));
+    out.text("429 Too Many Requests", "too many failures")
+}
+
+/// Session id from the `Cookie` header
+fn session_id<'a>(req: &HttpRequest<'a>) -> Option<&'a str> {
+    req.header("Cookie")?
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == session::SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+async fn has_session(ctx: &Context, req: &HttpRequest<'_>) -> bool {
+    match session_id(req) {
+        Some(id) => ctx.sessions.lock().await.check(id),
+        None => false,
+    }
+}
+
+/// API token, or a panel session
+async fn is_authorized(ctx: &Context, req: &HttpRequest<'_>) -> bool {
+    let token = req
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) => ctx.pairing.lock().await.authorize(token.trim()),
+        None => has_session(ctx, req).await,
+    }
+}
+
+/// Open a panel session, the body is the password
+async fn login(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let password = core::str::from_utf8(body).unwrap_or_default();
+    let result = ctx.sessions.lock().await.login(password);
+
+    match result {
+        Ok(id) => {
+            out.header(format_args!(
+                "Set-Cookie: {}={id}; Path=/; HttpOnly; SameSite=Strict",
+                session::SESSION_COOKIE
+            ));
+            out.text("200 OK", ".")
+        }
+        Err(LoginError::NoPassword) => out.text("409 Conflict", "no password set"),
+        Err(LoginError::WrongPassword) => {
+            Timer::after(PAIRING_FAILURE_DELAY).await;
+            out.text("403 Forbidden", "wrong password")
+        }
+        Err(LoginError::Locked(secs)) => locked_response(secs, out),
+    }
+}
+
+async fn logout(ctx: &Context, req: &HttpRequest<'_>, out: &mut Response<'_>) {
+    if let Some(id) = session_id(req) {
+        ctx.sessions.lock().await.logout(id);
+    }
+    out.header(format_args!(
+        "Set-Cookie: {}=; Path=/; Max-Age=0",
+        session::SESSION_COOKIE
+    ));
+    out.text("200 OK", ".")
+}
+
+/// Set or change the panel password, every session is closed
+async fn change_password(ctx: &Context, body: &[u8], out: &mut Response<'_>) {
+    let Ok((change, _)) = serde_json_core::from_slice::<PasswordChange>(body) else {
+        return out.text("422 Unprocessable Entity", "invalid body");
+    };
+
+    let pairing = ctx.pairing.lock().await;
+    let result = ctx
+        .sessions
+        .lock()
+        .await
+        .change_password(ctx.storage, &pairing, &change.current, &change.new)
+        .await;
+
+    match result {
+        Ok(()) => out.text("200 OK", "."),
+        Err(PasswordError::TooShort) => out.text_fmt(
+            "422 Unprocessable Entity",
+            format_args!("at least {} characters required", session::MIN_PASSWORD_LEN),
+        ),
+        Err(PasswordError::Denied) => {
+            Timer::after(PAIRING_FAILURE_DELAY).await;
+            out.text("403 Forbidden", "wrong password")
+        }
+        Err(PasswordError::Locked(secs)) => locked_response(secs, out),
+        Err(PasswordError::NotSaved) => out.text("500 Internal Server Error", "not saved"),
+    }
+}
+
+/// Answer to a CORS preflight, sent before any cross-origin call with a token
+fn preflight(cors: &Cors, out: &mut Response<'_>) {
+    allow_origin(cors, out);
+    out.header(format_args!(
+        "Access-Control-Allow-Methods: GET, POST, PUT, DELETE, OPTIONS"
+    ));
+    out.header(format_args!(
+        "Access-Control-Allow-Headers: Authorization, Content-Type"
+    ));
+    out.header(format_args!("Access-Control-Max-Age: {}", cors.max_age));
+    out.text("204 No Content", "")
+}
+
+/// CORS headers of every `/api/*` answer
+fn allow_origin(cors: &Cors, out: &mut Response<'_>) {
+    out.header(format_args!(
+        "Access-Control-Allow-Origin: {}",
+        cors.allow_origin
+    ));
+    out.header(format_args!("Vary: Origin"));
+}
+
+async fn handle_request(ctx: &Context, req: &HttpRequest<'_>, out: &mut Response<'_>) {
+    let is_api = req.path == "/api" || req.path.starts_with("/api/");
+    match ctx.cors {
+        Some(cors) if is_api && req.method == "OPTIONS" => preflight(&cors, out),
+        Some(cors) if is_api => {
+            allow_origin(&cors, out);
+            route(ctx, req, out).await
+        }
+        _ => route(ctx, req, out).await,
+    }
+}
+
+async fn route(ctx: &Context, req: &HttpRequest<'_>, out: &mut Response<'_>) {
+    let (path, query) = match req.path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (req.path, None),
+    };
+
+    match (req.method, path) {
+        ("GET", "/") => {
+            return if has_session(ctx, req).await {
+                out.send("200 OK", "text/html", include_str!("./settings.html"))
+            } else {
+                out.redirect("/login")
+            };
+        }
+        ("GET", "/login") => {
+            return out.send("200 OK", "text/html", include_str!("./login.html"));
+        }
+        ("POST", "/login") => return login(ctx, req.body, out).await,
+        ("POST", "/logout") => return logout(ctx, req, out).await,
+        // Authenticated by the current password, or the pairing code
+        ("POST", "/password") => return change_password(ctx, req.body, out).await,
+        // Index of the endpoints, for clients to adapt to the build
+        ("GET", "/api") => {
+            return out.parts("application/json", endpoints::write_index_part);
+        }
+        ("GET", "/api/device") => {
+            let id = device::device_id_hex();
+            return device::with_name(|name| out.json(&DeviceInfo { id: &id, name }));
+        }
+        ("POST", "/api/token") => return bootstrap_token(ctx, req, out).await,
+        _ => {}
+    }
+
+    if !is_authorized(ctx, req).await {
+        return out.text("401 Unauthorized", "Unauthorized");
+    }
+
+    let body = req.body;
+    match (req.method, path) {
+        ("GET", "/api/ntp/history") => ntp_history(query, out),
+        ("GET", "/api/ntp/accuracy") => out.json(&ntp::accuracy()),
+        ("GET", "/api/ntp/leap") => out.json(&ntp::leap_second()),
+        ("POST", "/api/animation") => upload_animation(body, out).await,
+        ("POST", "/api/message") => show_message(body, out),
+        ("POST", "/api/morse") => send_morse(body, out),
+        ("POST", "/api/snake/start") => snake_input(snake::Input::Start, out),
+        ("POST", "/api/snake/up") => snake_input(snake::Input::Turn(Direction::Up), out),
+        ("POST", "/api/snake/down") => snake_input(snake::Input::Turn(Direction::Down), out),
+        ("POST", "/api/snake/left") => snake_input(snake::Input::Turn(Direction::Left), out),
+        ("POST", "/api/snake/right") => snake_input(snake::Input::Turn(Direction::Right), out),
+        ("POST", "/api/snake/quit") => snake_input(snake::Input::Quit, out),
+        ("GET", "/api/timer") => countdown_state(out),
+        ("POST", "/api/timer") => start_countdown(query, out),
+        ("POST", "/api/timer/snooze") => {
+            if countdown::snooze() {
+                out.text("200 OK", ".")
+            } else {
+                out.text("409 Conflict", "no snooze left")
+            }
+        }
+        ("GET", "/api/timer/snooze/settings") => out.json(&countdown::settings()),
+        ("POST", "/api/timer/snooze/settings") => set_snooze(ctx, body, out).await,
+        ("POST", "/api/name") => set_name(ctx, body, out).await,
+        ("POST", "/api/identify") => {
+            device::identify();
+            out.text("200 OK", ".")
+        }
+        ("POST", "/api/timer/stop") => {
+            countdown::stop();
+            out.text("200 OK", ".")
+        }
+        ("GET", "/api/themes") => out.json(&theme::settings()),
+        ("POST", "/api/themes") => set_themes(ctx, body, out).await,
+        ("GET", "/api/ntp") => out.json(&ntp::settings().redacted()),
+        ("GET", "/api/timezone") => out.json(&timezone::settings()),
+        ("POST", "/api/timezone") => set_timezone(ctx, body, out).await,
+        ("POST", "/api/timezone/preview") => preview_timezone(body, out),
+        ("GET", "/api/units") => out.json(&units::settings()),
+        ("POST", "/api/units") => set_units(ctx, body, out).await,
+        ("GET", "/api/calendar") => out.json(&calendar::settings()),
+        ("POST", "/api/calendar") => set_calendar(ctx, body, out).await,
+        ("GET", "/api/holidays") => out.json(&holidays::holidays()),
+        ("POST", "/api/holidays") => set_holidays(ctx, body, out).await,
+        ("GET", "/api/location") => out.json(&location::settings()),
+        ("POST", "/api/location") => set_location(ctx, body, out).await,
+        ("GET", "/api/burnin") => out.json(&burnin::settings()),
+        ("POST", "/api/burnin") => set_burnin(ctx, body, out).await,
+        ("GET", "/api/powersave") => out.json(&powersave::settings()),
+        ("POST", "/api/powersave") => set_powersave(ctx, body, out).await,
+        ("GET", "/api/txpower") => out.json(&txpower::settings()),
+        ("GET", "/api/wifi/diagnostics") => out.json(&wifimanager::diagnostics()),
+        ("GET", "/api/network") => out.json(&connectivity::report()),
+        ("POST", "/api/txpower") => set_txpower(ctx, body, out).await,
+        ("POST", "/api/ntp") => set_ntp(ctx, body, out).await,
+        ("GET", "/api/metronome") => metronome_bpm(None, out),
+        ("POST", "/api/metronome") => metronome_bpm(query, out),
+        ("GET", "/api/maintenance") => out.json(&maintenance::settings()),
+        ("POST", "/api/maintenance") => set_maintenance(ctx, body, out).await,
+        ("GET", "/api/battery") => out.json(&battery::status()),
+        ("POST", "/api/battery/calibrate") => calibrate_battery(ctx, body, out).await,
+        ("GET", "/api/startup") => out.json(&startup::report()),
+        ("GET", "/api/profile") if cfg!(feature = "profiler") => out.json(&profiler::report()),
+        ("POST", "/api/profile/reset") if cfg!(feature = "profiler") => {
+            profiler::reset();
+            out.text("200 OK", ".")
+        }
+        ("GET", "/api/simtime") if cfg!(feature = "simtime") => {
+            out.json(&simtime::status(ntp::unix_time()))
+        }
+        ("POST", "/api/simtime") if cfg!(feature = "simtime") => set_simtime(body, out),
+        ("DELETE", "/api/simtime") if cfg!(feature = "simtime") => {
+            simtime::stop();
+            out.text("200 OK", ".")
+        }
+        ("GET", "/api/stats") => out.json(&stats::report()),
+        ("POST", "/api/stats/reset") => {
+            stats::reset(ctx.storage).await;
+            out.text("200 OK", ".")
+        }
+        ("GET", "/api/capabilities") => out.json(&capabilities::settings()),
+        ("POST", "/api/capabilities") => set_capabilities(ctx, body, out).await,
+        ("GET", "/api/wake") => out.json(&wake::settings()),
+        ("POST", "/api/wake") => set_wake(ctx, body, out).await,
+        ("GET", "/api/alarms") => out.json(&alarms::list()),
+        ("POST", "/api/alarms") => create_alarm(ctx, body, out).await,
+        ("PUT" | "DELETE", path) if path.starts_with("/api/alarms/") => {
+            change_alarm(
+                ctx,
+                req.method,
+                path.trim_start_matches("/api/alarms/"),
+                body,
+                out,
+            )
+            .await
+        }
+        ("GET", "/api/melodies") => out.json(&melody::settings()),
+        ("POST", "/api/melodies") => set_melodies(ctx, body, out).await,
+        ("GET", "/api/alerts") => out.json(&alerts::rules()),
+        ("POST", "/api/alerts") => set_alerts(ctx, body, out).await,
+        ("GET", "/api/automations") => out.json(&automations::automations()),
+        ("POST", "/api/automations") => set_automations(ctx, body, out).await,
+        ("GET", "/api/webhooks") => out.json(&webhooks::hooks()),
+        ("POST", "/api/webhooks") => set_webhooks(ctx, body, out).await,
+        ("GET", "/api/tasks") => out.json(&watchdog::report()),
+        ("GET", "/api/sockets") => out.json(&sockets::report()),
+        ("GET", "/api/satellites") => out.json(&satellite::readings()),
+        ("GET", "/api/climate") => out.parts("application/json", climate::write_json_part),
+        ("GET", "/api/dnd") => dnd_state(out),
+        ("POST", path) if path.starts_with("/api/dnd/") => {
+            switch_dnd(path.trim_start_matches("/api/dnd/"), out)
+        }
+        ("GET", "/api/score") => get_score(out),
+        ("POST", path) if path.starts_with("/api/score/") => {
+            update_score(path.trim_start_matches("/api/score/"), out)
+        }
+        ("GET", "/api/face") => face_state(out),
+        ("POST", "/api/face") => set_face_settings(ctx, body, out).await,
+        ("POST", path) if path.starts_with("/api/face/") => {
+            select_face(path.trim_start_matches("/api/face/"), out)
+        }
+        _ => out.text("404 Not Found", "Not Found"),
+    }
+}
+
+#[embassy_executor::task(pool_size = API_TASK_POOL_SIZE)]
+async fn api_task(
+    stack: Stack<'static>,
+    mut net_events: NetEventSubscriber,
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    pairing: &'static Mutex<CriticalSectionRawMutex, Pairing>,
+    sessions: &'static Mutex<CriticalSectionRawMutex, Sessions>,
+    cors: Option<Cors<'static>>,
+) {
+    let ctx = Context {
+        storage,
+        pairing,
+        sessions,
+        cors,
+    };
+    let mut rx_buffer = [0; sockets::API_RX_BUFFER];
+    let mut tx_buffer = [0; sockets::API_TX_BUFFER];
+    let mut http_buffer = alloc::vec![0; HTTP_BUFFER_SIZE];
+    let mut response_buffer = [0; RESPONSE_BUFFER_SIZE];
+
+    loop {
+        // Paused while the station has no address
+        wifimanager::wait_ip(stack, &mut net_events).await;
+
+        let _lease = sockets::lease(sockets::Use::Api);
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        let accept = socket.accept(API_PORT);
+        match select(accept, wifimanager::wait_ip_lost(stack, &mut net_events)).await {
+            Either::First(Ok(())) => {}
+            Either::First(Err(_)) => {
+                Timer::after(Duration::from_millis(100)).await;
+                continue;
+            }
+            Either::Second(()) => {
+                crate::log!("Http api paused, no IP");
+                continue;
+            }
+        }
+
+        watchdog::beat(Task::Api);
+        let request = read_request(&mut socket, &mut http_buffer).await;
+
+        let mut out = Response::new(&mut response_buffer);
+        let answered = match request {
+            Ok(total_read) => match parse_http_request(&http_buffer[..total_read]) {
+                Some(req) => {
+                    handle_request(&ctx, &req, &mut out).await;
+                    true
+                }
+                None => false,
+            },
+            Err(TooLarge) => {
+                out.text("413 Payload Too Large", "too large");
+                true
+            }
+        };
+        if answered {
+            if let Err(e) = out.write(&mut socket).await {
+                crate::log!("Http api write error: {e:?}");
+                let mut error = heapless::String::<32>::new();
+                _ = write!(error, "{e:?}");
+                watchdog::error(Task::Api, &error);
+            }
+        }
+
+        socket.close();
+        _ = socket.flush().await;
+    }
+}
+
+/// Every route but `/api/device` and `/api/token` requires the token issued
+/// in exchange for the pairing code, as `Authorization: Bearer <token>`, or a
+/// settings panel session. With `cors`, browsers may call `/api/*` from other
+/// origins; the panel cookie is never sent cross-origin, so tokens are needed.
+///
+/// The server only listens while the station has an address, from `net_events`.
+pub fn run_api_server(
+    spawner: &Spawner,
+    sta_stack: Stack<'static>,
+    net_events: &'static NetEventChannel,
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    pairing: &'static Mutex<CriticalSectionRawMutex, Pairing>,
+    sessions: &'static Mutex<CriticalSectionRawMutex, Sessions>,
+    cors: Option<Cors<'static>>,
+) {
+    for _ in 0..API_TASK_POOL_SIZE {
+        let events = net_events.subscriber().expect("api net events");
+        spawner.must_spawn(api_task(
+            sta_stack, events, storage, pairing, sessions, cors,
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+//! Scheduled messages and readings, so simple routines need no home
+//! automation server
+//!
+//! Automations are JSON, read and written through the HTTP API and kept in
+//! NVS:
+//!
+//! ```json
+//! [{"days":"Workdays","start":450,"text":"TAKE MEDS","seconds":30},
+//!  {"days":"Every","start":540,"every":60,"end":1020,"sensor":"Humidity"}]
+//! ```
+//!
+//! Each one runs at `start`, in minutes from midnight of the show time, then
+//! every `every` minutes until `end` when set. It scrolls its `text`, or the
+//! latest reading of its `sensor`, for `seconds`, at least once. Do not
+//! disturb silences them like the alerts. When they run is decided by
+//! `b_intime_logic::automations`.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::Duration;
+use jiff::civil::Weekday;
+
+use crate::{
+    alerts::{self, Sensor},
+    dnd, message, units,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub use b_intime_logic::automations::{Automation, MAX_SECONDS};
+
+pub const MAX_AUTOMATIONS: usize = 8;
+
+static AUTOMATIONS: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<Automation>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+/// Day and minute of the last run, so each minute runs once
+static RAN: BlockingMutex<CriticalSectionRawMutex, Cell<Option<(Weekday, u16)>>> =
+    BlockingMutex::new(Cell::new(None));
+static SHOW: Signal<CriticalSectionRawMutex, (String, Duration)> = Signal::new();
+
+/// Text to scroll, `None` for a sensor without a reading yet
+fn text(automation: &Automation) -> Option<String> {
+    if let Some(text) = &automation.text {
+        return message::fit(text).ok();
+    }
+    let value = alerts::reading(automation.sensor?)?;
+    match automation.sensor? {
+        Sensor::Temperature => Some(units::temperature(Some(value), 1)),
+        Sensor::Humidity => Some(alloc::format!("RH {value:.0}%")),
+        Sensor::Battery => Some(alloc::format!("BAT {value:.0}%")),
+    }
+}
+
+pub fn is_valid(automations: &[Automation]) -> bool {
+    automations.len() <= MAX_AUTOMATIONS
+        && automations
+            .iter()
+            .all(|automation| automation.is_valid(|text| message::fit(text).is_ok()))
+}
+
+/// Current automations
+pub fn automations() -> Vec<Automation> {
+    AUTOMATIONS.lock(|automations| automations.borrow().clone())
+}
+
+/// Run the automations due, called with the time each second
+pub fn tick(weekday: Weekday, minute: u16) {
+    if RAN.lock(|ran| ran.replace(Some((weekday, minute)))) == Some((weekday, minute)) {
+        return;
+    }
+
+    let due: Vec<Automation> = AUTOMATIONS.lock(|automations| {
+        automations
+            .borrow()
+            .iter()
+            .filter(|automation| automation.runs_at(weekday, minute))
+            .cloned()
+            .collect()
+    });
+    if due.is_empty() || !dnd::allows(dnd::Kind::Notification) {
+        return;
+    }
+
+    let texts: Vec<String> = due.iter().filter_map(text).collect();
+    if texts.is_empty() {
+        return;
+    }
+    let seconds = due
+        .iter()
+        .map(|automation| automation.seconds)
+        .max()
+        .unwrap_or(0);
+    let text = texts.join("   ");
+    crate::log!("Automation: {text}");
+    SHOW.signal((text, Duration::from_secs(seconds as u64)));
+}
+
+/// Wait for a text to scroll, and for how long
+pub async fn requested() -> (String, Duration) {
+    SHOW.wait().await
+}
+
+/// Read the automations saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<Vec<Automation>>(Record::Automations);
+    match saved {
+        Some(Ok(automations)) if is_valid(&automations) => {
+            AUTOMATIONS.lock(|current| *current.borrow_mut() = automations)
+        }
+        Some(_) => crate::log!("Invalid saved automations, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `automations`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    automations: Vec<Automation>,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::Automations, &automations)?;
+    AUTOMATIONS.lock(|current| *current.borrow_mut() = automations);
+    Ok(())
+}
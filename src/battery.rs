@@ -0,0 +1,194 @@
+//! Battery voltage and charge, for builds running on a Li-ion cell
+//!
+//! VBAT is read on an ADC pin through a `DIVIDER` resistor divider. The
+//! divider tolerance is corrected by `calibrate` with a voltage measured by a
+//! multimeter, the correction is kept in NVS. The charge is estimated from the
+//! voltage, and a notification warns when it gets low.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::Serialize;
+
+use crate::{
+    alerts::{self, Sensor},
+    display::Canvas,
+    wifimanager::{Nvs, Record},
+};
+
+/// Corrections beyond these, in ‰, are measurement mistakes
+const CALIBRATION_RANGE: core::ops::RangeInclusive<u16> = 800..=1200;
+
+/// VBAT over the ADC input voltage, two equal resistors
+const DIVIDER: u32 = 2;
+/// Weight of a new sample in the average, out of 8
+const SMOOTHING: u32 = 2;
+/// Warns under this charge, again once it went back over `LOW_CLEAR_PERCENT`
+const LOW_PERCENT: u8 = 15;
+const LOW_CLEAR_PERCENT: u8 = 20;
+const LOW_NOTIFICATION: &str = "LOW BATTERY";
+
+/// Discharge curve of a Li-ion cell, mV and %
+const DISCHARGE: [(u16, u8); 8] = [
+    (3300, 0),
+    (3600, 10),
+    (3700, 30),
+    (3800, 50),
+    (3900, 65),
+    (4000, 80),
+    (4100, 90),
+    (4200, 100),
+];
+
+#[derive(Clone, Copy)]
+struct State {
+    /// Last ADC input, mV
+    input: Option<u16>,
+    /// Averaged VBAT, mV
+    millivolts: Option<u16>,
+    low: bool,
+    /// Divider correction, ‰
+    calibration: u16,
+}
+
+static STATE: BlockingMutex<CriticalSectionRawMutex, Cell<State>> =
+    BlockingMutex::new(Cell::new(State {
+        input: None,
+        millivolts: None,
+        low: false,
+        calibration: 1000,
+    }));
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Status {
+    pub millivolts: Option<u16>,
+    pub percent: Option<u8>,
+    pub low: bool,
+}
+
+fn percent_of(millivolts: u16) -> u8 {
+    let (low, high) = match DISCHARGE.iter().position(|&(mv, _)| mv >= millivolts) {
+        Some(0) => return 0,
+        Some(idx) => (DISCHARGE[idx - 1], DISCHARGE[idx]),
+        None => return 100,
+    };
+    let ratio = (millivolts - low.0) as u32 * 100 / (high.0 - low.0) as u32;
+    low.1 + ((high.1 - low.1) as u32 * ratio / 100) as u8
+}
+
+/// New ADC reading, `input` mV on the pin
+pub fn on_sample(input: u16) {
+    let (percent, warn) = STATE.lock(|state| {
+        let mut current = state.get();
+        let millivolts = (input as u32 * DIVIDER * current.calibration as u32 / 1000) as u16;
+        let millivolts = current.millivolts.map_or(millivolts, |average| {
+            ((average as u32 * (8 - SMOOTHING) + millivolts as u32 * SMOOTHING) / 8) as u16
+        });
+        let percent = percent_of(millivolts);
+        let warn = !current.low && percent < LOW_PERCENT;
+        if warn {
+            current.low = true;
+        } else if percent > LOW_CLEAR_PERCENT {
+            current.low = false;
+        }
+        current.input = Some(input);
+        current.millivolts = Some(millivolts);
+        state.set(current);
+        (percent, warn)
+    });
+
+    if warn {
+        crate::log!("Low battery: {percent}%");
+        alerts::notify(LOW_NOTIFICATION.into());
+    }
+    alerts::evaluate(Sensor::Battery, percent as f32);
+}
+
+pub fn status() -> Status {
+    let state = STATE.lock(|state| state.get());
+    Status {
+        millivolts: state.millivolts,
+        percent: state.millivolts.map(percent_of),
+        low: state.low,
+    }
+}
+
+/// Read the calibration saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; 2];
+    let Some(&[low, high]) = storage.lock().await.read_record(Record::Battery, &mut buf) else {
+        return;
+    };
+
+    let calibration = u16::from_le_bytes([low, high]);
+    if CALIBRATION_RANGE.contains(&calibration) {
+        STATE.lock(|state| {
+            state.set(State {
+                calibration,
+                ..state.get()
+            })
+        });
+    }
+}
+
+/// Correct the divider so the last reading gives `millivolts`, measured on
+/// the cell; false without a reading or for an unlikely correction
+pub async fn calibrate(storage: &Mutex<CriticalSectionRawMutex, Nvs>, millivolts: u16) -> bool {
+    let Some(input) = STATE
+        .lock(|state| state.get().input)
+        .filter(|&input| input > 0)
+    else {
+        return false;
+    };
+    let calibration = (millivolts as u32 * 1000 / (input as u32 * DIVIDER)) as u16;
+    if !CALIBRATION_RANGE.contains(&calibration) {
+        return false;
+    }
+
+    if let Err(e) = storage
+        .lock()
+        .await
+        .write_record(Record::Battery, &calibration.to_le_bytes())
+    {
+        crate::log!("Battery calibration not saved: {e:?}");
+    }
+    // Restart the average from the corrected value
+    STATE.lock(|state| {
+        state.set(State {
+            millivolts: None,
+            calibration,
+            ..state.get()
+        })
+    });
+    on_sample(input);
+    true
+}
+
+/// Charge right aligned after a 5x7 battery icon, in the 30x7 area at `y`
+pub fn draw<const W: usize, const H: usize>(canvas: &mut Canvas<W, H>, y: usize, percent: u8) {
+    // Outline with the terminal on top
+    for x in 1..4 {
+        canvas.on(x, y);
+    }
+    for dy in 1..7 {
+        canvas.on(0, y + dy);
+        canvas.on(4, y + dy);
+    }
+    for x in 0..5 {
+        canvas.on(x, y + 6);
+    }
+    // Up to five rows of charge
+    let rows = (percent as usize * 5).div_ceil(100);
+    for dy in 6 - rows..6 {
+        for x in 1..4 {
+            canvas.on(x, y + dy);
+        }
+    }
+
+    let text = alloc::format!("{percent}%");
+    let width = crate::font::ALPHABET_NORMAL.text_width(&text);
+    canvas.print_5x7(30usize.saturating_sub(width), y, &text);
+}
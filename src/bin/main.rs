@@ -1,38 +1,104 @@
 #![no_std]
 #![no_main]
 
-use b_intime_5::display::{Canvas, Screen};
-use b_intime_5::wifimanager;
+extern crate alloc;
+
+use b_intime_5::alarms;
+use b_intime_5::alerts::{self, Sensor};
+use b_intime_5::animation::{self, Animation};
+use b_intime_5::automations;
+use b_intime_5::api;
+use b_intime_5::battery;
+use b_intime_5::board;
+use b_intime_5::bringup;
+use b_intime_5::burnin;
+use b_intime_5::brightness;
+use b_intime_5::buzzer;
+use b_intime_5::calendar;
+use b_intime_5::capabilities::{self, Capability};
+use b_intime_5::climate;
+use b_intime_5::compositor::{Compositor, LayerId};
+use b_intime_5::countdown;
+use b_intime_5::device::{self, Pairing};
+use b_intime_5::discovery;
+use b_intime_5::dnd;
+use b_intime_5::energy;
+use b_intime_5::holidays;
+use b_intime_5::location;
+use b_intime_5::face::{self, ClockFace, Face, Granularity, Separator};
+use b_intime_5::maintenance;
+use b_intime_5::melody;
+use b_intime_5::menu;
+use b_intime_5::morse;
+use b_intime_5::message::{self, MessageError};
+use b_intime_5::ntp;
+use b_intime_5::scheduler::Widget;
+use b_intime_5::pin;
+use b_intime_5::power;
+use b_intime_5::powersave;
+use b_intime_5::prefs;
+use b_intime_5::profiler;
+use b_intime_5::presence;
+use b_intime_5::satellite;
+use b_intime_5::sockets::{self, HTTP_CLIENT_BUFFER};
+use b_intime_5::score::{self, Side};
+use b_intime_5::session::Sessions;
+use b_intime_5::showsync;
+use b_intime_5::snake::{self, Snake};
+use b_intime_5::stats::{self, Counter};
+use b_intime_5::startup::{self, Stage};
+use b_intime_5::statusled;
+use b_intime_5::sun::{self, SunTimes};
+#[cfg(all(feature = "ssd1306", not(feature = "hub75")))]
+use b_intime_5::ssd1306::Ssd1306;
+use b_intime_5::theme::{self, TimeFont, Transition};
+use b_intime_5::transition;
+use b_intime_5::txpower;
+use b_intime_5::units;
+use b_intime_5::wake;
+use b_intime_5::watchdog::{self, Task};
+use b_intime_5::webhooks;
+#[cfg(not(any(feature = "hub75", feature = "ssd1306")))]
+use b_intime_5::display::{Screen, DMA_BUFFER_LEN};
+#[cfg(feature = "hub75")]
+use b_intime_5::{display::Rgb, hub75::Hub75};
+use b_intime_5::display::{Canvas, DisplayBackend, Zone};
+use b_intime_5::dns::CachedDns;
+use b_intime_5::font::{ALPHABET_BIG_DIGITS, ALPHABET_NORMAL};
+use b_intime_5::{log, logmirror};
+use b_intime_5::metronome;
+use b_intime_5::mqtt;
+use b_intime_5::i18n::Language;
+use b_intime_5::input::{self, Command, Encoder, InputEvent, Press};
+use b_intime_5::wordclock;
+use b_intime_5::wifimanager::{self, Location, NetEvent, NetEventSubscriber, Nvs};
 use reqwless::{client::HttpClient, request::RequestBuilder};
 use serde::Deserialize;
 
-use core::u16;
-use core::{
-    net::{IpAddr, SocketAddr},
-    str::from_utf8_unchecked,
-};
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::str::from_utf8_unchecked;
 
 use embassy_executor::Spawner;
+use embassy_futures::{
+    join::{join, join5},
+    select::{select, select3, select4, Either, Either3, Either4},
+};
 use embassy_net::{
-    dns::{DnsQueryType, DnsSocket},
     tcp::client::{TcpClient, TcpClientState},
-    udp::{PacketMetadata, UdpSocket},
     Stack,
 };
-use embassy_time::{Duration, Timer};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Instant, Ticker, Timer};
 use esp_backtrace as _;
 use esp_hal::{
-    analog::adc::{Adc, AdcConfig, Attenuation},
-    gpio::{Level, Output, OutputConfig},
+    analog::adc::{Adc, AdcCalCurve, AdcConfig, Attenuation},
+    gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull},
     peripherals,
+    rng::TrngSource,
     rtc_cntl::Rtc,
-    spi::{self, master::Spi},
-    time::Rate,
     timer::timg::TimerGroup,
-    Blocking,
 };
-use esp_println::println;
-use sntpc::{get_time, NtpContext, NtpResult, NtpTimestampGenerator};
 
 // When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
 // macro_rules! mk_static {
@@ -44,31 +110,178 @@ use sntpc::{get_time, NtpContext, NtpResult, NtpTimestampGenerator};
 //     }};
 // }
 
-const TIMEZONE: jiff::tz::TimeZone = jiff::tz::get!("Europe/Paris");
-const NTP_SERVER: &str = "pool.ntp.org";
+/// MQTT broker, none unless `MQTT_HOST` is set at build time
+const MQTT_HOST: Option<&str> = option_env!("MQTT_HOST");
+/// Household power in W, shown in turn with the temperature
+const ENERGY_TOPIC: &str = "home/power";
+/// Do not disturb commands: ON, OFF, TOGGLE or AUTO, after the lowercase
+/// device name: "b-intime-5/dnd/set"
+const DND_TOPIC_SUFFIX: &str = "/dnd/set";
+/// Text to scroll, after the lowercase device name: "b-intime-5/message"
+const MESSAGE_TOPIC_SUFFIX: &str = "/message";
+/// Why a message was refused, after the lowercase device name:
+/// "b-intime-5/message/error"
+const MESSAGE_ERROR_TOPIC_SUFFIX: &str = "/message/error";
+/// Battery state published as JSON, after the lowercase device name:
+/// "b-intime-5/battery"
+const BATTERY_TOPIC_SUFFIX: &str = "/battery";
+/// Battery sampling, the readings are averaged
+const BATTERY_PERIOD: Duration = Duration::from_secs(10);
+const BATTERY_PUBLISH_PERIOD: Duration = Duration::from_secs(5 * 60);
+/// USB power outages, published when the power comes back, after the
+/// lowercase device name: "b-intime-5/power"
+#[cfg(feature = "vbus-sense")]
+const POWER_TOPIC_SUFFIX: &str = "/power";
+/// Plugging and unplugging bounce
+#[cfg(feature = "vbus-sense")]
+const VBUS_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// MQTT client named after the device, the name is read at boot only
+fn mqtt_config(host: &'static str) -> mqtt::Config {
+    let name: &'static str = device::name().leak();
+    let dnd_topic: &'static str =
+        alloc::format!("{}{DND_TOPIC_SUFFIX}", name.to_lowercase()).leak();
+    let message_topic: &'static str =
+        alloc::format!("{}{MESSAGE_TOPIC_SUFFIX}", name.to_lowercase()).leak();
+    mqtt::Config {
+        host,
+        port: mqtt::DEFAULT_PORT,
+        client_id: name,
+        username: option_env!("MQTT_USERNAME"),
+        password: option_env!("MQTT_PASSWORD"),
+        topics: alloc::vec![ENERGY_TOPIC, dnd_topic, message_topic].leak(),
+    }
+}
 
-/// Microseconds in a second
-const USEC_IN_SEC: u64 = 1_000_000;
+/// Do not disturb window and what still comes through
+static DND: dnd::Settings = dnd::Settings {
+    schedule: Some((22 * 60 + 30, 7 * 60)),
+    exceptions: &[dnd::Kind::Alarm],
+};
 
-#[derive(Clone, Copy)]
-struct Timestamp<'a> {
-    rtc: &'a Rtc<'a>,
-    current_time_us: u64,
-}
+/// Phones whose presence keeps the display lit, e.g.
+/// `presence::Device::Address([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])`
+#[cfg(feature = "ble")]
+static PHONES: &[presence::Device] = &[];
 
-impl NtpTimestampGenerator for Timestamp<'_> {
-    fn init(&mut self) {
-        self.current_time_us = self.rtc.current_time_us();
-    }
+/// MAX7219 matrix, or the HUB75 panel or SSD1306 OLED with their features,
+/// in this order of preference
+#[cfg(not(any(feature = "hub75", feature = "ssd1306")))]
+type Display = Screen<'static, 8>;
+#[cfg(all(feature = "ssd1306", not(feature = "hub75")))]
+type Display = Ssd1306<'static>;
+#[cfg(feature = "hub75")]
+type Display = Hub75;
 
-    fn timestamp_sec(&self) -> u64 {
-        self.current_time_us / 1_000_000
-    }
+/// Lit pixels color on the HUB75 panel
+#[cfg(feature = "hub75")]
+const HUB75_COLOR: Rgb = Rgb::new(255, 96, 0);
 
-    fn timestamp_subsec_micros(&self) -> u32 {
-        (self.current_time_us % 1_000_000) as u32
-    }
+const TIMEZONE: jiff::tz::TimeZone = jiff::tz::get!("Europe/Paris");
+
+/// A fixed offset set in the button menu, or the POSIX TZ string set through
+/// the HTTP API, or `TIMEZONE`
+fn timezone() -> jiff::tz::TimeZone {
+    prefs::get()
+        .utc_offset
+        .and_then(|hours| jiff::tz::Offset::from_hours(hours).ok())
+        .map(jiff::tz::TimeZone::fixed)
+        .or_else(b_intime_5::timezone::get)
+        .unwrap_or(TIMEZONE)
 }
+/// Let browser dashboards served from other hosts call the HTTP API,
+/// e.g. restrict `allow_origin` to "http://dashboard.lan"
+const API_CORS: Option<api::Cors<'static>> = Some(api::Cors {
+    allow_origin: "*",
+    max_age: 600,
+});
+
+/// Show a warning icon after this long without a successful NTP sync
+const NTP_DESYNC_ALERT: Duration = Duration::from_secs(24 * 3600);
+/// Chirp the buzzer once per hour while desynced
+const NTP_DESYNC_CHIRP: bool = true;
+/// Time between two NTP syncs
+const NTP_POLL: Duration = Duration::from_secs(60);
+/// Bottom right pixel: lit while the last NTP sync is less than two polls
+/// old, blinking past that, off before the first sync
+const SYNC_PIXEL: bool = true;
+/// Pixel left of it: the status LED pattern, for builds without the LED
+const STATUS_PIXEL: bool = false;
+/// Wi-Fi signal bars in the bottom left corner, a blinking pixel when the
+/// link is poor, nothing while disconnected
+const SIGNAL_ICON: bool = true;
+/// Top right pixel, where Morse transmissions blink
+const MORSE_PIXEL: (usize, usize) = (31, 0);
+/// Send the network bring-up result in Morse: "OK" and the last octet of the
+/// address, or "ERR", for builds that do not show it
+const MORSE_STATUS: bool = false;
+
+/// Scroll the last log line on the matrix instead of the clock.
+/// Holding the boot button during reset enables it too.
+const LOG_MIRROR: bool = false;
+
+/// Kitchen timer started by holding the boot button
+const KITCHEN_TIMER_MINUTES: u32 = 5;
+
+/// Score face refresh period, a button press shows up within it
+const SCORE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Word clock language
+const LANGUAGE: Language = Language::English;
+
+/// Scrolling text speed, per pixel
+const TEXT_SCROLL_STEP: Duration = Duration::from_millis(60);
+
+/// Seconds the temperature, power and satellite temperature are shown each
+/// in turn
+const ENERGY_TURN_SECS: i8 = 5;
+
+/// Lowest brightness of a notification, raised from dimmed themes
+const NOTIFICATION_BRIGHTNESS: u8 = 8;
+/// Columns of the wake alarm sunrise, clear of the do not disturb moon and the
+/// sync pixel
+const SUNRISE_LEFT: usize = 2;
+const SUNRISE_RIGHT: usize = 30;
+
+/// Passes of an alert notification
+const ALERT_SCROLLS: usize = 3;
+
+/// Identification requested through the API: flashes, then the name scrolls
+const IDENTIFY_DURATION: Duration = Duration::from_secs(10);
+const IDENTIFY_FLASHES: usize = 3;
+const IDENTIFY_FLASH: Duration = Duration::from_millis(250);
+
+/// Home Assistant weather refresh period
+const WEATHER_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Metronome bar refresh period
+const METRONOME_FRAME: Duration = Duration::from_millis(20);
+
+/// Three short presses within it toggle do not disturb
+const TRIPLE_PRESS: Duration = Duration::from_secs(1);
+
+/// Second press making a double press
+const DOUBLE_PRESS: Duration = Duration::from_millis(300);
+/// Time on each page of the diagnostics face
+const DIAGNOSTICS_PAGE_SECS: i64 = 3;
+
+/// Row between the two dots of the colon, in both time fonts
+const TIME_MID_ROW: usize = 3;
+
+/// Snake speed
+const SNAKE_STEP: Duration = Duration::from_millis(200);
+
+/// VU meter refresh period
+#[cfg(feature = "microphone")]
+const VU_METER_PERIOD: Duration = Duration::from_millis(40);
+
+/// Flip minutes in step with the other clocks of the LAN
+const SHOW_SYNC: bool = true;
+
+/// Display refresh period, widgets are budgeted within it
+const FRAME_PERIOD: Duration = Duration::from_secs(1);
+/// Menu refresh period, short to follow the presses
+const MENU_FRAME: Duration = Duration::from_millis(100);
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
@@ -76,9 +289,15 @@ esp_bootloader_esp_idf::esp_app_desc!();
 async fn main(spawner: Spawner) {
     esp_alloc::heap_allocator!(size: 150 * 1024);
 
-    let peripherals = esp_hal::init(esp_hal::Config::default());
+    let mut peripherals = esp_hal::init(esp_hal::Config::default());
+
+    let boot_button = Input::new(
+        pin!(peripherals.button),
+        InputConfig::default().with_pull(Pull::Up),
+    );
+    logmirror::set_enabled(LOG_MIRROR || boot_button.is_low());
 
-    esp_println::println!("Init!");
+    log!("Init!");
 
     let sw_int =
         esp_hal::interrupt::software::SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
@@ -88,57 +307,639 @@ async fn main(spawner: Spawner) {
     let rtc = Rtc::new(peripherals.LPWR);
     // rtc.rwdt.set_timeout(RwdtStage::Stage0, esp_hal::time::Duration::from_millis(2000));
     // rtc.rwdt.enable();
-    esp_println::println!("RWDT watchdog enabled!");
+    log!("RWDT watchdog enabled!");
 
     let rng = esp_hal::rng::Rng::new();
 
+    startup::start(Stage::Display).expect("startup order");
+    #[cfg(not(any(feature = "hub75", feature = "ssd1306")))]
+    let display = {
+        use esp_hal::{
+            dma::{DmaRxBuf, DmaTxBuf},
+            dma_buffers,
+            spi::master::{Config, Spi},
+            time::Rate,
+        };
+
+        let config = OutputConfig::default();
+        let cs = Output::new(pin!(peripherals.matrix_cs), Level::High, config);
+        let mosi = Output::new(pin!(peripherals.matrix_mosi), Level::High, config);
+        let sclk = Output::new(pin!(peripherals.matrix_sclk), Level::High, config);
+
+        let spi = Spi::new(
+            peripherals.SPI2,
+            Config::default().with_frequency(Rate::from_khz(100)),
+        )
+        .unwrap()
+        .with_sck(sclk)
+        .with_mosi(mosi)
+        .with_cs(cs)
+        .with_dma(peripherals.DMA_CH1);
+
+        // Nothing is read back, the receive buffer only completes the bus
+        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) =
+            dma_buffers!(4, DMA_BUFFER_LEN);
+        let rx = DmaRxBuf::new(rx_descriptors, rx_buffer).expect("dma rx buffer");
+        let tx = DmaTxBuf::new(tx_descriptors, tx_buffer).expect("dma tx buffer");
+        Screen::new(spi.with_buffers(rx, tx).into_async())
+    };
+
+    #[cfg(all(feature = "ssd1306", not(feature = "hub75")))]
+    let display = {
+        use esp_hal::{
+            i2c::master::{Config, I2c},
+            time::Rate,
+        };
+
+        let i2c = I2c::new(
+            peripherals.I2C0,
+            Config::default().with_frequency(Rate::from_khz(400)),
+        )
+        .unwrap()
+        .with_sda(pin!(peripherals.oled_sda))
+        .with_scl(pin!(peripherals.oled_scl));
+        Ssd1306::new(i2c, b_intime_5::ssd1306::DEFAULT_ADDRESS)
+    };
+
+    // Takes GPIO20-23 from the SD card
+    #[cfg(feature = "hub75")]
+    let display = {
+        use b_intime_5::hub75::{refresh_task, Hub75Pins};
+        use esp_rtos::embassy::InterruptExecutor;
+
+        let config = OutputConfig::default();
+        let pins = Hub75Pins {
+            rgb1: [
+                Output::new(peripherals.GPIO17, Level::Low, config),
+                Output::new(peripherals.GPIO18, Level::Low, config),
+                Output::new(peripherals.GPIO19, Level::Low, config),
+            ],
+            rgb2: [
+                Output::new(peripherals.GPIO20, Level::Low, config),
+                Output::new(peripherals.GPIO21, Level::Low, config),
+                Output::new(peripherals.GPIO22, Level::Low, config),
+            ],
+            address: [
+                Output::new(peripherals.GPIO0, Level::Low, config),
+                Output::new(peripherals.GPIO1, Level::Low, config),
+                Output::new(peripherals.GPIO10, Level::Low, config),
+                Output::new(peripherals.GPIO11, Level::Low, config),
+            ],
+            clk: Output::new(peripherals.GPIO23, Level::Low, config),
+            lat: Output::new(peripherals.GPIO14, Level::Low, config),
+            oe: Output::new(peripherals.GPIO15, Level::High, config),
+        };
+
+        // Above the thread mode executor, so the refresh keeps its pace
+        let executor = b_intime_5::mk_static!(
+            InterruptExecutor<1>,
+            InterruptExecutor::new(sw_int.software_interrupt1)
+        );
+        executor
+            .start(esp_hal::interrupt::Priority::Priority3)
+            .spawn(refresh_task(pins))
+            .expect("hub75 refresh task");
+        Hub75::new(HUB75_COLOR)
+    };
+
+    // In log mirror mode the display belongs to the mirror task from the start,
+    // so wifi bring-up can be followed without serial
+    let mut display = if logmirror::is_enabled() {
+        spawner.spawn(log_mirror_loop(display)).expect("log mirror loop");
+        None
+    } else {
+        Some(display)
+    };
+    startup::done(Stage::Display);
+
+    startup::start(Stage::Settings).expect("startup order");
+
+    #[cfg(feature = "sdcard")]
+    {
+        use b_intime_5::sdcard::{self, SdCard, SoftSpi, Volume};
+
+        let config = OutputConfig::default();
+
+        let sd_spi = SoftSpi::new(
+            Output::new(pin!(peripherals.sd_sclk), Level::Low, config),
+            Output::new(pin!(peripherals.sd_mosi), Level::High, config),
+            Input::new(pin!(peripherals.sd_miso), InputConfig::default().with_pull(Pull::Up)),
+        );
+        let sd_cs = Output::new(pin!(peripherals.sd_cs), Level::High, config);
+
+        match SdCard::init(sd_spi, sd_cs).and_then(Volume::mount) {
+            Ok(volume) => {
+                sdcard::install(volume).await;
+                spawner.spawn(sdcard::log_task()).expect("sd log task");
+            }
+            Err(e) => log!("No SD card: {e:?}"),
+        }
+    }
+
+    let storage = match wifimanager::init_storage(peripherals.FLASH) {
+        Ok(storage) => storage,
+        Err(e) => {
+            startup::fail(Stage::Settings, &alloc::format!("{e:?}"));
+            panic!("storage: {e:?}");
+        }
+    };
+    device::load_name(storage).await;
+    txpower::load(storage).await;
     let wm_settings = wifimanager::WmSettings {
-        ssid: "B-intime-5".into(),
+        ssid: device::ap_ssid(),
+        hostname: Some(device::name()),
         wifi_conn_timeout: 30000,
+        tx_power: txpower::settings().dbm,
         esp_reset_timeout: Some(300000), // 5min
         ..Default::default()
     };
 
-    let wifi_res = wifimanager::init_wm(
-        wm_settings,
-        &spawner,
-        peripherals.FLASH,
-        rng.clone(),
-        peripherals.WIFI,
+    let pairing = {
+        // The radio is not started yet, the ADC noise feeds the RNG for the code
+        let _entropy = TrngSource::new(peripherals.RNG.reborrow(), peripherals.ADC1.reborrow());
+        Pairing::load_or_create(storage).await
+    };
+    // Serial only, `log!` lines are also mirrored and written to the card
+    esp_println::println!("Device {} pairing code {}", device::device_id_hex(), pairing.code());
+    capabilities::load(storage).await;
+    theme::load(storage).await;
+    ntp::load(storage).await;
+    maintenance::load(storage).await;
+    wake::load(storage).await;
+    alarms::load(storage).await;
+    stats::load(storage).await;
+    battery::load(storage).await;
+    face::load(storage).await;
+    prefs::load(storage).await;
+    units::load(storage).await;
+    powersave::load(storage).await;
+    calendar::load(storage).await;
+    holidays::load(storage).await;
+    burnin::load(storage).await;
+    b_intime_5::timezone::load(storage).await;
+    melody::load(storage).await;
+    countdown::load(storage).await;
+    dnd::init(&DND);
+    score::load(storage).await;
+    climate::load(storage).await;
+    alerts::load(storage).await;
+    automations::load(storage).await;
+    webhooks::load(storage).await;
+    startup::done(Stage::Settings);
+
+    // The code stays on the matrix while wifi connects or the setup AP runs
+    let pairing_code = (!pairing.is_paired()).then(|| pairing.code());
+    if let (Some(display), Some(code)) = (display.as_mut(), &pairing_code) {
+        show_pairing_code(display, code);
+    }
+    let pairing = b_intime_5::mk_static!(Mutex<CriticalSectionRawMutex, Pairing>, Mutex::new(pairing));
+
+    startup::start(Stage::Wifi).expect("startup order");
+    let wifi_res = match select(
+        wifimanager::init_wm(wm_settings, &spawner, storage, rng, peripherals.WIFI),
+        show_ap_clients(display.as_mut(), pairing_code),
     )
     .await
-    .expect("wm init");
-
-    esp_println::println!("wifi_res: {wifi_res:?}");
+    {
+        Either::First(Ok(wifi_res)) => wifi_res,
+        Either::First(Err(e)) => {
+            startup::fail(Stage::Wifi, &alloc::format!("{e:?}"));
+            panic!("wm init: {e:?}");
+        }
+        Either::Second(()) => unreachable!("shown until wifi is up"),
+    };
+    startup::done(Stage::Wifi);
+    // Done by the first sync, in the background
+    startup::start(Stage::Time).expect("startup order");
 
-    let config = OutputConfig::default();
-    let cs = Output::new(peripherals.GPIO17, Level::High, config);
-    let mosi = Output::new(peripherals.GPIO18, Level::High, config);
-    let sclk = Output::new(peripherals.GPIO19, Level::High, config);
+    log!("wifi_res: {wifi_res:?}");
+    location::init(wifi_res.setup.location());
 
-    let mut spi = spi::master::Spi::new(
-        peripherals.SPI2,
-        spi::master::Config::default().with_frequency(Rate::from_khz(100)),
-    )
-    .unwrap()
-    .with_sck(sclk)
-    .with_mosi(mosi)
-    .with_cs(cs);
+    startup::start(Stage::Services).expect("startup order");
+    #[cfg(feature = "ble")]
+    spawner
+        .spawn(presence::ble_scan_task(
+            wifi_res.wifi_init,
+            peripherals.BT,
+            peripherals.AES,
+            PHONES,
+        ))
+        .expect("ble scan task");
 
     spawner
-        .spawn(lum_loop(peripherals.GPIO2, peripherals.ADC1))
+        .spawn(watchdog::supervisor_task())
+        .expect("watchdog supervisor");
+    #[cfg(feature = "status-led")]
+    let status_led = Some(Output::new(
+        pin!(peripherals.status_led),
+        Level::Low,
+        OutputConfig::default(),
+    ));
+    #[cfg(not(feature = "status-led"))]
+    let status_led = None;
+    spawner
+        .spawn(statusled::status_task(status_led))
+        .expect("status led task");
+    #[cfg(feature = "battery")]
+    let battery_pin = Some(pin!(peripherals.battery));
+    #[cfg(not(feature = "battery"))]
+    let battery_pin = None;
+    spawner
+        .spawn(lum_loop(pin!(peripherals.light), battery_pin, peripherals.ADC1))
         .expect("lum loop");
 
-    main_loop(wifi_res.sta_stack, rtc, &mut spi).await
+    #[cfg(feature = "vbus-sense")]
+    spawner
+        .spawn(vbus_loop(Input::new(pin!(peripherals.vbus), InputConfig::default())))
+        .expect("vbus loop");
+
+    let buzzer_pin = Output::new(pin!(peripherals.buzzer), Level::Low, OutputConfig::default());
+    spawner
+        .spawn(buzzer::buzzer_task(buzzer_pin))
+        .expect("buzzer task");
+    spawner
+        .spawn(countdown::countdown_task())
+        .expect("countdown task");
+
+    let mut sessions = Sessions::load(storage).await;
+    if let Some(password) = wifi_res.setup.password.as_deref() {
+        if let Err(e) = sessions.set_password(storage, password).await {
+            log!("Panel password not set: {e:?}");
+        }
+    }
+    let sessions = b_intime_5::mk_static!(Mutex<CriticalSectionRawMutex, Sessions>, Mutex::new(sessions));
+
+    api::run_api_server(
+        &spawner,
+        wifi_res.sta_stack,
+        wifi_res.net_events,
+        storage,
+        pairing,
+        sessions,
+        API_CORS,
+    );
+
+    spawner
+        .spawn(discovery::discovery_task(wifi_res.sta_stack))
+        .expect("discovery task");
+
+    if let Some(host) = MQTT_HOST {
+        let config = b_intime_5::mk_static!(mqtt::Config, mqtt_config(host));
+        spawner
+            .spawn(mqtt::mqtt_task(
+                wifi_res.sta_stack,
+                wifi_res.subscribe().expect("mqtt net events"),
+                config,
+                mqtt_message,
+            ))
+            .expect("mqtt task");
+    }
+
+    spawner.spawn(ui_loop()).expect("ui loop");
+    spawner
+        .spawn(button_loop(boot_button))
+        .expect("button loop");
+
+    #[cfg(feature = "encoder")]
+    spawner
+        .spawn(b_intime_5::encoder::encoder_task(
+            Input::new(pin!(peripherals.encoder_a), InputConfig::default().with_pull(Pull::Up)),
+            Input::new(pin!(peripherals.encoder_b), InputConfig::default().with_pull(Pull::Up)),
+            Input::new(pin!(peripherals.encoder_push), InputConfig::default().with_pull(Pull::Up)),
+        ))
+        .expect("encoder task");
+
+    #[cfg(feature = "microphone")]
+    spawner
+        .spawn(b_intime_5::vumeter::mic_task(
+            peripherals.I2S0,
+            peripherals.DMA_CH0,
+            pin!(peripherals.mic_bclk).into(),
+            pin!(peripherals.mic_ws).into(),
+            pin!(peripherals.mic_din).into(),
+        ))
+        .expect("mic task");
+
+    let net_events = wifi_res.subscribe().expect("net events");
+    let weather_events = wifi_res.subscribe().expect("weather net events");
+
+    spawner
+        .spawn(webhooks::webhook_task(
+            wifi_res.sta_stack,
+            wifi_res.subscribe().expect("webhook net events"),
+        ))
+        .expect("webhook task");
+
+    #[cfg(feature = "espnow")]
+    spawner
+        .spawn(satellite::espnow_task(wifi_res.esp_now))
+        .expect("espnow task");
+    startup::done(Stage::Services);
+
+    main_loop(
+        wifi_res.sta_stack,
+        net_events,
+        weather_events,
+        storage,
+        rtc,
+        display.as_mut(),
+    )
+    .await
+}
+
+fn show_pairing_code(display: &mut Display, code: &str) {
+    let mut canvas = Canvas::<32, 16>::init();
+    display.init();
+    canvas.print_4x6(0, 1, "PAIR");
+    canvas.print_4x6(0, 9, code);
+    display.draw(&canvas);
+}
+
+/// Number of stations joined to the setup AP, taking turns with the pairing
+/// code when there is one
+async fn show_ap_clients(mut display: Option<&mut Display>, code: Option<alloc::string::String>) {
+    let mut shown = None;
+    loop {
+        Timer::after(Duration::from_secs(2)).await;
+        let Some(display) = display.as_deref_mut() else {
+            continue;
+        };
+
+        let count = wifimanager::ap_clients().len();
+        let next = (count > 0 && (code.is_none() || shown.is_none())).then_some(count);
+        if next == shown {
+            continue;
+        }
+        match (next, &code) {
+            (Some(count), _) => {
+                let mut canvas = Canvas::<32, 16>::init();
+                display.init();
+                canvas.print_5x7(0, 0, "JOINED");
+                canvas.print_5x7(0, 8, &alloc::format!("{count}"));
+                display.draw_async(&canvas).await;
+            }
+            (None, Some(code)) => show_pairing_code(display, code),
+            (None, None) => display.draw_async(&Canvas::<32, 16>::init()).await,
+        }
+        shown = next;
+    }
+}
+
+/// Boot button: reports its presses on the input bus
+///
+/// A short press right after another one is a double press when the UI tells
+/// them apart, otherwise both are reported.
+#[embassy_executor::task]
+async fn button_loop(mut button: Input<'static>) {
+    let mut short_released = None;
+    loop {
+        button.wait_for_falling_edge().await;
+        let pressed_at = Instant::now();
+        let after_short = short_released
+            .take()
+            .is_some_and(|released: Instant| pressed_at - released <= DOUBLE_PRESS);
+        webhooks::fire(webhooks::Event::ButtonPressed);
+        let long = select(Timer::after(Duration::from_secs(1)), button.wait_for_high())
+            .await
+            .is_first();
+        let held = long
+            && select(Timer::after(Duration::from_secs(2)), button.wait_for_high())
+                .await
+                .is_first();
+
+        let press = match (held, long, after_short) {
+            (true, _, true) => Press::ShortHeld,
+            (true, _, false) => Press::Held,
+            (false, true, true) => Press::ShortLong,
+            (false, true, false) => Press::Long,
+            _ if input::wants_double_press() && is_double_press(&mut button).await => {
+                button.wait_for_high().await;
+                Press::Double
+            }
+            _ => Press::Short,
+        };
+        input::send(InputEvent::Button(press));
+
+        button.wait_for_high().await;
+        if press == Press::Short {
+            short_released = Some(Instant::now());
+        }
+    }
+}
+
+/// Whether the UI tells double presses apart, for the button
+fn wants_double_press() -> bool {
+    !countdown::state().is_active()
+        && !menu::is_open()
+        && matches!(face::current(), Face::Score | Face::Metronome)
+}
+
+/// Single consumer of the input bus
+///
+/// Buttons: a long press starts snake, a short one turns the snake right,
+/// holding it starts the kitchen timer.
+/// While the timer runs or rings, a short press snoozes and a long one dismisses it.
+/// In the menu, a short press changes the value and a long one goes to the next item.
+/// On the score face, a short press scores left, a double press scores right,
+/// a long one resets and holding it goes to the next face.
+/// On the metronome face, a short press speeds up, a double press slows down,
+/// a long one restores the default tempo and holding it goes to the next face.
+/// On the diagnostics face, a press goes back to the clock.
+/// Elsewhere, three short presses toggle do not disturb, a long press right
+/// after a short one shows the diagnostics face and holding it opens the menu.
+///
+/// Encoder: outside the menu, turning sets the brightness, kept until a
+/// restart unless the menu saves it, and a push opens the menu. In the menu,
+/// turning changes the value and a push goes to the next item.
+#[embassy_executor::task]
+async fn ui_loop() {
+    input::set_double_press_filter(wants_double_press);
+    let mut first_press = Instant::now();
+    let mut presses = 0u8;
+    loop {
+        match input::receive().await {
+            InputEvent::Button(press) | InputEvent::Touch(press) => {
+                if press == Press::Short {
+                    if first_press.elapsed() > TRIPLE_PRESS {
+                        first_press = Instant::now();
+                        presses = 0;
+                    }
+                    presses += 1;
+                }
+                let triple = presses == 3;
+                if triple {
+                    presses = 0;
+                }
+                on_press(press, triple);
+            }
+            InputEvent::Encoder(Encoder::Turn { clockwise }) if menu::is_open() => {
+                menu::change(clockwise)
+            }
+            InputEvent::Encoder(Encoder::Turn { clockwise }) => {
+                let mut prefs = prefs::get();
+                let level = prefs.brightness.unwrap_or(brightness::level());
+                prefs.brightness = Some(if clockwise {
+                    (level + 1).min(theme::MAX_BRIGHTNESS)
+                } else {
+                    level.saturating_sub(1)
+                });
+                prefs::set(prefs);
+            }
+            InputEvent::Encoder(Encoder::Push) if menu::is_open() => menu::next(),
+            InputEvent::Encoder(Encoder::Push) => menu::open(),
+            InputEvent::Ir(code) => log!("IR command {code} ignored"),
+            InputEvent::Web(command) | InputEvent::Mqtt(command) => match command {
+                Command::Snake(input) => snake::send(input),
+                Command::Dnd(switch) => switch.apply(),
+            },
+        }
+    }
+}
+
+/// `triple` when the short press is the third in a row
+fn on_press(press: Press, triple: bool) {
+    let long = matches!(press, Press::Long | Press::ShortLong);
+    let held = matches!(press, Press::Held | Press::ShortHeld);
+    if countdown::state().is_active() {
+        if long || held {
+            countdown::stop();
+        } else {
+            countdown::snooze();
+        }
+    } else if menu::is_open() {
+        if long || held {
+            menu::next();
+        } else {
+            menu::change(true);
+        }
+    } else if face::current() == Face::Score {
+        match press {
+            _ if held => _ = face::next(),
+            _ if long => score::reset(),
+            Press::Double => score::increment(Side::Right),
+            _ => score::increment(Side::Left),
+        }
+    } else if face::current() == Face::Metronome {
+        match press {
+            _ if held => _ = face::next(),
+            _ if long => metronome::set_bpm(metronome::DEFAULT_BPM),
+            Press::Double => metronome::slower(),
+            _ => metronome::faster(),
+        }
+    } else if face::current() == Face::Diagnostics {
+        face::set(Face::Clock);
+    } else {
+        match press {
+            Press::ShortHeld => menu::open(),
+            Press::ShortLong => face::set(Face::Diagnostics),
+            Press::Held => countdown::start(KITCHEN_TIMER_MINUTES),
+            Press::Long => snake::send(snake::Input::Start),
+            Press::Short | Press::Double => {
+                // Turns are ignored outside a game, so they do not get in the way
+                snake::send(snake::Input::TurnClockwise);
+                if triple {
+                    dnd::toggle();
+                    log!("Do not disturb {}", if dnd::is_active() { "on" } else { "off" });
+                }
+            }
+        }
+    }
+}
+
+fn mqtt_message(topic: &str, payload: &[u8]) {
+    match topic {
+        ENERGY_TOPIC => energy::on_message(payload),
+        topic if topic.ends_with(DND_TOPIC_SUFFIX) => match dnd::Switch::from_payload(payload) {
+            Some(switch) => input::send(InputEvent::Mqtt(Command::Dnd(switch))),
+            None => log!("Invalid do not disturb command"),
+        },
+        topic if topic.ends_with(MESSAGE_TOPIC_SUFFIX) => {
+            let error = match core::str::from_utf8(payload).map(message::show) {
+                Ok(Ok(())) => return,
+                Ok(Err(MessageError::Unsupported(letters))) => {
+                    alloc::format!("unsupported characters: {}", message::describe(&letters))
+                }
+                Ok(Err(MessageError::Empty)) => "empty message".into(),
+                Ok(Err(MessageError::TooLong)) => "too long".into(),
+                Err(_) => "invalid UTF-8".into(),
+            };
+            log!("Message refused: {error}");
+            let name = device::name().to_lowercase();
+            mqtt::publish(alloc::format!("{name}{MESSAGE_ERROR_TOPIC_SUFFIX}"), error);
+        }
+        _ => {}
+    }
+}
+
+/// After a short press, whether a second one follows
+async fn is_double_press(button: &mut Input<'static>) -> bool {
+    select(Timer::after(DOUBLE_PRESS), button.wait_for_falling_edge())
+        .await
+        .is_second()
+}
+
+#[embassy_executor::task]
+async fn log_mirror_loop(mut display: Display) {
+    let mut canvas = Canvas::<32, 16>::init();
+    display.init();
+
+    loop {
+        let line = logmirror::last_line();
+        let width = ALPHABET_NORMAL.text_width(&line) as i32;
+
+        let mut x = 32;
+        while x > -width {
+            canvas.clear();
+            canvas.print_5x7_at(x, 4, &line);
+            display.draw_async(&canvas).await;
+
+            x -= 1;
+            Timer::after(Duration::from_millis(60)).await;
+        }
+
+        Timer::after(Duration::from_millis(500)).await;
+    }
 }
 
+/// USB power sense, high while powered: switches the power saving and tells
+/// over MQTT when the power comes back
+#[cfg(feature = "vbus-sense")]
 #[embassy_executor::task]
-async fn lum_loop(analog_pin: peripherals::GPIO2<'static>, adc1: peripherals::ADC1<'static>) {
+async fn vbus_loop(mut sense: Input<'static>) {
+    loop {
+        if let Some(lost_for) = power::set_vbus(sense.is_high()) {
+            if MQTT_HOST.is_some() {
+                let name = device::name().to_lowercase();
+                let topic = alloc::format!("{name}{POWER_TOPIC_SUFFIX}");
+                let payload = alloc::format!(r#"{{"lost_for_s":{}}}"#, lost_for.as_secs());
+                mqtt::publish(topic, payload);
+            }
+        }
+        sense.wait_for_any_edge().await;
+        Timer::after(VBUS_DEBOUNCE).await;
+    }
+}
+
+/// Light level, and the battery voltage when `battery_pin` is given
+#[embassy_executor::task]
+async fn lum_loop(
+    analog_pin: board::LightPin<'static>,
+    battery_pin: Option<board::BatteryPin<'static>>,
+    adc1: peripherals::ADC1<'static>,
+) {
     let mut adc1_config = AdcConfig::new();
     let mut pin = adc1_config.enable_pin(analog_pin, Attenuation::_11dB);
+    let mut battery_pin = battery_pin.map(|battery_pin| {
+        adc1_config.enable_pin_with_cal::<_, AdcCalCurve<peripherals::ADC1<'static>>>(
+            battery_pin,
+            Attenuation::_11dB,
+        )
+    });
     let mut adc1 = Adc::new(adc1, adc1_config).into_async();
 
     let mut previous = u16::MIN;
+    let mut last_battery: Option<Instant> = None;
+    let mut last_battery_publish: Option<Instant> = None;
 
     loop {
         let pin_value: u16 = adc1.read_oneshot(&mut pin).await;
@@ -147,6 +948,30 @@ async fn lum_loop(analog_pin: peripherals::GPIO2<'static>, adc1: peripherals::AD
             esp_println::println!("new lum {}", pin_value);
         }
 
+        if let Some(battery_pin) = battery_pin
+            .as_mut()
+            .filter(|_| capabilities::is_enabled(Capability::Sensors))
+            .filter(|_| last_battery.is_none_or(|last| last.elapsed() >= BATTERY_PERIOD))
+        {
+            last_battery = Some(Instant::now());
+            battery::on_sample(adc1.read_oneshot(battery_pin).await);
+
+            if MQTT_HOST.is_some()
+                && last_battery_publish.is_none_or(|last| last.elapsed() >= BATTERY_PUBLISH_PERIOD)
+            {
+                last_battery_publish = Some(Instant::now());
+                let status = battery::status();
+                let name = device::name().to_lowercase();
+                let topic = alloc::format!("{name}{BATTERY_TOPIC_SUFFIX}");
+                let payload = alloc::format!(
+                    r#"{{"millivolts":{},"percent":{}}}"#,
+                    status.millivolts.unwrap_or_default(),
+                    status.percent.unwrap_or_default()
+                );
+                mqtt::publish(topic, payload);
+            }
+        }
+
         Timer::after(Duration::from_secs(1)).await;
         previous = pin_value;
     }
@@ -163,7 +988,7 @@ impl<'a> Wrapper<'a> {
     fn new(buf: &'a mut [u8]) -> Self {
         buf.fill(0u8);
         Wrapper {
-            buf: buf,
+            buf,
             offset: 0,
         }
     }
@@ -195,161 +1020,1056 @@ impl<'a> fmt::Write for Wrapper<'a> {
     }
 }
 
-enum LigthLevel {
-    Bright,  // v < 2500
-    Low,  // v < 4090
-    Dark,  // V >= 4090
-}
-
 struct State {
     rtc: Rtc<'static>,
-    temperature: f32,
-    light_level: LigthLevel,
+    /// Last Home Assistant reading
+    temperature: Cell<Option<f32>>,
+    sync: RefCell<ntp::SyncTracker>,
 }
 
-enum Event {
-    Ntp(NtpResult),
-    Light(LigthLevel),
-    Temperature(f32),
-    Reset,
+/// Takes over the matrix from the faces until it ends
+enum Request {
+    Animation(alloc::vec::Vec<u8>),
+    Snake,
+    Notification(alloc::string::String),
+    Identify,
+    Show(alloc::string::String, Duration),
+    /// Opened by the button or the encoder
+    Menu,
 }
 
-async fn main_loop(stack: Stack<'static>, rtc: Rtc<'static>, spi: &mut Spi<'static, Blocking>) {
-    let buf = [0x20 as u8; 20];
-
-    let canvas = Canvas::<32, 16>::init();
-
-    Screen::<8>::init(spi);
-    let mut view = View { buf, canvas, spi };
-
-    let mut rx_meta = [PacketMetadata::EMPTY; 16];
-    let mut rx_buffer = [0; 4096];
-    let mut tx_meta = [PacketMetadata::EMPTY; 16];
-    let mut tx_buffer = [0; 4096];
+/// Wait for the next request for the matrix
+async fn request() -> Request {
+    let snake = async { while snake::input().await != snake::Input::Start {} };
+    let requests = select4(
+        animation::requested(),
+        snake,
+        alerts::notification(),
+        select3(
+            device::identify_requested(),
+            automations::requested(),
+            menu::opened(),
+        ),
+    );
+    match requests.await {
+        Either4::First(data) => Request::Animation(data),
+        Either4::Second(()) => Request::Snake,
+        Either4::Third(text) => Request::Notification(text),
+        Either4::Fourth(Either3::First(())) => Request::Identify,
+        Either4::Fourth(Either3::Second((text, duration))) => Request::Show(text, duration),
+        Either4::Fourth(Either3::Third(())) => Request::Menu,
+    }
+}
 
-    loop {
-        if stack.is_link_up() {
-            break;
+/// `display` is `None` when it is used by the log mirror
+async fn main_loop(
+    stack: Stack<'static>,
+    mut net_events: NetEventSubscriber,
+    mut weather_events: NetEventSubscriber,
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    rtc: Rtc<'static>,
+    display: Option<&mut Display>,
+) {
+    let mut view = display.map(|display| {
+        let buf = [0x20_u8; 20];
+
+        let canvas = Canvas::<32, 16>::init();
+
+        display.init();
+        View {
+            buf,
+            layers: Compositor::new(),
+            canvas,
+            display,
+            widgets: Widgets {
+                time: Widget::essential("time", Duration::from_millis(50)),
+                temperature: Widget::new("temperature", Duration::from_millis(20)),
+                energy: Widget::new("energy", Duration::from_millis(20)),
+                satellite: Widget::new("satellite", Duration::from_millis(20)),
+                battery: Widget::new("battery", Duration::from_millis(20)),
+                desync: Widget::new("desync", Duration::from_millis(5)),
+                dnd: Widget::new("dnd", Duration::from_millis(5)),
+                sunrise: Widget::new("sunrise", Duration::from_millis(5)),
+                signal: Widget::new("signal", Duration::from_millis(5)),
+                draw: Widget::essential("draw", Duration::from_millis(100)),
+            },
+            last_minute: None,
+            request: None,
+            brightness: None,
+            zones: &[],
         }
-        Timer::after(Duration::from_millis(500)).await;
+    });
+
+    if let Some(view) = view.as_mut() {
+        view.boot_logo().await;
     }
 
-    println!("Waiting to get IP address...");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}", config.address);
-            break;
+    // Never blocks the clock for good, the network loops catch up when the
+    // station comes back
+    let outcome = bringup::run(stack, &mut net_events, ntp::settings().primary(), |label| {
+        if let Some(view) = view.as_mut() {
+            view.message(label);
         }
-        Timer::after(Duration::from_millis(500)).await;
+    })
+    .await;
+    log!("Network bring-up: {outcome:?}");
+    if let Some(config) = stack.config_v4() {
+        log!("Got IP: {}", config.address);
+    }
+    if MORSE_STATUS {
+        let text = match (outcome, stack.config_v4()) {
+            (bringup::Outcome::Online(_), Some(config)) => {
+                alloc::format!("OK {}", config.address.address().octets()[3])
+            }
+            _ => "ERR".into(),
+        };
+        _ = morse::send(&text, false);
+    }
+    if let Some(view) = view.as_mut() {
+        view.message(outcome.label());
     }
-
-    let ha_res = access_website(stack.clone()).await;
 
     let state = State {
         rtc,
-        temperature: ha_res.temperature,
-        light_level: LigthLevel::Bright,
+        temperature: Cell::new(None),
+        sync: RefCell::new(ntp::SyncTracker::new(NTP_DESYNC_ALERT)),
+    };
+
+    // Display initial Rtc time before synchronization
+    let now = jiff::Timestamp::from_microsecond(state.rtc.current_time_us() as i64)
+        .unwrap()
+        .to_zoned(timezone());
+    log!("Rtc: {}", now.strftime("%H%M"));
+
+    // Refresh from the RTC independently of the sync, which can take seconds
+    let display = async {
+        let Some(view) = view.as_mut() else {
+            return;
+        };
+
+        loop {
+            if menu::is_open() {
+                view.menu(storage).await;
+            }
+            if morse::is_sending() {
+                view.morse(&state).await;
+            }
+            match face::current() {
+                Face::Clock => {}
+                #[cfg(feature = "microphone")]
+                Face::VuMeter => view.vu_meter().await,
+                #[cfg(not(feature = "microphone"))]
+                Face::VuMeter => {}
+                Face::Score => view.score(storage).await,
+                Face::Metronome => view.metronome(&state.rtc).await,
+                Face::Words => view.words(&state.rtc).await,
+                Face::Diagnostics => view.diagnostics(&state.rtc).await,
+                face => {
+                    if let Some(clock_face) = face.clock_face() {
+                        view.clock_face(face, clock_face, &state.rtc).await;
+                    }
+                }
+            }
+
+            if view.request.is_none() {
+                let start = Instant::now();
+                view.view(&state).await;
+                watchdog::beat(Task::Display);
+                if start.elapsed() > FRAME_PERIOD {
+                    log!("Frame took {}ms", start.elapsed().as_millis());
+                }
+
+                // Frames start on show time boundaries, shared by every clock
+                let next_frame = Timer::after(showsync::until_next_period(
+                    state.rtc.current_time_us(),
+                    FRAME_PERIOD,
+                ));
+                view.wait(next_frame).await;
+            }
+
+            // An uploaded animation or a game interrupts the faces until it ends
+            match view.request.take() {
+                None => {}
+                Some(Request::Animation(data)) => match Animation::parse(&data) {
+                    Ok(anim) => {
+                        showsync::next_second(&state.rtc).await;
+                        view.play(&anim).await
+                    }
+                    Err(e) => log!("Invalid animation: {e:?}"),
+                },
+                Some(Request::Snake) => view.snake(storage).await,
+                Some(Request::Notification(text)) => view.scroll(&text, ALERT_SCROLLS).await,
+                Some(Request::Identify) => view.identify(&device::name()).await,
+                Some(Request::Show(text, duration)) => view.scroll_for(&text, duration).await,
+                Some(Request::Menu) => view.menu(storage).await,
+            }
+        }
     };
 
-    let ntp_addrs = stack.dns_query(NTP_SERVER, DnsQueryType::A).await.unwrap();
+    // Alarms, schedules, theme changes and the chime, whatever face is shown
+    // and even without a display
+    let schedule = async {
+        let mut theme_idx = None;
+        let mut last_minute = None;
+        loop {
+            let now_us = showsync::show_time_us(state.rtc.current_time_us());
+            let time = jiff::Timestamp::from_microsecond(now_us as i64)
+                .unwrap()
+                .to_zoned(timezone());
+            let weekday = time.weekday();
+            let minute_of_day = time.hour() as u16 * 60 + time.minute() as u16;
+
+            dnd::tick(minute_of_day);
+            powersave::tick();
+            wake::tick(weekday, minute_of_day);
+            alarms::tick(weekday, minute_of_day);
+            automations::tick(weekday, minute_of_day);
+            let sunrise = wake::sunrise(weekday, minute_of_day, time.second() as u8);
+            brightness::set_sunrise(
+                sunrise.map(|progress| (progress * theme::MAX_BRIGHTNESS as f32) as u8),
+            );
+            brightness::set_vacant(presence::is_vacant());
+
+            let (idx, theme) = theme::active(weekday, minute_of_day);
+            if theme_idx != Some(idx) {
+                log!("Theme {idx}");
+                // A face saved through the API wins over the theme at boot
+                if theme_idx.is_some() || face::settings().face.is_none() {
+                    face::set(theme.face);
+                }
+                theme_idx = Some(idx);
+                brightness::set_base(theme.brightness);
+            }
 
-    if ntp_addrs.is_empty() {
-        panic!("Failed to resolve DNS. Empty result");
-    }
+            let minute_changed = last_minute.is_some_and(|minute| minute != time.minute());
+            last_minute = Some(time.minute());
+            if minute_changed
+                && time.minute() == 0
+                && dnd::allows(dnd::Kind::Chime)
+                && !countdown::state().is_active()
+            {
+                if let Some(notes) = melody::chime() {
+                    buzzer::play_melody(notes);
+                }
+            }
 
-    let mut socket = UdpSocket::new(
-        stack,
-        &mut rx_meta,
-        &mut rx_buffer,
-        &mut tx_meta,
-        &mut tx_buffer,
-    );
+            showsync::next_second(&state.rtc).await;
+        }
+    };
 
-    socket.bind(123).unwrap();
+    let sync = async {
+        let mut was_desynced = false;
+        loop {
+            if stack.is_config_up() {
+                match ntp::sync_any(stack, &state.rtc).await {
+                    Ok(_) => {
+                        state.sync.borrow_mut().synced();
+                        stats::count(Counter::NtpSync);
+                        if startup::state(Stage::Time) == startup::State::Running {
+                            startup::done(Stage::Time);
+                        }
+                    }
+                    Err(e) => {
+                        log!("Error getting time: {e:?}");
+                        watchdog::error(Task::Ntp, &alloc::format!("{e:?}"));
+                    }
+                }
+            } else {
+                log!("No IP, NTP sync skipped");
+            }
+            watchdog::beat(Task::Ntp);
 
-    // Display initial Rtc time before synchronization
-    let now = jiff::Timestamp::from_microsecond(state.rtc.current_time_us() as i64)
-        .unwrap()
-        .to_zoned(TIMEZONE);
-    esp_println::println!("Rtc: {}", now.strftime("%H%M"));
+            let desynced = state.sync.borrow().is_desynced();
+            if desynced && !was_desynced {
+                webhooks::fire(webhooks::Event::NtpDesync);
+            }
+            was_desynced = desynced;
+
+            if NTP_DESYNC_CHIRP
+                && dnd::allows(dnd::Kind::Chime)
+                && state
+                    .sync
+                    .borrow_mut()
+                    .alert_due(Duration::from_secs(3600))
+            {
+                log!("No NTP sync since {}s", NTP_DESYNC_ALERT.as_secs());
+                buzzer::play(buzzer::CHIRP);
+            }
 
-    loop {
-        let addr: IpAddr = ntp_addrs[0].into();
-        let result = get_time(
-            SocketAddr::from((addr, 123)),
-            &socket,
-            NtpContext::new(Timestamp {
-                rtc: &state.rtc,
-                current_time_us: 0,
-            }),
-        )
-        .await;
-
-        match result {
-            Ok(time) => {
-                // Set time immediately after receiving to reduce time offset.
-                state.rtc.set_current_time_us(
-                    (time.sec() as u64 * USEC_IN_SEC)
-                        + ((time.sec_fraction() as u64 * USEC_IN_SEC) >> 32),
-                );
+            // Sync again as soon as the IP is back
+            let got_ip =
+                async { while net_events.next_message_pure().await != NetEvent::GotIp {} };
+            select3(Timer::after(NTP_POLL), got_ip, ntp::leap(&state.rtc)).await;
+        }
+    };
 
-                view.view(&state).await;
+    let show_sync = async {
+        if SHOW_SYNC {
+            showsync::run(stack, &state.rtc).await;
+        }
+    };
+
+    // Weather from Home Assistant, or else at the location, for the face and
+    // the daily statistics
+    let weather = async {
+        loop {
+            // Not worth the power while on battery
+            if power::is_on_battery() || !capabilities::is_enabled(Capability::Weather) {
+                watchdog::beat(Task::Weather);
+                Timer::after(WEATHER_PERIOD).await;
+                continue;
             }
-            Err(e) => {
-                esp_println::println!("Error getting time: {e:?}");
+            // Nothing to read offline, read at once when the address is back
+            if !stack.is_config_up() {
+                watchdog::beat(Task::Weather);
+                let back = wifimanager::wait_ip(stack, &mut weather_events);
+                select(back, Timer::after(WEATHER_PERIOD)).await;
+                continue;
             }
+            // Also the sun times, asked for even with Home Assistant
+            location::lookup(stack).await;
+            let local = match location::location() {
+                Some(location) => open_meteo(stack, location).await,
+                None => None,
+            };
+            if let Some(weather) = access_website(stack).await.or(local) {
+                state.temperature.set(Some(weather.temperature));
+                alerts::evaluate(Sensor::Temperature, weather.temperature);
+                alerts::evaluate(Sensor::Humidity, weather.humidity as f32);
+
+                // Dated statistics need the time from NTP
+                let synced = state.sync.borrow().last_sync().is_some();
+                if synced {
+                    let date = jiff::Timestamp::from_microsecond(state.rtc.current_time_us() as i64)
+                        .unwrap()
+                        .to_zoned(timezone())
+                        .date();
+                    let humidity = weather.humidity.min(100) as u8;
+                    climate::record(storage, date, weather.temperature, humidity).await;
+                }
+            } else {
+                watchdog::error(Task::Weather, "no reading");
+            }
+            watchdog::beat(Task::Weather);
+            Timer::after(WEATHER_PERIOD).await;
         }
+    };
 
-        Timer::after(Duration::from_secs(60)).await;
-    }
+    // Scheduled reboot, on the time from NTP only
+    let maintenance = async {
+        loop {
+            // Reboots are set to the minute
+            showsync::next_minute(&state.rtc).await;
+            watchdog::beat(Task::Maintenance);
+            stats::save_if_due(storage).await;
+            if state.sync.borrow().last_sync().is_none() {
+                continue;
+            }
+            let now = jiff::Timestamp::from_microsecond(state.rtc.current_time_us() as i64)
+                .unwrap()
+                .to_zoned(timezone());
+            let minute = now.hour() as u16 * 60 + now.minute() as u16;
+            maintenance::reboot_if_due(storage, now.weekday(), minute).await;
+        }
+    };
+
+    join5(
+        display,
+        sync,
+        show_sync,
+        weather,
+        join(maintenance, schedule),
+    )
+    .await;
+}
+
+struct Widgets {
+    time: Widget,
+    temperature: Widget,
+    energy: Widget,
+    satellite: Widget,
+    battery: Widget,
+    desync: Widget,
+    dnd: Widget,
+    sunrise: Widget,
+    signal: Widget,
+    draw: Widget,
 }
 
 struct View<'a> {
     buf: [u8; 20],
+    /// Widgets output, kept between frames so deferred widgets stay visible
+    layers: Compositor<32, 16>,
+    /// `layers` composited then burn-in shifted, sent to the screen
     canvas: Canvas<32, 16>,
-    spi: &'a mut Spi<'static, Blocking>,
+    display: &'a mut Display,
+    widgets: Widgets,
+    last_minute: Option<i8>,
+    /// Taken by a face from the display loop, played once it returns
+    request: Option<Request>,
+    /// Level sent to the display
+    brightness: Option<u8>,
+    /// Dimmed areas of the current face
+    zones: &'static [Zone],
 }
 
 impl<'a> View<'a> {
+    async fn play(&mut self, anim: &Animation<'_>) {
+        animation::run(self.display, &mut self.canvas, anim).await;
+        // Widgets are only redrawn over their own area
+        self.layers.clear();
+    }
+
+    async fn snake(&mut self, storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+        let seed = esp_hal::rng::Rng::new().random();
+        let mut game = Snake::<32, 16>::new(seed);
+        let mut ticker = Ticker::every(SNAKE_STEP);
+
+        while game.alive {
+            match select(ticker.next(), snake::input()).await {
+                Either::First(_) => {
+                    game.step();
+                    game.draw(&mut self.canvas);
+                    self.display.draw_async(&self.canvas).await;
+                }
+                Either::Second(snake::Input::Quit) => break,
+                Either::Second(snake::Input::Start) => game = Snake::new(esp_hal::rng::Rng::new().random()),
+                Either::Second(input) => game.input(input),
+            }
+        }
+
+        let high_score = snake::load_high_score(storage).await;
+        if game.score > high_score {
+            snake::save_high_score(storage, game.score).await;
+        }
+        log!("Snake score {} (best {})", game.score, high_score.max(game.score));
+
+        self.message(&alloc::format!("{}/{}", game.score, high_score.max(game.score)));
+        Timer::after(Duration::from_secs(3)).await;
+        self.layers.clear();
+    }
+
+    /// Until the face is switched back to the clock
+    #[cfg(feature = "microphone")]
+    async fn vu_meter(&mut self) {
+        use b_intime_5::vumeter;
+
+        let mut ticker = Ticker::every(VU_METER_PERIOD);
+        while face::current() == Face::VuMeter && self.request.is_none() {
+            vumeter::draw(&mut self.canvas, &vumeter::levels());
+            self.display.draw_async(&self.canvas).await;
+            self.wait(ticker.next()).await;
+        }
+        self.layers.clear();
+    }
+
+    /// Until another face is selected, saving each score change
+    async fn score(&mut self, storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+        let mut shown = None;
+        let mut ticker = Ticker::every(SCORE_PERIOD);
+        while face::current() == Face::Score && self.request.is_none() {
+            let points = score::score();
+            if shown != Some(points) {
+                score::draw(&mut self.canvas, points);
+                self.display.draw_async(&self.canvas).await;
+                if shown.is_some() {
+                    score::save(storage, points).await;
+                }
+                shown = Some(points);
+            }
+            self.wait(ticker.next()).await;
+        }
+        self.layers.clear();
+    }
+
+    /// Until another face is selected
+    async fn clock_face(&mut self, face: Face, clock_face: &dyn ClockFace<32, 16>, rtc: &Rtc<'_>) {
+        self.set_zones(clock_face.zones());
+        while face::current() == face && self.request.is_none() {
+            self.apply_brightness();
+            let now_us = showsync::show_time_us(rtc.current_time_us());
+            let time = jiff::Timestamp::from_microsecond(now_us as i64)
+                .unwrap()
+                .to_zoned(timezone());
+
+            clock_face.draw(&mut self.canvas, time.datetime());
+            self.display.draw_async(&self.canvas).await;
+            let next = async {
+                match clock_face.granularity() {
+                    Granularity::Second => showsync::next_second(rtc).await,
+                    Granularity::Minute => showsync::next_minute(rtc).await,
+                    Granularity::OnDemand => core::future::pending().await,
+                }
+            };
+            self.wait(select(next, face::selected())).await;
+        }
+        self.set_zones(&[]);
+        self.layers.clear();
+    }
+
+    /// Frames with the Morse pixel over them, until the transmission ends
+    async fn morse(&mut self, state: &State) {
+        let mut frame = Instant::now();
+        while let Some(lit) = morse::lit() {
+            if frame.elapsed() >= FRAME_PERIOD {
+                self.view(state).await;
+                frame = Instant::now();
+            }
+            let mut canvas = self.canvas;
+            if lit {
+                canvas.on(MORSE_PIXEL.0, MORSE_PIXEL.1);
+            } else {
+                canvas.off(MORSE_PIXEL.0, MORSE_PIXEL.1);
+            }
+            self.display.draw_async(&canvas).await;
+            morse::next_unit().await;
+        }
+        self.layers.clear();
+    }
+
+    /// Until the menu closes, then save its values
+    async fn menu(&mut self, storage: &'static Mutex<CriticalSectionRawMutex, Nvs>) {
+        while menu::is_open() {
+            let Some((item, wifi_reset)) = menu::current() else {
+                break;
+            };
+            let prefs = prefs::get();
+            let value = match item {
+                menu::Item::Brightness => prefs
+                    .brightness
+                    .map_or("AUTO".into(), |level| alloc::format!("{level}")),
+                menu::Item::HourFormat => if prefs.hour12 { "12" } else { "24" }.into(),
+                menu::Item::UtcOffset => prefs
+                    .utc_offset
+                    .map_or("AUTO".into(), |hours| alloc::format!("{hours:+}")),
+                menu::Item::Face => {
+                    let mut name = alloc::format!("{:?}", face::current()).to_uppercase();
+                    name.truncate(5);
+                    name
+                }
+                menu::Item::WifiReset => if wifi_reset { "RESET" } else { "KEEP" }.into(),
+            };
+
+            self.canvas.clear();
+            self.canvas.print_5x7(0, 0, item.label());
+            self.canvas.print_5x7(0, 8, &value);
+            self.display.draw_async(&self.canvas).await;
+            Timer::after(MENU_FRAME).await;
+        }
+
+        prefs::save(storage).await;
+        if menu::take_wifi_reset() {
+            log!("Wifi settings reset from the menu");
+            if let Err(e) = wifimanager::forget_wifi(storage).await {
+                log!("Wifi settings not reset: {e:?}");
+                return;
+            }
+            Timer::after(Duration::from_millis(100)).await;
+            esp_hal::system::software_reset();
+        }
+        self.layers.clear();
+    }
+
+    /// UTC time, Unix time, the offset and DST state of the timezone, then the
+    /// local date, in turn until another face is selected
+    async fn diagnostics(&mut self, rtc: &Rtc<'_>) {
+        while face::current() == Face::Diagnostics && self.request.is_none() {
+            // The RTC time, before the show sync correction
+            let timestamp =
+                jiff::Timestamp::from_microsecond(rtc.current_time_us() as i64).unwrap();
+            let seconds = timestamp.as_second();
+            self.canvas.clear();
+            match seconds / DIAGNOSTICS_PAGE_SECS % 4 {
+                0 => {
+                    let utc = timestamp.to_zoned(jiff::tz::TimeZone::UTC);
+                    let time =
+                        alloc::format!("{:02}:{:02}:{:02}", utc.hour(), utc.minute(), utc.second());
+                    self.canvas.print_5x7(0, 0, "UTC");
+                    self.canvas.print_4x6(0, 9, &time);
+                }
+                1 => {
+                    let epoch = alloc::format!("{seconds:010}");
+                    self.canvas.print_4x6(6, 1, &epoch[..5]);
+                    self.canvas.print_4x6(6, 9, &epoch[5..]);
+                }
+                2 => {
+                    let timezone = timezone();
+                    let info = timezone.to_offset_info(timestamp);
+                    let offset = info.offset().seconds();
+                    let sign = if offset < 0 { '-' } else { '+' };
+                    let offset = offset.unsigned_abs();
+                    let offset =
+                        alloc::format!("{sign}{:02}{:02}", offset / 3600, offset % 3600 / 60);
+                    self.canvas.print_5x7(0, 0, &offset);
+                    self.canvas
+                        .print_5x7(0, 8, if info.dst().is_dst() { "DST" } else { "STD" });
+                }
+                _ => {
+                    let date = timestamp.to_zoned(timezone()).date();
+                    self.canvas.print_5x7(0, 0, &alloc::format!("{}", date.year()));
+                    self.canvas.print_5x7(0, 8, &units::short_date(date));
+                }
+            }
+            self.display.draw_async(&self.canvas).await;
+            self.wait(showsync::next_second(rtc)).await;
+        }
+        self.layers.clear();
+    }
+
+    /// Until another face is selected
+    async fn words(&mut self, rtc: &Rtc<'_>) {
+        let mut scroll = 0;
+        while face::current() == Face::Words && self.request.is_none() {
+            let now_us = showsync::show_time_us(rtc.current_time_us());
+            let time = jiff::Timestamp::from_microsecond(now_us as i64)
+                .unwrap()
+                .to_zoned(timezone());
+            let phrase = wordclock::phrase(LANGUAGE, time.hour() as u8, time.minute() as u8);
+
+            wordclock::draw(&mut self.canvas, &phrase, scroll);
+            self.display.draw_async(&self.canvas).await;
+
+            if wordclock::scrolls::<32>(&phrase) {
+                scroll += 1;
+                self.wait(Timer::after(TEXT_SCROLL_STEP)).await;
+            } else {
+                scroll = 0;
+                self.wait(showsync::next_second(rtc)).await;
+            }
+        }
+        self.layers.clear();
+    }
+
+    /// Until another face is selected
+    async fn metronome(&mut self, rtc: &Rtc<'_>) {
+        let mut last_beat = None;
+        while face::current() == Face::Metronome && self.request.is_none() {
+            let bpm = metronome::bpm();
+            let now_us = showsync::show_time_us(rtc.current_time_us());
+            let beat = metronome::beat(now_us, bpm);
+            if last_beat.is_some_and(|last| last != beat) {
+                metronome::tick(beat);
+            }
+            last_beat = Some(beat);
+
+            metronome::draw(&mut self.canvas, now_us, bpm);
+            self.display.draw_async(&self.canvas).await;
+
+            // Wake up on the beat even between frames
+            let next_beat =
+                showsync::until_next_period(rtc.current_time_us(), metronome::beat_period(bpm));
+            let next_frame = Timer::after(next_beat.min(METRONOME_FRAME));
+            self.wait(next_frame).await;
+        }
+        self.layers.clear();
+    }
+
+    /// Uploaded animation when stored on the SD card, built-in logo otherwise
+    async fn boot_logo(&mut self) {
+        #[cfg(feature = "sdcard")]
+        {
+            use animation::{ANIMATION_FILE, MAX_FILE_LEN};
+            if let Ok(data) = b_intime_5::sdcard::load(ANIMATION_FILE, MAX_FILE_LEN).await {
+                if let Ok(anim) = Animation::parse(&data) {
+                    self.play(&anim).await;
+                    return;
+                }
+            }
+        }
+
+        let anim = Animation::parse(&animation::BOOT_LOGO).expect("boot logo");
+        self.play(&anim).await;
+    }
+
+    /// Kitchen timer over the clock, blinking once it rings
+    fn countdown(&mut self) {
+        let state = countdown::state();
+        self.layers.set_visible(LayerId::Notification, state.is_active());
+
+        let notification = &mut self.layers.layer_mut(LayerId::Notification).canvas;
+        notification.clear();
+        match state {
+            countdown::State::Idle => {}
+            countdown::State::Running(seconds) => {
+                notification.print_5x7(1, 4, &countdown::format(seconds));
+            }
+            countdown::State::Ringing(since) => {
+                notification.print_5x7(1, 4, &countdown::format(0));
+                // A dot per snooze left, under the digits
+                for snooze in 0..countdown::snoozes_left() as usize {
+                    notification.on(1 + snooze * 3, 13);
+                    notification.on(2 + snooze * 3, 13);
+                }
+                if since.as_secs() % 2 == 1 {
+                    notification.invert();
+                }
+            }
+        }
+    }
+
+    /// Follow the brightness controller
+    fn apply_brightness(&mut self) {
+        let level = brightness::level();
+        if self.brightness != Some(level) {
+            if self.zones.is_empty() {
+                self.display.set_brightness(level);
+            } else {
+                self.display.set_brightness_zones::<32, 16>(level, self.zones);
+            }
+            self.brightness = Some(level);
+        }
+    }
+
+    /// Dim `zones` of the canvas until set again
+    fn set_zones(&mut self, zones: &'static [Zone]) {
+        if self.zones != zones {
+            self.zones = zones;
+            self.brightness = None;
+            self.apply_brightness();
+        }
+    }
+
+    /// Scroll `text` across the matrix `times` times, brighter when dimmed
+    async fn scroll(&mut self, text: &str, times: usize) {
+        brightness::boost(NOTIFICATION_BRIGHTNESS);
+        self.apply_brightness();
+
+        let width = ALPHABET_NORMAL.text_width(text) as i32;
+        for _ in 0..times {
+            let mut x = 32;
+            while x > -width {
+                self.canvas.clear();
+                self.canvas.print_5x7_at(x, 4, text);
+                self.display.draw_async(&self.canvas).await;
+                x -= 1;
+                Timer::after(TEXT_SCROLL_STEP).await;
+            }
+        }
+        self.layers.clear();
+
+        brightness::end_boost();
+        self.apply_brightness();
+    }
+
+    /// Scroll `text` until `duration` passes, at least once
+    async fn scroll_for(&mut self, text: &str, duration: Duration) {
+        let end = Instant::now() + duration;
+        loop {
+            self.scroll(text, 1).await;
+            if Instant::now() >= end {
+                break;
+            }
+        }
+    }
+
+    /// Flash the whole matrix, then scroll `name` until `IDENTIFY_DURATION` passes
+    async fn identify(&mut self, name: &str) {
+        log!("Identifying as {name}");
+        brightness::boost(NOTIFICATION_BRIGHTNESS);
+        self.apply_brightness();
+        let end = Instant::now() + IDENTIFY_DURATION;
+
+        for _ in 0..IDENTIFY_FLASHES {
+            self.canvas.clear();
+            self.canvas.invert();
+            self.display.draw_async(&self.canvas).await;
+            Timer::after(IDENTIFY_FLASH).await;
+            self.canvas.clear();
+            self.display.draw_async(&self.canvas).await;
+            Timer::after(IDENTIFY_FLASH).await;
+        }
+
+        let width = ALPHABET_NORMAL.text_width(name) as i32;
+        let mut x = 32;
+        while Instant::now() < end {
+            self.canvas.clear();
+            self.canvas.print_5x7_at(x, 4, name);
+            self.display.draw_async(&self.canvas).await;
+            x = if x > -width { x - 1 } else { 32 };
+            Timer::after(TEXT_SCROLL_STEP).await;
+        }
+        self.layers.clear();
+
+        brightness::end_boost();
+        self.apply_brightness();
+    }
+
+    /// Wait for `until`, or take a request for the display loop
+    async fn wait(&mut self, until: impl Future) {
+        if let Either::Second(request) = select(until, request()).await {
+            self.request = Some(request);
+        }
+    }
+
+    fn message(&mut self, text: &str) {
+        self.layers.clear();
+        self.canvas.clear();
+        self.canvas.print_5x7(1, 4, text);
+        self.display.draw(&self.canvas);
+    }
+
     async fn view(&mut self, state: &State) {
-        let time = jiff::Timestamp::from_microsecond(state.rtc.current_time_us() as i64)
+        let render = profiler::start();
+        let now_us = showsync::show_time_us(state.rtc.current_time_us());
+        let time = jiff::Timestamp::from_microsecond(now_us as i64)
             .unwrap()
-            .to_zoned(TIMEZONE);
+            .to_zoned(timezone());
+
+        // Switched and applied by the schedule, only drawn here
+        let minute_of_day = time.hour() as u16 * 60 + time.minute() as u16;
+        let (_, theme) = theme::active(time.weekday(), minute_of_day);
+        let sunrise = wake::sunrise(time.weekday(), minute_of_day, time.second() as u8);
+        self.apply_brightness();
+
+        let [face, overlay, _] = self.layers.layers_mut();
+        let face = &mut face.canvas;
+        let overlay = &mut overlay.canvas;
+        let buf = &mut self.buf;
+
+        self.widgets.time.render(|| {
+            face.clear_area(0, 0, 32, 8);
+            let mut buf = Wrapper::new(buf);
+            let format = if prefs::get().hour12 { "%I%M" } else { "%H%M" };
+            write!(buf, "{}", time.strftime(format)).expect("Can't write");
+            let text = unsafe { from_utf8_unchecked(buf.as_bytes()) };
+            let (hours, minutes) = text.split_at(2);
+
+            type Print = fn(&mut Canvas<32, 16>, usize, usize, &str);
+            let (x, colon, hours_width, print): (usize, usize, usize, Print) = match theme.font {
+                TimeFont::Big => (
+                    0,
+                    ALPHABET_BIG_DIGITS.width_of(':') as usize,
+                    ALPHABET_BIG_DIGITS.text_width(hours),
+                    Canvas::print_8x8,
+                ),
+                TimeFont::Normal => (
+                    2,
+                    ALPHABET_NORMAL.width_of(':') as usize,
+                    ALPHABET_NORMAL.text_width(hours),
+                    Canvas::print_5x7,
+                ),
+            };
+            // Centered as with a colon
+            let separator = face::separator();
+            let x = x + (colon - separator.width(colon)) / 2;
+            let separator_x = x + hours_width;
+            print(face, x, 0, hours);
+            match separator {
+                Separator::Colon => print(face, separator_x, 0, ":"),
+                Separator::Blink if time.second() % 2 == 0 => print(face, separator_x, 0, ":"),
+                Separator::Dot => face.on(separator_x, TIME_MID_ROW),
+                Separator::Blink | Separator::None => {}
+            }
+            print(face, separator_x + separator.width(colon), 0, minutes);
+        });
+
+        // Power, the satellite temperature and the battery charge in turn with
+        // the temperature, once they are received
+        let power = energy::reading();
+        let indoor = satellite::latest();
+        let charge = battery::status().percent;
+        let turns = 1 + power.is_some() as i8 + indoor.is_some() as i8 + charge.is_some() as i8;
+        let turn = time.second() / ENERGY_TURN_SECS % turns;
+        let indoor_turn = 1 + power.is_some() as i8;
+        let battery_turn = indoor_turn + indoor.is_some() as i8;
+        if let Some(reading) = power.filter(|_| turn == 1) {
+            self.widgets.energy.render(|| {
+                face.clear_area(0, 8, 30, 8);
+                energy::draw(face, 9, reading);
+            });
+        } else if let Some(indoor) = indoor.filter(|_| turn == indoor_turn) {
+            self.widgets.satellite.render(|| {
+                face.clear_area(0, 8, 30, 8);
+                satellite::draw(face, 9, &indoor);
+            });
+        } else if let Some(percent) = charge.filter(|_| turn == battery_turn) {
+            self.widgets.battery.render(|| {
+                face.clear_area(0, 8, 30, 8);
+                battery::draw(face, 9, percent);
+            });
+        } else {
+            self.widgets.temperature.render(|| {
+                face.clear_area(0, 8, 30, 8);
+                face.print_5x7(2, 9, &units::temperature(state.temperature.get(), 1));
+            });
+        }
 
-        let mut buf = Wrapper::new(&mut self.buf);
-        write!(buf, "{}", time.strftime("%H:%M")).expect("Can't write");
-        self.canvas
-            .print_8x8(0, 0, unsafe { from_utf8_unchecked(&buf.as_bytes()) });
+        self.widgets.desync.render(|| {
+            overlay.clear_area(30, 8, 2, 8);
+            let sync = state.sync.borrow();
+            if sync.is_desynced() {
+                // "!" in the bottom right corner
+                for y in 9..13 {
+                    overlay.on(30, y);
+                }
+                overlay.on(30, 14);
+            }
+            if let Some(last_sync) = sync.last_sync().filter(|_| SYNC_PIXEL) {
+                let fresh = last_sync.elapsed() < NTP_POLL * 2;
+                overlay.set_pixel(31, 15, fresh || time.second() % 2 == 0);
+            }
+            if STATUS_PIXEL {
+                overlay.set_pixel(30, 15, statusled::is_lit());
+            }
+        });
+
+        self.widgets.dnd.render(|| {
+            overlay.clear_area(0, 8, 2, 8);
+            if dnd::is_active() {
+                // Crescent moon in the bottom left corner
+                for (x, y) in [(1, 10), (0, 11), (0, 12), (1, 13)] {
+                    overlay.on(x, y);
+                }
+            }
+        });
+
+        self.widgets.sunrise.render(|| {
+            // Horizon line growing from the middle, between the corner widgets
+            overlay.clear_area(SUNRISE_LEFT, 15, SUNRISE_RIGHT - SUNRISE_LEFT, 1);
+            if let Some(progress) = sunrise {
+                let half = ((SUNRISE_RIGHT - SUNRISE_LEFT) as f32 / 2.0 * progress) as usize;
+                let middle = (SUNRISE_LEFT + SUNRISE_RIGHT) / 2;
+                for x in middle - half..middle + half {
+                    overlay.on(x, 15);
+                }
+            }
+        });
+
+        self.widgets.signal.render(|| {
+            // Under the moon of do not disturb, pixels lit from the first bar
+            overlay.clear_area(0, 14, 2, 2);
+            let bars = wifimanager::link_quality().map(|link| link.bars);
+            match bars.filter(|_| SIGNAL_ICON) {
+                Some(0) => overlay.set_pixel(0, 15, time.second() % 2 == 0),
+                Some(bars) => {
+                    for (x, y) in [(0, 15), (1, 15), (1, 14)].into_iter().take(bars as usize) {
+                        overlay.on(x, y);
+                    }
+                }
+                None => {}
+            }
+        });
 
-        let mut buf = Wrapper::new(&mut self.buf);
-        write!(buf, "{:.1}&", state.temperature).expect("Can't write");
-        self.canvas
-            .print_5x7(2, 9, unsafe { from_utf8_unchecked(&buf.as_bytes()) });
+        self.countdown();
 
-        Screen::<8>::draw(&mut self.spi, &self.canvas);
+        self.layers.compose(&mut self.canvas);
+        let burn_in = burnin::settings();
+        let (dx, dy) = burn_in.offset(time.timestamp().as_second() / 60);
+        self.canvas.shift(dx, dy);
+        let inverted = burn_in.is_inverted(time.hour(), time.minute());
+        if inverted {
+            self.canvas.invert();
+        }
+        profiler::record(profiler::Stage::Render, render);
+
+        let minute_changed = self.last_minute.is_some_and(|minute| minute != time.minute());
+        self.last_minute = Some(time.minute());
+        if theme.transition == Transition::FallingBlocks
+            && minute_changed
+            && !inverted
+            && !countdown::state().is_active()
+        {
+            // Digits rows, one more for the burn-in shift
+            let data = transition::falling_blocks(&self.canvas, 0..9);
+            if let Ok(anim) = Animation::parse(&data) {
+                let mut scratch = self.canvas;
+                animation::run(self.display, &mut scratch, &anim).await;
+            }
+        }
 
-        esp_println::println!("UPDATE");
+        let (display, canvas) = (&mut *self.display, &self.canvas);
+        self.widgets.draw.render_async(display.draw_async(canvas)).await;
     }
 }
 
-#[derive(Deserialize, Clone)]
-struct HAResponse<'a> {
-    state: &'a str,
+#[derive(Deserialize)]
+struct HAResponse {
     attributes: HAAttributes,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize)]
 struct HAAttributes {
     temperature: f32,
     humidity: usize,
-    wind_speed: f32,
 }
 
-async fn access_website(stack: Stack<'_>) -> HAAttributes {
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-    let dns = DnsSocket::new(stack);
-    let tcp_state = TcpClientState::<1, 4096, 4096>::new();
+#[derive(Deserialize)]
+struct OpenMeteoResponse {
+    current: OpenMeteoCurrent,
+    daily: OpenMeteoDaily,
+}
+
+#[derive(Deserialize)]
+struct OpenMeteoCurrent {
+    temperature_2m: f32,
+    relative_humidity_2m: usize,
+}
+
+/// Unix times of the one day asked for
+#[derive(Deserialize)]
+struct OpenMeteoDaily {
+    sunrise: [i64; 1],
+    sunset: [i64; 1],
+}
+
+/// Weather and sun times at `location`, free without a key
+async fn open_meteo(stack: Stack<'_>, location: Location) -> Option<HAAttributes> {
+    let _lease = sockets::lease(sockets::Use::HttpClient);
+    let dns = CachedDns::new(stack);
+    let tcp_state = TcpClientState::<1, HTTP_CLIENT_BUFFER, HTTP_CLIENT_BUFFER>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+
+    let url = alloc::format!(
+        "http://api.open-meteo.com/v1/forecast?latitude={:.2}&longitude={:.2}\
+         &current=temperature_2m,relative_humidity_2m\
+         &daily=sunrise,sunset&timeformat=unixtime&forecast_days=1",
+        location.latitude, location.longitude
+    );
+    let mut client = HttpClient::new(&tcp, &dns);
+    let mut buffer = [0u8; HTTP_CLIENT_BUFFER];
+    let body = async {
+        let mut request = client
+            .request(reqwless::request::Method::GET, &url)
+            .await
+            .ok()?;
+        let response = request.send(&mut buffer).await.ok()?;
+        response.body().read_to_end().await.ok()
+    };
+    let Some(body) = body.await else {
+        log!("Open-Meteo request error");
+        return None;
+    };
+
+    let data = match serde_json_core::from_slice::<OpenMeteoResponse>(body) {
+        Ok((data, _remainder)) => data,
+        Err(e) => {
+            log!("Open-Meteo response error: {e:?}");
+            return None;
+        }
+    };
+    let local = |time: i64| {
+        jiff::Timestamp::from_second(time)
+            .ok()
+            .map(|time| time.to_zoned(timezone()).datetime())
+    };
+    let (sunrise, sunset) = (local(data.daily.sunrise[0]), local(data.daily.sunset[0]));
+    if let (Some(sunrise), Some(sunset)) = (sunrise, sunset) {
+        sun::set(SunTimes {
+            date: sunrise.date(),
+            sunrise: sunrise.time(),
+            sunset: sunset.time(),
+        });
+    }
+    Some(HAAttributes {
+        temperature: data.current.temperature_2m,
+        humidity: data.current.relative_humidity_2m,
+    })
+}
+
+async fn access_website(stack: Stack<'_>) -> Option<HAAttributes> {
+    let _lease = sockets::lease(sockets::Use::HttpClient);
+    let dns = CachedDns::new(stack);
+    let tcp_state = TcpClientState::<1, HTTP_CLIENT_BUFFER, HTTP_CLIENT_BUFFER>::new();
     let tcp = TcpClient::new(stack, &tcp_state);
 
     let headers = [(
@@ -361,22 +2081,45 @@ async fn access_website(stack: Stack<'_>) -> HAAttributes {
     )];
 
     let mut client = HttpClient::new(&tcp, &dns);
-    let mut buffer = [0u8; 4096];
-    let mut http_req = client
+    let mut buffer = [0u8; HTTP_CLIENT_BUFFER];
+    let mut http_req = match client
         .request(
             reqwless::request::Method::GET,
             env!("HA_URI", "no home assistant uri provided"),
         )
         .await
-        .unwrap()
-        .headers(&headers);
-    let response = http_req.send(&mut buffer).await.unwrap();
-
-    esp_println::println!("Got response");
-    let res = response.body().read_to_end().await.unwrap();
+    {
+        Ok(req) => req.headers(&headers),
+        Err(e) => {
+            log!("Home Assistant connection error: {e:?}");
+            return None;
+        }
+    };
+    let response = match http_req.send(&mut buffer).await {
+        Ok(response) => response,
+        Err(e) => {
+            log!("Home Assistant request error: {e:?}");
+            return None;
+        }
+    };
 
-    let (data, _remainder) = serde_json_core::from_slice::<HAResponse<'_>>(res).unwrap();
+    log!("Got response");
+    let res = match response.body().read_to_end().await {
+        Ok(res) => res,
+        Err(e) => {
+            log!("Home Assistant read error: {e:?}");
+            return None;
+        }
+    };
 
-    esp_println::println!("Temp: {}", data.attributes.temperature);
-    return data.attributes;
+    match serde_json_core::from_slice::<HAResponse>(res) {
+        Ok((data, _remainder)) => {
+            log!("Temp: {}", data.attributes.temperature);
+            Some(data.attributes)
+        }
+        Err(e) => {
+            log!("Home Assistant response error: {e:?}");
+            None
+        }
+    }
 }
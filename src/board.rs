@@ -0,0 +1,97 @@
+//! Pins of each supported chip, selected with a cargo feature
+//!
+//! `esp32c6` is the default. The esp32c3 builds without the default features
+//! and for its own target:
+//!
+//! ```text
+//! cargo build --no-default-features --features esp32c3 --target riscv32imc-unknown-none-elf
+//! ```
+//!
+//! `pin!` names the pin of a function on the selected chip, so `main` takes
+//! the same peripherals on both. Both run the executor on TIMG0 and the
+//! software interrupts, and read the hardware RNG, random once wifi runs.
+//! The HUB75 panel is wired on the esp32c6 only, and the esp32c3 has too few
+//! free pins for the SD card and the microphone.
+//!
+//! | Function                  | esp32c6    | esp32c3    |
+//! |---------------------------|------------|------------|
+//! | Button                    | 9          | 9          |
+//! | Matrix CS, MOSI, CLK      | 17, 18, 19 | 10, 7, 6   |
+//! | OLED SDA, SCL             | 10, 11     | 7, 6       |
+//! | SD CLK, MOSI, MISO, CS    | 20 to 23   | -          |
+//! | Light sensor              | 2          | 0          |
+//! | Battery                   | 1          | 1          |
+//! | VBUS sense                | 0          | 3          |
+//! | Buzzer                    | 3          | 5          |
+//! | Encoder A, B, push        | 7, 8, 15   | 20, 21, 2  |
+//! | Microphone BCLK, WS, DIN  | 4, 5, 6    | -          |
+//! | Status LED                | 16         | 4          |
+
+use esp_hal::peripherals;
+
+#[cfg(not(any(feature = "esp32c6", feature = "esp32c3")))]
+compile_error!("select a chip: esp32c6 or esp32c3");
+#[cfg(all(feature = "esp32c6", feature = "esp32c3"))]
+compile_error!("select a single chip, with --no-default-features for the esp32c3");
+
+#[cfg(all(feature = "hub75", not(feature = "esp32c6")))]
+compile_error!("the hub75 panel is wired on the esp32c6 only");
+#[cfg(all(feature = "esp32c3", any(feature = "sdcard", feature = "microphone")))]
+compile_error!("the esp32c3 has no pins left for the SD card and the microphone");
+
+/// Analog inputs, on ADC1 since ADC2 is taken by wifi
+#[cfg(feature = "esp32c6")]
+pub type LightPin<'d> = peripherals::GPIO2<'d>;
+#[cfg(feature = "esp32c6")]
+pub type BatteryPin<'d> = peripherals::GPIO1<'d>;
+#[cfg(feature = "esp32c3")]
+pub type LightPin<'d> = peripherals::GPIO0<'d>;
+#[cfg(feature = "esp32c3")]
+pub type BatteryPin<'d> = peripherals::GPIO1<'d>;
+
+/// `$peripherals` field of the pin wired to a function
+#[cfg(feature = "esp32c6")]
+#[macro_export]
+macro_rules! pin {
+    ($p:ident.button) => { $p.GPIO9 };
+    ($p:ident.matrix_cs) => { $p.GPIO17 };
+    ($p:ident.matrix_mosi) => { $p.GPIO18 };
+    ($p:ident.matrix_sclk) => { $p.GPIO19 };
+    ($p:ident.oled_sda) => { $p.GPIO10 };
+    ($p:ident.oled_scl) => { $p.GPIO11 };
+    ($p:ident.sd_sclk) => { $p.GPIO20 };
+    ($p:ident.sd_mosi) => { $p.GPIO21 };
+    ($p:ident.sd_miso) => { $p.GPIO22 };
+    ($p:ident.sd_cs) => { $p.GPIO23 };
+    ($p:ident.light) => { $p.GPIO2 };
+    ($p:ident.battery) => { $p.GPIO1 };
+    ($p:ident.vbus) => { $p.GPIO0 };
+    ($p:ident.buzzer) => { $p.GPIO3 };
+    ($p:ident.encoder_a) => { $p.GPIO7 };
+    ($p:ident.encoder_b) => { $p.GPIO8 };
+    ($p:ident.encoder_push) => { $p.GPIO15 };
+    ($p:ident.mic_bclk) => { $p.GPIO4 };
+    ($p:ident.mic_ws) => { $p.GPIO5 };
+    ($p:ident.mic_din) => { $p.GPIO6 };
+    ($p:ident.status_led) => { $p.GPIO16 };
+}
+
+#[cfg(feature = "esp32c3")]
+#[macro_export]
+macro_rules! pin {
+    ($p:ident.button) => { $p.GPIO9 };
+    ($p:ident.matrix_cs) => { $p.GPIO10 };
+    ($p:ident.matrix_mosi) => { $p.GPIO7 };
+    ($p:ident.matrix_sclk) => { $p.GPIO6 };
+    ($p:ident.oled_sda) => { $p.GPIO7 };
+    ($p:ident.oled_scl) => { $p.GPIO6 };
+    ($p:ident.light) => { $p.GPIO0 };
+    ($p:ident.battery) => { $p.GPIO1 };
+    ($p:ident.vbus) => { $p.GPIO3 };
+    ($p:ident.buzzer) => { $p.GPIO5 };
+    // UART0 pins, the log goes over USB
+    ($p:ident.encoder_a) => { $p.GPIO20 };
+    ($p:ident.encoder_b) => { $p.GPIO21 };
+    ($p:ident.encoder_push) => { $p.GPIO2 };
+    ($p:ident.status_led) => { $p.GPIO4 };
+}
@@ -0,0 +1,88 @@
+//! Display brightness controller
+//!
+//! Features ask for a brightness here instead of driving the display: the
+//! theme sets the base level, unless one is picked in the button menu,
+//! notifications boost it while they show and a vacant room lowers it to the
+//! minimum. The wake alarm sunrise ramps it up, even in a vacant room. On
+//! battery, the level stays at the minimum whatever is asked. The view
+//! applies `level()` to the display when it changes.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+#[derive(Clone, Copy)]
+struct Levels {
+    base: u8,
+    manual: Option<u8>,
+    boost: Option<u8>,
+    vacant: bool,
+    sunrise: Option<u8>,
+    saving: bool,
+}
+
+static LEVELS: Mutex<CriticalSectionRawMutex, Cell<Levels>> = Mutex::new(Cell::new(Levels {
+    base: 0,
+    manual: None,
+    boost: None,
+    vacant: false,
+    sunrise: None,
+    saving: false,
+}));
+
+fn update(f: impl FnOnce(&mut Levels)) {
+    LEVELS.lock(|levels| {
+        let mut current = levels.get();
+        f(&mut current);
+        levels.set(current);
+    });
+}
+
+/// Level outside of boosts, from the theme
+pub fn set_base(level: u8) {
+    update(|levels| levels.base = level);
+}
+
+/// Level replacing the theme's, `None` to follow the theme again
+pub fn set_manual(level: Option<u8>) {
+    update(|levels| levels.manual = level);
+}
+
+/// Raise the level to at least `level` until `end_boost`
+pub fn boost(level: u8) {
+    update(|levels| levels.boost = Some(level));
+}
+
+pub fn end_boost() {
+    update(|levels| levels.boost = None);
+}
+
+/// Nobody is in the room, boosts still apply
+pub fn set_vacant(vacant: bool) {
+    update(|levels| levels.vacant = vacant);
+}
+
+/// Raise the level to at least `level` before the wake alarm, `None` after
+pub fn set_sunrise(level: Option<u8>) {
+    update(|levels| levels.sunrise = level);
+}
+
+/// Power saving, the lowest level over any other request
+pub fn set_saving(saving: bool) {
+    update(|levels| levels.saving = saving);
+}
+
+/// Level the display should have
+pub fn level() -> u8 {
+    let levels = LEVELS.lock(|levels| levels.get());
+    if levels.saving {
+        return 0;
+    }
+    let base = if levels.vacant {
+        0
+    } else {
+        levels.manual.unwrap_or(levels.base)
+    };
+    let base = levels.sunrise.map_or(base, |sunrise| sunrise.max(base));
+    levels.boost.map_or(base, |boost| boost.max(base))
+}
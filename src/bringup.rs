@@ -0,0 +1,122 @@
+//! Station bring-up before the clock starts: link, address, then internet
+//!
+//! Each step has a timeout and a number of attempts. A step running out of
+//! attempts does not stop the clock, it starts offline and the network tasks
+//! catch up once the station comes back.
+
+use embassy_net::Stack;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::{
+    connectivity::{self, NetworkStatus},
+    wifimanager::{self, NetEventSubscriber},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Associated to the access point
+    Link,
+    /// DHCP lease
+    Address,
+    /// DNS and NTP reachable
+    Internet,
+}
+
+impl Step {
+    /// Shown while the step runs, like `NetworkStatus::label`
+    pub fn label(self) -> &'static str {
+        match self {
+            Step::Link => "WIFI..",
+            Step::Address => "DHCP..",
+            Step::Internet => "NET...",
+        }
+    }
+
+    fn timeout(self) -> Duration {
+        match self {
+            Step::Link => Duration::from_secs(20),
+            Step::Address => Duration::from_secs(20),
+            // The self-test has its own timeouts, one per check
+            Step::Internet => Duration::from_secs(30),
+        }
+    }
+
+    fn attempts(self) -> u8 {
+        match self {
+            Step::Link | Step::Address => 3,
+            Step::Internet => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Online(NetworkStatus),
+    /// `Step` ran out of attempts
+    Offline(Step),
+}
+
+impl Outcome {
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::Online(status) => status.label(),
+            Outcome::Offline(_) => "NO IP",
+        }
+    }
+}
+
+/// Pause between two attempts of a step
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// One attempt at `step`, `Some` once it succeeded
+async fn attempt(
+    stack: Stack<'_>,
+    events: &mut NetEventSubscriber,
+    step: Step,
+    ntp_host: &str,
+) -> Option<Option<NetworkStatus>> {
+    let run = async {
+        match step {
+            Step::Link => {
+                wifimanager::wait_link_up(stack, events).await;
+                None
+            }
+            Step::Address => {
+                wifimanager::wait_ip(stack, events).await;
+                None
+            }
+            Step::Internet => Some(connectivity::self_test(stack, ntp_host, true).await),
+        }
+    };
+    with_timeout(step.timeout(), run).await.ok()
+}
+
+/// Run the steps in order, `show` is called with the label of each step
+///
+/// The link and address steps wait on `events` rather than polling `stack`.
+pub async fn run(
+    stack: Stack<'_>,
+    events: &mut NetEventSubscriber,
+    ntp_host: &str,
+    mut show: impl FnMut(&str),
+) -> Outcome {
+    for step in [Step::Link, Step::Address, Step::Internet] {
+        show(step.label());
+
+        let mut attempts = 1;
+        loop {
+            match attempt(stack, events, step, ntp_host).await {
+                // The last self-test result is kept, even without internet
+                Some(Some(NetworkStatus::NoInternet)) if attempts < step.attempts() => {}
+                Some(Some(status)) => return Outcome::Online(status),
+                Some(None) => break,
+                None if attempts >= step.attempts() => return Outcome::Offline(step),
+                None => {}
+            }
+            crate::log!("Bring-up: {step:?} attempt {attempts} failed");
+            attempts += 1;
+            Timer::after(RETRY_DELAY).await;
+        }
+    }
+    unreachable!("the internet step always returns")
+}
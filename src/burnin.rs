@@ -0,0 +1,101 @@
+//! Anti burn-in of the LED matrix
+//!
+//! Settings are JSON, read and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"shift_interval":10,"invert_hour":3}
+//! ```
+//!
+//! Without `shift_interval` or `invert_hour` the shift or the inversion is off.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::wifimanager::{Nvs, Record, RecordError};
+
+/// Frame offsets cycled through by the anti burn-in shift, all within ±1 px
+const SHIFT_PATTERN: [(i32, i32); 6] = [(0, 0), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0)];
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<BurnInSettings>> =
+    BlockingMutex::new(Cell::new(BurnInSettings::DEFAULT));
+
+/// Anti burn-in for the LED matrix
+///
+/// Static digits age LED segments unevenly, so the whole frame is moved by one
+/// pixel on a slow schedule and inverted for one minute every night.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BurnInSettings {
+    /// Minutes between two frame shifts, `None` disables shifting
+    #[serde(default)]
+    pub shift_interval: Option<u32>,
+
+    /// Local hour at which the frame is inverted for one minute, `None` disables it
+    #[serde(default)]
+    pub invert_hour: Option<i8>,
+}
+
+impl Default for BurnInSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl BurnInSettings {
+    const DEFAULT: Self = Self {
+        shift_interval: Some(10),
+        invert_hour: Some(3),
+    };
+
+    pub fn is_valid(&self) -> bool {
+        self.invert_hour.is_none_or(|hour| (0..24).contains(&hour))
+    }
+
+    /// Offset to apply to the frame at `minutes` (unix time, in minutes)
+    pub fn offset(&self, minutes: i64) -> (i32, i32) {
+        match self.shift_interval {
+            Some(interval) if interval > 0 => {
+                let step = minutes.div_euclid(interval as i64) as usize;
+                SHIFT_PATTERN[step % SHIFT_PATTERN.len()]
+            }
+            _ => (0, 0),
+        }
+    }
+
+    /// Whether the frame should be inverted at local `hour`:`minute`
+    pub fn is_inverted(&self, hour: i8, minute: i8) -> bool {
+        self.invert_hour == Some(hour) && minute == 0
+    }
+}
+
+/// Current settings
+pub fn settings() -> BurnInSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<BurnInSettings>(Record::BurnIn);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => SETTINGS.lock(|current| current.set(settings)),
+        Some(_) => crate::log!("Invalid saved burn-in, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: BurnInSettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::BurnIn, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::select;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Timer;
+use esp_hal::gpio::Output;
+
+/// Sequence of (on, off) durations in ms for an active buzzer
+pub type Pattern = &'static [(u16, u16)];
+
+/// MIDI pitch, 0 for a rest, and duration in ms
+pub type Note = (u8, u16);
+
+pub const CHIRP: Pattern = &[(30, 0)];
+
+/// Silence between two notes, so repeated notes stay distinct
+const NOTE_GAP_MS: u16 = 20;
+
+enum Sound {
+    Pattern(Pattern),
+    Melody(Vec<Note>),
+}
+
+static REQUEST: Signal<CriticalSectionRawMutex, Sound> = Signal::new();
+static STOP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static PLAYING: AtomicBool = AtomicBool::new(false);
+
+/// Play `pattern`, replacing the one waiting to be played if any
+pub fn play(pattern: Pattern) {
+    REQUEST.signal(Sound::Pattern(pattern));
+}
+
+/// Play `notes`, replacing the sound waiting to be played if any
+///
+/// An active buzzer has a single pitch and only plays the rhythm, the
+/// `passive-buzzer` feature drives a passive one at the note frequencies.
+pub fn play_melody(notes: Vec<Note>) {
+    REQUEST.signal(Sound::Melody(notes));
+}
+
+/// Drop the sound waiting to be played and cut the one playing
+pub fn stop() {
+    REQUEST.reset();
+    STOP.signal(());
+}
+
+/// A sound is waiting or playing
+pub fn is_playing() -> bool {
+    REQUEST.signaled() || PLAYING.load(Ordering::Relaxed)
+}
+
+/// Hz, of the equal temperament with A4 (69) at 440 Hz
+#[cfg_attr(not(feature = "passive-buzzer"), allow(dead_code))]
+fn frequency(pitch: u8) -> u32 {
+    // C4 to B4
+    const OCTAVE_4: [u32; 12] = [262, 277, 294, 311, 330, 349, 370, 392, 415, 440, 466, 494];
+    let base = OCTAVE_4[pitch as usize % 12];
+    match pitch / 12 {
+        octave @ 0..5 => base >> (5 - octave),
+        octave => base << (octave - 5),
+    }
+}
+
+async fn tone(pin: &mut Output<'static>, pitch: u8, ms: u16) {
+    #[cfg(feature = "passive-buzzer")]
+    {
+        let half_period_us = 500_000 / frequency(pitch).max(1) as u64;
+        let end = embassy_time::Instant::now() + embassy_time::Duration::from_millis(ms as u64);
+        while embassy_time::Instant::now() < end {
+            pin.toggle();
+            Timer::after_micros(half_period_us).await;
+        }
+        pin.set_low();
+    }
+    #[cfg(not(feature = "passive-buzzer"))]
+    {
+        let _ = pitch;
+        pin.set_high();
+        Timer::after_millis(ms as u64).await;
+        pin.set_low();
+    }
+}
+
+async fn play_sound(pin: &mut Output<'static>, sound: Sound) {
+    match sound {
+        Sound::Pattern(pattern) => {
+            for &(on, off) in pattern {
+                pin.set_high();
+                Timer::after_millis(on as u64).await;
+                pin.set_low();
+                Timer::after_millis(off as u64).await;
+            }
+        }
+        Sound::Melody(notes) => {
+            for (pitch, ms) in notes {
+                let sounding = ms.saturating_sub(NOTE_GAP_MS);
+                if pitch == 0 {
+                    Timer::after_millis(ms as u64).await;
+                    continue;
+                }
+                tone(pin, pitch, sounding).await;
+                Timer::after_millis((ms - sounding) as u64).await;
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn buzzer_task(mut pin: Output<'static>) {
+    loop {
+        let sound = REQUEST.wait().await;
+        // A stop before this sound is not for it
+        STOP.reset();
+        PLAYING.store(true, Ordering::Relaxed);
+        select(play_sound(&mut pin, sound), STOP.wait()).await;
+        pin.set_low();
+        PLAYING.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,93 @@
+//! First day of the week and week numbers, for the date face
+//!
+//! The date face shows the label of the holidays table instead of the week
+//! number on the days it lists. Weeks are numbered by
+//! `b_intime_logic::calendar`. Settings are JSON, read and written through the
+//! HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"first_day":"Sunday"}
+//! ```
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::{Date, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    display::Canvas,
+    face::{ClockFace, Granularity},
+    holidays, units,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub use b_intime_logic::calendar::FirstDay;
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<CalendarSettings>> =
+    BlockingMutex::new(Cell::new(CalendarSettings {
+        first_day: FirstDay::Monday,
+    }));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CalendarSettings {
+    #[serde(default)]
+    pub first_day: FirstDay,
+}
+
+/// Current settings
+pub fn settings() -> CalendarSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Week of the year of `date`, from 1, with weeks starting on the first day
+pub fn week_number(date: Date) -> i8 {
+    b_intime_logic::calendar::week_number(date, settings().first_day)
+}
+
+/// Day and month above the week number, or the holiday of the day
+pub struct DateFace;
+
+impl<const W: usize, const H: usize> ClockFace<W, H> for DateFace {
+    fn draw(&self, canvas: &mut Canvas<W, H>, now: DateTime) {
+        let date = now.date();
+        let below = holidays::label(date)
+            .unwrap_or_else(|| alloc::format!("W{:02}", week_number(date)));
+        canvas.clear();
+        canvas.print_5x7(1, 0, &units::short_date(date));
+        canvas.print_5x7(1, 8, &below);
+    }
+
+    fn granularity(&self) -> Granularity {
+        Granularity::Minute
+    }
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<CalendarSettings>(Record::Calendar);
+    match saved {
+        Some(Ok(settings)) => SETTINGS.lock(|current| current.set(settings)),
+        Some(Err(_)) => crate::log!("Invalid saved calendar, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: CalendarSettings,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::Calendar, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
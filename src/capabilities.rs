@@ -0,0 +1,127 @@
+//! Optional subsystems enabled or disabled at runtime, so one build serves
+//! devices without a broker, a Home Assistant or a battery
+//!
+//! All are enabled by default. Settings are JSON, read and written through the
+//! HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"mqtt":true,"weather":false,"discovery":true,"webhooks":true,"sensors":true}
+//! ```
+//!
+//! Tasks of a disabled subsystem stay idle, and only start their work, like
+//! binding a socket or connecting to the broker, once it is enabled.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use embassy_time::{Duration, Timer};
+use serde::{Deserialize, Serialize};
+
+use crate::wifimanager::{Nvs, Record, RecordError};
+
+/// How often idle tasks check whether they were enabled
+const WAIT_PERIOD: Duration = Duration::from_secs(1);
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<Capabilities>> =
+    BlockingMutex::new(Cell::new(Capabilities::ALL));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Mqtt,
+    /// Weather from Home Assistant
+    Weather,
+    /// LAN announces and probe answers
+    Discovery,
+    Webhooks,
+    /// The battery monitor, on builds with one
+    Sensors,
+}
+
+const fn enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default = "enabled")]
+    pub mqtt: bool,
+    #[serde(default = "enabled")]
+    pub weather: bool,
+    #[serde(default = "enabled")]
+    pub discovery: bool,
+    #[serde(default = "enabled")]
+    pub webhooks: bool,
+    #[serde(default = "enabled")]
+    pub sensors: bool,
+}
+
+impl Capabilities {
+    const ALL: Capabilities = Capabilities {
+        mqtt: true,
+        weather: true,
+        discovery: true,
+        webhooks: true,
+        sensors: true,
+    };
+
+    pub fn contains(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Mqtt => self.mqtt,
+            Capability::Weather => self.weather,
+            Capability::Discovery => self.discovery,
+            Capability::Webhooks => self.webhooks,
+            Capability::Sensors => self.sensors,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::ALL
+    }
+}
+
+/// Current settings
+pub fn settings() -> Capabilities {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+pub fn is_enabled(capability: Capability) -> bool {
+    settings().contains(capability)
+}
+
+/// Wait until `capability` is enabled
+pub async fn wait_enabled(capability: Capability) {
+    while !is_enabled(capability) {
+        Timer::after(WAIT_PERIOD).await;
+    }
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<Capabilities>(Record::Capabilities);
+    match saved {
+        Some(Ok(settings)) => SETTINGS.lock(|current| current.set(settings)),
+        Some(Err(_)) => crate::log!("Invalid saved capabilities, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: Capabilities,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::Capabilities, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
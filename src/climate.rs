@@ -0,0 +1,283 @@
+//! Daily temperature and humidity statistics
+//!
+//! Readings are accumulated for the current day in RAM. At the first reading
+//! of a new day the finished day is summarized and kept, with the 6 days
+//! before it, in NVS.
+
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::{Date, DateTime};
+
+use crate::{
+    display::Canvas,
+    face::{ClockFace, Granularity},
+    units,
+    wifimanager::{Nvs, Record},
+};
+
+/// Finished days kept
+pub const HISTORY_DAYS: usize = 7;
+const RECORD_LEN: usize = 12;
+
+static CLIMATE: BlockingMutex<CriticalSectionRawMutex, RefCell<Climate>> =
+    BlockingMutex::new(RefCell::new(Climate::new()));
+
+/// One day, temperatures in tenths of °C, humidity in %
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DaySummary {
+    /// Days since 1970-01-01
+    pub day: u16,
+    pub temperature_min: i16,
+    pub temperature_max: i16,
+    pub temperature_avg: i16,
+    pub humidity_min: u8,
+    pub humidity_max: u8,
+    pub humidity_avg: u8,
+}
+
+impl DaySummary {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0u8; RECORD_LEN];
+        bytes[0..2].copy_from_slice(&self.day.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.temperature_min.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.temperature_max.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.temperature_avg.to_le_bytes());
+        bytes[8] = self.humidity_min;
+        bytes[9] = self.humidity_max;
+        bytes[10] = self.humidity_avg;
+        bytes
+    }
+
+    /// `None` for an empty slot
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let day = u16::from_le_bytes([bytes[0], bytes[1]]);
+        (day != 0 && day != u16::MAX).then(|| Self {
+            day,
+            temperature_min: i16::from_le_bytes([bytes[2], bytes[3]]),
+            temperature_max: i16::from_le_bytes([bytes[4], bytes[5]]),
+            temperature_avg: i16::from_le_bytes([bytes[6], bytes[7]]),
+            humidity_min: bytes[8],
+            humidity_max: bytes[9],
+            humidity_avg: bytes[10],
+        })
+    }
+
+    pub fn date(&self) -> Date {
+        Date::constant(1970, 1, 1)
+            .checked_add(jiff::Span::new().days(self.day as i64))
+            .unwrap_or_default()
+    }
+
+    fn write_json(self, out: &mut dyn Write) -> fmt::Result {
+        write!(
+            out,
+            r#"{{"date":"{}","temperature_min":{:.1},"temperature_max":{:.1},"temperature_avg":{:.1},"humidity_min":{},"humidity_max":{},"humidity_avg":{}}}"#,
+            self.date(),
+            self.temperature_min as f32 / 10.0,
+            self.temperature_max as f32 / 10.0,
+            self.temperature_avg as f32 / 10.0,
+            self.humidity_min,
+            self.humidity_max,
+            self.humidity_avg,
+        )
+    }
+}
+
+/// Readings of the current day
+#[derive(Clone, Copy)]
+struct Accumulator {
+    summary: DaySummary,
+    temperature_sum: i32,
+    humidity_sum: u32,
+    count: u32,
+}
+
+impl Accumulator {
+    fn new(day: u16, temperature: i16, humidity: u8) -> Self {
+        Self {
+            summary: DaySummary {
+                day,
+                temperature_min: temperature,
+                temperature_max: temperature,
+                temperature_avg: temperature,
+                humidity_min: humidity,
+                humidity_max: humidity,
+                humidity_avg: humidity,
+            },
+            temperature_sum: temperature as i32,
+            humidity_sum: humidity as u32,
+            count: 1,
+        }
+    }
+
+    fn push(&mut self, temperature: i16, humidity: u8) {
+        let summary = &mut self.summary;
+        summary.temperature_min = summary.temperature_min.min(temperature);
+        summary.temperature_max = summary.temperature_max.max(temperature);
+        summary.humidity_min = summary.humidity_min.min(humidity);
+        summary.humidity_max = summary.humidity_max.max(humidity);
+
+        self.temperature_sum += temperature as i32;
+        self.humidity_sum += humidity as u32;
+        self.count += 1;
+        summary.temperature_avg = (self.temperature_sum / self.count as i32) as i16;
+        summary.humidity_avg = (self.humidity_sum / self.count) as u8;
+    }
+}
+
+struct Climate {
+    today: Option<Accumulator>,
+    /// Finished days, most recent first
+    history: [Option<DaySummary>; HISTORY_DAYS],
+}
+
+impl Climate {
+    const fn new() -> Self {
+        Self {
+            today: None,
+            history: [None; HISTORY_DAYS],
+        }
+    }
+
+    /// Returns whether a day was finished
+    fn record(&mut self, day: u16, temperature: i16, humidity: u8) -> bool {
+        match self.today.as_mut() {
+            Some(today) if today.summary.day == day => {
+                today.push(temperature, humidity);
+                false
+            }
+            Some(today) => {
+                self.history.copy_within(..HISTORY_DAYS - 1, 1);
+                self.history[0] = Some(today.summary);
+                self.today = Some(Accumulator::new(day, temperature, humidity));
+                true
+            }
+            None => {
+                self.today = Some(Accumulator::new(day, temperature, humidity));
+                false
+            }
+        }
+    }
+}
+
+fn days_since_epoch(date: Date) -> u16 {
+    date.since(Date::constant(1970, 1, 1)).map_or(0, |span| {
+        span.get_days().clamp(0, u16::MAX as i32 - 1) as u16
+    })
+}
+
+/// Today's statistics so far
+pub fn today() -> Option<DaySummary> {
+    CLIMATE.lock(|climate| climate.borrow().today.map(|today| today.summary))
+}
+
+/// Part `idx` of `{"today":{...},"history":[{...}]}`: today, null before the
+/// first reading, then one finished day each, returns whether parts follow
+pub fn write_json_part(idx: usize, out: &mut dyn Write) -> Result<bool, fmt::Error> {
+    if idx == 0 {
+        out.write_str(r#"{"today":"#)?;
+        match today() {
+            Some(today) => today.write_json(out)?,
+            None => out.write_str("null")?,
+        }
+        out.write_str(r#","history":["#)?;
+        return Ok(true);
+    }
+
+    let day = CLIMATE.lock(|climate| {
+        let history = climate.borrow().history;
+        history.into_iter().flatten().nth(idx - 1)
+    });
+    match day {
+        Some(day) => {
+            if idx > 1 {
+                out.write_char(',')?;
+            }
+            day.write_json(out)?;
+            Ok(true)
+        }
+        None => {
+            out.write_str("]}")?;
+            Ok(false)
+        }
+    }
+}
+
+/// Add a reading taken on `date`, temperature in °C, humidity in %
+pub async fn record(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    date: Date,
+    temperature: f32,
+    humidity: u8,
+) {
+    let day = days_since_epoch(date);
+    let temperature = (temperature * 10.0) as i16;
+    let finished = CLIMATE.lock(|climate| climate.borrow_mut().record(day, temperature, humidity));
+    if finished {
+        save(storage).await;
+    }
+}
+
+/// Restore the history saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; HISTORY_DAYS * RECORD_LEN];
+    let Some(history) = storage.lock().await.read_record(Record::Climate, &mut buf) else {
+        return;
+    };
+
+    CLIMATE.lock(|climate| {
+        let mut climate = climate.borrow_mut();
+        for (slot, bytes) in climate
+            .history
+            .iter_mut()
+            .zip(history.chunks_exact(RECORD_LEN))
+        {
+            *slot = DaySummary::from_bytes(bytes);
+        }
+    });
+}
+
+async fn save(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; HISTORY_DAYS * RECORD_LEN];
+    CLIMATE.lock(|climate| {
+        let climate = climate.borrow();
+        for (day, bytes) in climate.history.iter().zip(buf.chunks_mut(RECORD_LEN)) {
+            if let Some(day) = day {
+                bytes.copy_from_slice(&day.to_bytes());
+            }
+        }
+    });
+
+    if let Err(e) = storage.lock().await.write_record(Record::Climate, &buf) {
+        crate::log!("Climate history not saved: {e:?}");
+    }
+}
+
+/// Today's highest temperature above the lowest, readings come minutes apart
+pub struct ClimateFace;
+
+impl<const W: usize, const H: usize> ClockFace<W, H> for ClimateFace {
+    fn draw(&self, canvas: &mut Canvas<W, H>, _now: DateTime) {
+        canvas.clear();
+        match today() {
+            Some(today) => {
+                let max = units::temperature(Some(today.temperature_max as f32 / 10.0), 1);
+                let min = units::temperature(Some(today.temperature_min as f32 / 10.0), 1);
+                canvas.print_5x7(2, 0, &max);
+                canvas.print_5x7(2, 8, &min);
+            }
+            None => canvas.print_5x7(1, 4, &units::temperature(None, 1)),
+        }
+    }
+
+    fn granularity(&self) -> Granularity {
+        Granularity::Minute
+    }
+}
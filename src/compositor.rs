@@ -0,0 +1,108 @@
+//! Layered frame composition
+//!
+//! The face, widget overlays and notifications draw on their own layer, the
+//! layers are composited bottom to top each frame, so none of them needs to
+//! know about the others.
+
+use crate::display::Canvas;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    /// Hides the layers below
+    Replace,
+    /// Lit pixels are drawn over the layers below, the others are transparent
+    Or,
+    /// Unlit pixels turn off the pixels below, a mask
+    And,
+    /// Lit pixels invert the pixels below
+    Xor,
+}
+
+/// Bottom to top
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerId {
+    Face,
+    Widgets,
+    Notification,
+}
+
+pub const LAYERS: usize = 3;
+
+#[derive(Clone, Copy)]
+pub struct Layer<const W: usize, const H: usize> {
+    pub canvas: Canvas<W, H>,
+    pub visible: bool,
+    pub blend: Blend,
+}
+
+impl<const W: usize, const H: usize> Layer<W, H> {
+    pub fn new(visible: bool, blend: Blend) -> Self {
+        Self {
+            canvas: Canvas::init(),
+            visible,
+            blend,
+        }
+    }
+}
+
+pub struct Compositor<const W: usize, const H: usize> {
+    layers: [Layer<W, H>; LAYERS],
+}
+
+impl<const W: usize, const H: usize> Default for Compositor<W, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const W: usize, const H: usize> Compositor<W, H> {
+    /// Opaque face, transparent widgets, notifications hidden until shown
+    pub fn new() -> Self {
+        Self {
+            layers: [
+                Layer::new(true, Blend::Replace),
+                Layer::new(true, Blend::Or),
+                Layer::new(false, Blend::Replace),
+            ],
+        }
+    }
+
+    pub fn layer(&self, id: LayerId) -> &Layer<W, H> {
+        &self.layers[id as usize]
+    }
+
+    pub fn layer_mut(&mut self, id: LayerId) -> &mut Layer<W, H> {
+        &mut self.layers[id as usize]
+    }
+
+    /// Every layer at once, in `LayerId` order, to draw on several of them
+    pub fn layers_mut(&mut self) -> &mut [Layer<W, H>; LAYERS] {
+        &mut self.layers
+    }
+
+    pub fn set_visible(&mut self, id: LayerId, visible: bool) {
+        self.layer_mut(id).visible = visible;
+    }
+
+    /// Clear the canvas of every layer
+    pub fn clear(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.canvas.clear();
+        }
+    }
+
+    /// Composite the visible layers into `out`
+    pub fn compose(&self, out: &mut Canvas<W, H>) {
+        out.clear();
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            for (out_row, &row) in out.rows_mut().iter_mut().zip(layer.canvas.rows()) {
+                *out_row = match layer.blend {
+                    Blend::Replace => row,
+                    Blend::Or => *out_row | row,
+                    Blend::And => *out_row & row,
+                    Blend::Xor => *out_row ^ row,
+                };
+            }
+        }
+    }
+}
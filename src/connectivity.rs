@@ -0,0 +1,184 @@
+use core::{
+    cell::Cell,
+    net::{IpAddr, SocketAddr},
+};
+
+use embassy_net::{
+    dns::DnsQueryType,
+    tcp::client::{TcpClient, TcpClientState},
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration, Instant};
+use reqwless::{client::HttpClient, request::Method};
+use serde::Serialize;
+use sntpc::{get_time, NtpContext, NtpTimestampGenerator};
+
+use crate::{dns::CachedDns, sockets};
+
+/// Max time spent on each step of the self-test
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Endpoint answering `204 No Content` when the internet is reachable
+const CAPTIVE_CHECK_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+
+/// Result of the last self-test, and the uptime it ran at
+static LAST: Mutex<CriticalSectionRawMutex, Cell<Option<(NetworkStatus, u64)>>> =
+    Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum NetworkStatus {
+    /// DNS, NTP (and HTTP when probed) are reachable
+    Full,
+    /// DNS works but HTTP is intercepted by a login page
+    Captive,
+    /// DNS or NTP server unreachable
+    NoInternet,
+}
+
+impl NetworkStatus {
+    /// Short label fitting the matrix with the 5x7 font
+    pub fn label(&self) -> &'static str {
+        match self {
+            NetworkStatus::Full => "NET OK",
+            NetworkStatus::Captive => "LOGIN?",
+            NetworkStatus::NoInternet => "NO NET",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct NetworkReport {
+    /// Uptime now, to date the self-test
+    pub uptime_s: u64,
+    /// `None` before the first self-test
+    pub status: Option<NetworkStatus>,
+    /// Uptime at the last self-test
+    pub checked_s: Option<u64>,
+}
+
+/// Last self-test result, for the status API
+pub fn report() -> NetworkReport {
+    let last = LAST.lock(|last| last.get());
+    NetworkReport {
+        uptime_s: Instant::now().as_secs(),
+        status: last.map(|(status, _)| status),
+        checked_s: last.map(|(_, at)| at),
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct InstantTimestamp {
+    current_time_us: u64,
+}
+
+impl NtpTimestampGenerator for InstantTimestamp {
+    fn init(&mut self) {
+        self.current_time_us = Instant::now().as_micros();
+    }
+
+    fn timestamp_sec(&self) -> u64 {
+        self.current_time_us / 1_000_000
+    }
+
+    fn timestamp_subsec_micros(&self) -> u32 {
+        (self.current_time_us % 1_000_000) as u32
+    }
+}
+
+/// Classify the network reachable through `stack`, kept for `report`
+///
+/// Runs a DNS resolution of `ntp_server`, an HTTP probe when `http_probe` is set
+/// and a single NTP request.
+pub async fn self_test(stack: Stack<'_>, ntp_server: &str, http_probe: bool) -> NetworkStatus {
+    let status = classify(stack, ntp_server, http_probe).await;
+    LAST.lock(|last| last.set(Some((status, Instant::now().as_secs()))));
+    status
+}
+
+async fn classify(stack: Stack<'_>, ntp_server: &str, http_probe: bool) -> NetworkStatus {
+    let addrs = match with_timeout(CHECK_TIMEOUT, stack.dns_query(ntp_server, DnsQueryType::A)).await
+    {
+        Ok(Ok(addrs)) if !addrs.is_empty() => addrs,
+        Ok(Ok(_)) | Ok(Err(_)) | Err(_) => {
+            crate::log!("Self-test: failed to resolve {ntp_server}");
+            return NetworkStatus::NoInternet;
+        }
+    };
+
+    if http_probe {
+        let status = check_http(stack).await;
+        if status != NetworkStatus::Full {
+            return status;
+        }
+    }
+
+    let _lease = sockets::lease(sockets::Use::SelfTest);
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if socket.bind(0).is_err() {
+        return NetworkStatus::NoInternet;
+    }
+
+    let addr: IpAddr = addrs[0].into();
+    let result = with_timeout(
+        CHECK_TIMEOUT,
+        get_time(
+            SocketAddr::from((addr, 123)),
+            &socket,
+            NtpContext::new(InstantTimestamp::default()),
+        ),
+    )
+    .await;
+
+    match result {
+        Ok(Ok(_)) => NetworkStatus::Full,
+        Ok(Err(e)) => {
+            crate::log!("Self-test: NTP error {e:?}");
+            NetworkStatus::NoInternet
+        }
+        Err(_) => {
+            crate::log!("Self-test: NTP timeout");
+            NetworkStatus::NoInternet
+        }
+    }
+}
+
+/// A probe answered by something else than `204 No Content` is a captive portal
+async fn check_http(stack: Stack<'_>) -> NetworkStatus {
+    let _lease = sockets::lease(sockets::Use::SelfTest);
+    let dns = CachedDns::new(stack);
+    let tcp_state = TcpClientState::<1, 1024, 1024>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+    let mut client = HttpClient::new(&tcp, &dns);
+    let mut buffer = [0u8; 1024];
+
+    let probe = async {
+        let mut http_req = client.request(Method::GET, CAPTIVE_CHECK_URL).await.ok()?;
+        let response = http_req.send(&mut buffer).await.ok()?;
+        Some(response.status.0)
+    };
+
+    match with_timeout(CHECK_TIMEOUT, probe).await {
+        Ok(Some(204)) => NetworkStatus::Full,
+        Ok(Some(status)) => {
+            crate::log!("Self-test: HTTP probe intercepted ({status})");
+            NetworkStatus::Captive
+        }
+        Ok(None) | Err(_) => {
+            crate::log!("Self-test: HTTP probe failed");
+            NetworkStatus::NoInternet
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Kitchen timer: a one-shot countdown set in one step
+//!
+//! Started from the HTTP API or the boot button, the remaining MM:SS replaces
+//! the clock. At zero the buzzer rings with patterns getting louder and denser
+//! until the timer is dismissed, snoozed, or `RING_TIMEOUT` passes.
+//!
+//! A ringing timer can be snoozed `max_snoozes` times, for `snooze_minutes`
+//! each; both are set through the HTTP API and kept in NVS. Snoozing a timer
+//! still running adds the snooze time without counting.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::{Cell, RefCell};
+
+use embassy_futures::select::select;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    mutex::Mutex as AsyncMutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buzzer::{self, Note, Pattern},
+    dnd, melody,
+    webhooks::{self, Event},
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// Longest countdown, MM:SS shows up to 99:59
+pub const MAX_MINUTES: u32 = 99;
+
+pub const MAX_SNOOZE_MINUTES: u8 = 30;
+
+/// Pause between two ring patterns
+const RING_PERIOD: Duration = Duration::from_secs(2);
+/// Ring time before the next, more insistent, pattern
+const RING_STEP: Duration = Duration::from_secs(20);
+/// Gives up ringing after this long
+const RING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+const RING_PATTERNS: [Pattern; 3] = [
+    &[(100, 0)],
+    &[(100, 100), (100, 0)],
+    &[(80, 60), (80, 60), (80, 60), (400, 0)],
+];
+
+static ALARM: Mutex<CriticalSectionRawMutex, Cell<Alarm>> = Mutex::new(Cell::new(Alarm {
+    deadline: None,
+    snoozes: 0,
+}));
+static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<SnoozeSettings>> =
+    Mutex::new(Cell::new(SnoozeSettings {
+        snooze_minutes: 1,
+        max_snoozes: 3,
+    }));
+/// Melody of the alarm ringing, instead of the alarm melody of the settings
+static MELODY: Mutex<CriticalSectionRawMutex, RefCell<Option<Vec<Note>>>> =
+    Mutex::new(RefCell::new(None));
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Clone, Copy)]
+struct Alarm {
+    /// Instant the countdown ends
+    deadline: Option<Instant>,
+    /// Snoozes of the ring
+    snoozes: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnoozeSettings {
+    pub snooze_minutes: u8,
+    pub max_snoozes: u8,
+}
+
+impl SnoozeSettings {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_SNOOZE_MINUTES).contains(&self.snooze_minutes)
+    }
+
+    fn snooze(&self) -> Duration {
+        Duration::from_secs(self.snooze_minutes as u64 * 60)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    /// Whole seconds left, rounded up
+    Running(u32),
+    /// Time since the end
+    Ringing(Duration),
+}
+
+impl State {
+    pub fn is_active(self) -> bool {
+        self != State::Idle
+    }
+}
+
+fn set_alarm(alarm: Alarm) {
+    ALARM.lock(|current| current.set(alarm));
+    CHANGED.signal(());
+}
+
+/// Start a countdown of `minutes`, replacing the current one
+pub fn start(minutes: u32) {
+    let minutes = minutes.clamp(1, MAX_MINUTES);
+    MELODY.lock(|melody| melody.borrow_mut().take());
+    set_alarm(Alarm {
+        deadline: Some(Instant::now() + Duration::from_secs(minutes as u64 * 60)),
+        snoozes: 0,
+    });
+}
+
+/// Ring now, replacing the current countdown, for the wake alarm
+pub fn ring() {
+    ring_melody(None);
+}
+
+/// Ring now with `notes`, or the alarm melody without, for the alarms
+pub fn ring_melody(notes: Option<Vec<Note>>) {
+    MELODY.lock(|melody| *melody.borrow_mut() = notes);
+    set_alarm(Alarm {
+        deadline: Some(Instant::now()),
+        snoozes: 0,
+    });
+}
+
+/// Ring again after the snooze time, false when no snooze is left
+///
+/// A running countdown gets the snooze time added, without counting.
+pub fn snooze() -> bool {
+    let alarm = ALARM.lock(|current| current.get());
+    let settings = settings();
+    let now = Instant::now();
+    match alarm.deadline {
+        None => false,
+        Some(deadline) if deadline > now => {
+            set_alarm(Alarm {
+                deadline: Some(deadline + settings.snooze()),
+                ..alarm
+            });
+            true
+        }
+        Some(_) if alarm.snoozes >= settings.max_snoozes => {
+            crate::log!("No snooze left");
+            false
+        }
+        Some(_) => {
+            set_alarm(Alarm {
+                deadline: Some(now + settings.snooze()),
+                snoozes: alarm.snoozes + 1,
+            });
+            buzzer::stop();
+            true
+        }
+    }
+}
+
+/// Dismiss the countdown, running or ringing
+pub fn stop() {
+    set_alarm(Alarm {
+        deadline: None,
+        snoozes: 0,
+    });
+    buzzer::stop();
+}
+
+/// Snoozes the current countdown may still take
+pub fn snoozes_left() -> u8 {
+    let snoozes = ALARM.lock(|current| current.get().snoozes);
+    settings().max_snoozes.saturating_sub(snoozes)
+}
+
+pub fn settings() -> SnoozeSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Read the snooze settings saved in NVS
+pub async fn load(storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>) {
+    let mut record = [0u8; 2];
+    let Some(&[snooze_minutes, max_snoozes]) = storage
+        .lock()
+        .await
+        .read_record(Record::Snooze, &mut record)
+    else {
+        return;
+    };
+
+    let settings = SnoozeSettings {
+        snooze_minutes,
+        max_snoozes,
+    };
+    if settings.is_valid() {
+        SETTINGS.lock(|current| current.set(settings));
+    } else {
+        crate::log!("Invalid saved snooze settings, ignored");
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>,
+    settings: SnoozeSettings,
+) -> Result<(), RecordError> {
+    let record = [settings.snooze_minutes, settings.max_snoozes];
+    storage.lock().await.write_record(Record::Snooze, &record)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
+
+pub fn state() -> State {
+    let Some(deadline) = ALARM.lock(|current| current.get().deadline) else {
+        return State::Idle;
+    };
+    let now = Instant::now();
+    if now < deadline {
+        State::Running((deadline - now).as_millis().div_ceil(1000) as u32)
+    } else {
+        State::Ringing(now - deadline)
+    }
+}
+
+/// "MM:SS" for the display
+pub fn format(seconds: u32) -> String {
+    alloc::format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Ring when the countdown ends
+#[embassy_executor::task]
+pub async fn countdown_task() {
+    // Deadline whose ring was announced to the webhooks
+    let mut announced = None;
+    loop {
+        let deadline = ALARM.lock(|current| current.get().deadline);
+        let Some(deadline) = deadline else {
+            CHANGED.wait().await;
+            continue;
+        };
+
+        let now = Instant::now();
+        if now < deadline {
+            select(Timer::at(deadline), CHANGED.wait()).await;
+            continue;
+        }
+
+        let ringing = now - deadline;
+        if ringing > RING_TIMEOUT {
+            crate::log!("Kitchen timer not stopped, giving up");
+            stop();
+            continue;
+        }
+        if announced != Some(deadline) {
+            announced = Some(deadline);
+            webhooks::fire(Event::Alarm);
+        }
+        let step = (ringing.as_secs() / RING_STEP.as_secs()) as usize;
+        if dnd::allows(dnd::Kind::Alarm) {
+            let notes = MELODY.lock(|melody| melody.borrow().clone());
+            match notes.or_else(melody::alarm) {
+                // Melodies outlast the period, queued again once ended
+                Some(_) if buzzer::is_playing() => {}
+                Some(notes) => buzzer::play_melody(notes),
+                None => buzzer::play(RING_PATTERNS[step.min(RING_PATTERNS.len() - 1)]),
+            }
+        }
+        select(Timer::after(RING_PERIOD), CHANGED.wait()).await;
+    }
+}
@@ -0,0 +1,229 @@
+//! Device identity and API pairing
+//!
+//! A 6 digits pairing code is drawn on first boot and shown on the matrix. It is
+//! the only way to obtain the bearer token required by the HTTP API, so nobody
+//! joining the open setup AP or the LAN can drive the clock without having seen
+//! the device. The code stops working once the token is issued, later tokens
+//! are issued to a panel session or to the holder of the current one.
+//!
+//! With several clocks on the network, `identify` makes one flash and show its
+//! name, to tell which one answers at an address. The name is set through the
+//! API and kept in NVS; the setup AP, the DHCP hostname and the MQTT topics
+//! take it at boot, the rest right away.
+
+use alloc::string::String;
+use core::{cell::RefCell, fmt::Write};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use esp_hal::rng::Trng;
+
+use crate::{
+    sha1,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// Code, token flag and token
+const RECORD_LEN: usize = 4 + 1 + TOKEN_LEN;
+
+pub const TOKEN_LEN: usize = 16;
+
+/// Name until another is set, and prefix of the setup AP SSID
+pub const DEFAULT_NAME: &str = "B-intime-5";
+/// The setup AP SSID, "B-intime-5-" and the name, stays within 32 bytes
+pub const MAX_NAME_LEN: usize = 21;
+
+static NAME: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<String>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+static IDENTIFY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Flash the display and show the device name
+pub fn identify() {
+    IDENTIFY.signal(());
+}
+
+/// Wait for an identification request
+pub async fn identify_requested() {
+    IDENTIFY.wait().await
+}
+
+/// First bytes of the SHA-1 of the efuse MAC, stable across flashes and resets
+pub fn device_id() -> [u8; 4] {
+    let digest = sha1::digest(&[&esp_hal::efuse::Efuse::mac_address()]);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+pub fn device_id_hex() -> heapless::String<8> {
+    let mut hex = heapless::String::new();
+    for byte in device_id() {
+        _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// Letters, digits and dashes, as a DHCP hostname, not starting or ending
+/// with a dash
+pub fn is_valid_name(name: &str) -> bool {
+    (1..=MAX_NAME_LEN).contains(&name.len())
+        && name.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Name set by the user, `DEFAULT_NAME` otherwise
+pub fn name() -> String {
+    NAME.lock(|name| name.borrow().clone())
+        .unwrap_or_else(|| DEFAULT_NAME.into())
+}
+
+/// Run `f` with the name, without copying it
+pub fn with_name<R>(f: impl FnOnce(&str) -> R) -> R {
+    NAME.lock(|name| f(name.borrow().as_deref().unwrap_or(DEFAULT_NAME)))
+}
+
+/// SSID of the setup AP, suffixed with the name when one is set
+pub fn ap_ssid() -> String {
+    let name = name();
+    if name == DEFAULT_NAME {
+        name
+    } else {
+        alloc::format!("{DEFAULT_NAME}-{name}")
+    }
+}
+
+/// Read the name saved in NVS
+pub async fn load_name(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut record = [0u8; MAX_NAME_LEN];
+    let Some(name) = storage
+        .lock()
+        .await
+        .read_record(Record::DeviceName, &mut record)
+    else {
+        return;
+    };
+
+    match core::str::from_utf8(name) {
+        Ok(name) if is_valid_name(name) => {
+            NAME.lock(|current| *current.borrow_mut() = Some(name.into()))
+        }
+        _ => crate::log!("Invalid saved device name, ignored"),
+    }
+}
+
+/// Apply and save `name`, it must be valid
+pub async fn save_name(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    name: &str,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_record(Record::DeviceName, name.as_bytes())?;
+    NAME.lock(|current| *current.borrow_mut() = Some(name.into()));
+    Ok(())
+}
+
+/// Lowercase hex encoding
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+pub struct Pairing {
+    code: u32,
+    token: Option<[u8; TOKEN_LEN]>,
+}
+
+/// Bytes from the true RNG, for codes, tokens and salts
+///
+/// Needs an entropy source: the radio once started, before that a
+/// `TrngSource` kept alive by the caller. Without one the RNG is only a
+/// pseudo random sequence, so this panics instead.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let trng = Trng::try_new().expect("no entropy source");
+    let mut bytes = [0u8; N];
+    for chunk in bytes.chunks_mut(4) {
+        let random = trng.random().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+impl Pairing {
+    /// Read the pairing record, drawing a new code when there is none
+    ///
+    /// Runs before the radio starts, see `random_bytes`.
+    pub async fn load_or_create(storage: &Mutex<CriticalSectionRawMutex, Nvs>) -> Self {
+        let mut record = [0u8; RECORD_LEN];
+        let loaded = storage
+            .lock()
+            .await
+            .read_record(Record::Pairing, &mut record)
+            .is_some_and(|record| record.len() == RECORD_LEN);
+
+        if loaded {
+            let mut token = [0u8; TOKEN_LEN];
+            token.copy_from_slice(&record[5..]);
+            return Self {
+                code: u32::from_le_bytes([record[0], record[1], record[2], record[3]]),
+                token: (record[4] == 1).then_some(token),
+            };
+        }
+
+        let pairing = Self {
+            code: u32::from_le_bytes(random_bytes()) % 1_000_000,
+            token: None,
+        };
+        pairing.save(storage).await;
+        pairing
+    }
+
+    async fn save(&self, storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+        let mut record = [0u8; RECORD_LEN];
+        record[..4].copy_from_slice(&self.code.to_le_bytes());
+        if let Some(token) = self.token {
+            record[4] = 1;
+            record[5..].copy_from_slice(&token);
+        }
+
+        if let Err(e) = storage.lock().await.write_record(Record::Pairing, &record) {
+            crate::log!("Pairing not saved: {e:?}");
+        }
+    }
+
+    /// Code to show on the matrix, zero padded
+    pub fn code(&self) -> String {
+        alloc::format!("{:06}", self.code)
+    }
+
+    pub fn is_paired(&self) -> bool {
+        self.token.is_some()
+    }
+
+    /// Whether `code` is the pairing code, never once paired
+    pub fn matches(&self, code: &str) -> bool {
+        !self.is_paired() && sha1::ct_eq(code.trim().as_bytes(), self.code().as_bytes())
+    }
+
+    /// Issue a new token, replacing the previous one
+    pub async fn issue_token(&mut self, storage: &Mutex<CriticalSectionRawMutex, Nvs>) -> String {
+        let token = random_bytes::<TOKEN_LEN>();
+        self.token = Some(token);
+        self.save(storage).await;
+
+        to_hex(&token)
+    }
+
+    /// Whether `token` (hex) is the issued token
+    pub fn authorize(&self, token: &str) -> bool {
+        self.token
+            .is_some_and(|expected| sha1::ct_eq(token.as_bytes(), to_hex(&expected).as_bytes()))
+    }
+}
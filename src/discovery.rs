@@ -0,0 +1,89 @@
+//! LAN discovery for companion apps, without mDNS
+//!
+//! Every clock broadcasts an announce on `DISCOVERY_PORT` periodically, and
+//! answers a `PROBE` datagram sent to that port (usually broadcast) with the
+//! same announce, unicast. An announce is a JSON object:
+//!
+//! `{"id":"1a2b3c4d","name":"B-intime-5","ip":"192.168.1.20","version":"0.1.0"}`
+
+use alloc::string::String;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Ipv4Address, Stack,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{
+    capabilities::{wait_enabled, Capability},
+    device,
+};
+
+pub const DISCOVERY_PORT: u16 = 47_625;
+
+/// Payload asking every clock to announce itself
+pub const PROBE: &[u8] = b"B5?";
+
+const ANNOUNCE_PERIOD: Duration = Duration::from_secs(30);
+
+fn announce(stack: Stack<'static>) -> Option<String> {
+    let ip = stack.config_v4()?.address.address();
+    Some(alloc::format!(
+        r#"{{"id":"{}","name":"{}","ip":"{}","version":"{}"}}"#,
+        device::device_id_hex(),
+        device::name(),
+        ip,
+        env!("CARGO_PKG_VERSION")
+    ))
+}
+
+/// Broadcast announces and answer probes while the station has an address
+#[embassy_executor::task]
+pub async fn discovery_task(stack: Stack<'static>) {
+    // The socket is only taken once enabled
+    wait_enabled(Capability::Discovery).await;
+    let _lease = crate::sockets::lease(crate::sockets::Use::Discovery);
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 256];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(DISCOVERY_PORT) {
+        crate::log!("Discovery bind error: {e:?}");
+        return;
+    }
+
+    let broadcast = IpEndpoint::new(Ipv4Address::BROADCAST.into(), DISCOVERY_PORT);
+    let mut next_announce = Instant::now();
+    let mut probe = [0u8; 16];
+
+    loop {
+        wait_enabled(Capability::Discovery).await;
+        stack.wait_config_up().await;
+
+        let to = match select(Timer::at(next_announce), socket.recv_from(&mut probe)).await {
+            Either::First(_) => {
+                next_announce = Instant::now() + ANNOUNCE_PERIOD;
+                broadcast
+            }
+            Either::Second(Ok((len, meta))) if &probe[..len] == PROBE => meta.endpoint,
+            Either::Second(_) => continue,
+        };
+
+        let Some(packet) = announce(stack) else {
+            continue;
+        };
+        crate::watchdog::beat(crate::watchdog::Task::Discovery);
+        if let Err(e) = socket.send_to(packet.as_bytes(), to).await {
+            crate::log!("Discovery send error: {e:?}");
+            crate::watchdog::error(crate::watchdog::Task::Discovery, &alloc::format!("{e:?}"));
+        }
+    }
+}
@@ -1,9 +1,14 @@
-use esp_hal::{spi::master::Spi, Blocking};
+use core::future::Future;
+
+use embedded_hal_async::spi::SpiBus;
+use esp_hal::{spi::master::SpiDmaBus, Async};
 
 use crate::font::{Font, ALPHABET_BIG_DIGITS, ALPHABET_NANO, ALPHABET_NORMAL, ALPHABET_TINY};
+use crate::profiler::{self, Stage};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub enum Command {
+    #[default]
     Noop = 0x00,
     Digit0 = 0x01,
     Digit1 = 0x02,
@@ -36,12 +41,6 @@ pub static NOOP: Order = Order {
     data: 0,
 };
 
-impl Default for Command {
-    fn default() -> Self {
-        Command::Noop
-    }
-}
-
 #[derive(Clone, Copy, Default)]
 pub struct Order {
     pub command: Command,
@@ -52,18 +51,169 @@ pub fn order(command: Command, data: u8) -> Order {
     Order { command, data }
 }
 
-pub struct Canvas<const W: usize, const H: usize>(pub [[bool; H]; W]);
+/// Pixel types a `Canvas` can hold, with the storage of `W` x `H` of them
+pub trait Pixel: Copy + Default {
+    type Pixels<const W: usize, const H: usize>: Copy;
 
-impl<const W: usize, const H: usize> Canvas<W, H> {
+    fn blank<const W: usize, const H: usize>() -> Self::Pixels<W, H>;
+
+    /// (`x`, `y`) is in the canvas
+    fn get<const W: usize, const H: usize>(pixels: &Self::Pixels<W, H>, x: usize, y: usize)
+        -> Self;
+
+    /// (`x`, `y`) is in the canvas
+    fn set<const W: usize, const H: usize>(
+        pixels: &mut Self::Pixels<W, H>,
+        x: usize,
+        y: usize,
+        val: Self,
+    );
+}
+
+/// One `u64` per row, the leftmost pixel in the most significant bit: rows
+/// are already the bytes the MAX7219 modules take
+impl Pixel for bool {
+    type Pixels<const W: usize, const H: usize> = [u64; H];
+
+    fn blank<const W: usize, const H: usize>() -> [u64; H] {
+        const { assert!(W <= 64, "monochrome canvases are at most 64 pixels wide") };
+        [0; H]
+    }
+
+    fn get<const W: usize, const H: usize>(rows: &[u64; H], x: usize, y: usize) -> bool {
+        rows[y] & column_bit(x) != 0
+    }
+
+    fn set<const W: usize, const H: usize>(rows: &mut [u64; H], x: usize, y: usize, val: bool) {
+        if val {
+            rows[y] |= column_bit(x);
+        } else {
+            rows[y] &= !column_bit(x);
+        }
+    }
+}
+
+impl Pixel for Rgb {
+    type Pixels<const W: usize, const H: usize> = [[Rgb; H]; W];
+
+    fn blank<const W: usize, const H: usize>() -> [[Rgb; H]; W] {
+        [[Rgb::BLACK; H]; W]
+    }
+
+    fn get<const W: usize, const H: usize>(pixels: &[[Rgb; H]; W], x: usize, y: usize) -> Rgb {
+        pixels[x][y]
+    }
+
+    fn set<const W: usize, const H: usize>(
+        pixels: &mut [[Rgb; H]; W],
+        x: usize,
+        y: usize,
+        val: Rgb,
+    ) {
+        pixels[x][y] = val;
+    }
+}
+
+/// Bit of column `x` in a monochrome row
+const fn column_bit(x: usize) -> u64 {
+    1 << (63 - x)
+}
+
+/// `byte` placed at column `x` of a monochrome row, bits past 64 dropped
+const fn byte_at(byte: u8, x: usize) -> u64 {
+    ((byte as u64) << 56) >> x
+}
+
+/// Columns of a `w` pixels wide monochrome row
+const fn width_mask(w: usize) -> u64 {
+    if w == 0 {
+        0
+    } else {
+        !0 << (64 - w)
+    }
+}
+
+/// `W` x `H` pixels
+///
+/// Pixels are on/off by default, the MAX7219 only knows those. Other pixel
+/// types (`Rgb`) are for backends able to show them, `P::default()` is the
+/// pixel turned off.
+pub struct Canvas<const W: usize, const H: usize, P: Pixel = bool>(P::Pixels<W, H>);
+
+impl<const W: usize, const H: usize, P: Pixel> Clone for Canvas<W, H, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<const W: usize, const H: usize, P: Pixel> Copy for Canvas<W, H, P> {}
+
+impl<const W: usize, const H: usize, P: Pixel> Canvas<W, H, P> {
     pub fn init() -> Self {
-        Canvas([[false; H]; W])
+        Canvas(P::blank::<W, H>())
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = P::blank::<W, H>();
+    }
+
+    /// Turn off the `w` x `h` area starting at (`x`, `y`)
+    pub fn clear_area(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        for px in x..(x + w).min(W) {
+            for py in y..(y + h).min(H) {
+                P::set(&mut self.0, px, py, P::default());
+            }
+        }
     }
 
-    pub fn set_pixel(&mut self, x: usize, y: usize, val: bool) {
+    /// Move every pixel by (`dx`, `dy`), pixels moved out of the canvas are lost
+    pub fn shift(&mut self, dx: i32, dy: i32) {
+        let src = self.0;
+        for x in 0..W {
+            for y in 0..H {
+                let sx = x as i32 - dx;
+                let sy = y as i32 - dy;
+                let val = if sx >= 0 && sy >= 0 && (sx as usize) < W && (sy as usize) < H {
+                    P::get(&src, sx as usize, sy as usize)
+                } else {
+                    P::default()
+                };
+                P::set(&mut self.0, x, y, val);
+            }
+        }
+    }
+
+    /// `P::default()` outside of the canvas
+    pub fn get(&self, x: usize, y: usize) -> P {
+        if x >= W || y >= H {
+            return P::default();
+        }
+        P::get(&self.0, x, y)
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, val: P) {
         if x >= W || y >= H {
             return;
         }
-        self.0[x][y] = val;
+        P::set(&mut self.0, x, y, val);
+    }
+}
+
+impl<const W: usize, const H: usize> Canvas<W, H> {
+    /// One `u64` per row, the leftmost pixel in the most significant bit
+    pub fn rows(&self) -> &[u64; H] {
+        &self.0
+    }
+
+    /// As `rows`, bits past `W` must stay clear
+    pub fn rows_mut(&mut self) -> &mut [u64; H] {
+        &mut self.0
+    }
+
+    pub fn invert(&mut self) {
+        for row in self.0.iter_mut() {
+            *row ^= width_mask(W);
+        }
     }
 
     pub fn on(&mut self, x: usize, y: usize) {
@@ -78,15 +228,9 @@ impl<const W: usize, const H: usize> Canvas<W, H> {
             return;
         }
 
-        for idx_bits in 0..(W - x).min(8) {
-            if line >> (7 - idx_bits) & 0b1 == 1 {
-                self.0[x + idx_bits][y] = true;
-            } else {
-                self.0[x + idx_bits][y] = false;
-            }
-        }
+        let row = &mut self.0[y];
+        *row = (*row & !byte_at(0xFF, x)) | (byte_at(line, x) & width_mask(W));
     }
-
     fn print_font<const N: usize>(&mut self, font: Font<N>, x: usize, y: usize, text: &str) {
         let mut cursor = x;
         for letter in text.chars() {
@@ -98,6 +242,29 @@ impl<const W: usize, const H: usize> Canvas<W, H> {
         }
     }
 
+    /// Same as `print_font` but `x` can be negative, clipping the first letters
+    fn print_font_at<const N: usize>(&mut self, font: Font<N>, x: i32, y: usize, text: &str) {
+        let mut cursor = x;
+        for letter in text.chars() {
+            if cursor >= W as i32 {
+                break;
+            }
+
+            let width = font.width_of(letter) as i32;
+            if cursor + width > 0 {
+                for row in 0..font.height {
+                    let code = font.to_line(row, letter);
+                    if cursor < 0 {
+                        self.print_line8(0, y + row, code << (-cursor) as u32);
+                    } else {
+                        self.print_line8(cursor as usize, y + row, code);
+                    }
+                }
+            }
+            cursor += width;
+        }
+    }
+
     pub fn print_8x8(&mut self, x: usize, y: usize, text: &str) {
         self.print_font(ALPHABET_BIG_DIGITS, x, y, text);
     }
@@ -106,6 +273,10 @@ impl<const W: usize, const H: usize> Canvas<W, H> {
         self.print_font(ALPHABET_NORMAL, x, y, text);
     }
 
+    pub fn print_5x7_at(&mut self, x: i32, y: usize, text: &str) {
+        self.print_font_at(ALPHABET_NORMAL, x, y, text);
+    }
+
     pub fn print_4x6(&mut self, x: usize, y: usize, text: &str) {
         self.print_font(ALPHABET_TINY, x, y, text);
     }
@@ -114,94 +285,254 @@ impl<const W: usize, const H: usize> Canvas<W, H> {
         self.print_font(ALPHABET_NANO, x, y, text);
     }
 
+    /// Bytes of the 8 digits of `T` chained 8x8 modules, bands of 8 rows
+    /// from the top, modules left to right in each band
+    ///
+    /// Fails to build when the `T` modules do not tile the canvas exactly.
     pub fn to_raw<const T: usize>(&self) -> [[u8; T]; 8] {
+        const {
+            assert!(
+                W % 8 == 0 && H % 8 == 0 && (W / 8) * (H / 8) == T,
+                "canvas size does not match the module count"
+            )
+        };
         let mut buf = [[0u8; T]; 8];
-        // for y in 0..H {
-        //     for x in 0..W {
-        //         print!("{}", self.0[x][y] as u8);
-        //     }
-        //     print!("\n");
-        // }
-        for x in 0..(W / 8) {
-            for y in 0..H {
-                let fy = if y >= 8 { y - 8 } else { y };
-                let fx = if y >= 8 { x + 4 } else { x };
+        let modules = W / 8;
+        for (y, row) in self.0.iter().enumerate() {
+            for x in 0..modules {
+                buf[y % 8][x + (y / 8) * modules] = (row << (8 * x) >> 56) as u8;
+            }
+        }
+        buf
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
 
-                for idx in 0..8 {
-                    if self.0[(x * 8) + idx][y] {
-                        buf[fy][fx] |= 0b1 << (7 - idx);
+impl<const W: usize, const H: usize> Canvas<W, H, Rgb> {
+    /// Lit pixels of `canvas` in `color`, scaled `scale` times from (`x`, `y`)
+    pub fn paint<const CW: usize, const CH: usize>(
+        &mut self,
+        canvas: &Canvas<CW, CH>,
+        x: usize,
+        y: usize,
+        scale: usize,
+        color: Rgb,
+    ) {
+        for cx in 0..CW {
+            for cy in 0..CH {
+                if !canvas.get(cx, cy) {
+                    continue;
+                }
+                for dx in 0..scale {
+                    for dy in 0..scale {
+                        self.set_pixel(x + cx * scale + dx, y + cy * scale + dy, color);
                     }
                 }
             }
         }
-        // for y in 0..H {
-        //     if y < 8 {
-        //         info!("{:08b} {:08b} {:08b} {:08b}", buf[y][0], buf[y][1], buf[y][2], buf[y][3]);
-        //     } else {
-        //         info!("{:08b} {:08b} {:08b} {:08b}", buf[y-8][4], buf[y-8][5], buf[y-8][6], buf[y-8][7]);
-        //     };
-        // }
-        buf
     }
 }
 
-pub struct Screen<const N: usize> {}
+/// Output hardware the faces render to
+///
+/// Canvases are the same whatever the backend, each one maps them to its
+/// own pixels.
+/// Area of a `W` x `H` canvas shown dimmer than the rest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Zone {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+    /// Levels below the brightness of the rest
+    pub dim: u8,
+}
+
+impl Zone {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.w).contains(&x) && (self.y..self.y + self.h).contains(&y)
+    }
+}
+
+pub trait DisplayBackend {
+    /// Configure the hardware, blanked
+    fn init(&mut self);
+
+    fn draw<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>);
+
+    /// As `draw`, letting other tasks run during the transfer on panels that
+    /// can, blocking on the others
+    fn draw_async<const W: usize, const H: usize>(
+        &mut self,
+        canvas: &Canvas<W, H>,
+    ) -> impl Future<Output = ()> {
+        self.draw(canvas);
+        core::future::ready(())
+    }
+
+    /// From 0 to 15, panels without brightness control ignore it
+    fn set_brightness(&mut self, _level: u8) {}
+
+    /// `level` with `zones` of the canvas dimmed, panels with a single
+    /// brightness ignore the zones
+    fn set_brightness_zones<const W: usize, const H: usize>(&mut self, level: u8, _zones: &[Zone]) {
+        self.set_brightness(level);
+    }
+}
+
+/// Chain of `N` MAX7219 8x8 modules
+///
+/// The bus is async: `draw_async` and the `_async` commands yield to the
+/// executor during the transfers, the blocking ones are kept for code that
+/// cannot wait, like the boot screens.
+///
+/// Transfers go through the DMA engine. A frame is built once, the 8 digit
+/// rows of every module, then each row is pushed as one transfer: the chip
+/// select rises in between, latching it in the modules.
+pub struct Screen<'d, const N: usize> {
+    spi: SpiDmaBus<'d, Async>,
+}
 
 const MAX_DISPLAYS_COUNT: usize = 16;
+/// Transmit buffer of the DMA bus, a digit row of the longest chain
+pub const DMA_BUFFER_LEN: usize = 2 * MAX_DISPLAYS_COUNT;
 
-impl<const N: usize> Screen<N> {
-    pub fn init(spi: &mut Spi<'_, Blocking>) {
-        if N > MAX_DISPLAYS_COUNT {
-            panic!("too many displays {N}");
-        }
+impl<'d, const N: usize> Screen<'d, N> {
+    pub fn new(spi: SpiDmaBus<'d, Async>) -> Self {
+        const { assert!(N <= MAX_DISPLAYS_COUNT, "too many displays") };
+        Self { spi }
+    }
 
-        Screen::<N>::send_all(spi, order(Command::DisplayTest, 0));
-        Screen::<N>::send_all(spi, order(Command::ScanLimit, 0x07));
-        Screen::<N>::send_all(spi, order(Command::DecodeMode, 0));
+    pub fn send_all(&mut self, order: Order) {
+        self.spi
+            .write(&Self::all_frame(order)[0..(2 * N)])
+            .expect("spi write fail");
+    }
 
-        for cmd in COMMAND_DIGITS {
-            Screen::<N>::send_all(spi, order(cmd, 0));
-        }
+    pub fn send(&mut self, command: Command, data: &[u8; N]) {
+        self.spi
+            .write(&Self::frame(command, data)[0..(2 * N)])
+            .expect("spi write fail");
+    }
 
-        Screen::<N>::send_all(spi, order(Command::Intensity, 0));
-        Screen::<N>::send_all(spi, order(Command::Power, 1));
+    pub async fn send_all_async(&mut self, order: Order) {
+        SpiBus::write(&mut self.spi, &Self::all_frame(order)[0..(2 * N)])
+            .await
+            .expect("spi write fail");
     }
 
-    pub fn send_all(spi: &mut Spi<'_, Blocking>, order: Order) {
-        if N > MAX_DISPLAYS_COUNT {
-            panic!("too many displays {N}");
-        }
+    pub async fn send_async(&mut self, command: Command, data: &[u8; N]) {
+        SpiBus::write(&mut self.spi, &Self::frame(command, data)[0..(2 * N)])
+            .await
+            .expect("spi write fail");
+    }
 
+    /// `order` for every module
+    fn all_frame(order: Order) -> [u8; 2 * MAX_DISPLAYS_COUNT] {
         let mut buf = [0u8; 2 * MAX_DISPLAYS_COUNT];
         for idx_data in 0..N {
             let idx = idx_data * 2;
             buf[idx] = order.command as u8;
             buf[idx + 1] = order.data;
         }
-        spi.write(&buf[0..(2 * N)]).expect("spi write fail");
+        buf
     }
 
-    pub fn send(spi: &mut Spi<'_, Blocking>, command: Command, data: &[u8; N]) {
-        if N > MAX_DISPLAYS_COUNT {
-            panic!("too many displays {N}");
-        }
-
+    /// `command` with a value per module
+    fn frame(command: Command, data: &[u8; N]) -> [u8; 2 * MAX_DISPLAYS_COUNT] {
         let mut buf = [0u8; 2 * MAX_DISPLAYS_COUNT];
         for (idx_data, val) in data.iter().enumerate() {
             let idx = idx_data * 2;
             buf[idx] = command as u8;
-            buf[idx + 1] = val.clone();
+            buf[idx + 1] = *val;
         }
-        spi.write(&buf[0..(2 * N)]).expect("spi write fail");
+        buf
     }
 
-    pub fn draw<const W: usize, const H: usize>(
-        spi: &mut Spi<'_, Blocking>,
+    /// Every digit row of `canvas`, ready for the bus
+    fn digit_frames<const W: usize, const H: usize>(
         canvas: &Canvas<W, H>,
-    ) {
+    ) -> [[u8; 2 * MAX_DISPLAYS_COUNT]; 8] {
         let raw = canvas.to_raw::<N>();
-        for (idx_digit, cmd) in COMMAND_DIGITS.iter().enumerate() {
-            Screen::send(spi, cmd.clone(), &raw[idx_digit]);
+        core::array::from_fn(|idx_digit| Self::frame(COMMAND_DIGITS[idx_digit], &raw[idx_digit]))
+    }
+
+    /// Show `canvas`, yielding to the executor while the DMA engine sends
+    /// each digit row
+    pub async fn draw_dma<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>) {
+        let frames = profiler::measure(Stage::ToRaw, || Self::digit_frames(canvas));
+        let flush = profiler::start();
+        for frame in &frames {
+            SpiBus::write(&mut self.spi, &frame[0..(2 * N)])
+                .await
+                .expect("spi write fail");
         }
+        profiler::record(Stage::Flush, flush);
+    }
+
+    /// Intensity of each module, from 0 to 15, in the order of `Canvas::to_raw`
+    pub fn set_intensity_map(&mut self, levels: [u8; N]) {
+        self.send(Command::Intensity, &levels.map(|level| level.min(0x0F)));
+    }
+}
+
+impl<const N: usize> DisplayBackend for Screen<'_, N> {
+    fn init(&mut self) {
+        self.send_all(order(Command::DisplayTest, 0));
+        self.send_all(order(Command::ScanLimit, 0x07));
+        self.send_all(order(Command::DecodeMode, 0));
+
+        for cmd in COMMAND_DIGITS {
+            self.send_all(order(cmd, 0));
+        }
+
+        self.send_all(order(Command::Intensity, 0));
+        self.send_all(order(Command::Power, 1));
+    }
+
+    fn draw<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>) {
+        let frames = profiler::measure(Stage::ToRaw, || Self::digit_frames(canvas));
+        profiler::measure(Stage::Flush, || {
+            for frame in &frames {
+                self.spi.write(&frame[0..(2 * N)]).expect("spi write fail");
+            }
+        });
+    }
+
+    async fn draw_async<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>) {
+        self.draw_dma(canvas).await;
+    }
+
+    fn set_brightness(&mut self, level: u8) {
+        self.send_all(order(Command::Intensity, level.min(0x0F)));
+    }
+
+    /// A module is dimmed when its center is in a zone
+    fn set_brightness_zones<const W: usize, const H: usize>(&mut self, level: u8, zones: &[Zone]) {
+        let modules = (W / 8).max(1);
+        let levels = core::array::from_fn(|idx| {
+            let x = (idx % modules) * 8 + 4;
+            let y = (idx / modules) * 8 + 4;
+            zones
+                .iter()
+                .filter(|zone| zone.contains(x, y))
+                .fold(level, |level, zone| level.saturating_sub(zone.dim))
+        });
+        self.set_intensity_map(levels);
     }
 }
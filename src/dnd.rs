@@ -0,0 +1,145 @@
+//! Do not disturb: silences notifications, chimes and alarms
+//!
+//! Active during the configured nightly window, unless switched on or off by
+//! hand (API, MQTT or a triple press of the boot button). A manual switch
+//! lasts until the window next starts or ends.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+/// What do not disturb can silence
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Alert texts and beeps
+    Notification,
+    /// Periodic sounds, like the NTP desync chirp
+    Chime,
+    /// The kitchen timer and the wake alarm
+    Alarm,
+}
+
+pub struct Settings {
+    /// Start and end in minutes from midnight, the end can be the next day
+    pub schedule: Option<(u16, u16)>,
+    /// Still allowed while active
+    pub exceptions: &'static [Kind],
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    scheduled: bool,
+    manual: Option<bool>,
+}
+
+static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<Option<&'static Settings>>> =
+    Mutex::new(Cell::new(None));
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State {
+    scheduled: false,
+    manual: None,
+}));
+
+pub fn init(settings: &'static Settings) {
+    SETTINGS.lock(|current| current.set(Some(settings)));
+}
+
+fn update_state(f: impl FnOnce(&mut State)) {
+    STATE.lock(|state| {
+        let mut current = state.get();
+        f(&mut current);
+        state.set(current);
+    });
+}
+
+/// Follow the schedule, called with the time each second
+pub fn tick(minute: u16) {
+    let Some((start, end)) = SETTINGS.lock(|settings| settings.get().and_then(|s| s.schedule))
+    else {
+        return;
+    };
+    let scheduled = if start <= end {
+        (start..end).contains(&minute)
+    } else {
+        minute >= start || minute < end
+    };
+
+    update_state(|state| {
+        if state.scheduled != scheduled {
+            crate::log!("Do not disturb {}", if scheduled { "on" } else { "off" });
+            state.scheduled = scheduled;
+            state.manual = None;
+        }
+    });
+}
+
+pub fn is_active() -> bool {
+    let state = STATE.lock(|state| state.get());
+    state.manual.unwrap_or(state.scheduled)
+}
+
+/// Switch on or off until the schedule next changes
+pub fn set(active: bool) {
+    update_state(|state| state.manual = Some(active));
+}
+
+pub fn toggle() {
+    set(!is_active());
+}
+
+/// Back to the schedule
+pub fn resume_schedule() {
+    update_state(|state| state.manual = None);
+}
+
+/// Whether `kind` may disturb now
+pub fn allows(kind: Kind) -> bool {
+    !is_active()
+        || SETTINGS.lock(|settings| {
+            settings
+                .get()
+                .is_some_and(|settings| settings.exceptions.contains(&kind))
+        })
+}
+
+/// Manual switch, from the API, MQTT or the button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Switch {
+    On,
+    Off,
+    Toggle,
+    /// Back to the schedule
+    Auto,
+}
+
+impl Switch {
+    /// API name, `on`, `off`, `toggle` or `auto`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "on" => Some(Switch::On),
+            "off" => Some(Switch::Off),
+            "toggle" => Some(Switch::Toggle),
+            "auto" => Some(Switch::Auto),
+            _ => None,
+        }
+    }
+
+    /// MQTT command, `ON`, `OFF`, `TOGGLE` or `AUTO`
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        match payload.trim_ascii() {
+            b"ON" => Some(Switch::On),
+            b"OFF" => Some(Switch::Off),
+            b"TOGGLE" => Some(Switch::Toggle),
+            b"AUTO" => Some(Switch::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self) {
+        match self {
+            Switch::On => set(true),
+            Switch::Off => set(false),
+            Switch::Toggle => toggle(),
+            Switch::Auto => resume_schedule(),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Small DNS cache shared by the network clients
+//!
+//! NTP, MQTT and the HTTP clients resolve through here, so a host is asked
+//! once every `TTL` instead of at every request. When the resolver fails, an
+//! expired address is still used for up to `STALE` rather than failing too.
+//!
+//! The embassy-net resolver does not return the records TTL, a fixed one is
+//! used for every host.
+
+use alloc::{string::String, vec::Vec};
+use core::{cell::RefCell, net::IpAddr};
+
+use embassy_net::{
+    dns::{DnsQueryType, DnsSocket, Error},
+    IpAddress, Stack,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use embedded_nal_async::{AddrType, Dns};
+
+/// Lifetime of a resolved address
+const TTL: Duration = Duration::from_secs(5 * 60);
+/// How long past its TTL an address is used when the resolver fails
+const STALE: Duration = Duration::from_secs(24 * 3600);
+/// Hosts kept, the one expiring first makes room
+const CAPACITY: usize = 8;
+
+struct Entry {
+    host: String,
+    address: IpAddress,
+    expires: Instant,
+}
+
+static CACHE: Mutex<CriticalSectionRawMutex, RefCell<Vec<Entry>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+fn lookup(host: &str, max_age: Duration) -> Option<IpAddress> {
+    let now = Instant::now();
+    CACHE.lock(|cache| {
+        cache
+            .borrow()
+            .iter()
+            .find(|entry| entry.host == host && now < entry.expires + max_age)
+            .map(|entry| entry.address)
+    })
+}
+
+fn insert(host: &str, address: IpAddress) {
+    let expires = Instant::now() + TTL;
+    CACHE.lock(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(entry) = cache.iter_mut().find(|entry| entry.host == host) {
+            entry.address = address;
+            entry.expires = expires;
+            return;
+        }
+        if cache.len() >= CAPACITY {
+            if let Some(oldest) = (0..cache.len()).min_by_key(|&idx| cache[idx].expires) {
+                cache.swap_remove(oldest);
+            }
+        }
+        cache.push(Entry {
+            host: String::from(host),
+            address,
+            expires,
+        });
+    });
+}
+
+/// First IPv4 address of `host`, from the cache when fresh
+pub async fn resolve(stack: Stack<'_>, host: &str) -> Result<IpAddress, Error> {
+    // IP addresses are not worth a cache slot
+    if let Ok(address) = host.parse() {
+        return Ok(IpAddress::Ipv4(address));
+    }
+    if let Some(address) = lookup(host, Duration::from_ticks(0)) {
+        return Ok(address);
+    }
+
+    let resolved = stack
+        .dns_query(host, DnsQueryType::A)
+        .await
+        .and_then(|addrs| addrs.first().copied().ok_or(Error::Failed));
+    match resolved {
+        Ok(address) => {
+            insert(host, address);
+            Ok(address)
+        }
+        Err(e) => match lookup(host, STALE) {
+            Some(address) => {
+                crate::log!("DNS error for {host}: {e:?}, using cached address");
+                Ok(address)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// `DnsSocket` going through the cache, for reqwless clients
+pub struct CachedDns<'a> {
+    stack: Stack<'a>,
+}
+
+impl<'a> CachedDns<'a> {
+    pub fn new(stack: Stack<'a>) -> Self {
+        Self { stack }
+    }
+}
+
+impl Dns for CachedDns<'_> {
+    type Error = Error;
+
+    async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, Error> {
+        match addr_type {
+            AddrType::IPv4 | AddrType::Either => resolve(self.stack, host).await.map(Into::into),
+            // Not cached, the station has no IPv6 anyway
+            AddrType::IPv6 => {
+                DnsSocket::new(self.stack)
+                    .get_host_by_name(host, addr_type)
+                    .await
+            }
+        }
+    }
+
+    async fn get_host_by_address(&self, addr: IpAddr, result: &mut [u8]) -> Result<usize, Error> {
+        DnsSocket::new(self.stack)
+            .get_host_by_address(addr, result)
+            .await
+    }
+}
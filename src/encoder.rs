@@ -0,0 +1,41 @@
+//! Rotary encoder with a push button, an alternative to the boot button
+//!
+//! Reports its turns and pushes on the input bus.
+
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::Input;
+
+use crate::input::{self, Encoder, InputEvent};
+
+/// Contact bounce of the switches
+const DEBOUNCE: Duration = Duration::from_millis(2);
+
+/// Quadrature decoding on the falling edges of `a`, the level of `b` tells
+/// the direction
+#[embassy_executor::task]
+pub async fn encoder_task(mut a: Input<'static>, b: Input<'static>, mut button: Input<'static>) {
+    loop {
+        match embassy_futures::select::select(
+            a.wait_for_falling_edge(),
+            button.wait_for_falling_edge(),
+        )
+        .await
+        {
+            embassy_futures::select::Either::First(()) => {
+                Timer::after(DEBOUNCE).await;
+                if a.is_low() {
+                    input::send(InputEvent::Encoder(Encoder::Turn {
+                        clockwise: b.is_high(),
+                    }));
+                }
+            }
+            embassy_futures::select::Either::Second(()) => {
+                Timer::after(DEBOUNCE).await;
+                if button.is_low() {
+                    input::send(InputEvent::Encoder(Encoder::Push));
+                    button.wait_for_high().await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Machine-readable index of the HTTP API, served on `/api`
+//!
+//! Companion apps and integrations read it to adapt to the features compiled
+//! into a device. Endpoints of a feature left out are not listed.
+//!
+//! Parameters are handcrafted JSON: keys ending with `?` are optional, values
+//! are a type (`string`, `bool`, `u8`, `u16`, `i64`, `f32`), alternatives
+//! separated by `|`, an object or a one element array for a list.
+//! `{name}` in a path is described by `params`.
+
+use core::fmt::{self, Write};
+
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str,
+    /// Needs an API token or a panel session
+    pub auth: bool,
+    /// Path and query parameters
+    pub params: Option<&'static str>,
+    /// JSON body, or `"text"` or `"binary"`
+    pub body: Option<&'static str>,
+    /// Compiled in
+    pub available: bool,
+}
+
+const fn get(path: &'static str) -> Endpoint {
+    Endpoint {
+        method: "GET",
+        path,
+        auth: true,
+        params: None,
+        body: None,
+        available: true,
+    }
+}
+
+const fn post(path: &'static str, body: Option<&'static str>) -> Endpoint {
+    Endpoint {
+        method: "POST",
+        body,
+        ..get(path)
+    }
+}
+
+const fn put(path: &'static str, body: Option<&'static str>) -> Endpoint {
+    Endpoint {
+        method: "PUT",
+        ..post(path, body)
+    }
+}
+
+const fn delete(path: &'static str) -> Endpoint {
+    Endpoint {
+        method: "DELETE",
+        ..get(path)
+    }
+}
+
+const fn with_params(endpoint: Endpoint, params: &'static str) -> Endpoint {
+    Endpoint {
+        params: Some(params),
+        ..endpoint
+    }
+}
+
+const fn public(endpoint: Endpoint) -> Endpoint {
+    Endpoint {
+        auth: false,
+        ..endpoint
+    }
+}
+
+const fn only_if(endpoint: Endpoint, available: bool) -> Endpoint {
+    Endpoint {
+        available,
+        ..endpoint
+    }
+}
+
+const SNOOZE: &str = r#"{"snooze_minutes":"u8","max_snoozes":"u8"}"#;
+const THEMES: &str = r#"{"themes":[{"face":"Face","font":"Big|Normal","brightness":"u8","transition":"None|FallingBlocks"}],"schedule":[{"days":"Every|Workdays|Weekend","start":"u16","theme":"u8"}]}"#;
+const NTP: &str = r#"{"servers":[{"host":"string","key_id?":"u32","secret?":"string"}],"port?":"u16","leap?":"Smear|Repeat"}"#;
+const TIMEZONE: &str = r#"{"posix?":"string"}"#;
+const TIMEZONE_PREVIEW: &str = r#"{"posix":"string","at?":"i64"}"#;
+const UNITS: &str =
+    r#"{"temperature?":"Celsius|Fahrenheit","date_order?":"Dmy|Mdy|Ymd","decimal?":"Point|Comma"}"#;
+const CALENDAR: &str = r#"{"first_day?":"Monday|Saturday|Sunday"}"#;
+const LOCATION: &str = r#"{"latitude?":"f32","longitude?":"f32"}"#;
+const HOLIDAYS: &str = r#"[{"year?":"u16","month":"u8","day":"u8","label":"string"}]"#;
+const BURN_IN: &str = r#"{"shift_interval?":"u32","invert_hour?":"i8"}"#;
+const POWER_SAVE: &str = r#"{"mode?":"None|Minimum|Maximum","quiet?":"None|Minimum|Maximum"}"#;
+const TX_POWER: &str = r#"{"dbm?":"u8"}"#;
+const MORSE: &str = r#"{"text":"string","buzzer?":"bool"}"#;
+const SIMTIME: &str = r#"{"at?":"i64","rate?":"u16"}"#;
+const MAINTENANCE: &str = r#"{"reboot?":{"day?":"u8","minute":"u16"}}"#;
+const CAPABILITIES: &str = r#"{"mqtt?":"bool","weather?":"bool","discovery?":"bool","webhooks?":"bool","sensors?":"bool"}"#;
+const WAKE: &str =
+    r#"{"alarm?":{"days":"Every|Workdays|Weekend","minute":"u16","sunrise?":"bool"}}"#;
+const MELODIES: &str = r#"{"melodies":[{"name":"string","notes":[["u8","u16"]]}],"chime?":"string","alarm?":"string"}"#;
+const ALARM: &str = r#"{"days":"u8","minute":"u16","melody?":"string","enabled?":"bool"}"#;
+const ALERTS: &str = r#"[{"sensor":"Temperature|Humidity|Battery","comparator":"Above|Below","threshold":"f32","hysteresis?":"f32","notify?":"string","beep?":"bool"}]"#;
+const AUTOMATIONS: &str = r#"[{"days":"Every|Workdays|Weekend","start":"u16","every?":"u16","end?":"u16","text?":"string","sensor?":"Temperature|Humidity|Battery","seconds?":"u16"}]"#;
+const WEBHOOKS: &str = r#"[{"url":"string","events":["Alarm|NtpDesync|WifiReconnected|ButtonPressed"],"template?":"string"}]"#;
+const FACE: &str = r#"{"face?":"Face","carousel?":["Face"],"separator?":"Colon|Blink|Dot|None"}"#;
+
+pub const ENDPOINTS: &[Endpoint] = &[
+    public(get("/api")),
+    public(get("/api/device")),
+    public(post("/api/token", Some("text"))),
+    with_params(get("/api/ntp/history"), r#"{"format?":"csv"}"#),
+    get("/api/ntp/accuracy"),
+    get("/api/ntp/leap"),
+    get("/api/ntp"),
+    post("/api/ntp", Some(NTP)),
+    get("/api/timezone"),
+    post("/api/timezone", Some(TIMEZONE)),
+    post("/api/timezone/preview", Some(TIMEZONE_PREVIEW)),
+    get("/api/units"),
+    post("/api/units", Some(UNITS)),
+    get("/api/calendar"),
+    post("/api/calendar", Some(CALENDAR)),
+    get("/api/holidays"),
+    post("/api/holidays", Some(HOLIDAYS)),
+    get("/api/location"),
+    post("/api/location", Some(LOCATION)),
+    get("/api/burnin"),
+    post("/api/burnin", Some(BURN_IN)),
+    get("/api/powersave"),
+    post("/api/powersave", Some(POWER_SAVE)),
+    get("/api/txpower"),
+    get("/api/wifi/diagnostics"),
+    get("/api/network"),
+    post("/api/txpower", Some(TX_POWER)),
+    post("/api/animation", Some("binary")),
+    post("/api/message", Some("text")),
+    post("/api/morse", Some(MORSE)),
+    with_params(
+        post("/api/snake/{action}", None),
+        r#"{"action":"start|up|down|left|right|quit"}"#,
+    ),
+    get("/api/timer"),
+    with_params(post("/api/timer", None), r#"{"min":"u32"}"#),
+    post("/api/timer/snooze", None),
+    post("/api/timer/stop", None),
+    get("/api/timer/snooze/settings"),
+    post("/api/timer/snooze/settings", Some(SNOOZE)),
+    post("/api/name", Some(r#"{"name":"string"}"#)),
+    post("/api/identify", None),
+    get("/api/themes"),
+    post("/api/themes", Some(THEMES)),
+    get("/api/metronome"),
+    with_params(post("/api/metronome", None), r#"{"bpm":"u16"}"#),
+    get("/api/maintenance"),
+    post("/api/maintenance", Some(MAINTENANCE)),
+    only_if(get("/api/battery"), cfg!(feature = "battery")),
+    only_if(
+        post("/api/battery/calibrate", Some(r#"{"millivolts":"u16"}"#)),
+        cfg!(feature = "battery"),
+    ),
+    get("/api/startup"),
+    get("/api/stats"),
+    only_if(get("/api/profile"), cfg!(feature = "profiler")),
+    only_if(post("/api/profile/reset", None), cfg!(feature = "profiler")),
+    only_if(get("/api/simtime"), cfg!(feature = "simtime")),
+    only_if(post("/api/simtime", Some(SIMTIME)), cfg!(feature = "simtime")),
+    only_if(delete("/api/simtime"), cfg!(feature = "simtime")),
+    post("/api/stats/reset", None),
+    get("/api/capabilities"),
+    post("/api/capabilities", Some(CAPABILITIES)),
+    get("/api/wake"),
+    post("/api/wake", Some(WAKE)),
+    get("/api/alarms"),
+    post("/api/alarms", Some(ALARM)),
+    with_params(put("/api/alarms/{id}", Some(ALARM)), r#"{"id":"u8"}"#),
+    with_params(delete("/api/alarms/{id}"), r#"{"id":"u8"}"#),
+    get("/api/melodies"),
+    post("/api/melodies", Some(MELODIES)),
+    get("/api/alerts"),
+    post("/api/alerts", Some(ALERTS)),
+    get("/api/automations"),
+    post("/api/automations", Some(AUTOMATIONS)),
+    get("/api/webhooks"),
+    post("/api/webhooks", Some(WEBHOOKS)),
+    get("/api/tasks"),
+    get("/api/sockets"),
+    only_if(get("/api/satellites"), cfg!(feature = "espnow")),
+    get("/api/climate"),
+    get("/api/dnd"),
+    with_params(
+        post("/api/dnd/{action}", None),
+        r#"{"action":"on|off|toggle|auto"}"#,
+    ),
+    get("/api/score"),
+    with_params(
+        post("/api/score/{action}", None),
+        r#"{"action":"left|right|reset"}"#,
+    ),
+    get("/api/face"),
+    post("/api/face", Some(FACE)),
+    with_params(
+        post("/api/face/{name}", None),
+        r#"{"name":"next|clock|vumeter|score|metronome|words|binary|hex|climate|date|sun|diagnostics"}"#,
+    ),
+];
+
+/// Cargo features, and whether they are compiled in
+const FEATURES: [(&str, bool); 14] = [
+    ("sdcard", cfg!(feature = "sdcard")),
+    ("microphone", cfg!(feature = "microphone")),
+    ("ssd1306", cfg!(feature = "ssd1306")),
+    ("hub75", cfg!(feature = "hub75")),
+    ("espnow", cfg!(feature = "espnow")),
+    ("more-sockets", cfg!(feature = "more-sockets")),
+    ("ble", cfg!(feature = "ble")),
+    ("passive-buzzer", cfg!(feature = "passive-buzzer")),
+    ("encoder", cfg!(feature = "encoder")),
+    ("battery", cfg!(feature = "battery")),
+    ("vbus-sense", cfg!(feature = "vbus-sense")),
+    ("status-led", cfg!(feature = "status-led")),
+    ("profiler", cfg!(feature = "profiler")),
+    ("simtime", cfg!(feature = "simtime")),
+];
+
+/// Part `idx` of the index: the version and the features compiled in, then one
+/// endpoint each, returns whether parts follow
+pub fn write_index_part(idx: usize, out: &mut dyn Write) -> Result<bool, fmt::Error> {
+    if idx == 0 {
+        write!(
+            out,
+            r#"{{"version":"{}","features":["#,
+            env!("CARGO_PKG_VERSION")
+        )?;
+        let features = FEATURES.iter().filter(|(_, enabled)| *enabled);
+        for (idx, (name, _)) in features.enumerate() {
+            if idx > 0 {
+                out.write_char(',')?;
+            }
+            write!(out, r#""{name}""#)?;
+        }
+        out.write_str(r#"],"endpoints":["#)?;
+        return Ok(true);
+    }
+
+    let Some(endpoint) = ENDPOINTS.get(idx - 1) else {
+        out.write_str("]}")?;
+        return Ok(false);
+    };
+    if !endpoint.available {
+        return Ok(true);
+    }
+    if ENDPOINTS[..idx - 1]
+        .iter()
+        .any(|endpoint| endpoint.available)
+    {
+        out.write_char(',')?;
+    }
+    write!(
+        out,
+        r#"{{"method":"{}","path":"{}","auth":{}"#,
+        endpoint.method, endpoint.path, endpoint.auth
+    )?;
+    if let Some(params) = endpoint.params {
+        write!(out, r#","params":{params}"#)?;
+    }
+    match endpoint.body {
+        Some(body) if body.starts_with(['{', '[']) => write!(out, r#","body":{body}"#)?,
+        Some(body) => write!(out, r#","body":"{body}""#)?,
+        None => {}
+    }
+    out.write_char('}')?;
+    Ok(true)
+}
@@ -0,0 +1,78 @@
+//! Household power widget, fed by an MQTT topic
+//!
+//! The payload is the power in W as plain text ("1234" or "1234.5"). The
+//! widget shows it with an arrow comparing it to the previous reading.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+use crate::display::Canvas;
+
+/// Change from the previous reading under which the trend is flat
+const FLAT_RATIO: f32 = 0.02;
+
+static READING: Mutex<CriticalSectionRawMutex, Cell<Option<Reading>>> = Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trend {
+    Up,
+    Flat,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub watts: f32,
+    pub trend: Trend,
+}
+
+/// MQTT message handler
+pub fn on_message(payload: &[u8]) {
+    let Some(watts) = core::str::from_utf8(payload)
+        .ok()
+        .and_then(|text| text.trim().parse::<f32>().ok())
+        .filter(|watts| watts.is_finite())
+    else {
+        crate::log!("Invalid power payload");
+        return;
+    };
+
+    READING.lock(|reading| {
+        let trend = match reading.get() {
+            Some(previous) if watts > previous.watts * (1.0 + FLAT_RATIO) => Trend::Up,
+            Some(previous) if watts < previous.watts * (1.0 - FLAT_RATIO) => Trend::Down,
+            _ => Trend::Flat,
+        };
+        reading.set(Some(Reading { watts, trend }));
+    });
+}
+
+pub fn reading() -> Option<Reading> {
+    READING.lock(|reading| reading.get())
+}
+
+/// Value right aligned before a 3x5 arrow, in the 30x7 area at `y`
+pub fn draw<const W: usize, const H: usize>(canvas: &mut Canvas<W, H>, y: usize, reading: Reading) {
+    let watts = reading.watts.max(0.0) as u32;
+    let text = if watts < 10_000 {
+        alloc::format!("{watts}")
+    } else {
+        alloc::format!("{}k", watts / 1000)
+    };
+    let width = crate::font::ALPHABET_NORMAL.text_width(&text);
+    canvas.print_5x7(26usize.saturating_sub(width), y, &text);
+
+    let arrow: [u8; 5] = match reading.trend {
+        Trend::Up => [0b010, 0b111, 0b010, 0b010, 0b010],
+        Trend::Flat => [0b000, 0b000, 0b111, 0b000, 0b000],
+        Trend::Down => [0b010, 0b010, 0b010, 0b111, 0b010],
+    };
+    for (row, bits) in arrow.iter().enumerate() {
+        for col in 0..3 {
+            if bits & (0b100 >> col) != 0 {
+                canvas.on(26 + col, y + 1 + row);
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Face carousel: what the matrix shows instead of the clock
+//!
+//! Selected through the HTTP API, the boot button or a theme. Faces needing a
+//! disabled feature are skipped.
+//!
+//! The face picked at boot and the faces the carousel goes through are JSON,
+//! read and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"face":"Words","carousel":["Clock","Words","Climate"],"separator":"Blink"}
+//! ```
+//!
+//! Without `face` the theme picks it, an empty carousel has every face.
+//! `separator` goes between the hours and the minutes of the clock.
+//!
+//! Faces drawn from the local date and time implement `ClockFace` and only need
+//! a variant here and an entry in `Face::clock_face`, the display loop
+//! refreshes them as often as their `Granularity` asks.
+
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    mutex::Mutex as AsyncMutex,
+    signal::Signal,
+};
+use jiff::civil::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calendar, climate,
+    display::{Canvas, Zone},
+    geek, sun,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// How often a face changes, it is drawn and sent to the matrix no more often
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    /// At each second of show time
+    Second,
+    /// At each minute of show time, for faces without seconds
+    Minute,
+    /// Once, until the face is selected again
+    OnDemand,
+}
+
+/// Face drawn from the local date and time alone, or from state that changes
+/// as slowly
+pub trait ClockFace<const W: usize, const H: usize> {
+    fn draw(&self, canvas: &mut Canvas<W, H>, now: DateTime);
+
+    /// Areas shown dimmer, to put the emphasis on the rest
+    fn zones(&self) -> &'static [Zone] {
+        &[]
+    }
+
+    /// How often to draw the face
+    fn granularity(&self) -> Granularity {
+        Granularity::Second
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Face {
+    Clock,
+    /// The clock without the `microphone` feature
+    VuMeter,
+    Score,
+    Metronome,
+    /// Word clock
+    Words,
+    /// Binary coded decimal
+    Binary,
+    Hex,
+    /// Today's temperature range
+    Climate,
+    /// Day, month and week number
+    Date,
+    /// Sunrise and sunset at the location
+    Sun,
+    /// UTC, Unix time and the timezone in effect, out of the carousel
+    Diagnostics,
+}
+
+/// Carousel order
+pub const FACES: [Face; 10] = [
+    Face::Clock,
+    Face::Words,
+    Face::Binary,
+    Face::Hex,
+    Face::VuMeter,
+    Face::Score,
+    Face::Metronome,
+    Face::Climate,
+    Face::Date,
+    Face::Sun,
+];
+
+static CURRENT: Mutex<CriticalSectionRawMutex, Cell<Face>> = Mutex::new(Cell::new(Face::Clock));
+/// A face was selected, to cut short the wait of a slow face
+static SELECTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static SETTINGS: Mutex<CriticalSectionRawMutex, RefCell<FaceSettings>> =
+    Mutex::new(RefCell::new(FaceSettings {
+        face: None,
+        carousel: Vec::new(),
+        separator: Separator::Colon,
+    }));
+
+/// Between the hours and the minutes of the clock
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Separator {
+    #[default]
+    Colon,
+    /// Colon shown on even seconds
+    Blink,
+    /// One pixel at mid height
+    Dot,
+    None,
+}
+
+impl Separator {
+    /// Columns taken, in a font with a colon `colon` wide
+    pub fn width(self, colon: usize) -> usize {
+        match self {
+            Separator::Colon | Separator::Blink => colon,
+            // The digits have a blank column on their right
+            Separator::Dot => 2,
+            Separator::None => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FaceSettings {
+    /// Shown at boot, the theme's face when `None`
+    #[serde(default)]
+    pub face: Option<Face>,
+    /// Faces of the carousel in its order, every face when empty
+    #[serde(default)]
+    pub carousel: Vec<Face>,
+    #[serde(default)]
+    pub separator: Separator,
+}
+
+impl FaceSettings {
+    pub fn is_valid(&self) -> bool {
+        self.face.is_none_or(Face::is_available)
+            && self.carousel.len() <= FACES.len()
+            && self
+                .carousel
+                .iter()
+                .enumerate()
+                .all(|(idx, face)| !self.carousel[..idx].contains(face))
+    }
+}
+
+impl Face {
+    pub fn is_available(self) -> bool {
+        self != Face::VuMeter || cfg!(feature = "microphone")
+    }
+
+    /// API name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clock" => Some(Face::Clock),
+            "vumeter" => Some(Face::VuMeter),
+            "score" => Some(Face::Score),
+            "metronome" => Some(Face::Metronome),
+            "words" => Some(Face::Words),
+            "binary" => Some(Face::Binary),
+            "hex" => Some(Face::Hex),
+            "climate" => Some(Face::Climate),
+            "date" => Some(Face::Date),
+            "sun" => Some(Face::Sun),
+            "diagnostics" => Some(Face::Diagnostics),
+            _ => None,
+        }
+    }
+
+    /// Registry of the `ClockFace` implementations
+    pub fn clock_face<const W: usize, const H: usize>(
+        self,
+    ) -> Option<&'static dyn ClockFace<W, H>> {
+        match self {
+            Face::Binary => Some(&geek::Binary),
+            Face::Hex => Some(&geek::Hex),
+            Face::Climate => Some(&climate::ClimateFace),
+            Face::Date => Some(&calendar::DateFace),
+            Face::Sun => Some(&sun::SunFace),
+            _ => None,
+        }
+    }
+}
+
+pub fn current() -> Face {
+    CURRENT.lock(|current| current.get())
+}
+
+/// Show `face`, the clock when it is not available
+pub fn set(face: Face) {
+    let face = if face.is_available() {
+        face
+    } else {
+        Face::Clock
+    };
+    CURRENT.lock(|current| current.set(face));
+    SELECTED.signal(());
+}
+
+/// Wait for the next selection of a face, the same one or another
+pub async fn selected() {
+    SELECTED.wait().await
+}
+
+/// Next available face of the carousel
+pub fn next() -> Face {
+    rotate(true)
+}
+
+/// Previous available face of the carousel
+pub fn previous() -> Face {
+    rotate(false)
+}
+
+fn rotate(forward: bool) -> Face {
+    let carousel = SETTINGS.lock(|settings| settings.borrow().carousel.clone());
+    let faces = if carousel.is_empty() {
+        &FACES[..]
+    } else {
+        &carousel[..]
+    };
+    // From the start when the current face is not in the carousel
+    let idx = faces
+        .iter()
+        .position(|&face| face == current())
+        .unwrap_or(if forward { faces.len() - 1 } else { 0 });
+    let face = (1..=faces.len())
+        .map(|step| {
+            let step = if forward { step } else { faces.len() - step };
+            faces[(idx + step) % faces.len()]
+        })
+        .find(|face| face.is_available())
+        .unwrap_or(Face::Clock);
+    set(face);
+    face
+}
+
+/// Current settings
+pub fn settings() -> FaceSettings {
+    SETTINGS.lock(|settings| settings.borrow().clone())
+}
+
+/// Between the hours and the minutes
+pub fn separator() -> Separator {
+    SETTINGS.lock(|settings| settings.borrow().separator)
+}
+
+fn apply(settings: FaceSettings) {
+    if let Some(face) = settings.face {
+        set(face);
+    }
+    SETTINGS.lock(|current| *current.borrow_mut() = settings);
+}
+
+/// Read the settings saved in NVS and show the saved face
+pub async fn load(storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage.lock().await.read_json::<FaceSettings>(Record::Face);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => apply(settings),
+        Some(_) => crate::log!("Invalid saved face settings, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>,
+    settings: FaceSettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Face, &settings)?;
+    apply(settings);
+    Ok(())
+}
@@ -1,9 +1,9 @@
 
 const fn build_glyph(width: u8, val: u64) -> Glyph {
-    return Glyph {
+    Glyph {
         width,
         data: val.to_be_bytes(),
-    };
+    }
 }
 
 pub struct Glyph {
@@ -44,24 +44,33 @@ impl<const N: usize> Font<N> {
 
     pub fn width_of_unchecked(&self, val: char) -> u8 {
         let idx = val as u8 - self.lower;
-        return self.glyphs[idx as usize].width;
+        self.glyphs[idx as usize].width
+    }
+
+    /// Whether `val` has a glyph, otherwise the fallback is drawn
+    pub fn has(&self, val: char) -> bool {
+        (self.lower as u32..=self.higher as u32).contains(&(val as u32))
     }
 
     pub fn width_of(&self, val: char) -> u8 {
-        if (val as u8) < self.lower || (val as u8) > self.higher {
+        if !self.has(val) {
             self.width_of_unchecked(self.fallback)
         } else {
             self.width_of_unchecked(val)
         }
     }
     
+    pub fn text_width(&self, text: &str) -> usize {
+        text.chars().map(|letter| self.width_of(letter) as usize).sum()
+    }
+
     pub fn to_line_unchecked(&self, position: usize, val: char) -> u8 {
         let idx = val as u8 - self.lower;
-        return self.glyphs[idx as usize].data[position]
+        self.glyphs[idx as usize].data[position]
     }
 
     pub fn to_line(&self, position: usize, val: char) -> u8 {
-        if (val as u8) < self.lower || (val as u8) > self.higher {
+        if !self.has(val) {
             self.to_line_unchecked(position, self.fallback)
         } else {
             self.to_line_unchecked(position, val)
@@ -102,7 +111,7 @@ pub const ALPHABET_NORMAL: Font<95> = Font::init(
         build_glyph(5, 0x0050f850f8500000), // #
         build_glyph(5, 0x0070a07028700000), // $
         build_glyph(5, 0x8090204090100000), // %
-        build_glyph(6, 0xc0c0182020180000), // &
+        build_glyph(6, 0xc0c0182020180000), // & (°C)
         build_glyph(5, 0x8080800000000000), // '
         build_glyph(5, 0x2040404040200000), // (
         build_glyph(5, 0x4020202020400000), // )
@@ -160,7 +169,7 @@ pub const ALPHABET_NORMAL: Font<95> = Font::init(
         build_glyph(5, 0x7010101010700000), // ]
         build_glyph(5, 0x2050000000000000), // ^
         build_glyph(5, 0x0000000000f00000), // _
-        build_glyph(5, 0x4020000000000000), // `
+        build_glyph(6, 0xc0c0382030200000), // ` (°F)
         build_glyph(5, 0x00007090b0500000), // a
         build_glyph(5, 0x8080e09090e00000), // b
         build_glyph(5, 0x0000608080600000), // c
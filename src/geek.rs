@@ -0,0 +1,70 @@
+//! Binary and hexadecimal clock faces
+
+use jiff::civil::DateTime;
+
+use crate::{
+    display::{Canvas, Zone},
+    face::ClockFace,
+};
+
+/// Seconds of the binary face, behind the hours and minutes
+const BINARY_SECONDS: Zone = Zone {
+    x: 24,
+    y: 0,
+    w: 8,
+    h: 16,
+    dim: 6,
+};
+
+/// Binary coded decimal HH MM SS, one column of 2x2 dots per digit, the
+/// least significant bit at the bottom
+pub struct Binary;
+
+impl<const W: usize, const H: usize> ClockFace<W, H> for Binary {
+    fn draw(&self, canvas: &mut Canvas<W, H>, time: DateTime) {
+        let digits = [
+            time.hour() / 10,
+            time.hour() % 10,
+            time.minute() / 10,
+            time.minute() % 10,
+            time.second() / 10,
+            time.second() % 10,
+        ];
+
+        canvas.clear();
+        for (column, digit) in digits.iter().enumerate() {
+            // Pairs of columns 4 pixels apart, pairs 11 pixels apart
+            let x = 2 + column / 2 * 11 + column % 2 * 4;
+            for bit in 0..4 {
+                if digit & (1 << bit) == 0 {
+                    continue;
+                }
+                let y = (3 - bit) * 4 + 1;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    if x + dx < W && y + dy < H {
+                        canvas.on(x + dx, y + dy);
+                    }
+                }
+            }
+        }
+    }
+
+    fn zones(&self) -> &'static [Zone] {
+        &[BINARY_SECONDS]
+    }
+}
+
+/// Hexadecimal time: the day is 0x10000 units of about 1.3 s, shown as
+/// "HH_MM" with hex hours of 1.5 h and hex minutes of 5.6 min
+pub struct Hex;
+
+impl<const W: usize, const H: usize> ClockFace<W, H> for Hex {
+    fn draw(&self, canvas: &mut Canvas<W, H>, time: DateTime) {
+        let seconds = time.hour() as u32 * 3600 + time.minute() as u32 * 60 + time.second() as u32;
+        let units = seconds * 0x10000 / 86_400;
+
+        canvas.clear();
+        let text = alloc::format!("{:02X}_{:02X}", units >> 8, units & 0xFF);
+        canvas.print_5x7(1, 4, &text);
+    }
+}
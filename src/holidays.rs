@@ -0,0 +1,148 @@
+//! Table of days with a short label, shown by the date face when today
+//! matches: public holidays, birthdays, name days
+//!
+//! The table is uploaded as JSON through the HTTP API, an entry without a
+//! year comes back every year:
+//!
+//! ```json
+//! [{"month":12,"day":25,"label":"XMAS"},{"year":2026,"month":4,"day":5,"label":"EASTER"}]
+//! ```
+//!
+//! It is kept in NVS in a fixed binary layout, `MAX_ENTRIES` of `ENTRY_LEN`
+//! bytes at most: year (0 for every year), month, day and the label padded
+//! with zeros. Labels fit the width of the matrix, in the glyphs of its font.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::Date;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// Longest label, 6 letters fill the 32 columns
+pub const MAX_LABEL_LEN: usize = 6;
+const ENTRY_LEN: usize = 4 + MAX_LABEL_LEN;
+/// The JSON of a full table fits the API responses
+pub const MAX_ENTRIES: usize = 16;
+
+static TABLE: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<Holiday>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holiday {
+    /// Every year when `None`
+    #[serde(default)]
+    pub year: Option<u16>,
+    pub month: u8,
+    pub day: u8,
+    pub label: String,
+}
+
+impl Holiday {
+    fn is_valid(&self) -> bool {
+        // A leap year, so February 29th is allowed every year
+        let year = self.year.unwrap_or(2024);
+        (1..=9999).contains(&year)
+            && Date::new(year as i16, self.month as i8, self.day as i8).is_ok()
+            && !self.label.is_empty()
+            && self.label.len() <= MAX_LABEL_LEN
+            && message::fit(&self.label).is_ok_and(|fitted| fitted == self.label)
+    }
+
+    fn is_on(&self, date: Date) -> bool {
+        self.year.is_none_or(|year| year as i16 == date.year())
+            && self.month as i8 == date.month()
+            && self.day as i8 == date.day()
+    }
+
+    fn to_bytes(&self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0u8; ENTRY_LEN];
+        bytes[..2].copy_from_slice(&self.year.unwrap_or(0).to_le_bytes());
+        bytes[2] = self.month;
+        bytes[3] = self.day;
+        bytes[4..4 + self.label.len()].copy_from_slice(self.label.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let year = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let label = &bytes[4..ENTRY_LEN];
+        let len = label
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(MAX_LABEL_LEN);
+        let holiday = Holiday {
+            year: (year != 0).then_some(year),
+            month: bytes[2],
+            day: bytes[3],
+            label: core::str::from_utf8(&label[..len]).ok()?.into(),
+        };
+        holiday.is_valid().then_some(holiday)
+    }
+}
+
+pub fn is_valid(holidays: &[Holiday]) -> bool {
+    holidays.len() <= MAX_ENTRIES && holidays.iter().all(Holiday::is_valid)
+}
+
+/// Current table
+pub fn holidays() -> Vec<Holiday> {
+    TABLE.lock(|table| table.borrow().clone())
+}
+
+/// Label of `date`, the first entry matching when several do
+pub fn label(date: Date) -> Option<String> {
+    TABLE.lock(|table| {
+        table
+            .borrow()
+            .iter()
+            .find(|holiday| holiday.is_on(date))
+            .map(|holiday| holiday.label.clone())
+    })
+}
+
+/// Read the table saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; MAX_ENTRIES * ENTRY_LEN];
+    let Some(table) = storage.lock().await.read_record(Record::Holidays, &mut buf) else {
+        return;
+    };
+
+    let holidays: Option<Vec<Holiday>> = table
+        .chunks_exact(ENTRY_LEN)
+        .map(Holiday::from_bytes)
+        .collect();
+    match holidays {
+        Some(holidays) if table.len() % ENTRY_LEN == 0 => {
+            TABLE.lock(|current| *current.borrow_mut() = holidays)
+        }
+        _ => crate::log!("Invalid saved holidays, ignored"),
+    }
+}
+
+/// Apply and save `holidays`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    holidays: Vec<Holiday>,
+) -> Result<(), RecordError> {
+    let mut buf = [0u8; MAX_ENTRIES * ENTRY_LEN];
+    for (holiday, bytes) in holidays.iter().zip(buf.chunks_exact_mut(ENTRY_LEN)) {
+        bytes.copy_from_slice(&holiday.to_bytes());
+    }
+
+    let len = holidays.len() * ENTRY_LEN;
+    storage
+        .lock()
+        .await
+        .write_record(Record::Holidays, &buf[..len])?;
+    TABLE.lock(|current| *current.borrow_mut() = holidays);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! HUB75 64x32 RGB panel (1/16 scan), an alternative to the MAX7219 matrix
+//!
+//! Enabled with the `hub75` feature. The panel has no memory: `refresh_task`
+//! shifts the rows out continuously, with binary code modulation for
+//! `BITS` bits per channel, and should run on a high priority interrupt
+//! executor so the clock work does not make it flicker. The C6 has a single
+//! core, so the refresh shares it with everything else.
+//!
+//! Drawing only packs the frame into bit planes for the refresh task.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Timer;
+use esp_hal::gpio::{Level, Output};
+
+use crate::display::{Canvas, DisplayBackend, Rgb};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+/// Rows lit together, one in each half of the panel
+const SCAN_ROWS: usize = HEIGHT / 2;
+
+/// Bits per channel, the most significant ones of each `Rgb` value
+const BITS: usize = 4;
+/// Display time of the least significant bit plane, doubled for each next one
+const BASE_PLANE_US: u64 = 20;
+
+/// For each bit plane, scan row and column: R1 G1 B1 R2 G2 B2 from the LSB
+type Planes = [[[u8; WIDTH]; SCAN_ROWS]; BITS];
+
+static PLANES: Mutex<CriticalSectionRawMutex, RefCell<Planes>> =
+    Mutex::new(RefCell::new([[[0; WIDTH]; SCAN_ROWS]; BITS]));
+
+pub struct Hub75Pins {
+    /// R1 G1 B1, upper half of the panel
+    pub rgb1: [Output<'static>; 3],
+    /// R2 G2 B2, lower half of the panel
+    pub rgb2: [Output<'static>; 3],
+    /// A B C D row address
+    pub address: [Output<'static>; 4],
+    pub clk: Output<'static>,
+    pub lat: Output<'static>,
+    /// Output enable, active low
+    pub oe: Output<'static>,
+}
+
+fn pack(pixel: impl Fn(usize, usize) -> Rgb) -> Planes {
+    let mut planes = [[[0; WIDTH]; SCAN_ROWS]; BITS];
+    for row in 0..SCAN_ROWS {
+        for x in 0..WIDTH {
+            let (top, bottom) = (pixel(x, row), pixel(x, row + SCAN_ROWS));
+            let channels = [top.r, top.g, top.b, bottom.r, bottom.g, bottom.b];
+            for (plane, planes) in planes.iter_mut().enumerate() {
+                let shift = 8 - BITS + plane;
+                planes[row][x] = channels.iter().enumerate().fold(0, |byte, (idx, value)| {
+                    byte | (((value >> shift) & 1) << idx)
+                });
+            }
+        }
+    }
+    planes
+}
+
+/// Frame sink for `refresh_task`
+pub struct Hub75 {
+    /// Color of lit pixels of monochrome canvases
+    pub color: Rgb,
+}
+
+impl Hub75 {
+    pub fn new(color: Rgb) -> Self {
+        Self { color }
+    }
+
+    pub fn draw_color(&mut self, canvas: &Canvas<WIDTH, HEIGHT, Rgb>) {
+        let planes = pack(|x, y| canvas.get(x, y));
+        PLANES.lock(|current| *current.borrow_mut() = planes);
+    }
+}
+
+impl DisplayBackend for Hub75 {
+    fn init(&mut self) {
+        PLANES.lock(|current| *current.borrow_mut() = [[[0; WIDTH]; SCAN_ROWS]; BITS]);
+    }
+
+    /// Scaled by the largest integer factor fitting the panel, centered
+    fn draw<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>) {
+        let scale = (WIDTH / W.max(1)).min(HEIGHT / H.max(1)).max(1);
+        let left = WIDTH.saturating_sub(W * scale) / 2;
+        let top = HEIGHT.saturating_sub(H * scale) / 2;
+
+        let color = self.color;
+        let planes = pack(|x, y| {
+            let (Some(cx), Some(cy)) = (x.checked_sub(left), y.checked_sub(top)) else {
+                return Rgb::BLACK;
+            };
+            let (cx, cy) = (cx / scale, cy / scale);
+            if canvas.get(cx, cy) {
+                color
+            } else {
+                Rgb::BLACK
+            }
+        });
+        PLANES.lock(|current| *current.borrow_mut() = planes);
+    }
+}
+
+/// Scan the panel forever, see the module documentation for the executor
+#[embassy_executor::task]
+pub async fn refresh_task(mut pins: Hub75Pins) {
+    pins.oe.set_high();
+    loop {
+        for row in 0..SCAN_ROWS {
+            let planes = PLANES.lock(|planes| {
+                let planes = planes.borrow();
+                core::array::from_fn::<_, BITS, _>(|plane| planes[plane][row])
+            });
+
+            for (plane, columns) in planes.iter().enumerate() {
+                for &bits in columns {
+                    for (idx, pin) in pins.rgb1.iter_mut().chain(pins.rgb2.iter_mut()).enumerate() {
+                        pin.set_level(Level::from(bits & (1 << idx) != 0));
+                    }
+                    pins.clk.set_high();
+                    pins.clk.set_low();
+                }
+
+                // Blank while switching row and latching the new data
+                pins.oe.set_high();
+                for (bit, pin) in pins.address.iter_mut().enumerate() {
+                    pin.set_level(Level::from(row & (1 << bit) != 0));
+                }
+                pins.lat.set_high();
+                pins.lat.set_low();
+                pins.oe.set_low();
+
+                Timer::after_micros(BASE_PLANE_US << plane).await;
+            }
+        }
+    }
+}
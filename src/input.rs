@@ -0,0 +1,85 @@
+//! Input event bus
+//!
+//! Input devices and remote controls only report what happened, as an
+//! `InputEvent`; the UI task of the binary is the single consumer and decides
+//! what each event does in the current state. A new input method sends its
+//! events here instead of driving the features.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel::Channel,
+};
+
+use crate::{dnd, snake};
+
+static EVENTS: Channel<CriticalSectionRawMutex, InputEvent, 8> = Channel::new();
+static DOUBLE_PRESS_FILTER: Mutex<CriticalSectionRawMutex, Cell<fn() -> bool>> =
+    Mutex::new(Cell::new(|| false));
+
+/// Press of a button, classified when released or held long enough
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    /// Two short presses, only when the consumer wants them told apart
+    Double,
+    /// A second
+    Long,
+    /// Three seconds
+    Held,
+    /// Long press right after a short one
+    ShortLong,
+    /// Held press right after a short one
+    ShortHeld,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoder {
+    /// One detent
+    Turn {
+        clockwise: bool,
+    },
+    Push,
+}
+
+/// Remote request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Snake(snake::Input),
+    Dnd(dnd::Switch),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Button(Press),
+    Encoder(Encoder),
+    /// Touch pad, no driver on the ESP32-C6
+    Touch(Press),
+    /// Infrared remote command code, no receiver driver yet
+    Ir(u8),
+    Web(Command),
+    Mqtt(Command),
+}
+
+/// Report `event`, dropped when the consumer lags too far behind
+pub fn send(event: InputEvent) {
+    if EVENTS.try_send(event).is_err() {
+        crate::log!("Input {event:?} dropped");
+    }
+}
+
+/// Set by the consumer, whether the buttons should wait to tell a double
+/// press from two short ones, delaying the short presses
+pub fn set_double_press_filter(filter: fn() -> bool) {
+    DOUBLE_PRESS_FILTER.lock(|current| current.set(filter));
+}
+
+pub fn wants_double_press() -> bool {
+    DOUBLE_PRESS_FILTER.lock(|filter| filter.get())()
+}
+
+/// Next event, for the single consumer
+pub async fn receive() -> InputEvent {
+    EVENTS.receive().await
+}
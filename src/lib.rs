@@ -1,9 +1,92 @@
 #![no_std]
-#![feature(impl_trait_in_assoc_type)]
 
 extern crate alloc;
 
+#[cfg(all(feature = "hub75", feature = "sdcard"))]
+compile_error!("the hub75 panel and the SD card share GPIO20-23");
+#[cfg(all(feature = "hub75", feature = "encoder"))]
+compile_error!("the hub75 panel and the encoder share GPIO15");
+#[cfg(all(feature = "hub75", feature = "battery"))]
+compile_error!("the hub75 panel and the battery sense share GPIO1");
+#[cfg(all(feature = "hub75", feature = "vbus-sense"))]
+compile_error!("the hub75 panel and the VBUS sense share GPIO0");
+
+pub mod alarms;
+pub mod alerts;
+pub mod animation;
+pub mod automations;
+pub mod api;
+pub mod battery;
+pub mod board;
+pub mod bringup;
+pub mod burnin;
+pub mod brightness;
+pub mod buzzer;
+pub mod calendar;
+pub mod capabilities;
+pub mod climate;
+pub mod compositor;
+pub mod connectivity;
+pub mod countdown;
+pub mod device;
+pub mod discovery;
+#[cfg(feature = "encoder")]
+pub mod encoder;
+pub mod endpoints;
+pub mod energy;
 pub mod display;
+pub mod dns;
+pub mod dnd;
+pub mod face;
 pub mod font;
+pub mod geek;
+pub mod holidays;
+#[cfg(feature = "hub75")]
+pub mod hub75;
+pub use b_intime_logic::i18n;
+pub mod input;
+pub mod location;
+pub mod logmirror;
+pub mod maintenance;
+pub mod melody;
+pub mod menu;
+pub mod message;
+pub mod metronome;
+pub mod morse;
+pub mod mqtt;
+pub mod ntp;
+pub mod power;
+pub mod powersave;
+pub mod prefs;
+pub mod profiler;
+pub mod presence;
+pub mod satellite;
+pub mod scheduler;
+pub mod score;
+#[cfg(feature = "sdcard")]
+pub mod sdcard;
+pub mod session;
+pub mod showsync;
+pub mod simtime;
+pub use b_intime_logic::sha1;
+pub mod snake;
+pub mod sockets;
+pub mod startup;
+pub mod stats;
+pub mod statusled;
+pub mod sun;
+#[cfg(feature = "ssd1306")]
+pub mod ssd1306;
+pub mod theme;
+pub mod timezone;
+pub mod transition;
+pub mod txpower;
+pub mod units;
+#[cfg(feature = "microphone")]
+pub mod vumeter;
+pub mod wake;
+pub mod watchdog;
+pub mod webhooks;
 pub mod wifimanager;
+pub mod wordclock;
 pub mod mk_static;
@@ -0,0 +1,151 @@
+//! Where the clock stands, for the weather and the sun times
+//!
+//! Entered in the setup portal or through the HTTP API, and kept with the
+//! wifi settings in NVS:
+//!
+//! ```json
+//! {"latitude":48.85,"longitude":2.35}
+//! ```
+//!
+//! Without one, the position of the public address is looked up while the
+//! network is up. It is only a default, it is not saved.
+
+use core::cell::Cell;
+
+use embassy_net::{
+    tcp::client::{TcpClient, TcpClientState},
+    Stack,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use reqwless::{client::HttpClient, request::Method};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dns::CachedDns,
+    sockets,
+    wifimanager::{self, Location, Nvs},
+};
+
+/// Geolocation of the public address, free without a key over plain HTTP
+const LOOKUP_URL: &str = "http://ip-api.com/json/?fields=status,lat,lon";
+
+static CURRENT: BlockingMutex<CriticalSectionRawMutex, Cell<Option<(Location, Source)>>> =
+    BlockingMutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Source {
+    /// Entered in the portal or through the API
+    Saved,
+    /// From the public address, until one is saved
+    Lookup,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct LocationSettings {
+    /// Degrees, north positive
+    #[serde(default)]
+    pub latitude: Option<f32>,
+    /// Degrees, east positive
+    #[serde(default)]
+    pub longitude: Option<f32>,
+    /// Answered only
+    #[serde(default, skip_deserializing)]
+    pub source: Option<Source>,
+}
+
+#[derive(Debug)]
+pub enum LocationError {
+    /// Not both coordinates or none, or out of range
+    Invalid,
+    NotSaved,
+}
+
+#[derive(Deserialize)]
+struct LookupResponse<'a> {
+    status: &'a str,
+    lat: f32,
+    lon: f32,
+}
+
+/// Location in use, `None` until one is saved or looked up
+pub fn location() -> Option<Location> {
+    CURRENT
+        .lock(|current| current.get())
+        .map(|(location, _)| location)
+}
+
+pub fn settings() -> LocationSettings {
+    let current = CURRENT.lock(|current| current.get());
+    LocationSettings {
+        latitude: current.map(|(location, _)| location.latitude),
+        longitude: current.map(|(location, _)| location.longitude),
+        source: current.map(|(_, source)| source),
+    }
+}
+
+/// Use the location saved with the wifi settings, at boot
+pub fn init(saved: Option<Location>) {
+    CURRENT.lock(|current| current.set(saved.map(|location| (location, Source::Saved))));
+}
+
+/// Save and use `settings`, no coordinates go back to the lookup
+pub async fn save(
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: LocationSettings,
+) -> Result<(), LocationError> {
+    match wifimanager::save_location(storage, settings.latitude, settings.longitude).await {
+        Ok(true) => {}
+        Ok(false) => return Err(LocationError::Invalid),
+        Err(e) => {
+            crate::log!("Location not saved: {e:?}");
+            return Err(LocationError::NotSaved);
+        }
+    }
+
+    init(
+        settings
+            .latitude
+            .zip(settings.longitude)
+            .and_then(|(latitude, longitude)| Location::new(latitude, longitude)),
+    );
+    Ok(())
+}
+
+/// Default to the position of the public address, unless a location is set
+pub async fn lookup(stack: Stack<'_>) {
+    if location().is_some() {
+        return;
+    }
+
+    let _lease = sockets::lease(sockets::Use::HttpClient);
+    let dns = CachedDns::new(stack);
+    let tcp_state = TcpClientState::<1, 1024, 1024>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+    let mut client = HttpClient::new(&tcp, &dns);
+    let mut buffer = [0u8; 1024];
+
+    let body = async {
+        let mut request = client.request(Method::GET, LOOKUP_URL).await.ok()?;
+        let response = request.send(&mut buffer).await.ok()?;
+        response.body().read_to_end().await.ok()
+    };
+    let found = body.await.and_then(|body| {
+        let (response, _) = serde_json_core::from_slice::<LookupResponse<'_>>(body).ok()?;
+        (response.status == "success")
+            .then(|| Location::new(response.lat, response.lon))
+            .flatten()
+    });
+
+    match found {
+        // Saved through the API in the meantime
+        Some(_) if location().is_some() => {}
+        Some(found) => {
+            crate::log!("Location looked up: {found:?}");
+            CURRENT.lock(|current| current.set(Some((found, Source::Lookup))));
+        }
+        None => crate::log!("Location lookup failed"),
+    }
+}
@@ -0,0 +1,57 @@
+use alloc::string::{String, ToString};
+use core::{
+    cell::RefCell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+/// Longest line kept for the matrix, the rest is only printed on serial
+const MAX_LINE_LEN: usize = 64;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static LAST_LINE: Mutex<CriticalSectionRawMutex, RefCell<String>> =
+    Mutex::new(RefCell::new(String::new()));
+
+/// Print on serial and keep the line for the matrix when mirroring is enabled
+///
+/// Use it like `println!`: `log!("Got IP: {}", address)`
+///
+/// Lines are also written to LOG.TXT with the `sdcard` feature, never pass
+/// secrets: print them with `esp_println::println!`, on serial only.
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logmirror::log(format_args!($($arg)*))
+    };
+}
+
+pub fn log(args: fmt::Arguments) {
+    esp_println::println!("{}", args);
+
+    #[cfg(feature = "sdcard")]
+    crate::sdcard::log_line(args);
+
+    if !is_enabled() {
+        return;
+    }
+
+    let mut line = args.to_string();
+    if let Some((idx, _)) = line.char_indices().nth(MAX_LINE_LEN) {
+        line.truncate(idx);
+    }
+    LAST_LINE.lock(|last| *last.borrow_mut() = line);
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Last line logged through `log!` since mirroring was enabled
+pub fn last_line() -> String {
+    LAST_LINE.lock(|last| last.borrow().clone())
+}
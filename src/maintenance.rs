@@ -0,0 +1,130 @@
+//! Maintenance reboot, against the slow heap fragmentation of a device never
+//! turned off
+//!
+//! Disabled by default. Settings are JSON, read and written through the HTTP
+//! API and kept in NVS:
+//!
+//! ```json
+//! {"reboot":{"day":7,"minute":240}}
+//! ```
+//!
+//! `day` is 1 for Monday to 7 for Sunday, every day when missing, and
+//! `minute` is from midnight, local time. A reboot is held back while the
+//! kitchen timer runs or rings, and given up an hour after its time.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use embassy_time::{Duration, Instant};
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    countdown,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// Time left to reboot once the scheduled minute passed
+const WINDOW_MINUTES: u16 = 60;
+/// No reboot before this uptime, so a reboot does not run again in its window
+const MIN_UPTIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<MaintenanceSettings>> =
+    BlockingMutex::new(Cell::new(MaintenanceSettings { reboot: None }));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reboot {
+    /// 1 for Monday to 7 for Sunday, every day when `None`
+    #[serde(default)]
+    pub day: Option<u8>,
+    /// From midnight
+    pub minute: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceSettings {
+    #[serde(default)]
+    pub reboot: Option<Reboot>,
+}
+
+impl MaintenanceSettings {
+    pub fn is_valid(&self) -> bool {
+        self.reboot.is_none_or(|reboot| {
+            reboot.day.is_none_or(|day| (1..=7).contains(&day)) && reboot.minute < 24 * 60
+        })
+    }
+
+    /// Whether the reboot window is open on `weekday` at `minute` from midnight
+    fn reboot_due(&self, weekday: Weekday, minute: u16) -> bool {
+        let Some(reboot) = self.reboot else {
+            return false;
+        };
+        // The window may start the day before
+        let (day, since) = if minute >= reboot.minute {
+            (weekday, minute - reboot.minute)
+        } else {
+            (weekday.previous(), minute + 24 * 60 - reboot.minute)
+        };
+        reboot
+            .day
+            .is_none_or(|wanted| day.to_monday_one_offset() as u8 == wanted)
+            && since < WINDOW_MINUTES
+    }
+}
+
+/// Current settings
+pub fn settings() -> MaintenanceSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<MaintenanceSettings>(Record::Maintenance);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => SETTINGS.lock(|current| current.set(settings)),
+        Some(_) => crate::log!("Invalid saved maintenance settings, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: MaintenanceSettings,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::Maintenance, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
+
+/// Reboot if it is time, on `weekday` at `minute` from midnight, and nothing
+/// would be interrupted
+///
+/// The storage stays locked until the reset, so no write is cut halfway.
+pub async fn reboot_if_due(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    weekday: Weekday,
+    minute: u16,
+) {
+    if Instant::now().as_secs() < MIN_UPTIME.as_secs()
+        || !settings().reboot_due(weekday, minute)
+        || countdown::state().is_active()
+    {
+        return;
+    }
+
+    crate::stats::save(storage).await;
+    let _storage = storage.lock().await;
+    crate::log!("Maintenance reboot");
+    embassy_time::Timer::after(Duration::from_millis(100)).await;
+    esp_hal::system::software_reset();
+}
@@ -0,0 +1,167 @@
+//! Melodies for the hourly chime and the alarms
+//!
+//! A melody is a list of `[pitch, ms]` notes, pitch as a MIDI number (60 is
+//! middle C) and 0 for a rest. Built-ins are always there, custom ones are
+//! uploaded through the HTTP API and kept in NVS with the choice of melodies:
+//!
+//! ```json
+//! {"melodies":[{"name":"up","notes":[[60,200],[64,200],[67,400]]}],
+//!  "chime":"westminster","alarm":"up"}
+//! ```
+//!
+//! Without a chime melody there is no hourly chime, without an alarm melody
+//! alarms ring with their own patterns.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buzzer::Note,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub const MAX_MELODIES: usize = 2;
+pub const MAX_NOTES: usize = 32;
+const MAX_NAME_LEN: usize = 16;
+/// Pitches of a piano
+const PITCHES: core::ops::RangeInclusive<u8> = 21..=108;
+const MAX_NOTE_MS: u16 = 4000;
+
+const BEEP: &[Note] = &[(84, 150)];
+
+/// Westminster quarters, the four phrases before the hour
+const WESTMINSTER: &[Note] = &[
+    (68, 400),
+    (66, 400),
+    (64, 400),
+    (59, 800),
+    (0, 200),
+    (64, 400),
+    (68, 400),
+    (66, 400),
+    (59, 800),
+    (0, 200),
+    (68, 400),
+    (64, 400),
+    (66, 400),
+    (59, 800),
+    (0, 200),
+    (59, 400),
+    (66, 400),
+    (68, 400),
+    (64, 800),
+];
+
+pub const BUILT_IN: [(&str, &[Note]); 2] = [("beep", BEEP), ("westminster", WESTMINSTER)];
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<MelodySettings>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Melody {
+    pub name: String,
+    pub notes: Vec<Note>,
+}
+
+impl Melody {
+    fn is_valid(&self) -> bool {
+        !self.name.is_empty()
+            && self.name.len() <= MAX_NAME_LEN
+            && !BUILT_IN.iter().any(|(name, _)| *name == self.name)
+            && !self.notes.is_empty()
+            && self.notes.len() <= MAX_NOTES
+            && self
+                .notes
+                .iter()
+                .all(|&(pitch, ms)| (pitch == 0 || PITCHES.contains(&pitch)) && ms <= MAX_NOTE_MS)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MelodySettings {
+    #[serde(default)]
+    pub melodies: Vec<Melody>,
+    /// Played on the hour
+    #[serde(default)]
+    pub chime: Option<String>,
+    /// Played by ringing alarms
+    #[serde(default)]
+    pub alarm: Option<String>,
+}
+
+impl MelodySettings {
+    pub fn is_valid(&self) -> bool {
+        self.melodies.len() <= MAX_MELODIES
+            && self.melodies.iter().all(Melody::is_valid)
+            && [&self.chime, &self.alarm]
+                .into_iter()
+                .flatten()
+                .all(|name| self.find(name).is_some())
+    }
+
+    fn find(&self, name: &str) -> Option<Vec<Note>> {
+        BUILT_IN
+            .iter()
+            .find(|(built_in, _)| *built_in == name)
+            .map(|(_, notes)| notes.to_vec())
+            .or_else(|| {
+                self.melodies
+                    .iter()
+                    .find(|melody| melody.name == name)
+                    .map(|melody| melody.notes.clone())
+            })
+    }
+}
+
+/// Current settings
+pub fn settings() -> MelodySettings {
+    SETTINGS.lock(|settings| settings.borrow().clone().unwrap_or_default())
+}
+
+/// Notes of the hourly chime, if any
+pub fn chime() -> Option<Vec<Note>> {
+    let settings = settings();
+    settings.find(settings.chime.as_deref()?)
+}
+
+/// Notes of the alarms, if any
+pub fn alarm() -> Option<Vec<Note>> {
+    let settings = settings();
+    settings.find(settings.alarm.as_deref()?)
+}
+
+/// Notes of the built-in or custom melody `name`
+pub fn notes(name: &str) -> Option<Vec<Note>> {
+    settings().find(name)
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<MelodySettings>(Record::Melody);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => {
+            SETTINGS.lock(|current| *current.borrow_mut() = Some(settings));
+        }
+        Some(_) => crate::log!("Invalid saved melodies, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: MelodySettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Melody, &settings)?;
+    SETTINGS.lock(|current| *current.borrow_mut() = Some(settings));
+    Ok(())
+}
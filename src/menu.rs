@@ -0,0 +1,195 @@
+//! Settings menu drawn on the matrix, driven by the boot button, so the clock
+//! can be set up without any network
+//!
+//! A short press or an encoder turn changes the value of the item shown, a
+//! long press or an encoder push goes to the next item and closes the menu
+//! after the last one. The menu also closes after `TIMEOUT` without a press.
+//! Values apply at once, the view saves them when the menu closes.
+
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    face,
+    prefs::{self, UTC_OFFSETS},
+    theme::MAX_BRIGHTNESS,
+};
+
+/// Closes the menu without a press
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Step of the manual brightness
+const BRIGHTNESS_STEP: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Item {
+    Brightness,
+    HourFormat,
+    UtcOffset,
+    Face,
+    /// Forget the wifi settings and restart in the setup AP
+    WifiReset,
+}
+
+const ITEMS: [Item; 5] = [
+    Item::Brightness,
+    Item::HourFormat,
+    Item::UtcOffset,
+    Item::Face,
+    Item::WifiReset,
+];
+
+impl Item {
+    /// Shown above the value
+    pub fn label(self) -> &'static str {
+        match self {
+            Item::Brightness => "LUM",
+            Item::HourFormat => "HOUR",
+            Item::UtcOffset => "UTC",
+            Item::Face => "FACE",
+            Item::WifiReset => "WIFI",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    item: Item,
+    /// The wifi reset is confirmed, done when the menu closes
+    wifi_reset: bool,
+    last_press: Instant,
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, Cell<Option<State>>> = Mutex::new(Cell::new(None));
+static WIFI_RESET: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+static OPENED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn update(f: impl FnOnce(&mut State)) {
+    STATE.lock(|state| {
+        if let Some(mut current) = state.get() {
+            f(&mut current);
+            current.last_press = Instant::now();
+            state.set(Some(current));
+        }
+    });
+}
+
+pub fn open() {
+    STATE.lock(|state| {
+        state.set(Some(State {
+            item: ITEMS[0],
+            wifi_reset: false,
+            last_press: Instant::now(),
+        }))
+    });
+    OPENED.signal(());
+}
+
+/// Wait for the menu to open, for the view to leave the face shown
+pub async fn opened() {
+    loop {
+        OPENED.wait().await;
+        if is_open() {
+            return;
+        }
+    }
+}
+
+fn close() {
+    let state = STATE.lock(|state| state.take());
+    if let Some(state) = state {
+        WIFI_RESET.lock(|reset| reset.set(state.wifi_reset));
+    }
+}
+
+/// Open and pressed recently, closed on timeout
+pub fn is_open() -> bool {
+    let timed_out = STATE.lock(|state| {
+        state
+            .get()
+            .is_some_and(|state| state.last_press.elapsed() > TIMEOUT)
+    });
+    if timed_out {
+        close();
+    }
+    STATE.lock(|state| state.get().is_some())
+}
+
+/// Item shown and whether the wifi reset is confirmed
+pub fn current() -> Option<(Item, bool)> {
+    STATE.lock(|state| state.get().map(|state| (state.item, state.wifi_reset)))
+}
+
+/// Whether the menu closed with the wifi reset confirmed, once
+pub fn take_wifi_reset() -> bool {
+    WIFI_RESET.lock(|reset| reset.replace(false))
+}
+
+/// Value after `current` in `values`, or before it, wrapping around
+fn cycle<T: Copy + PartialEq>(values: impl Iterator<Item = T>, current: T, forward: bool) -> T {
+    let values: Vec<T> = values.collect();
+    let idx = values
+        .iter()
+        .position(|&value| value == current)
+        .unwrap_or(0);
+    let idx = if forward {
+        (idx + 1) % values.len()
+    } else {
+        (idx + values.len() - 1) % values.len()
+    };
+    values[idx]
+}
+
+/// Short press or encoder turn: the next value of the item shown, or the
+/// previous one
+pub fn change(forward: bool) {
+    let Some((item, _)) = current() else {
+        return;
+    };
+    let mut prefs = prefs::get();
+    match item {
+        Item::Brightness => {
+            let levels = (0..=MAX_BRIGHTNESS)
+                .step_by(BRIGHTNESS_STEP as usize)
+                .map(Some);
+            prefs.brightness = cycle(
+                core::iter::once(None).chain(levels),
+                prefs.brightness,
+                forward,
+            );
+        }
+        Item::HourFormat => prefs.hour12 = !prefs.hour12,
+        Item::UtcOffset => {
+            prefs.utc_offset = cycle(
+                core::iter::once(None).chain(UTC_OFFSETS.map(Some)),
+                prefs.utc_offset,
+                forward,
+            );
+        }
+        Item::Face if forward => {
+            face::next();
+        }
+        Item::Face => {
+            face::previous();
+        }
+        Item::WifiReset => update(|state| state.wifi_reset = !state.wifi_reset),
+    }
+    prefs::set(prefs);
+    update(|_| {});
+}
+
+/// Long press or encoder push: the next item, closes after the last one
+pub fn next() {
+    let Some((item, _)) = current() else {
+        return;
+    };
+    match ITEMS.iter().position(|&other| other == item) {
+        Some(idx) if idx + 1 < ITEMS.len() => update(|state| state.item = ITEMS[idx + 1]),
+        _ => close(),
+    }
+}
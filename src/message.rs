@@ -0,0 +1,28 @@
+//! Text pushed to the matrix from the HTTP API or MQTT, scrolled like an alert
+//!
+//! The matrix font only has printable ASCII, where `&` and `` ` `` are drawn
+//! as °C and °F. Characters without a glyph are transliterated by
+//! `b_intime_logic::transliteration`, or refused and reported instead of being
+//! drawn as `?`.
+
+use alloc::string::String;
+
+use b_intime_logic::transliteration;
+
+use crate::{alerts, font::ALPHABET_NORMAL};
+
+pub use b_intime_logic::transliteration::{describe, MessageError};
+
+/// Longest message, once transliterated
+pub const MAX_LEN: usize = alerts::MAX_NOTIFY_LEN;
+
+/// `text` written with the glyphs of the matrix font
+pub fn fit(text: &str) -> Result<String, MessageError> {
+    transliteration::fit(text, |letter| ALPHABET_NORMAL.has(letter), MAX_LEN)
+}
+
+/// Scroll `text` once fitted, unless do not disturb silences notifications
+pub fn show(text: &str) -> Result<(), MessageError> {
+    alerts::notify(fit(text)?);
+    Ok(())
+}
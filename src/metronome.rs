@@ -0,0 +1,87 @@
+//! Metronome face: a bar sweeping across the matrix and a buzzer tick per beat
+//!
+//! Beats are computed from the shared show time (`showsync`), not by adding
+//! up timer delays, so the tempo does not drift and clocks on the same LAN
+//! tick together.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+
+use crate::{
+    buzzer::{self, Pattern},
+    display::Canvas,
+};
+
+pub const MIN_BPM: u16 = 30;
+pub const MAX_BPM: u16 = 250;
+pub const DEFAULT_BPM: u16 = 120;
+/// Change of a button press
+pub const BPM_STEP: u16 = 5;
+
+/// The first beat of each bar is accented
+const BEATS_PER_BAR: u64 = 4;
+const ACCENT: Pattern = &[(40, 0)];
+const TICK: Pattern = &[(10, 0)];
+
+static BPM: Mutex<CriticalSectionRawMutex, Cell<u16>> = Mutex::new(Cell::new(DEFAULT_BPM));
+
+pub fn bpm() -> u16 {
+    BPM.lock(|bpm| bpm.get())
+}
+
+/// Clamped to `MIN_BPM..=MAX_BPM`
+pub fn set_bpm(bpm: u16) {
+    BPM.lock(|current| current.set(bpm.clamp(MIN_BPM, MAX_BPM)));
+}
+
+pub fn faster() {
+    set_bpm(bpm().saturating_add(BPM_STEP));
+}
+
+pub fn slower() {
+    set_bpm(bpm().saturating_sub(BPM_STEP));
+}
+
+pub fn beat_period(bpm: u16) -> Duration {
+    Duration::from_micros(60_000_000 / bpm.max(1) as u64)
+}
+
+/// Beat count since the show time origin
+pub fn beat(show_time_us: u64, bpm: u16) -> u64 {
+    show_time_us / beat_period(bpm).as_micros()
+}
+
+/// Tick for `beat`
+pub fn tick(beat: u64) {
+    buzzer::play(if beat % BEATS_PER_BAR == 0 {
+        ACCENT
+    } else {
+        TICK
+    });
+}
+
+/// BPM on top, below it a bar going one way on even beats and back on odd ones
+pub fn draw<const W: usize, const H: usize>(
+    canvas: &mut Canvas<W, H>,
+    show_time_us: u64,
+    bpm: u16,
+) {
+    let period_us = beat_period(bpm).as_micros();
+    let beat = show_time_us / period_us;
+    let phase = show_time_us % period_us;
+
+    let travel = (W - 2) as u64;
+    let mut x = (phase * travel / period_us) as usize;
+    if beat % 2 == 1 {
+        x = travel as usize - x;
+    }
+
+    canvas.clear();
+    canvas.print_4x6(1, 0, &alloc::format!("{bpm}"));
+    for y in 8..H {
+        canvas.on(x, y);
+        canvas.on(x + 1, y);
+    }
+}
@@ -0,0 +1,113 @@
+//! Short status words in Morse, on a corner pixel of the matrix, the status
+//! LED and, if asked, the buzzer
+//!
+//! A minimal diagnostic channel for builds that do not scroll text: the boot
+//! result goes out as "OK" and the last octet of the address, or "ERR", and
+//! any short word can be sent through the HTTP API:
+//!
+//! ```json
+//! {"text":"OK 42","buzzer":true}
+//! ```
+//!
+//! Letters, digits and spaces only, encoded by `b_intime_logic::morse`. Every
+//! output follows the same unit timeline, from a start set slightly ahead so
+//! the display catches the first unit at its next frame.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use serde::Deserialize;
+
+use crate::buzzer::{self, Note};
+
+pub use b_intime_logic::morse::{encode, MorseError, MAX_LEN};
+
+/// Dot length, about 10 words per minute
+const UNIT: Duration = Duration::from_millis(120);
+/// Delay before the first unit, a display frame
+const LEAD_IN: Duration = Duration::from_secs(1);
+/// A5, 880 Hz on a passive buzzer
+const PITCH: u8 = 81;
+
+struct Transmission {
+    start: Instant,
+    /// Light of each unit
+    units: Vec<bool>,
+}
+
+static CURRENT: Mutex<CriticalSectionRawMutex, RefCell<Option<Transmission>>> =
+    Mutex::new(RefCell::new(None));
+static STARTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MorseRequest {
+    pub text: String,
+    /// Sound it too
+    #[serde(default)]
+    pub buzzer: bool,
+}
+
+/// Send `text`, replacing the transmission in progress if any
+pub fn send(text: &str, buzzer: bool) -> Result<(), MorseError> {
+    let units = encode(text)?;
+    if buzzer {
+        let mut notes: Vec<Note> = alloc::vec![(0, LEAD_IN.as_millis() as u16)];
+        for run in units.chunk_by(|a, b| a == b) {
+            let ms = (run.len() as u64 * UNIT.as_millis()) as u16;
+            notes.push((if run[0] { PITCH } else { 0 }, ms));
+        }
+        buzzer::play_melody(notes);
+    }
+
+    crate::log!("Morse: {text}");
+    let start = Instant::now() + LEAD_IN;
+    CURRENT.lock(|current| *current.borrow_mut() = Some(Transmission { start, units }));
+    STARTED.signal(());
+    Ok(())
+}
+
+/// Light now, `None` once the transmission ended or without one
+pub fn lit() -> Option<bool> {
+    CURRENT.lock(|current| {
+        let current = current.borrow();
+        let transmission = current.as_ref()?;
+        let Some(elapsed) = Instant::now().checked_duration_since(transmission.start) else {
+            return Some(false);
+        };
+        let unit = elapsed.as_ticks() / UNIT.as_ticks();
+        transmission.units.get(unit as usize).copied()
+    })
+}
+
+pub fn is_sending() -> bool {
+    lit().is_some()
+}
+
+/// Wait for the next unit boundary
+pub async fn next_unit() {
+    let start = CURRENT.lock(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|transmission| transmission.start)
+    });
+    let Some(start) = start else {
+        return;
+    };
+    let now = Instant::now();
+    let wait = match now.checked_duration_since(start) {
+        Some(elapsed) => UNIT - Duration::from_ticks(elapsed.as_ticks() % UNIT.as_ticks()),
+        None => start - now,
+    };
+    Timer::after(wait).await
+}
+
+/// Wait for a transmission to start
+pub async fn started() {
+    STARTED.wait().await
+}
@@ -0,0 +1,298 @@
+//! Minimal MQTT 3.1.1 client, QoS 0 only
+//!
+//! Connects to the broker, subscribes to `Config::topics` and hands every
+//! message received to a handler. Messages queued with `publish` are sent
+//! while connected. Reconnects after any error.
+
+use alloc::{string::String, vec::Vec};
+
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+
+use crate::capabilities::Capability;
+use crate::wifimanager::{self, NetEventSubscriber};
+
+pub const DEFAULT_PORT: u16 = 1883;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Ping well before the broker's keep alive expires
+const PING_PERIOD: Duration = Duration::from_secs(30);
+/// Dead connection detection, a bit over a keep alive
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(90);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Largest message handled, larger ones are skipped
+const MAX_PACKET: usize = 512;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+const DISCONNECT: u8 = 0xE0;
+
+/// Topic and payload of the messages to send
+static OUTBOX: Channel<CriticalSectionRawMutex, (String, String), 4> = Channel::new();
+
+pub struct Config {
+    pub host: &'static str,
+    pub port: u16,
+    pub client_id: &'static str,
+    pub username: Option<&'static str>,
+    pub password: Option<&'static str>,
+    /// Topic filters subscribed after each connection
+    pub topics: &'static [&'static str],
+}
+
+/// Called with the topic and payload of each message
+pub type Handler = fn(&str, &[u8]);
+
+#[derive(Debug)]
+pub enum Error {
+    Dns,
+    Tcp(embassy_net::tcp::Error),
+    Connect(embassy_net::tcp::ConnectError),
+    /// Connection closed by the broker
+    Closed,
+    /// CONNACK return code
+    Refused(u8),
+    Protocol,
+}
+
+impl From<embassy_net::tcp::Error> for Error {
+    fn from(e: embassy_net::tcp::Error) -> Self {
+        Error::Tcp(e)
+    }
+}
+
+fn push_remaining_length(packet: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn push_str(body: &mut Vec<u8>, text: &str) {
+    body.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    body.extend_from_slice(text.as_bytes());
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = alloc::vec![header];
+    push_remaining_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn connect_packet(config: &Config) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    push_str(&mut body, "MQTT");
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    push_str(&mut body, config.client_id);
+    for field in [config.username, config.password].into_iter().flatten() {
+        push_str(&mut body, field);
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload.as_bytes());
+    packet(PUBLISH, &body)
+}
+
+/// Queue a message, dropped when too many are pending
+pub fn publish(topic: String, payload: String) {
+    if !crate::capabilities::is_enabled(Capability::Mqtt) {
+        return;
+    }
+    if OUTBOX.try_send((topic, payload)).is_err() {
+        crate::log!("MQTT message dropped");
+    }
+}
+
+fn subscribe_packet(topics: &[&str]) -> Vec<u8> {
+    let mut body = alloc::vec![0, 1]; // packet id
+    for topic in topics {
+        push_str(&mut body, topic);
+        body.push(0); // QoS 0
+    }
+    packet(SUBSCRIBE, &body)
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, mut buf: &mut [u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match socket.read(buf).await? {
+            0 => return Err(Error::Closed),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+async fn write_all(socket: &mut TcpSocket<'_>, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        let n = socket.write(buf).await?;
+        buf = &buf[n..];
+    }
+    socket.flush().await?;
+    Ok(())
+}
+
+/// Rest of a packet after its first byte, `None` when larger than `buf`
+/// (it is skipped)
+async fn read_packet<'b>(
+    socket: &mut TcpSocket<'_>,
+    buf: &'b mut [u8],
+) -> Result<Option<&'b [u8]>, Error> {
+    let mut len = 0usize;
+    for shift in 0..4 {
+        let mut byte = [0u8];
+        read_exact(socket, &mut byte).await?;
+        len |= ((byte[0] & 0x7F) as usize) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            if len <= buf.len() {
+                read_exact(socket, &mut buf[..len]).await?;
+                return Ok(Some(&buf[..len]));
+            }
+            while len > 0 {
+                let chunk = len.min(buf.len());
+                read_exact(socket, &mut buf[..chunk]).await?;
+                len -= chunk;
+            }
+            return Ok(None);
+        }
+    }
+    Err(Error::Protocol)
+}
+
+/// Topic and payload of a QoS 0 PUBLISH body
+fn parse_publish(body: &[u8]) -> Option<(&str, &[u8])> {
+    let len = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = core::str::from_utf8(body.get(2..2 + len)?).ok()?;
+    Some((topic, &body[2 + len..]))
+}
+
+async fn session(
+    stack: Stack<'static>,
+    config: &Config,
+    handler: Handler,
+    rx_buffer: &mut [u8],
+    tx_buffer: &mut [u8],
+) -> Result<(), Error> {
+    let address = crate::dns::resolve(stack, config.host)
+        .await
+        .map_err(|_| Error::Dns)?;
+
+    let _lease = crate::sockets::lease(crate::sockets::Use::Mqtt);
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    socket.set_timeout(Some(SOCKET_TIMEOUT));
+    socket
+        .connect((address, config.port))
+        .await
+        .map_err(Error::Connect)?;
+
+    write_all(&mut socket, &connect_packet(config)).await?;
+    let mut buf = [0u8; MAX_PACKET];
+    let mut header = [0u8];
+    read_exact(&mut socket, &mut header).await?;
+    match read_packet(&mut socket, &mut buf).await? {
+        Some([_, 0]) if header[0] == CONNACK => {}
+        Some([_, code]) if header[0] == CONNACK => return Err(Error::Refused(*code)),
+        _ => return Err(Error::Protocol),
+    }
+    crate::log!("MQTT connected to {}", config.host);
+
+    if !config.topics.is_empty() {
+        write_all(&mut socket, &subscribe_packet(config.topics)).await?;
+    }
+
+    loop {
+        // Only the first byte is awaited with the ping timer, a packet is
+        // never left half read
+        match select3(
+            Timer::after(PING_PERIOD),
+            socket.read(&mut header),
+            OUTBOX.receive(),
+        )
+        .await
+        {
+            Either3::First(_) if !crate::capabilities::is_enabled(Capability::Mqtt) => {
+                crate::log!("MQTT disabled, disconnecting");
+                return write_all(&mut socket, &[DISCONNECT, 0]).await;
+            }
+            Either3::First(_) => write_all(&mut socket, &[PINGREQ, 0]).await?,
+            Either3::Second(Ok(0)) => return Err(Error::Closed),
+            Either3::Second(Ok(_)) => {
+                let body = read_packet(&mut socket, &mut buf).await?;
+                // QoS 0 only, as subscribed
+                if header[0] & 0xF6 == PUBLISH {
+                    if let Some((topic, payload)) = body.and_then(parse_publish) {
+                        handler(topic, payload);
+                    }
+                }
+            }
+            Either3::Second(Err(e)) => return Err(e.into()),
+            Either3::Third((topic, payload)) => {
+                write_all(&mut socket, &publish_packet(&topic, &payload)).await?
+            }
+        }
+    }
+}
+
+/// Stay connected to the broker while the station has an address
+///
+/// The session is dropped as soon as `net_events` reports the address lost,
+/// without waiting for the socket to time out.
+#[embassy_executor::task]
+pub async fn mqtt_task(
+    stack: Stack<'static>,
+    mut net_events: NetEventSubscriber,
+    config: &'static Config,
+    handler: Handler,
+) {
+    let mut rx_buffer = [0u8; crate::sockets::MQTT_RX_BUFFER];
+    let mut tx_buffer = [0u8; crate::sockets::MQTT_TX_BUFFER];
+    loop {
+        crate::capabilities::wait_enabled(Capability::Mqtt).await;
+        wifimanager::wait_ip(stack, &mut net_events).await;
+        crate::watchdog::beat(crate::watchdog::Task::Mqtt);
+        match select(
+            session(stack, config, handler, &mut rx_buffer, &mut tx_buffer),
+            wifimanager::wait_ip_lost(stack, &mut net_events),
+        )
+        .await
+        {
+            Either::First(Ok(())) => {}
+            Either::First(Err(e)) => {
+                crate::log!("MQTT error: {e:?}");
+                crate::watchdog::error(crate::watchdog::Task::Mqtt, &alloc::format!("{e:?}"));
+            }
+            Either::Second(()) => {
+                crate::log!("MQTT paused, no IP");
+                continue;
+            }
+        }
+        Timer::after(RECONNECT_DELAY).await;
+    }
+}
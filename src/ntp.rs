@@ -0,0 +1,729 @@
+use alloc::{string::String, vec::Vec};
+
+use core::{
+    cell::{Cell, RefCell},
+    net::{IpAddr, SocketAddr},
+};
+
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    mutex::Mutex as AsyncMutex,
+};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use esp_hal::rtc_cntl::Rtc;
+use serde::{Deserialize, Serialize};
+use sntpc::{get_time, NtpContext, NtpResult, NtpTimestampGenerator, NtpUdpSocket};
+
+use crate::{
+    dns, sha1, sockets,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub const NTP_PORT: u16 = 123;
+
+/// Used until other servers are set
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+/// Servers tried in turn
+pub const MAX_SERVERS: usize = 4;
+const MAX_HOST_LEN: usize = 64;
+/// Longest key, ntpd reads longer `SHA1` keys as hex
+const MAX_KEY_LEN: usize = sha1::DIGEST_LEN;
+/// Prefix of the hex keys of chrony
+const HEX_PREFIX: &str = "HEX:";
+
+/// Microseconds in a second
+const USEC_IN_SEC: u64 = 1_000_000;
+
+/// Max time waiting for the DNS answer or the NTP response
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// NTP header size, without extension fields nor MAC
+const NTP_PACKET_LEN: usize = 48;
+
+/// Key identifier followed by the SHA-1 digest
+const MAC_LEN: usize = 4 + sha1::DIGEST_LEN;
+
+/// Number of sync results kept in the history
+pub const HISTORY_LEN: usize = 128;
+/// Last syncs the RTC drift is estimated from
+const DRIFT_SAMPLES: usize = 8;
+
+/// Time over which the displayed time absorbs a leap second
+const LEAP_SMEAR: Duration = Duration::from_secs(20 * 60);
+
+static HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History>> =
+    Mutex::new(RefCell::new(History::new()));
+/// Leap second announced by the last sync
+static LEAP: Mutex<CriticalSectionRawMutex, Cell<Option<LeapSecond>>> = Mutex::new(Cell::new(None));
+/// Unix time of the last leap second applied, so a late announce is ignored
+static LEAP_APPLIED: Mutex<CriticalSectionRawMutex, Cell<Option<i64>>> =
+    Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug)]
+pub struct SyncRecord {
+    /// Unix time of the sync, in seconds
+    pub timestamp: u32,
+    /// Clock offset corrected by the sync, in µs
+    pub offset_us: i64,
+    /// Round trip delay, in µs
+    pub delay_us: u64,
+    pub server: IpAddr,
+    /// Uptime at the sync
+    pub at: Instant,
+}
+
+/// Ring buffer of the last `HISTORY_LEN` successful syncs
+pub struct History {
+    records: [Option<SyncRecord>; HISTORY_LEN],
+    next: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            records: [None; HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, record: SyncRecord) {
+        self.records[self.next] = Some(record);
+        self.next = (self.next + 1) % HISTORY_LEN;
+    }
+
+    /// Records from the oldest to the newest
+    pub fn iter(&self) -> impl Iterator<Item = &SyncRecord> {
+        self.records[self.next..]
+            .iter()
+            .chain(self.records[..self.next].iter())
+            .flatten()
+    }
+}
+
+/// Estimated clock error, for users checking the clock meets their needs
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Accuracy {
+    /// Bound of the error now, in µs
+    pub error_us: u64,
+    /// Half the round trip delay of the last sync, its own error bound, in µs
+    pub sync_error_us: u64,
+    /// Worst RTC drift over the last syncs, in µs per second
+    pub drift_ppm: f32,
+    /// Since the last sync
+    pub since_sync_s: u64,
+}
+
+/// Error bound from the last sync and the worst recent drift, `None` before
+/// the first sync
+///
+/// The offset a sync corrects is the drift accumulated since the previous one.
+pub fn accuracy() -> Option<Accuracy> {
+    with_history(|history| {
+        let records: Vec<&SyncRecord> = history.iter().collect();
+        let last = records.last()?;
+        let drift_ppm = records
+            .windows(2)
+            .rev()
+            .take(DRIFT_SAMPLES)
+            .filter_map(|pair| {
+                let elapsed = pair[1].timestamp.checked_sub(pair[0].timestamp)?;
+                (elapsed > 0).then(|| pair[1].offset_us.unsigned_abs() as f32 / elapsed as f32)
+            })
+            .fold(0.0, f32::max);
+        let sync_error_us = last.delay_us / 2;
+        let since_sync_s = last.at.elapsed().as_secs();
+        Some(Accuracy {
+            error_us: sync_error_us + (drift_ppm * since_sync_s as f32) as u64,
+            sync_error_us,
+            drift_ppm,
+            since_sync_s,
+        })
+    })
+}
+
+/// Run `f` with the sync history
+pub fn with_history<R>(f: impl FnOnce(&History) -> R) -> R {
+    HISTORY.lock(|history| f(&history.borrow()))
+}
+
+/// Unix time estimated from the last sync, for code without the RTC, `None`
+/// before the first sync
+pub fn unix_time() -> Option<i64> {
+    with_history(|history| {
+        let last = history.iter().last()?;
+        Some(last.timestamp as i64 + last.at.elapsed().as_secs() as i64)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Leap {
+    /// 23:59:60 follows 23:59:59
+    Insert,
+    /// 23:59:58 is followed by 00:00:00
+    Delete,
+}
+
+/// Leap second announced by the leap indicator of the servers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct LeapSecond {
+    pub leap: Leap,
+    /// Unix time of the midnight ending the day with the leap second
+    pub at: i64,
+}
+
+impl LeapSecond {
+    /// RTC time the second is repeated or skipped at, in µs
+    fn step_us(&self) -> i64 {
+        match self.leap {
+            Leap::Insert => self.at * USEC_IN_SEC as i64,
+            Leap::Delete => (self.at - 1) * USEC_IN_SEC as i64,
+        }
+    }
+}
+
+/// How the display goes over a leap second, the RTC is stepped either way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeapMode {
+    /// Slow down or speed up the displayed time over `LEAP_SMEAR` before it
+    #[default]
+    Smear,
+    /// Show 23:59:59 twice, or skip it
+    Repeat,
+}
+
+/// Leap second to come, `None` when none is announced
+pub fn leap_second() -> Option<LeapSecond> {
+    LEAP.lock(|leap| leap.get())
+}
+
+/// Keep the leap second announced with the leap indicator `li` of a response
+/// at `now`, in Unix seconds
+fn announce_leap(li: u8, now: u32) {
+    let leap = match li {
+        1 => Leap::Insert,
+        2 => Leap::Delete,
+        // 3 is an unsynchronized server, rejected by the client
+        _ => {
+            LEAP.lock(|leap| leap.set(None));
+            return;
+        }
+    };
+
+    // Servers may announce it for the whole month, it happens at the end of
+    // its last day
+    let today = jiff::Timestamp::from_second(now as i64)
+        .unwrap_or_default()
+        .to_zoned(jiff::tz::TimeZone::UTC)
+        .date();
+    let at = today
+        .tomorrow()
+        .ok()
+        .filter(|tomorrow| tomorrow.day() == 1)
+        .and_then(|tomorrow| tomorrow.to_zoned(jiff::tz::TimeZone::UTC).ok())
+        .map(|midnight| midnight.timestamp().as_second())
+        .filter(|&at| LEAP_APPLIED.lock(|applied| applied.get()) != Some(at));
+
+    let announced = at.map(|at| LeapSecond { leap, at });
+    let previous = LEAP.lock(|current| current.replace(announced));
+    if let Some(announced) = announced.filter(|_| previous != announced) {
+        crate::log!("Leap second {:?} at {}", announced.leap, announced.at);
+    }
+}
+
+/// Wait for the leap second announced and step `rtc` over it, pending while
+/// there is none
+pub async fn leap(rtc: &Rtc<'_>) {
+    let Some(leap) = leap_second() else {
+        return core::future::pending().await;
+    };
+    let left_us = leap.step_us() - rtc.current_time_us() as i64;
+    Timer::after(Duration::from_micros(left_us.max(0) as u64)).await;
+
+    let now_us = rtc.current_time_us();
+    match leap.leap {
+        Leap::Insert => rtc.set_current_time_us(now_us.saturating_sub(USEC_IN_SEC)),
+        Leap::Delete => rtc.set_current_time_us(now_us + USEC_IN_SEC),
+    }
+    LEAP.lock(|current| current.set(None));
+    LEAP_APPLIED.lock(|applied| applied.set(Some(leap.at)));
+    crate::log!("Leap second {:?} applied", leap.leap);
+}
+
+/// Correction of the displayed time for the RTC time `rtc_us` while a leap
+/// second is smeared, in µs
+pub fn leap_smear_us(rtc_us: u64) -> i64 {
+    let Some(leap) = leap_second().filter(|_| settings().leap == LeapMode::Smear) else {
+        return 0;
+    };
+    let start_us = leap.step_us() - LEAP_SMEAR.as_micros() as i64;
+    let elapsed_us = (rtc_us as i64 - start_us).clamp(0, LEAP_SMEAR.as_micros() as i64);
+    let smear_us = elapsed_us * USEC_IN_SEC as i64 / LEAP_SMEAR.as_micros() as i64;
+    match leap.leap {
+        Leap::Insert => -smear_us,
+        Leap::Delete => smear_us,
+    }
+}
+
+/// Pre-shared key for symmetric NTP authentication (RFC 5905 MAC, SHA-1 digest)
+///
+/// Must match a `SHA1` entry of the server keys file (ntpd `keys`, chrony `keyfile`).
+#[derive(Clone, Copy)]
+pub struct SymmetricKey {
+    pub id: u32,
+    secret: [u8; MAX_KEY_LEN],
+    len: usize,
+}
+
+impl SymmetricKey {
+    /// `secret` written as in the keys file: ASCII up to 20 characters, or
+    /// hex, longer for ntpd and prefixed by `HEX:` for chrony
+    pub fn new(id: u32, secret: &str) -> Option<Self> {
+        let mut key = Self {
+            id,
+            secret: [0; MAX_KEY_LEN],
+            len: 0,
+        };
+        let hex = secret
+            .strip_prefix(HEX_PREFIX)
+            .or((secret.len() > MAX_KEY_LEN).then_some(secret));
+        match hex {
+            Some(hex) => {
+                if hex.is_empty() || hex.len() % 2 != 0 || hex.len() > 2 * MAX_KEY_LEN {
+                    return None;
+                }
+                for (byte, digits) in key.secret.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                    let digits = core::str::from_utf8(digits).ok()?;
+                    *byte = u8::from_str_radix(digits, 16).ok()?;
+                }
+                key.len = hex.len() / 2;
+            }
+            None => {
+                if secret.is_empty() || !secret.bytes().all(|b| b.is_ascii_graphic()) {
+                    return None;
+                }
+                key.secret[..secret.len()].copy_from_slice(secret.as_bytes());
+                key.len = secret.len();
+            }
+        }
+        (id != 0).then_some(key)
+    }
+
+    fn secret(&self) -> &[u8] {
+        &self.secret[..self.len]
+    }
+}
+
+impl ::core::fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        f.debug_struct("SymmetricKey").field("id", &self.id).finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NtpServer<'a> {
+    /// Hostname or IP address
+    pub host: &'a str,
+    pub port: u16,
+
+    /// Authenticate requests and responses with this key
+    pub key: Option<SymmetricKey>,
+}
+
+/// Appends a MAC to outgoing packets and rejects responses without a valid one
+struct AuthenticatedSocket<'a, 's> {
+    socket: &'a UdpSocket<'s>,
+    key: SymmetricKey,
+}
+
+impl AuthenticatedSocket<'_, '_> {
+    fn digest(&self, packet: &[u8]) -> [u8; sha1::DIGEST_LEN] {
+        sha1::digest(&[self.key.secret(), packet])
+    }
+}
+
+impl NtpUdpSocket for AuthenticatedSocket<'_, '_> {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> sntpc::Result<usize> {
+        if buf.len() != NTP_PACKET_LEN {
+            return Err(sntpc::Error::IncorrectPayload);
+        }
+
+        let mut packet = [0u8; NTP_PACKET_LEN + MAC_LEN];
+        packet[..NTP_PACKET_LEN].copy_from_slice(buf);
+        packet[NTP_PACKET_LEN..NTP_PACKET_LEN + 4].copy_from_slice(&self.key.id.to_be_bytes());
+        packet[NTP_PACKET_LEN + 4..].copy_from_slice(&self.digest(buf));
+
+        NtpUdpSocket::send_to(self.socket, &packet, addr).await?;
+        Ok(buf.len())
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> sntpc::Result<(usize, SocketAddr)> {
+        let mut packet = [0u8; NTP_PACKET_LEN + MAC_LEN];
+        let (len, addr) = NtpUdpSocket::recv_from(self.socket, &mut packet).await?;
+
+        let (header, mac) = packet.split_at(NTP_PACKET_LEN);
+        let valid = len == packet.len()
+            && mac[..4] == self.key.id.to_be_bytes()
+            && sha1::ct_eq(&mac[4..], &self.digest(header));
+        if !valid || buf.len() < NTP_PACKET_LEN {
+            crate::log!("NTP response from {addr} failed authentication");
+            return Err(sntpc::Error::IncorrectPayload);
+        }
+
+        buf[..NTP_PACKET_LEN].copy_from_slice(header);
+        Ok((NTP_PACKET_LEN, addr))
+    }
+}
+
+/// Keeps the leap indicator of the responses, the client does not report it
+struct LeapIndicator<'a, S> {
+    socket: &'a S,
+    li: &'a Cell<u8>,
+}
+
+impl<S: NtpUdpSocket> NtpUdpSocket for LeapIndicator<'_, S> {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> sntpc::Result<usize> {
+        self.socket.send_to(buf, addr).await
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> sntpc::Result<(usize, SocketAddr)> {
+        let (len, addr) = self.socket.recv_from(buf).await?;
+        if let Some(&first) = buf[..len].first() {
+            self.li.set(first >> 6);
+        }
+        Ok((len, addr))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Timestamp<'a, 'd> {
+    rtc: &'a Rtc<'d>,
+    current_time_us: u64,
+}
+
+impl NtpTimestampGenerator for Timestamp<'_, '_> {
+    fn init(&mut self) {
+        self.current_time_us = self.rtc.current_time_us();
+    }
+
+    fn timestamp_sec(&self) -> u64 {
+        self.current_time_us / USEC_IN_SEC
+    }
+
+    fn timestamp_subsec_micros(&self) -> u32 {
+        (self.current_time_us % USEC_IN_SEC) as u32
+    }
+}
+
+#[derive(Debug)]
+pub enum NtpError {
+    /// Stack has no IP configuration
+    NoLink,
+    Dns(embassy_net::dns::Error),
+    /// DNS answered without any address
+    NoAddress,
+    Bind(embassy_net::udp::BindError),
+    Sntp(sntpc::Error),
+    Timeout,
+}
+
+/// Servers to sync from, set through the HTTP API for a LAN server
+///
+/// ```json
+/// {"servers":[{"host":"192.168.1.2","key_id":1,"secret":"..."},{"host":"pool.ntp.org"}],
+///  "port":123,"leap":"Smear"}
+/// ```
+///
+/// A server with `key_id` and `secret` is authenticated with that key, the
+/// secrets are never answered back.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NtpSettings {
+    /// In order of preference
+    pub servers: Vec<ServerEntry>,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub leap: LeapMode,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerEntry {
+    /// Hostname or IP address
+    pub host: String,
+    /// Symmetric key shared with the server, see `SymmetricKey`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// Settings saved before the keys, servers were hosts alone
+#[derive(Deserialize)]
+struct LegacySettings {
+    servers: Vec<String>,
+    #[serde(default = "default_port")]
+    port: u16,
+    #[serde(default)]
+    leap: LeapMode,
+}
+
+fn default_port() -> u16 {
+    NTP_PORT
+}
+
+impl Default for NtpSettings {
+    fn default() -> Self {
+        Self {
+            servers: alloc::vec![ServerEntry::unauthenticated(DEFAULT_SERVER.into())],
+            port: NTP_PORT,
+            leap: LeapMode::Smear,
+        }
+    }
+}
+
+impl From<LegacySettings> for NtpSettings {
+    fn from(legacy: LegacySettings) -> Self {
+        Self {
+            servers: legacy
+                .servers
+                .into_iter()
+                .map(ServerEntry::unauthenticated)
+                .collect(),
+            port: legacy.port,
+            leap: legacy.leap,
+        }
+    }
+}
+
+impl ServerEntry {
+    fn unauthenticated(host: String) -> Self {
+        Self {
+            host,
+            key_id: None,
+            secret: None,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let key = match (self.key_id, &self.secret) {
+            (None, None) => true,
+            (Some(id), Some(secret)) => SymmetricKey::new(id, secret).is_some(),
+            _ => false,
+        };
+        key && !self.host.is_empty() && self.host.len() <= MAX_HOST_LEN
+    }
+
+    /// Key requests to this server are authenticated with
+    pub fn key(&self) -> Option<SymmetricKey> {
+        SymmetricKey::new(self.key_id?, self.secret.as_deref()?)
+    }
+}
+
+impl NtpSettings {
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_SERVERS).contains(&self.servers.len())
+            && self.servers.iter().all(ServerEntry::is_valid)
+            && self.port != 0
+    }
+
+    /// First server, checked by the connectivity self-test
+    pub fn primary(&self) -> &str {
+        self.servers
+            .first()
+            .map_or(DEFAULT_SERVER, |server| server.host.as_str())
+    }
+
+    /// Without the secrets, to answer through the API
+    pub fn redacted(mut self) -> Self {
+        for server in &mut self.servers {
+            server.secret = None;
+        }
+        self
+    }
+}
+
+static SETTINGS: Mutex<CriticalSectionRawMutex, RefCell<Option<NtpSettings>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Current settings, the defaults until some are loaded or set
+pub fn settings() -> NtpSettings {
+    SETTINGS.lock(|current| current.borrow().clone().unwrap_or_default())
+}
+
+/// Read the settings saved in NVS, or the servers saved before the keys
+pub async fn load(storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>) {
+    let mut nvs = storage.lock().await;
+    let settings = match nvs.read_json::<NtpSettings>(Record::Ntp) {
+        Some(settings) => settings.ok(),
+        None => match nvs.read_json::<LegacySettings>(Record::NtpLegacy) {
+            Some(legacy) => legacy.ok().map(Into::into),
+            None => return,
+        },
+    };
+
+    match settings.filter(NtpSettings::is_valid) {
+        Some(settings) => SETTINGS.lock(|current| *current.borrow_mut() = Some(settings)),
+        None => crate::log!("Invalid saved NTP servers, using defaults"),
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &AsyncMutex<CriticalSectionRawMutex, Nvs>,
+    settings: NtpSettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Ntp, &settings)?;
+    SETTINGS.lock(|current| *current.borrow_mut() = Some(settings));
+    Ok(())
+}
+
+/// Sync from the first configured server answering, the error of the last
+/// one otherwise, each with its own key
+pub async fn sync_any(stack: Stack<'_>, rtc: &Rtc<'_>) -> Result<NtpResult, NtpError> {
+    let settings = settings();
+    let mut result = Err(NtpError::NoAddress);
+    for entry in &settings.servers {
+        let server = NtpServer {
+            host: &entry.host,
+            port: settings.port,
+            key: entry.key(),
+        };
+        result = sync(stack, &server, rtc).await;
+        match &result {
+            Ok(_) | Err(NtpError::NoLink) => break,
+            Err(e) => crate::log!("NTP server {} failed: {e:?}", entry.host),
+        }
+    }
+    result
+}
+
+/// Query `server` once and set `rtc` from the answer
+///
+/// A fresh socket bound to an ephemeral port is used for every sync, so a stack
+/// reset after a Wi-Fi drop never leaves the client with a dead socket.
+pub async fn sync(
+    stack: Stack<'_>,
+    server: &NtpServer<'_>,
+    rtc: &Rtc<'_>,
+) -> Result<NtpResult, NtpError> {
+    if !stack.is_config_up() {
+        return Err(NtpError::NoLink);
+    }
+
+    let addr: IpAddr = with_timeout(SYNC_TIMEOUT, dns::resolve(stack, server.host))
+        .await
+        .map_err(|_| NtpError::Timeout)?
+        .map_err(NtpError::Dns)?
+        .into();
+
+    let _lease = sockets::lease(sockets::Use::Ntp);
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 512];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 512];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).map_err(NtpError::Bind)?;
+
+    let server_addr = SocketAddr::from((addr, server.port));
+    let context = NtpContext::new(Timestamp {
+        rtc,
+        current_time_us: 0,
+    });
+    let leap_indicator = Cell::new(0);
+    let result = match server.key {
+        Some(key) => {
+            let socket = AuthenticatedSocket {
+                socket: &socket,
+                key,
+            };
+            let socket = LeapIndicator {
+                socket: &socket,
+                li: &leap_indicator,
+            };
+            with_timeout(SYNC_TIMEOUT, get_time(server_addr, &socket, context)).await
+        }
+        None => {
+            let socket = LeapIndicator {
+                socket: &socket,
+                li: &leap_indicator,
+            };
+            with_timeout(SYNC_TIMEOUT, get_time(server_addr, &socket, context)).await
+        }
+    };
+    let time = result
+        .map_err(|_| NtpError::Timeout)?
+        .map_err(NtpError::Sntp)?;
+
+    // Set time immediately after receiving to reduce time offset.
+    rtc.set_current_time_us(
+        (time.sec() as u64 * USEC_IN_SEC) + ((time.sec_fraction() as u64 * USEC_IN_SEC) >> 32),
+    );
+    announce_leap(leap_indicator.get(), time.sec());
+
+    HISTORY.lock(|history| {
+        history.borrow_mut().push(SyncRecord {
+            timestamp: time.sec(),
+            offset_us: time.offset(),
+            delay_us: time.roundtrip(),
+            server: addr,
+            at: Instant::now(),
+        })
+    });
+
+    Ok(time)
+}
+
+/// Tracks successful syncs to warn when the displayed time may be drifting
+pub struct SyncTracker {
+    last_sync: Option<Instant>,
+    last_alert: Option<Instant>,
+
+    /// Time without successful sync after which the clock is considered desynced
+    pub alert_after: Duration,
+}
+
+impl SyncTracker {
+    pub fn new(alert_after: Duration) -> Self {
+        Self {
+            last_sync: None,
+            last_alert: None,
+            alert_after,
+        }
+    }
+
+    pub fn synced(&mut self) {
+        self.last_sync = Some(Instant::now());
+        self.last_alert = None;
+    }
+
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.last_sync
+    }
+
+    /// Never synced counts from boot
+    pub fn is_desynced(&self) -> bool {
+        self.last_sync.unwrap_or(Instant::MIN).elapsed() >= self.alert_after
+    }
+
+    /// True at most once per `every` while desynced
+    pub fn alert_due(&mut self, every: Duration) -> bool {
+        if !self.is_desynced() {
+            return false;
+        }
+
+        let due = self.last_alert.is_none_or(|last| last.elapsed() >= every);
+        if due {
+            self.last_alert = Some(Instant::now());
+        }
+        due
+    }
+}
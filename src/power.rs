@@ -0,0 +1,46 @@
+//! USB power loss detection, for builds with a battery backup
+//!
+//! VBUS is sensed on a GPIO through a divider. While on battery, the display
+//! stays at the lowest brightness and non-essential work, like the weather
+//! fetch, is skipped.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+use crate::brightness;
+
+/// When VBUS was lost, `None` while powered
+static LOST_AT: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+
+pub fn is_on_battery() -> bool {
+    LOST_AT.lock(|lost_at| lost_at.get().is_some())
+}
+
+/// VBUS sensed `present`, returns how long it was away when it comes back
+pub fn set_vbus(present: bool) -> Option<Duration> {
+    let lost_at = LOST_AT.lock(|lost_at| {
+        let previous = lost_at.get();
+        match (present, previous) {
+            (false, None) => lost_at.set(Some(Instant::now())),
+            (true, Some(_)) => lost_at.set(None),
+            _ => {}
+        }
+        previous
+    });
+
+    match (present, lost_at) {
+        (false, None) => {
+            crate::log!("USB power lost, on battery");
+            brightness::set_saving(true);
+            None
+        }
+        (true, Some(lost_at)) => {
+            crate::log!("USB power back after {}s", lost_at.elapsed().as_secs());
+            brightness::set_saving(false);
+            Some(lost_at.elapsed())
+        }
+        _ => None,
+    }
+}
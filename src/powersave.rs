@@ -0,0 +1,92 @@
+//! Modem sleep of the station
+//!
+//! The radio stays awake by default, answering at once but drawing about
+//! 80 mA more than asleep. `Minimum` wakes it for every DTIM beacon: API
+//! requests, MQTT messages and pings wait up to a DTIM period, 100 to 300ms
+//! on most access points. `Maximum` wakes it at the listen interval, several
+//! beacons apart: a second or more of latency, and multicast like the
+//! discovery announcements is often missed. `quiet` replaces `mode` while do
+//! not disturb is active. Settings are JSON, read and written through the
+//! HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"mode":"Minimum","quiet":"Maximum"}
+//! ```
+//!
+//! The setup AP always runs with the radio awake.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::dnd;
+use crate::wifimanager::{self, Nvs, Record, RecordError};
+
+pub use crate::wifimanager::radio::PowerSave;
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<PowerSaveSettings>> =
+    BlockingMutex::new(Cell::new(PowerSaveSettings {
+        mode: PowerSave::None,
+        quiet: None,
+    }));
+/// Mode given to the radio, `None` before the first tick
+static APPLIED: BlockingMutex<CriticalSectionRawMutex, Cell<Option<PowerSave>>> =
+    BlockingMutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerSaveSettings {
+    #[serde(default)]
+    pub mode: PowerSave,
+    /// While do not disturb is active, `mode` when `None`
+    #[serde(default)]
+    pub quiet: Option<PowerSave>,
+}
+
+/// Current settings
+pub fn settings() -> PowerSaveSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Give the radio the mode of the moment, when it changed
+pub fn tick() {
+    let settings = settings();
+    let mode = match settings.quiet {
+        Some(quiet) if dnd::is_active() => quiet,
+        _ => settings.mode,
+    };
+    let changed = APPLIED.lock(|applied| applied.replace(Some(mode)) != Some(mode));
+    if changed {
+        wifimanager::set_power_save(mode);
+    }
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<PowerSaveSettings>(Record::PowerSave);
+    match saved {
+        Some(Ok(settings)) => SETTINGS.lock(|current| current.set(settings)),
+        Some(Err(_)) => crate::log!("Invalid saved power save, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: PowerSaveSettings,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::PowerSave, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    tick();
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Preferences set on the clock itself, through the button menu
+//!
+//! Kept in NVS as three bytes: the manual brightness, 0xFF to
+//! follow the theme, the 12 hour clock flag and the fixed UTC offset in hours,
+//! 0x7F to follow the timezone rules.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+
+use crate::{
+    brightness,
+    wifimanager::{Nvs, Record},
+};
+
+const UNSET: u8 = 0xFF;
+const NO_OFFSET: i8 = 0x7F;
+
+/// Fixed offsets, the timezones in use
+pub const UTC_OFFSETS: core::ops::RangeInclusive<i8> = -12..=14;
+
+static PREFS: BlockingMutex<CriticalSectionRawMutex, Cell<Preferences>> =
+    BlockingMutex::new(Cell::new(Preferences {
+        brightness: None,
+        hour12: false,
+        utc_offset: None,
+    }));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Preferences {
+    /// Replaces the theme's
+    pub brightness: Option<u8>,
+    /// 1 to 12 hours instead of 0 to 23
+    pub hour12: bool,
+    /// Hours from UTC, replacing the timezone rules
+    pub utc_offset: Option<i8>,
+}
+
+/// Current preferences
+pub fn get() -> Preferences {
+    PREFS.lock(|prefs| prefs.get())
+}
+
+/// Apply `prefs`, kept until the next `save`
+pub fn set(prefs: Preferences) {
+    brightness::set_manual(prefs.brightness);
+    PREFS.lock(|current| current.set(prefs));
+}
+
+/// Read the preferences saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; 3];
+    let Some(&[brightness, hour12, utc_offset]) =
+        storage.lock().await.read_record(Record::Prefs, &mut buf)
+    else {
+        return;
+    };
+
+    let utc_offset = utc_offset as i8;
+    set(Preferences {
+        brightness: (brightness != UNSET).then_some(brightness),
+        hour12: hour12 != 0,
+        utc_offset: UTC_OFFSETS.contains(&utc_offset).then_some(utc_offset),
+    });
+}
+
+/// Save the current preferences
+pub async fn save(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let prefs = get();
+    let record = [
+        prefs.brightness.unwrap_or(UNSET),
+        prefs.hour12 as u8,
+        prefs.utc_offset.unwrap_or(NO_OFFSET) as u8,
+    ];
+
+    if let Err(e) = storage.lock().await.write_record(Record::Prefs, &record) {
+        crate::log!("Preferences not saved: {e:?}");
+    }
+}
@@ -0,0 +1,187 @@
+//! Room occupancy from Bluetooth advertisements of known phones
+//!
+//! With the `ble` feature, a passive scan watches for the advertisements of
+//! the configured devices. The room is vacant once none was heard for
+//! `ABSENT_AFTER`, and the display then dims to its lowest level. Without the
+//! scanner the room is always occupied.
+//!
+//! Phones hiding their address behind resolvable private addresses are
+//! recognized with their identity resolving key (IRK).
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// Vacant after this long without an advertisement from a known device
+const ABSENT_AFTER: Duration = Duration::from_secs(3 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Device {
+    /// Public or static address, as written by phones: "AA:BB:CC:DD:EE:FF"
+    /// is `[0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]`
+    Address([u8; 6]),
+    /// Identity resolving key, most significant byte first
+    Irk([u8; 16]),
+}
+
+#[derive(Clone, Copy)]
+struct Presence {
+    /// Start of the scan, `None` when there is no scanner
+    scanning_since: Option<Instant>,
+    last_seen: Option<Instant>,
+}
+
+static PRESENCE: Mutex<CriticalSectionRawMutex, Cell<Presence>> = Mutex::new(Cell::new(Presence {
+    scanning_since: None,
+    last_seen: None,
+}));
+
+fn update(f: impl FnOnce(&mut Presence)) {
+    PRESENCE.lock(|presence| {
+        let mut current = presence.get();
+        f(&mut current);
+        presence.set(current);
+    });
+}
+
+/// Called once the scan runs
+pub fn scanning() {
+    update(|presence| presence.scanning_since = Some(Instant::now()));
+}
+
+/// A known device was heard
+pub fn seen() {
+    update(|presence| presence.last_seen = Some(Instant::now()));
+}
+
+/// No known device heard for a while, always false without a scanner
+pub fn is_vacant() -> bool {
+    let presence = PRESENCE.lock(|presence| presence.get());
+    let Some(since) = presence.scanning_since else {
+        return false;
+    };
+    presence.last_seen.unwrap_or(since).elapsed() > ABSENT_AFTER
+}
+
+#[cfg(feature = "ble")]
+pub use scanner::ble_scan_task;
+
+#[cfg(feature = "ble")]
+mod scanner {
+    use bt_hci::{
+        cmd::{
+            le::{LeSetScanEnable, LeSetScanParams},
+            SyncCmd,
+        },
+        controller::{Controller, ExternalController},
+        event::{le::LeEvent, Event},
+        param::{AddrKind, Duration, LeScanKind, ScanningFilterPolicy},
+        ControllerToHostPacket,
+    };
+    use embassy_futures::join::join;
+    use esp_hal::{
+        aes::Aes,
+        peripherals::{AES, BT},
+    };
+    use esp_radio::ble::controller::BleConnector;
+
+    use super::Device;
+
+    /// Scan interval and window, leaving the radio to Wi-Fi half of the time
+    const SCAN_INTERVAL_MS: u32 = 100;
+    const SCAN_WINDOW_MS: u32 = 50;
+
+    /// Top bits of the most significant byte of a resolvable private address
+    const RPA_MARK: u8 = 0b01;
+
+    impl Device {
+        /// `address` as received over HCI, least significant byte first
+        fn matches(&self, address: &[u8], random: bool, aes: &mut Aes<'_>) -> bool {
+            match self {
+                Device::Address(known) => known.iter().rev().eq(address.iter()),
+                Device::Irk(irk) if random && address[5] >> 6 == RPA_MARK => {
+                    // ah(irk, prand) must give the hash in the low 24 bits
+                    let mut block = [0u8; 16];
+                    block[13] = address[5];
+                    block[14] = address[4];
+                    block[15] = address[3];
+                    aes.encrypt(&mut block, *irk);
+                    block[13..] == [address[2], address[1], address[0]]
+                }
+                Device::Irk(_) => false,
+            }
+        }
+    }
+
+    /// Scan for the advertisements of `devices`
+    #[embassy_executor::task]
+    pub async fn ble_scan_task(
+        radio: &'static esp_radio::Controller<'static>,
+        bt: BT<'static>,
+        aes: AES<'static>,
+        devices: &'static [Device],
+    ) {
+        let connector = match BleConnector::new(radio, bt, Default::default()) {
+            Ok(connector) => connector,
+            Err(e) => {
+                crate::log!("BLE init error: {e:?}");
+                return;
+            }
+        };
+        let controller: ExternalController<_, 4> = ExternalController::new(connector);
+        let mut aes = Aes::new(aes);
+
+        // Commands complete while the events are read
+        let start = async {
+            let params = LeSetScanParams::new(
+                LeScanKind::Passive,
+                Duration::from_millis(SCAN_INTERVAL_MS),
+                Duration::from_millis(SCAN_WINDOW_MS),
+                AddrKind::PUBLIC,
+                ScanningFilterPolicy::BasicUnfiltered,
+            );
+            let result = match params.exec(&controller).await {
+                Ok(()) => LeSetScanEnable::new(true, false).exec(&controller).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    crate::log!("BLE scanning for {} devices", devices.len());
+                    super::scanning();
+                }
+                Err(e) => crate::log!("BLE scan not started: {e:?}"),
+            }
+        };
+
+        let read = async {
+            let mut buf = [0u8; 259];
+            loop {
+                let packet = match controller.read(&mut buf).await {
+                    Ok(ControllerToHostPacket::Event(event)) => event,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        crate::log!("BLE read error: {e:?}");
+                        continue;
+                    }
+                };
+                let Ok(Event::Le(LeEvent::LeAdvertisingReport(report))) = Event::try_from(packet)
+                else {
+                    continue;
+                };
+                for report in report.reports.iter().flatten() {
+                    let random = report.addr_kind == AddrKind::RANDOM;
+                    let address = report.addr.raw();
+                    if devices
+                        .iter()
+                        .any(|device| device.matches(address, random, &mut aes))
+                    {
+                        super::seen();
+                    }
+                }
+            }
+        };
+
+        join(start, read).await;
+    }
+}
@@ -0,0 +1,112 @@
+//! Frame timing histograms, with the `profiler` feature, to measure
+//! optimizations on hardware
+//!
+//! Each stage of a frame records its duration in power of two buckets: bucket
+//! `i` counts durations from 2^i µs up to 2^(i+1) µs, the last one everything
+//! longer. `/api/profile` reports them, and resets them between two runs.
+//! Without the feature nothing is measured and the stages cost nothing.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use serde::Serialize;
+
+const ENABLED: bool = cfg!(feature = "profiler");
+/// Up to 2^15 µs, 32ms, in the bucket before the last
+const BUCKETS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Stage {
+    /// Widgets drawn and composited into the frame
+    Render,
+    /// Frame converted to the MAX7219 rows, `Canvas::to_raw`
+    ToRaw,
+    /// Rows sent to the MAX7219 chain over SPI
+    Flush,
+    /// Animation frame decoded into the canvas
+    Animation,
+}
+
+const STAGES: [Stage; 4] = [Stage::Render, Stage::ToRaw, Stage::Flush, Stage::Animation];
+
+impl Stage {
+    fn index(self) -> usize {
+        STAGES.iter().position(|&stage| stage == self).unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Histogram {
+    pub stage: Stage,
+    pub count: u32,
+    pub min_us: u64,
+    pub max_us: u64,
+    pub total_us: u64,
+    pub buckets: [u32; BUCKETS],
+}
+
+impl Histogram {
+    const fn new(stage: Stage) -> Self {
+        Histogram {
+            stage,
+            count: 0,
+            min_us: 0,
+            max_us: 0,
+            total_us: 0,
+            buckets: [0; BUCKETS],
+        }
+    }
+
+    fn add(&mut self, took_us: u64) {
+        let bucket = (u64::BITS - took_us.leading_zeros()).saturating_sub(1) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.min_us = if self.count == 0 {
+            took_us
+        } else {
+            self.min_us.min(took_us)
+        };
+        self.max_us = self.max_us.max(took_us);
+        self.total_us += took_us;
+        self.count += 1;
+    }
+}
+
+static HISTOGRAMS: Mutex<CriticalSectionRawMutex, RefCell<[Histogram; STAGES.len()]>> =
+    Mutex::new(RefCell::new([
+        Histogram::new(Stage::Render),
+        Histogram::new(Stage::ToRaw),
+        Histogram::new(Stage::Flush),
+        Histogram::new(Stage::Animation),
+    ]));
+
+/// Start of a stage, `None` without the feature
+pub fn start() -> Option<Instant> {
+    ENABLED.then(Instant::now)
+}
+
+/// Record the stage started at `started`
+pub fn record(stage: Stage, started: Option<Instant>) {
+    if let Some(started) = started {
+        let took_us = started.elapsed().as_micros();
+        HISTOGRAMS.lock(|histograms| histograms.borrow_mut()[stage.index()].add(took_us));
+    }
+}
+
+/// Run `f` as `stage`
+pub fn measure<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    let started = start();
+    let result = f();
+    record(stage, started);
+    result
+}
+
+/// Histogram of every stage
+pub fn report() -> [Histogram; STAGES.len()] {
+    HISTOGRAMS.lock(|histograms| *histograms.borrow())
+}
+
+/// Empty the histograms
+pub fn reset() {
+    HISTOGRAMS.lock(|histograms| *histograms.borrow_mut() = STAGES.map(Histogram::new));
+}
@@ -0,0 +1,146 @@
+//! Battery powered sensors in other rooms, pushing readings over ESP-NOW
+//!
+//! Satellites send a broadcast or unicast ESP-NOW frame on the channel of the
+//! access point the clock is connected to:
+//!
+//! | Bytes | Content                                  |
+//! |-------|------------------------------------------|
+//! | 0..2  | `b"B5"`                                  |
+//! | 2     | Version, 1                               |
+//! | 3..5  | Temperature in tenths of °C, i16 LE      |
+//! | 5     | Humidity in %, 0xFF when not measured    |
+//! | 6     | Battery in %, 0xFF when not measured     |
+//!
+//! Readings are kept per sender MAC address, without the `espnow` feature no
+//! reading is ever received.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::display::Canvas;
+
+const MAGIC: &[u8; 2] = b"B5";
+const VERSION: u8 = 1;
+const PACKET_LEN: usize = 7;
+/// Marks a value the satellite does not measure
+const NOT_MEASURED: u8 = 0xFF;
+
+/// Satellites tracked, the one heard from the longest ago makes room
+pub const MAX_SATELLITES: usize = 4;
+/// A satellite not heard from for this long is ignored
+const STALE: Duration = Duration::from_secs(15 * 60);
+/// Battery level logged as low
+const LOW_BATTERY: u8 = 10;
+
+static SATELLITES: Mutex<CriticalSectionRawMutex, RefCell<Vec<Satellite>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Satellite {
+    pub mac: [u8; 6],
+    /// °C
+    pub temperature: f32,
+    /// %
+    pub humidity: Option<u8>,
+    /// %
+    pub battery: Option<u8>,
+    #[serde(skip)]
+    seen: Instant,
+}
+
+fn parse(mac: [u8; 6], data: &[u8]) -> Option<Satellite> {
+    if data.len() < PACKET_LEN || &data[..2] != MAGIC || data[2] != VERSION {
+        return None;
+    }
+    let measured = |value: u8| (value != NOT_MEASURED).then_some(value);
+    Some(Satellite {
+        mac,
+        temperature: i16::from_le_bytes([data[3], data[4]]) as f32 / 10.0,
+        humidity: measured(data[5]),
+        battery: measured(data[6]),
+        seen: Instant::now(),
+    })
+}
+
+/// Handle a frame received from `mac`
+pub fn on_frame(mac: [u8; 6], data: &[u8]) {
+    let Some(reading) = parse(mac, data) else {
+        crate::log!("Invalid satellite frame from {mac:02X?}");
+        return;
+    };
+    if reading
+        .battery
+        .is_some_and(|battery| battery <= LOW_BATTERY)
+    {
+        crate::log!("Satellite {mac:02X?} battery low");
+    }
+
+    SATELLITES.lock(|satellites| {
+        let mut satellites = satellites.borrow_mut();
+        if let Some(known) = satellites.iter_mut().find(|known| known.mac == mac) {
+            *known = reading;
+            return;
+        }
+        if satellites.len() >= MAX_SATELLITES {
+            if let Some(oldest) = (0..satellites.len()).min_by_key(|&idx| satellites[idx].seen) {
+                satellites.swap_remove(oldest);
+            }
+        }
+        satellites.push(reading);
+    });
+}
+
+/// Satellites heard from recently
+pub fn readings() -> Vec<Satellite> {
+    let now = Instant::now();
+    SATELLITES.lock(|satellites| {
+        satellites
+            .borrow()
+            .iter()
+            .filter(|satellite| now - satellite.seen < STALE)
+            .copied()
+            .collect()
+    })
+}
+
+/// Most recent reading of any satellite
+pub fn latest() -> Option<Satellite> {
+    readings()
+        .into_iter()
+        .max_by_key(|satellite| satellite.seen)
+}
+
+/// House icon and the rounded temperature, in the 30x7 area at `y`
+pub fn draw<const W: usize, const H: usize>(
+    canvas: &mut Canvas<W, H>,
+    y: usize,
+    satellite: &Satellite,
+) {
+    // 5x5 house: roof then walls
+    for (x, dy) in [(2, 2), (3, 1), (4, 0), (5, 1), (6, 2)] {
+        canvas.on(x, y + dy);
+    }
+    for dy in 3..6 {
+        canvas.on(3, y + dy);
+        canvas.on(5, y + dy);
+    }
+    canvas.on(4, y + 5);
+
+    let text = crate::units::temperature(Some(satellite.temperature), 0);
+    canvas.print_5x7(9, y, &text);
+}
+
+/// Receive satellite frames
+#[cfg(feature = "espnow")]
+#[embassy_executor::task]
+pub async fn espnow_task(mut esp_now: esp_radio::esp_now::EspNow<'static>) {
+    crate::log!("ESP-NOW listening for satellites");
+    loop {
+        let frame = esp_now.receive_async().await;
+        on_frame(frame.info.src_address, frame.data());
+    }
+}
@@ -0,0 +1,104 @@
+use core::future::Future;
+
+use embassy_time::{Duration, Instant};
+
+/// Longest deferral of a slow widget, in frames
+const MAX_BACKOFF: u32 = 16;
+
+/// Render time accounting for one part of the frame
+///
+/// A widget whose smoothed render time exceeds its budget is skipped for a
+/// growing number of frames, leaving its previous pixels on the canvas, so slow
+/// widgets cannot delay the time update.
+pub struct Widget {
+    name: &'static str,
+    budget: Duration,
+    /// Never deferred, only reported
+    essential: bool,
+    /// Render time smoothed over the last frames, in µs
+    avg_us: u64,
+    /// Frames left to skip
+    skip: u32,
+    /// Frames skipped after the last overrun, doubled on each new one
+    backoff: u32,
+}
+
+impl Widget {
+    pub const fn new(name: &'static str, budget: Duration) -> Self {
+        Self {
+            name,
+            budget,
+            essential: false,
+            avg_us: 0,
+            skip: 0,
+            backoff: 0,
+        }
+    }
+
+    pub const fn essential(name: &'static str, budget: Duration) -> Self {
+        Self {
+            essential: true,
+            ..Self::new(name, budget)
+        }
+    }
+
+    /// Run `f` unless the widget is deferred, returns whether it ran
+    pub fn render(&mut self, f: impl FnOnce()) -> bool {
+        if self.is_deferred() {
+            return false;
+        }
+
+        let start = Instant::now();
+        f();
+        self.account(start.elapsed().as_micros());
+        true
+    }
+
+    /// As `render` for work that waits, the waits count in its time
+    pub async fn render_async(&mut self, f: impl Future<Output = ()>) -> bool {
+        if self.is_deferred() {
+            return false;
+        }
+
+        let start = Instant::now();
+        f.await;
+        self.account(start.elapsed().as_micros());
+        true
+    }
+
+    /// Skip this frame, counted as one of the deferred frames
+    fn is_deferred(&mut self) -> bool {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return true;
+        }
+        false
+    }
+
+    fn account(&mut self, took_us: u64) {
+        // Exponential moving average, so a single slow frame (interrupt, flash
+        // access) does not defer the widget
+        self.avg_us = match self.avg_us {
+            0 => took_us,
+            avg => (avg * 3 + took_us) / 4,
+        };
+
+        let budget_us = self.budget.as_micros();
+        if took_us > budget_us {
+            crate::log!(
+                "Widget {} took {}us, budget {}us",
+                self.name,
+                took_us,
+                budget_us
+            );
+        }
+
+        if self.essential || self.avg_us <= budget_us {
+            self.backoff = 0;
+        } else {
+            self.backoff = (self.backoff * 2).clamp(1, MAX_BACKOFF);
+            self.skip = self.backoff;
+            crate::log!("Widget {} deferred for {} frames", self.name, self.skip);
+        }
+    }
+}
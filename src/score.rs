@@ -0,0 +1,77 @@
+//! Two-sided score counter face, for table tennis or foosball
+//!
+//! Incremented through the HTTP API or the boot button, and kept in NVS so a
+//! reset in the middle of a game does not lose it.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+
+use crate::{
+    display::Canvas,
+    wifimanager::{Nvs, Record},
+};
+
+/// Two digits per side
+pub const MAX_POINTS: u8 = 99;
+
+static SCORE: BlockingMutex<CriticalSectionRawMutex, Cell<[u8; 2]>> =
+    BlockingMutex::new(Cell::new([0; 2]));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left = 0,
+    Right = 1,
+}
+
+impl Side {
+    /// API name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(Side::Left),
+            "right" => Some(Side::Right),
+            _ => None,
+        }
+    }
+}
+
+/// Left then right
+pub fn score() -> [u8; 2] {
+    SCORE.lock(|score| score.get())
+}
+
+pub fn increment(side: Side) {
+    SCORE.lock(|score| {
+        let mut points = score.get();
+        points[side as usize] = (points[side as usize] + 1).min(MAX_POINTS);
+        score.set(points);
+    });
+}
+
+pub fn reset() {
+    SCORE.lock(|score| score.set([0; 2]));
+}
+
+/// Restore the score saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let mut buf = [0u8; 2];
+    if let Some(&[left, right]) = storage.lock().await.read_record(Record::Score, &mut buf) {
+        SCORE.lock(|score| score.set([left.min(MAX_POINTS), right.min(MAX_POINTS)]));
+    }
+}
+
+pub async fn save(storage: &Mutex<CriticalSectionRawMutex, Nvs>, points: [u8; 2]) {
+    if let Err(e) = storage.lock().await.write_record(Record::Score, &points) {
+        crate::log!("Score not saved: {e:?}");
+    }
+}
+
+/// "LL-RR" in the middle of `canvas`
+pub fn draw<const W: usize, const H: usize>(canvas: &mut Canvas<W, H>, points: [u8; 2]) {
+    canvas.clear();
+    let text = alloc::format!("{:>2}-{:<2}", points[0], points[1]);
+    canvas.print_5x7(1, 4, &text);
+}
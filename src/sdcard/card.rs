@@ -0,0 +1,270 @@
+use esp_hal::{
+    delay::Delay,
+    gpio::{Input, Level, Output},
+};
+
+use super::SdError;
+
+pub const BLOCK_LEN: usize = 512;
+
+/// Half clock period while the card is in identification mode (max 400 kHz)
+const INIT_HALF_PERIOD_NS: u32 = 1250;
+
+/// Bytes polled for a response or a data token before giving up
+const MAX_POLL: usize = 50_000;
+
+const CMD_GO_IDLE_STATE: u8 = 0;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE_BLOCK: u8 = 17;
+const CMD_WRITE_BLOCK: u8 = 24;
+const CMD_APP_CMD: u8 = 55;
+const CMD_READ_OCR: u8 = 58;
+const ACMD_SD_SEND_OP_COND: u8 = 41;
+
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const DATA_START_TOKEN: u8 = 0xFE;
+const DATA_ACCEPTED: u8 = 0x05;
+
+/// Bit-banged SPI mode 0, the hardware SPI belongs to the matrix
+pub struct SoftSpi {
+    sclk: Output<'static>,
+    mosi: Output<'static>,
+    miso: Input<'static>,
+    half_period_ns: u32,
+    delay: Delay,
+}
+
+impl SoftSpi {
+    pub fn new(sclk: Output<'static>, mosi: Output<'static>, miso: Input<'static>) -> Self {
+        Self {
+            sclk,
+            mosi,
+            miso,
+            half_period_ns: INIT_HALF_PERIOD_NS,
+            delay: Delay::new(),
+        }
+    }
+
+    fn transfer_byte(&mut self, out: u8) -> u8 {
+        let mut read = 0;
+        for bit in (0..8).rev() {
+            self.mosi.set_level(Level::from((out >> bit) & 1 == 1));
+            self.delay.delay_nanos(self.half_period_ns);
+            self.sclk.set_high();
+            read = (read << 1) | self.miso.is_high() as u8;
+            self.delay.delay_nanos(self.half_period_ns);
+            self.sclk.set_low();
+        }
+        read
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        self.transfer_byte(0xFF)
+    }
+}
+
+/// SD card in SPI mode, 512 bytes blocks
+pub struct SdCard {
+    spi: SoftSpi,
+    cs: Output<'static>,
+    /// SDHC/SDXC cards are addressed by block, older ones by byte
+    high_capacity: bool,
+}
+
+impl SdCard {
+    pub fn init(spi: SoftSpi, cs: Output<'static>) -> Result<Self, SdError> {
+        let mut card = Self {
+            spi,
+            cs,
+            high_capacity: false,
+        };
+
+        // At least 74 clocks with CS and MOSI high to enter native mode
+        card.cs.set_high();
+        for _ in 0..10 {
+            card.spi.read_byte();
+        }
+
+        card.select();
+        let result = card.identify();
+        card.deselect();
+        result?;
+
+        card.spi.half_period_ns = 0;
+        Ok(card)
+    }
+
+    fn identify(&mut self) -> Result<(), SdError> {
+        let r1 = self.command(CMD_GO_IDLE_STATE, 0)?;
+        if r1 != R1_IDLE {
+            return Err(SdError::Command(CMD_GO_IDLE_STATE, r1));
+        }
+
+        // Only v2 cards know CMD8, they echo the check pattern
+        let r1 = self.command(CMD_SEND_IF_COND, 0x1AA)?;
+        let v2 = r1 & R1_ILLEGAL_COMMAND == 0;
+        if v2 {
+            let mut r7 = [0u8; 4];
+            self.read_bytes(&mut r7);
+            if r7[3] != 0xAA {
+                return Err(SdError::Unsupported);
+            }
+        }
+
+        let hcs = if v2 { 1 << 30 } else { 0 };
+        let mut ready = false;
+        for _ in 0..1000 {
+            self.command(CMD_APP_CMD, 0)?;
+            if self.command(ACMD_SD_SEND_OP_COND, hcs)? == 0 {
+                ready = true;
+                break;
+            }
+            Delay::new().delay_millis(1);
+        }
+        if !ready {
+            return Err(SdError::Timeout);
+        }
+
+        if v2 {
+            let r1 = self.command(CMD_READ_OCR, 0)?;
+            if r1 != 0 {
+                return Err(SdError::Command(CMD_READ_OCR, r1));
+            }
+            let mut ocr = [0u8; 4];
+            self.read_bytes(&mut ocr);
+            self.high_capacity = ocr[0] & 0x40 != 0;
+        }
+
+        if !self.high_capacity {
+            let r1 = self.command(CMD_SET_BLOCKLEN, BLOCK_LEN as u32)?;
+            if r1 != 0 {
+                return Err(SdError::Command(CMD_SET_BLOCKLEN, r1));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn select(&mut self) {
+        self.cs.set_low();
+    }
+
+    fn deselect(&mut self) {
+        self.cs.set_high();
+        // Release MISO
+        self.spi.read_byte();
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.spi.read_byte();
+        }
+    }
+
+    fn wait_ready(&mut self) -> Result<(), SdError> {
+        for _ in 0..MAX_POLL {
+            if self.spi.read_byte() == 0xFF {
+                return Ok(());
+            }
+        }
+        Err(SdError::Timeout)
+    }
+
+    /// Send a command and return its R1 response
+    fn command(&mut self, cmd: u8, arg: u32) -> Result<u8, SdError> {
+        if cmd != CMD_GO_IDLE_STATE {
+            self.wait_ready()?;
+        }
+
+        // The CRC is only checked for CMD0 and CMD8 until CRC mode is enabled
+        let crc = match cmd {
+            CMD_GO_IDLE_STATE => 0x95,
+            CMD_SEND_IF_COND => 0x87,
+            _ => 0x01,
+        };
+
+        self.spi.transfer_byte(0x40 | cmd);
+        for byte in arg.to_be_bytes() {
+            self.spi.transfer_byte(byte);
+        }
+        self.spi.transfer_byte(crc);
+
+        for _ in 0..8 {
+            let r1 = self.spi.read_byte();
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+        Err(SdError::Timeout)
+    }
+
+    fn address(&self, block: u32) -> u32 {
+        if self.high_capacity {
+            block
+        } else {
+            block * BLOCK_LEN as u32
+        }
+    }
+
+    pub fn read_block(&mut self, block: u32, buf: &mut [u8; BLOCK_LEN]) -> Result<(), SdError> {
+        self.select();
+        let result = self.read_block_selected(block, buf);
+        self.deselect();
+        result
+    }
+
+    fn read_block_selected(&mut self, block: u32, buf: &mut [u8; BLOCK_LEN]) -> Result<(), SdError> {
+        let r1 = self.command(CMD_READ_SINGLE_BLOCK, self.address(block))?;
+        if r1 != 0 {
+            return Err(SdError::Command(CMD_READ_SINGLE_BLOCK, r1));
+        }
+
+        let mut token = 0xFF;
+        for _ in 0..MAX_POLL {
+            token = self.spi.read_byte();
+            if token != 0xFF {
+                break;
+            }
+        }
+        if token != DATA_START_TOKEN {
+            return Err(SdError::Timeout);
+        }
+
+        self.read_bytes(buf);
+        // CRC, unchecked
+        self.read_bytes(&mut [0u8; 2]);
+        Ok(())
+    }
+
+    pub fn write_block(&mut self, block: u32, buf: &[u8; BLOCK_LEN]) -> Result<(), SdError> {
+        self.select();
+        let result = self.write_block_selected(block, buf);
+        self.deselect();
+        result
+    }
+
+    fn write_block_selected(&mut self, block: u32, buf: &[u8; BLOCK_LEN]) -> Result<(), SdError> {
+        let r1 = self.command(CMD_WRITE_BLOCK, self.address(block))?;
+        if r1 != 0 {
+            return Err(SdError::Command(CMD_WRITE_BLOCK, r1));
+        }
+
+        self.spi.transfer_byte(DATA_START_TOKEN);
+        for &byte in buf {
+            self.spi.transfer_byte(byte);
+        }
+        // Dummy CRC
+        self.spi.transfer_byte(0xFF);
+        self.spi.transfer_byte(0xFF);
+
+        let response = self.spi.read_byte() & 0x1F;
+        if response != DATA_ACCEPTED {
+            return Err(SdError::Rejected(response));
+        }
+
+        // Card holds MISO low while programming
+        self.wait_ready()
+    }
+}
@@ -0,0 +1,226 @@
+use super::{
+    card::{SdCard, BLOCK_LEN},
+    SdError,
+};
+
+const DIR_ENTRY_LEN: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const ENTRY_FREE: u8 = 0xE5;
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buf[offset],
+        buf[offset + 1],
+        buf[offset + 2],
+        buf[offset + 3],
+    ])
+}
+
+/// Space padded upper case 8.3 name, as stored in directory entries
+fn short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut short = [b' '; 11];
+    for (dst, src) in short.iter_mut().zip(base.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    for (dst, src) in short[8..].iter_mut().zip(ext.bytes()) {
+        *dst = src.to_ascii_uppercase();
+    }
+    Some(short)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct File {
+    first_cluster: u32,
+    pub size: u32,
+}
+
+/// Minimal FAT32 driver: files of the root directory only, 8.3 names only
+///
+/// Files are never created nor grown, writes stay within the clusters already
+/// allocated to the file. Create them from a computer beforehand.
+pub struct Volume {
+    card: SdCard,
+    fat_start: u32,
+    data_start: u32,
+    sectors_per_cluster: u32,
+    root_cluster: u32,
+    block: [u8; BLOCK_LEN],
+}
+
+impl Volume {
+    /// Mount the first partition, or the whole card when it has no partition table
+    pub fn mount(mut card: SdCard) -> Result<Self, SdError> {
+        let mut block = [0u8; BLOCK_LEN];
+        card.read_block(0, &mut block)?;
+        if u16_at(&block, 510) != 0xAA55 {
+            return Err(SdError::NoFilesystem);
+        }
+
+        // A boot sector starts with a jump instruction, a MBR does not
+        let start = if block[0] == 0xEB || block[0] == 0xE9 {
+            0
+        } else {
+            let start = u32_at(&block, 446 + 8);
+            card.read_block(start, &mut block)?;
+            start
+        };
+
+        let bytes_per_sector = u16_at(&block, 11);
+        let sectors_per_cluster = block[13] as u32;
+        let reserved = u16_at(&block, 14) as u32;
+        let fats = block[16] as u32;
+        let fat_size16 = u16_at(&block, 22);
+        let fat_size = u32_at(&block, 36);
+        let root_cluster = u32_at(&block, 44);
+
+        if bytes_per_sector as usize != BLOCK_LEN || sectors_per_cluster == 0 {
+            return Err(SdError::NoFilesystem);
+        }
+        if fat_size16 != 0 {
+            // FAT12/16
+            return Err(SdError::Unsupported);
+        }
+
+        let fat_start = start + reserved;
+        Ok(Self {
+            card,
+            fat_start,
+            data_start: fat_start + fats * fat_size,
+            sectors_per_cluster,
+            root_cluster,
+            block,
+        })
+    }
+
+    fn cluster_bytes(&self) -> u32 {
+        self.sectors_per_cluster * BLOCK_LEN as u32
+    }
+
+    fn cluster_block(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, SdError> {
+        let offset = cluster * 4;
+        self.card.read_block(
+            self.fat_start + offset / BLOCK_LEN as u32,
+            &mut self.block,
+        )?;
+        let next = u32_at(&self.block, (offset as usize) % BLOCK_LEN) & 0x0FFF_FFFF;
+        Ok((2..END_OF_CHAIN).contains(&next).then_some(next))
+    }
+
+    /// Cluster holding byte `offset` of the chain starting at `first`
+    fn seek(&mut self, first: u32, offset: u32) -> Result<u32, SdError> {
+        let mut cluster = first;
+        for _ in 0..offset / self.cluster_bytes() {
+            cluster = self.next_cluster(cluster)?.ok_or(SdError::EndOfFile)?;
+        }
+        Ok(cluster)
+    }
+
+    /// Look `name` up in the root directory
+    pub fn open(&mut self, name: &str) -> Result<File, SdError> {
+        let wanted = short_name(name).ok_or(SdError::NotFound)?;
+
+        let mut cluster = Some(self.root_cluster);
+        while let Some(current) = cluster {
+            for sector in 0..self.sectors_per_cluster {
+                self.card
+                    .read_block(self.cluster_block(current) + sector, &mut self.block)?;
+
+                for entry in self.block.chunks_exact(DIR_ENTRY_LEN) {
+                    let attr = entry[11];
+                    match entry[0] {
+                        0 => return Err(SdError::NotFound),
+                        ENTRY_FREE => continue,
+                        _ if attr == ATTR_LONG_NAME => continue,
+                        _ if attr & (ATTR_VOLUME_ID | ATTR_DIRECTORY) != 0 => continue,
+                        _ if entry[..11] == wanted => {
+                            let high = u16_at(entry, 20) as u32;
+                            let low = u16_at(entry, 26) as u32;
+                            return Ok(File {
+                                first_cluster: (high << 16) | low,
+                                size: u32_at(entry, 28),
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            cluster = self.next_cluster(current)?;
+        }
+
+        Err(SdError::NotFound)
+    }
+
+    /// Read from `offset`, returns the number of bytes read (0 at the end of the file)
+    pub fn read(&mut self, file: &File, offset: u32, buf: &mut [u8]) -> Result<usize, SdError> {
+        let len = (file.size.saturating_sub(offset) as usize).min(buf.len());
+        self.transfer(file, offset, len, |block, range, pos| {
+            buf[pos..pos + range.len()].copy_from_slice(&block[range]);
+            false
+        })?;
+        Ok(len)
+    }
+
+    /// Overwrite from `offset`, returns the number of bytes written
+    ///
+    /// Stops at the end of the file, which is never grown.
+    pub fn write(&mut self, file: &File, offset: u32, data: &[u8]) -> Result<usize, SdError> {
+        let len = (file.size.saturating_sub(offset) as usize).min(data.len());
+        self.transfer(file, offset, len, |block, range, pos| {
+            block[range.clone()].copy_from_slice(&data[pos..pos + range.len()]);
+            true
+        })?;
+        Ok(len)
+    }
+
+    /// Visit `len` bytes of `file` from `offset` block by block, writing the
+    /// block back when `f` returns true
+    fn transfer(
+        &mut self,
+        file: &File,
+        offset: u32,
+        len: usize,
+        mut f: impl FnMut(&mut [u8; BLOCK_LEN], core::ops::Range<usize>, usize) -> bool,
+    ) -> Result<(), SdError> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut cluster = self.seek(file.first_cluster, offset)?;
+        let mut pos = 0;
+        while pos < len {
+            let file_pos = offset + pos as u32;
+            let in_cluster = file_pos % self.cluster_bytes();
+            if pos > 0 && in_cluster == 0 {
+                cluster = self.next_cluster(cluster)?.ok_or(SdError::EndOfFile)?;
+            }
+
+            let block = self.cluster_block(cluster) + in_cluster / BLOCK_LEN as u32;
+            let start = file_pos as usize % BLOCK_LEN;
+            let end = (start + len - pos).min(BLOCK_LEN);
+
+            self.card.read_block(block, &mut self.block)?;
+            if f(&mut self.block, start..end, pos) {
+                self.card.write_block(block, &self.block)?;
+            }
+            pos += end - start;
+        }
+
+        Ok(())
+    }
+}
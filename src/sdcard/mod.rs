@@ -0,0 +1,147 @@
+//! Optional SPI SD card, for assets too large for the flash and long-term logs
+//!
+//! Enabled with the `sdcard` feature. The card must be FAT32 formatted.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex};
+
+mod card;
+mod fat;
+
+pub use card::{SdCard, SoftSpi};
+pub use fat::{File, Volume};
+
+/// Log file, written as a ring. Must exist and be filled with zeros,
+/// `truncate -s 1M LOG.TXT` from a computer does it. A zero byte follows the
+/// last line written, the next line starts over it.
+pub const LOG_FILE: &str = "LOG.TXT";
+
+/// Lines waiting to be written, the following ones are dropped
+const LOG_QUEUE_LEN: usize = 16;
+
+static LOG_LINES: Channel<CriticalSectionRawMutex, String, LOG_QUEUE_LEN> = Channel::new();
+
+static VOLUME: Mutex<CriticalSectionRawMutex, Option<Volume>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub enum SdError {
+    /// No card mounted
+    NoCard,
+    Timeout,
+    /// Command rejected, with its R1 response
+    Command(u8, u8),
+    /// Data response token of a rejected write
+    Rejected(u8),
+    /// Card or filesystem kind not handled (SDv1 without echo, FAT12/16)
+    Unsupported,
+    NoFilesystem,
+    NotFound,
+    EndOfFile,
+}
+
+impl fmt::Display for SdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Queue a log line for the card, called by `log!`
+pub fn log_line(args: fmt::Arguments) {
+    let mut line = alloc::format!("{args}");
+    line.push('\n');
+    _ = LOG_LINES.try_send(line);
+}
+
+/// Make `volume` available to `load`, `store` and the log task
+pub async fn install(volume: Volume) {
+    *VOLUME.lock().await = Some(volume);
+}
+
+async fn with_volume<R>(f: impl FnOnce(&mut Volume) -> Result<R, SdError>) -> Result<R, SdError> {
+    let mut volume = VOLUME.lock().await;
+    f(volume.as_mut().ok_or(SdError::NoCard)?)
+}
+
+/// Read the start of the `name` asset from the root directory, at most
+/// `max_len` bytes: files are often created larger than their content
+pub async fn load(name: &str, max_len: usize) -> Result<Vec<u8>, SdError> {
+    with_volume(|volume| {
+        let file = volume.open(name)?;
+        let mut data = alloc::vec![0; (file.size as usize).min(max_len)];
+        volume.read(&file, 0, &mut data)?;
+        Ok(data)
+    })
+    .await
+}
+
+/// Overwrite the start of the existing `name` file, returns the number of bytes written
+pub async fn store(name: &str, data: &[u8]) -> Result<usize, SdError> {
+    with_volume(|volume| {
+        let file = volume.open(name)?;
+        volume.write(&file, 0, data)
+    })
+    .await
+}
+
+/// Write queued log lines at the end of `LOG_FILE`, wrapping to its start when full
+///
+/// Errors are printed on serial only, logging them would feed the queue again.
+#[embassy_executor::task]
+pub async fn log_task() {
+    let file = match with_volume(|volume| volume.open(LOG_FILE)).await {
+        Ok(file) if file.size > 0 => file,
+        Ok(_) => {
+            esp_println::println!("SD log disabled: {LOG_FILE} is empty");
+            return;
+        }
+        Err(e) => {
+            esp_println::println!("SD log disabled: {e}");
+            return;
+        }
+    };
+
+    // Resume over the zero byte after the last line, the first one: the
+    // unused part of the file is zeroed too
+    let mut offset = 0;
+    let mut chunk = [0u8; 64];
+    while offset < file.size {
+        let Ok(len) = with_volume(|volume| volume.read(&file, offset, &mut chunk)).await else {
+            break;
+        };
+        if let Some(idx) = chunk[..len].iter().position(|&byte| byte == 0) {
+            offset += idx as u32;
+            break;
+        }
+        offset += len as u32;
+        embassy_futures::yield_now().await;
+    }
+
+    loop {
+        let mut line = LOG_LINES.receive().await.into_bytes();
+        // Marks the write position once the ring wrapped and no zeroed part
+        // is left, the oldest data is overwritten either way
+        line.push(0);
+        let mut data = &line[..];
+
+        while !data.is_empty() {
+            if offset >= file.size {
+                offset = 0;
+            }
+            match with_volume(|volume| volume.write(&file, offset, data)).await {
+                Ok(len) => {
+                    offset += len as u32;
+                    data = &data[len..];
+                }
+                Err(e) => {
+                    esp_println::println!("SD log write error: {e}");
+                    break;
+                }
+            }
+        }
+        if data.is_empty() {
+            offset -= 1;
+        }
+    }
+}
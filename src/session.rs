@@ -0,0 +1,239 @@
+//! Web panel password and login sessions
+//!
+//! The password is set from the setup portal and only its salted hash is kept
+//! in NVS. Logging in gives a session cookie, repeated failures lock the login
+//! for a while. The pairing code shares the failure count and the lockout, so
+//! guesses spread over both are limited the same.
+
+use alloc::string::String;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    device,
+    sha1::{self, DIGEST_LEN},
+    wifimanager::{Nvs, Record},
+};
+
+const SALT_LEN: usize = 16;
+const RECORD_LEN: usize = SALT_LEN + DIGEST_LEN;
+
+/// SHA-1 rounds, slows down offline guessing from a flash dump
+const HASH_ROUNDS: usize = 1000;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+const MAX_SESSIONS: usize = 4;
+/// Sessions expire after this long without a request
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+const SESSION_ID_LEN: usize = 16;
+pub const SESSION_COOKIE: &str = "sid";
+
+/// Failed logins before the lockout
+const MAX_FAILURES: u8 = 5;
+const LOCKOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+pub enum LoginError {
+    /// No password set yet
+    NoPassword,
+    WrongPassword,
+    /// Too many failures, retry after this many seconds
+    Locked(u64),
+    /// The flash could not be written, the password is unchanged
+    NotSaved,
+}
+
+#[derive(Debug)]
+pub enum PasswordError {
+    TooShort,
+    /// Current password (or pairing code when none is set) does not match
+    Denied,
+    /// Too many failures, retry after this many seconds
+    Locked(u64),
+    /// The flash could not be written, the password is unchanged
+    NotSaved,
+}
+
+struct Session {
+    id: [u8; SESSION_ID_LEN],
+    expires: Instant,
+}
+
+pub struct Sessions {
+    /// Salt and hash of the password
+    password: Option<([u8; SALT_LEN], [u8; DIGEST_LEN])>,
+    sessions: [Option<Session>; MAX_SESSIONS],
+    failures: u8,
+    locked_until: Option<Instant>,
+}
+
+fn hash(salt: &[u8; SALT_LEN], password: &str) -> [u8; DIGEST_LEN] {
+    let mut digest = sha1::digest(&[salt, password.as_bytes()]);
+    for _ in 1..HASH_ROUNDS {
+        digest = sha1::digest(&[&digest, salt, password.as_bytes()]);
+    }
+    digest
+}
+
+impl Sessions {
+    pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) -> Self {
+        let mut record = [0u8; RECORD_LEN];
+        let password = storage
+            .lock()
+            .await
+            .read_record(Record::Password, &mut record)
+            .filter(|record| record.len() == RECORD_LEN)
+            .map(|record| {
+                let mut salt = [0u8; SALT_LEN];
+                let mut digest = [0u8; DIGEST_LEN];
+                salt.copy_from_slice(&record[..SALT_LEN]);
+                digest.copy_from_slice(&record[SALT_LEN..]);
+                (salt, digest)
+            });
+
+        Self {
+            password,
+            sessions: Default::default(),
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        self.password
+            .is_some_and(|(salt, digest)| sha1::ct_eq(&hash(&salt, password), &digest))
+    }
+
+    /// Replace the password and close every session
+    pub async fn set_password(
+        &mut self,
+        storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+        password: &str,
+    ) -> Result<(), PasswordError> {
+        if password.len() < MIN_PASSWORD_LEN {
+            return Err(PasswordError::TooShort);
+        }
+
+        let salt = device::random_bytes::<SALT_LEN>();
+        let digest = hash(&salt, password);
+        let mut record = [0u8; RECORD_LEN];
+        record[..SALT_LEN].copy_from_slice(&salt);
+        record[SALT_LEN..].copy_from_slice(&digest);
+        if let Err(e) = storage.lock().await.write_record(Record::Password, &record) {
+            crate::log!("Password not saved: {e:?}");
+            return Err(PasswordError::NotSaved);
+        }
+
+        self.password = Some((salt, digest));
+        self.sessions = Default::default();
+        Ok(())
+    }
+
+    /// Change the password from the panel
+    ///
+    /// `current` is the current password, or the pairing code for a device
+    /// provisioned without one. Checked like a login, failures count towards
+    /// the same lockout.
+    pub async fn change_password(
+        &mut self,
+        storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+        pairing: &device::Pairing,
+        current: &str,
+        new: &str,
+    ) -> Result<(), PasswordError> {
+        if new.len() < MIN_PASSWORD_LEN {
+            return Err(PasswordError::TooShort);
+        }
+        let checked = self.guard(|sessions| {
+            if sessions.has_password() {
+                sessions.verify(current)
+            } else {
+                pairing.matches(current)
+            }
+        });
+        match checked {
+            Ok(()) => self.set_password(storage, new).await,
+            Err(LoginError::Locked(secs)) => Err(PasswordError::Locked(secs)),
+            Err(_) => Err(PasswordError::Denied),
+        }
+    }
+
+    /// Run the check of a secret unless locked, counting its failures
+    /// towards the lockout
+    pub fn guard(&mut self, check: impl FnOnce(&Self) -> bool) -> Result<(), LoginError> {
+        let now = Instant::now();
+        if let Some(until) = self.locked_until {
+            if now < until {
+                return Err(LoginError::Locked((until - now).as_secs() + 1));
+            }
+            self.locked_until = None;
+            self.failures = 0;
+        }
+
+        if !check(self) {
+            self.failures += 1;
+            if self.failures >= MAX_FAILURES {
+                crate::log!("Web panel locked after {} failures", self.failures);
+                self.locked_until = Some(now + LOCKOUT);
+            }
+            return Err(LoginError::WrongPassword);
+        }
+        self.failures = 0;
+        Ok(())
+    }
+
+    /// Open a session, returns its id for the cookie
+    pub fn login(&mut self, password: &str) -> Result<String, LoginError> {
+        if !self.has_password() {
+            return Err(LoginError::NoPassword);
+        }
+        self.guard(|sessions| sessions.verify(password))?;
+        let now = Instant::now();
+
+        // Reuse an expired slot, or drop the session closest to expiry
+        let slot = self
+            .sessions
+            .iter_mut()
+            .min_by_key(|session| session.as_ref().map_or(Instant::MIN, |s| s.expires))
+            .expect("at least one session slot");
+        let id = device::random_bytes::<SESSION_ID_LEN>();
+        *slot = Some(Session {
+            id,
+            expires: now + SESSION_TTL,
+        });
+        Ok(device::to_hex(&id))
+    }
+
+    fn find(&mut self, id: &str) -> Option<&mut Option<Session>> {
+        let now = Instant::now();
+        self.sessions.iter_mut().find(|slot| {
+            slot.as_ref().is_some_and(|session| {
+                session.expires > now
+                    && sha1::ct_eq(device::to_hex(&session.id).as_bytes(), id.as_bytes())
+            })
+        })
+    }
+
+    /// Whether `id` is an open session, extending it
+    pub fn check(&mut self, id: &str) -> bool {
+        match self.find(id) {
+            Some(Some(session)) => {
+                session.expires = Instant::now() + SESSION_TTL;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn logout(&mut self, id: &str) {
+        if let Some(slot) = self.find(id) {
+            *slot = None;
+        }
+    }
+}
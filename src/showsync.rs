@@ -0,0 +1,176 @@
+//! Display phase synchronization between clocks on the same LAN
+//!
+//! NTP alone leaves clocks some tens of ms apart over wifi, enough to see the
+//! minutes flip one after the other. One clock, the coordinator, multicasts its
+//! RTC time every `BEACON_PERIOD`, the others keep their offset to it and pace
+//! the display on this shared "show time".
+//!
+//! The coordinator is the clock with the lowest device ID heard recently, so
+//! there is nothing to configure and another one takes over when it goes away.
+//!
+//! Beacon, 16 bytes: magic `B5S1`, device ID (4 bytes), RTC time in µs (u64 LE).
+
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Ipv4Address, Stack,
+};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::rtc_cntl::Rtc;
+
+use crate::device;
+use crate::simtime;
+
+const SHOW_GROUP: Ipv4Address = Ipv4Address::new(239, 255, 47, 25);
+const SHOW_PORT: u16 = 47_626;
+const MAGIC: &[u8; 4] = b"B5S1";
+const BEACON_LEN: usize = 16;
+
+const BEACON_PERIOD: Duration = Duration::from_secs(1);
+/// A coordinator not heard for this long is replaced
+const COORDINATOR_TIMEOUT: Duration = Duration::from_secs(5);
+/// Beacons per offset estimate, the least delayed one is kept
+const WINDOW: u8 = 8;
+
+/// Coordinator time minus local RTC time, in µs
+static OFFSET_US: Mutex<CriticalSectionRawMutex, Cell<i64>> = Mutex::new(Cell::new(0));
+
+/// Shared show time for the local RTC time `rtc_us`
+///
+/// A leap second announced is smeared into it, and it runs simulated with
+/// the `simtime` feature.
+pub fn show_time_us(rtc_us: u64) -> u64 {
+    let offset = OFFSET_US.lock(|offset| offset.get()) + crate::ntp::leap_smear_us(rtc_us);
+    simtime::now_us(rtc_us.saturating_add_signed(offset))
+}
+
+/// Time left until the next multiple of `period` in show time
+pub fn until_next_period(rtc_us: u64, period: Duration) -> Duration {
+    let period_us = period.as_micros().max(1);
+    Duration::from_micros(simtime::real_us(period_us - show_time_us(rtc_us) % period_us))
+}
+
+/// Wait for the next second of show time, so pages and animations never
+/// change mid-second
+pub async fn next_second(rtc: &Rtc<'_>) {
+    Timer::after(until_next_period(rtc.current_time_us(), Duration::from_secs(1))).await
+}
+
+/// Wait for the next minute of show time
+pub async fn next_minute(rtc: &Rtc<'_>) {
+    Timer::after(until_next_period(rtc.current_time_us(), Duration::from_secs(60))).await
+}
+
+struct Follower {
+    coordinator: [u8; 4],
+    last_seen: Instant,
+    /// Largest offset sample of the window: delays only lower samples
+    best_sample: i64,
+    samples: u8,
+}
+
+impl Follower {
+    fn new(coordinator: [u8; 4], sample: i64) -> Self {
+        // First estimate right away, refined by the following windows
+        OFFSET_US.lock(|offset| offset.set(sample));
+        Self {
+            coordinator,
+            last_seen: Instant::now(),
+            best_sample: i64::MIN,
+            samples: 0,
+        }
+    }
+
+    fn push(&mut self, sample: i64) {
+        self.last_seen = Instant::now();
+        self.best_sample = self.best_sample.max(sample);
+        self.samples += 1;
+        if self.samples == WINDOW {
+            OFFSET_US.lock(|offset| offset.set(self.best_sample));
+            self.best_sample = i64::MIN;
+            self.samples = 0;
+        }
+    }
+}
+
+/// Exchange beacons, coordinating or following, while the station is up
+pub async fn run(stack: Stack<'static>, rtc: &Rtc<'_>) {
+    let own_id = device::device_id();
+    let _lease = crate::sockets::lease(crate::sockets::Use::ShowSync);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 4 * BEACON_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; BEACON_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(SHOW_PORT) {
+        crate::log!("Show sync bind error: {e:?}");
+        return;
+    }
+
+    stack.wait_config_up().await;
+    if let Err(e) = stack.join_multicast_group(SHOW_GROUP) {
+        crate::log!("Show sync group error: {e:?}");
+        return;
+    }
+
+    let group = IpEndpoint::new(SHOW_GROUP.into(), SHOW_PORT);
+    let mut follower: Option<Follower> = None;
+    let mut beacon = [0u8; BEACON_LEN];
+    let mut next_beacon = Instant::now();
+
+    loop {
+        if follower
+            .as_ref()
+            .is_some_and(|f| f.last_seen.elapsed() > COORDINATOR_TIMEOUT)
+        {
+            crate::log!("Show sync coordinator lost");
+            follower = None;
+            OFFSET_US.lock(|offset| offset.set(0));
+        }
+
+        match select(Timer::at(next_beacon), socket.recv_from(&mut beacon)).await {
+            Either::First(_) => {
+                next_beacon = Instant::now() + BEACON_PERIOD;
+                if follower.is_some() || !stack.is_config_up() {
+                    continue;
+                }
+                let mut packet = [0u8; BEACON_LEN];
+                packet[..4].copy_from_slice(MAGIC);
+                packet[4..8].copy_from_slice(&own_id);
+                packet[8..].copy_from_slice(&rtc.current_time_us().to_le_bytes());
+                if let Err(e) = socket.send_to(&packet, group).await {
+                    crate::log!("Show sync send error: {e:?}");
+                }
+            }
+            Either::Second(Ok((BEACON_LEN, _))) if &beacon[..4] == MAGIC => {
+                let received_us = rtc.current_time_us();
+                let id = [beacon[4], beacon[5], beacon[6], beacon[7]];
+                let mut time = [0u8; 8];
+                time.copy_from_slice(&beacon[8..]);
+                let sample = u64::from_le_bytes(time) as i64 - received_us as i64;
+
+                // Lowest ID leads, ignore the others
+                match follower.as_mut() {
+                    Some(f) if f.coordinator == id => f.push(sample),
+                    Some(f) if id < f.coordinator => follower = Some(Follower::new(id, sample)),
+                    None if id < own_id => {
+                        crate::log!("Show sync following {:02x?}", id);
+                        follower = Some(Follower::new(id, sample));
+                    }
+                    _ => {}
+                }
+            }
+            Either::Second(_) => {}
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Simulated time, with the `simtime` feature, to go through minute, hour and
+//! DST boundaries in seconds during development and demos
+//!
+//! The show time runs `rate` times faster from a set date, or stands still at
+//! a rate of 0, until sent back to the real time. Only what follows the show
+//! time is simulated: the faces, animations, themes and alarms. NTP, the API
+//! dates and the logs stay on the real time. Without the feature the show
+//! time is always the real one.
+//!
+//! ```json
+//! {"at":1774746000,"rate":60}
+//! ```
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use serde::{Deserialize, Serialize};
+
+const ENABLED: bool = cfg!(feature = "simtime");
+/// An hour per second
+pub const MAX_RATE: u16 = 3600;
+
+#[derive(Clone, Copy)]
+struct Sim {
+    /// When simulated time started or last changed
+    since: Instant,
+    /// Simulated time then, unix µs
+    sim_us: u64,
+    rate: u16,
+}
+
+impl Sim {
+    fn now_us(&self) -> u64 {
+        let elapsed = self.since.elapsed().as_micros();
+        self.sim_us.saturating_add(elapsed.saturating_mul(self.rate as u64))
+    }
+}
+
+static SIM: Mutex<CriticalSectionRawMutex, Cell<Option<Sim>>> = Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct SimRequest {
+    /// Unix time to jump to in seconds, the current show time when `None`
+    #[serde(default)]
+    pub at: Option<i64>,
+    /// Speed, 0 to freeze, the current one when `None`
+    #[serde(default)]
+    pub rate: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct SimStatus {
+    pub simulated: bool,
+    pub rate: u16,
+    /// Show time, unix seconds, `None` before the first sync
+    pub now: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum SimError {
+    /// Above `MAX_RATE`
+    InvalidRate,
+    /// Before 1970
+    InvalidTime,
+    /// No `at` and no time to start from, before the first sync
+    NoTime,
+}
+
+/// Show time for the real one `real_us`, itself without the feature or out of
+/// simulation
+pub fn now_us(real_us: u64) -> u64 {
+    if !ENABLED {
+        return real_us;
+    }
+    match SIM.lock(|sim| sim.get()) {
+        Some(sim) => sim.now_us(),
+        None => real_us,
+    }
+}
+
+/// Real time for `sim_us` of simulated time, rounded up so a wait never ends
+/// before a boundary, unchanged when frozen or out of simulation
+pub fn real_us(sim_us: u64) -> u64 {
+    if !ENABLED {
+        return sim_us;
+    }
+    match SIM.lock(|sim| sim.get()) {
+        Some(sim) if sim.rate > 0 => sim_us.div_ceil(sim.rate as u64),
+        _ => sim_us,
+    }
+}
+
+/// Simulate as asked, from `unix_time` when neither `at` nor a simulation
+/// gives a start
+pub fn set(request: SimRequest, unix_time: Option<i64>) -> Result<(), SimError> {
+    if request.rate.is_some_and(|rate| rate > MAX_RATE) {
+        return Err(SimError::InvalidRate);
+    }
+    if request.at.is_some_and(|at| at < 0) {
+        return Err(SimError::InvalidTime);
+    }
+
+    SIM.lock(|sim| {
+        let current = sim.get();
+        let sim_us = match (request.at, current) {
+            (Some(at), _) => at as u64 * 1_000_000,
+            (None, Some(current)) => current.now_us(),
+            (None, None) => unix_time.ok_or(SimError::NoTime)?.max(0) as u64 * 1_000_000,
+        };
+        sim.set(Some(Sim {
+            since: Instant::now(),
+            sim_us,
+            rate: request.rate.or(current.map(|current| current.rate)).unwrap_or(1),
+        }));
+        Ok(())
+    })
+}
+
+/// Back to the real time
+pub fn stop() {
+    SIM.lock(|sim| sim.set(None));
+}
+
+/// Simulation state, the show time being `unix_time` out of simulation
+pub fn status(unix_time: Option<i64>) -> SimStatus {
+    let sim = SIM.lock(|sim| sim.get()).filter(|_| ENABLED);
+    SimStatus {
+        simulated: sim.is_some(),
+        rate: sim.map_or(1, |sim| sim.rate),
+        now: sim.map(|sim| (sim.now_us() / 1_000_000) as i64).or(unix_time),
+    }
+}
@@ -0,0 +1,190 @@
+//! Snake on the matrix, an easter-egg face
+//!
+//! Started and steered through the HTTP API or the boot button, the best score
+//! is kept in NVS.
+
+use alloc::collections::VecDeque;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex};
+
+use crate::{
+    display::Canvas,
+    wifimanager::{Nvs, Record},
+};
+
+static INPUT: Channel<CriticalSectionRawMutex, Input, 4> = Channel::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn opposite(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    /// Quarter turn to the right, for single button play
+    pub fn clockwise(self) -> Self {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// Start a game, or restart the current one
+    Start,
+    Turn(Direction),
+    /// Turn right relative to the current direction
+    TurnClockwise,
+    Quit,
+}
+
+/// Queue an input for the game, dropped when the queue is full
+pub fn send(input: Input) {
+    _ = INPUT.try_send(input);
+}
+
+pub async fn input() -> Input {
+    INPUT.receive().await
+}
+
+pub struct Snake<const W: usize, const H: usize> {
+    /// Head first
+    body: VecDeque<(usize, usize)>,
+    direction: Direction,
+    /// Applied on the next step, so two quick turns cannot reverse the snake
+    next_direction: Direction,
+    food: (usize, usize),
+    rng: u32,
+    pub score: u16,
+    pub alive: bool,
+}
+
+impl<const W: usize, const H: usize> Snake<W, H> {
+    pub fn new(seed: u32) -> Self {
+        let mut body = VecDeque::new();
+        for x in 0..3 {
+            body.push_back((W / 2 - x, H / 2));
+        }
+
+        let mut snake = Self {
+            body,
+            direction: Direction::Right,
+            next_direction: Direction::Right,
+            food: (0, 0),
+            rng: seed | 1,
+            score: 0,
+            alive: true,
+        };
+        snake.place_food();
+        snake
+    }
+
+    /// xorshift32, good enough to place food
+    fn random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn place_food(&mut self) {
+        if self.body.len() >= W * H {
+            return;
+        }
+        loop {
+            let food = (
+                self.random() as usize % W,
+                self.random() as usize % H,
+            );
+            if !self.body.contains(&food) {
+                self.food = food;
+                return;
+            }
+        }
+    }
+
+    pub fn input(&mut self, input: Input) {
+        let direction = match input {
+            Input::Turn(direction) => direction,
+            Input::TurnClockwise => self.next_direction.clockwise(),
+            Input::Start | Input::Quit => return,
+        };
+        if direction != self.direction.opposite() {
+            self.next_direction = direction;
+        }
+    }
+
+    /// Move one cell, the borders wrap around
+    pub fn step(&mut self) {
+        if !self.alive {
+            return;
+        }
+
+        self.direction = self.next_direction;
+        let (x, y) = self.body[0];
+        let head = match self.direction {
+            Direction::Up => (x, (y + H - 1) % H),
+            Direction::Down => (x, (y + 1) % H),
+            Direction::Left => ((x + W - 1) % W, y),
+            Direction::Right => ((x + 1) % W, y),
+        };
+
+        if head == self.food {
+            self.score += 1;
+            self.body.push_front(head);
+            self.place_food();
+            return;
+        }
+
+        self.body.pop_back();
+        if self.body.contains(&head) {
+            self.alive = false;
+        }
+        self.body.push_front(head);
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<W, H>) {
+        canvas.clear();
+        for &(x, y) in self.body.iter() {
+            canvas.on(x, y);
+        }
+        canvas.on(self.food.0, self.food.1);
+    }
+}
+
+pub async fn load_high_score(storage: &Mutex<CriticalSectionRawMutex, Nvs>) -> u16 {
+    let mut buf = [0u8; 2];
+    match storage
+        .lock()
+        .await
+        .read_record(Record::SnakeScore, &mut buf)
+    {
+        Some(&[low, high]) => u16::from_le_bytes([low, high]),
+        _ => 0,
+    }
+}
+
+pub async fn save_high_score(storage: &Mutex<CriticalSectionRawMutex, Nvs>, score: u16) {
+    if let Err(e) = storage
+        .lock()
+        .await
+        .write_record(Record::SnakeScore, &score.to_le_bytes())
+    {
+        crate::log!("Snake high score not saved: {e:?}");
+    }
+}
@@ -0,0 +1,121 @@
+//! Socket budget of the station stack
+//!
+//! embassy-net panics when a socket is added past the `StackResources` size,
+//! fixed at build time. `STA_SOCKETS` is the sum of the sockets every use
+//! below can hold at once; the `more-sockets` feature doubles it for builds
+//! adding more.
+//!
+//! Every socket opened on the station holds a `Lease` while it lives, so the
+//! usage and its peak can be checked from `/api/sockets`.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use serde::Serialize;
+
+pub use b_intime_logic::sockets::Use;
+use b_intime_logic::sockets::{budget, USES};
+
+/// Sockets of the station stack
+#[cfg(not(feature = "more-sockets"))]
+pub const STA_SOCKETS: usize = budget();
+#[cfg(feature = "more-sockets")]
+pub const STA_SOCKETS: usize = 2 * budget();
+
+/// HTTP API connection buffers
+pub const API_RX_BUFFER: usize = 1024;
+pub const API_TX_BUFFER: usize = 1024;
+/// MQTT connection buffers, a message must fit in the receive buffer
+pub const MQTT_RX_BUFFER: usize = 1024;
+pub const MQTT_TX_BUFFER: usize = 512;
+/// Home Assistant client connection buffers
+pub const HTTP_CLIENT_BUFFER: usize = 4096;
+
+/// DHCP and DNS sockets, always there
+const STACK_SOCKETS: u8 = Use::Stack.max_open() as u8;
+
+#[derive(Clone, Copy)]
+struct Count {
+    open: u8,
+    peak: u8,
+}
+
+static COUNTS: Mutex<CriticalSectionRawMutex, Cell<[Count; USES.len()]>> = Mutex::new(Cell::new({
+    let mut counts = [Count { open: 0, peak: 0 }; USES.len()];
+    counts[0] = Count {
+        open: STACK_SOCKETS,
+        peak: STACK_SOCKETS,
+    };
+    counts
+}));
+static PEAK: Mutex<CriticalSectionRawMutex, Cell<u8>> = Mutex::new(Cell::new(STACK_SOCKETS));
+
+fn index(usage: Use) -> usize {
+    USES.iter().position(|&known| known == usage).unwrap_or(0)
+}
+
+/// Held while a socket of `usage` is open
+pub struct Lease(Use);
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let idx = index(self.0);
+        COUNTS.lock(|counts| {
+            let mut current = counts.get();
+            current[idx].open = current[idx].open.saturating_sub(1);
+            counts.set(current);
+        });
+    }
+}
+
+/// Record a socket of `usage`, before creating it
+pub fn lease(usage: Use) -> Lease {
+    let idx = index(usage);
+    let (open, total) = COUNTS.lock(|counts| {
+        let mut current = counts.get();
+        current[idx].open += 1;
+        current[idx].peak = current[idx].peak.max(current[idx].open);
+        counts.set(current);
+        let total = current.iter().map(|count| count.open).sum::<u8>();
+        (current[idx].open, total)
+    });
+    PEAK.lock(|peak| peak.set(peak.get().max(total)));
+
+    if open as usize > usage.max_open() {
+        crate::log!("More {usage:?} sockets than budgeted: {open}");
+    }
+    if total as usize > STA_SOCKETS {
+        crate::log!("Station sockets exhausted: {total}/{STA_SOCKETS} ({usage:?})");
+    }
+    Lease(usage)
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct UseReport {
+    pub usage: Use,
+    pub open: u8,
+    pub peak: u8,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub capacity: usize,
+    pub open: u8,
+    pub peak: u8,
+    pub uses: [UseReport; USES.len()],
+}
+
+/// Current and peak usage, overall and per use
+pub fn report() -> Report {
+    let counts = COUNTS.lock(|counts| counts.get());
+    Report {
+        capacity: STA_SOCKETS,
+        open: counts.iter().map(|count| count.open).sum(),
+        peak: PEAK.lock(|peak| peak.get()),
+        uses: core::array::from_fn(|idx| UseReport {
+            usage: USES[idx],
+            open: counts[idx].open,
+            peak: counts[idx].peak,
+        }),
+    }
+}
@@ -0,0 +1,108 @@
+//! SSD1306 128x64 OLED over I2C, an alternative to the MAX7219 matrix
+//!
+//! Enabled with the `ssd1306` feature. Canvas pixels are scaled up by the
+//! largest integer factor fitting the panel and centered, a 32x16 canvas fills
+//! it with 4x4 blocks.
+
+use esp_hal::{i2c::master::I2c, Blocking};
+
+use crate::display::{Canvas, DisplayBackend};
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+const PAGES: usize = HEIGHT / 8;
+
+/// With SA0 tied low, 0x3D otherwise
+pub const DEFAULT_ADDRESS: u8 = 0x3C;
+
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+const INIT: &[u8] = &[
+    0xAE, // display off
+    0xD5, 0x80, // clock divide
+    0xA8, 0x3F, // multiplex 64
+    0xD3, 0x00, // display offset
+    0x40, // start line 0
+    0x8D, 0x14, // charge pump on
+    0x20, 0x00, // horizontal addressing
+    0xA1, // segment remap, column 127 is SEG0
+    0xC8, // COM scan from the bottom
+    0xDA, 0x12, // COM pins
+    0x81, 0x40, // contrast
+    0xD9, 0xF1, // precharge
+    0xDB, 0x40, // VCOMH deselect
+    0xA4, // show RAM content
+    0xA6, // not inverted
+    0xAF, // display on
+];
+
+pub struct Ssd1306<'d> {
+    i2c: I2c<'d, Blocking>,
+    address: u8,
+}
+
+impl<'d> Ssd1306<'d> {
+    pub fn new(i2c: I2c<'d, Blocking>, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    fn command(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if let Err(e) = self.i2c.write(self.address, &[CONTROL_COMMAND, *byte]) {
+                crate::log!("SSD1306 command error: {e:?}");
+                return;
+            }
+        }
+    }
+
+    /// `frame` is the data control byte then the whole RAM, one byte per
+    /// column and page, LSB on top
+    fn write_frame(&mut self, frame: &[u8; 1 + WIDTH * PAGES]) {
+        // Whole RAM window, the address wraps back to the start after it
+        self.command(&[0x21, 0, (WIDTH - 1) as u8, 0x22, 0, (PAGES - 1) as u8]);
+        if let Err(e) = self.i2c.write(self.address, frame) {
+            crate::log!("SSD1306 write error: {e:?}");
+        }
+    }
+}
+
+impl DisplayBackend for Ssd1306<'_> {
+    fn init(&mut self) {
+        self.command(INIT);
+
+        let mut blank = [0u8; 1 + WIDTH * PAGES];
+        blank[0] = CONTROL_DATA;
+        self.write_frame(&blank);
+    }
+
+    fn draw<const W: usize, const H: usize>(&mut self, canvas: &Canvas<W, H>) {
+        let scale = (WIDTH / W.max(1)).min(HEIGHT / H.max(1)).max(1);
+        let left = WIDTH.saturating_sub(W * scale) / 2;
+        let top = HEIGHT.saturating_sub(H * scale) / 2;
+
+        let mut frame = [0u8; 1 + WIDTH * PAGES];
+        frame[0] = CONTROL_DATA;
+        for page in 0..PAGES {
+            for x in 0..WIDTH {
+                let mut byte = 0u8;
+                for bit in 0..8 {
+                    let y = page * 8 + bit;
+                    let (Some(cx), Some(cy)) = (x.checked_sub(left), y.checked_sub(top)) else {
+                        continue;
+                    };
+                    let (cx, cy) = (cx / scale, cy / scale);
+                    if canvas.get(cx, cy) {
+                        byte |= 1 << bit;
+                    }
+                }
+                frame[1 + page * WIDTH + x] = byte;
+            }
+        }
+        self.write_frame(&frame);
+    }
+
+    fn set_brightness(&mut self, level: u8) {
+        self.command(&[0x81, level.min(15) * 17]);
+    }
+}
@@ -0,0 +1,184 @@
+//! Boot stages, in dependency order, with their timing and failures
+//!
+//! `main` brings the clock up in stages: the display, the settings from NVS,
+//! wifi, the time from NTP and the optional services. A stage starts once its
+//! dependencies are done, so a new subsystem slotted in the wrong place is
+//! reported instead of running on missing state. `/api/startup` reports when
+//! each stage started, how long it took and why it failed.
+
+use alloc::string::String;
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+/// Longest error kept, the report must fit in an API response
+const MAX_ERROR_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Stage {
+    Display,
+    /// Storage and the settings saved in it
+    Settings,
+    Wifi,
+    /// First NTP sync, done in the background
+    Time,
+    /// Tasks of the optional subsystems: API, MQTT, discovery, sensors
+    Services,
+}
+
+const STAGES: [Stage; 5] = [
+    Stage::Display,
+    Stage::Settings,
+    Stage::Wifi,
+    Stage::Time,
+    Stage::Services,
+];
+
+impl Stage {
+    /// Stages that must be done before this one starts
+    fn dependencies(self) -> &'static [Stage] {
+        match self {
+            Stage::Display | Stage::Settings => &[],
+            // The credentials, hostname and setup AP name are settings
+            Stage::Wifi => &[Stage::Settings],
+            Stage::Time => &[Stage::Wifi],
+            Stage::Services => &[Stage::Settings, Stage::Wifi],
+        }
+    }
+
+    fn index(self) -> usize {
+        STAGES.iter().position(|&stage| stage == self).unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum State {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Clone)]
+struct Record {
+    started: Option<Instant>,
+    took: Option<Duration>,
+    error: Option<String>,
+}
+
+impl Record {
+    fn state(&self) -> State {
+        match (self.started, self.took, &self.error) {
+            (None, _, _) => State::Pending,
+            (_, _, Some(_)) => State::Failed,
+            (_, None, None) => State::Running,
+            (_, Some(_), None) => State::Done,
+        }
+    }
+}
+
+static RECORDS: Mutex<CriticalSectionRawMutex, RefCell<[Record; STAGES.len()]>> =
+    Mutex::new(RefCell::new(
+        [const {
+            Record {
+                started: None,
+                took: None,
+                error: None,
+            }
+        }; STAGES.len()],
+    ));
+
+/// State of `stage`
+pub fn state(stage: Stage) -> State {
+    RECORDS.lock(|records| records.borrow()[stage.index()].state())
+}
+
+/// Whether every stage is done
+pub fn is_done() -> bool {
+    STAGES.iter().all(|&stage| state(stage) == State::Done)
+}
+
+/// Whether a stage failed
+pub fn has_failed() -> bool {
+    STAGES.iter().any(|&stage| state(stage) == State::Failed)
+}
+
+/// Start `stage`, fails with the first dependency not done, and then the stage
+/// is failed too
+pub fn start(stage: Stage) -> Result<(), Stage> {
+    let missing = stage
+        .dependencies()
+        .iter()
+        .copied()
+        .find(|&dependency| state(dependency) != State::Done);
+
+    RECORDS.lock(|records| records.borrow_mut()[stage.index()].started = Some(Instant::now()));
+    match missing {
+        Some(dependency) => {
+            fail(stage, &alloc::format!("{dependency:?} not done"));
+            Err(dependency)
+        }
+        None => {
+            crate::log!("Startup: {stage:?}");
+            Ok(())
+        }
+    }
+}
+
+/// `stage` is done
+pub fn done(stage: Stage) {
+    let took = finish(stage, None);
+    crate::log!("Startup: {stage:?} done in {}ms", took.as_millis());
+}
+
+/// `stage` failed with `error`, shortened
+pub fn fail(stage: Stage, error: &str) {
+    let end = (0..=error.len().min(MAX_ERROR_LEN))
+        .rev()
+        .find(|&end| error.is_char_boundary(end))
+        .unwrap_or(0);
+    let took = finish(stage, Some(error[..end].into()));
+    crate::log!(
+        "Startup: {stage:?} failed after {}ms: {error}",
+        took.as_millis()
+    );
+}
+
+fn finish(stage: Stage, error: Option<String>) -> Duration {
+    RECORDS.lock(|records| {
+        let record = &mut records.borrow_mut()[stage.index()];
+        let took = record
+            .started
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        record.took = Some(took);
+        record.error = error;
+        took
+    })
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub state: State,
+    /// Since boot, `None` before it starts
+    pub started_ms: Option<u64>,
+    pub took_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// State of every stage
+pub fn report() -> [StageReport; STAGES.len()] {
+    RECORDS.lock(|records| {
+        let records = records.borrow();
+        core::array::from_fn(|idx| StageReport {
+            stage: STAGES[idx],
+            state: records[idx].state(),
+            started_ms: records[idx].started.map(|started| started.as_millis()),
+            took_ms: records[idx].took.map(|took| took.as_millis()),
+            error: records[idx].error.clone(),
+        })
+    })
+}
@@ -0,0 +1,141 @@
+//! Counters kept across reboots, to compare the reliability of firmware
+//! versions
+//!
+//! Saved in NVS at boot, every `SAVE_PERIOD` and before a maintenance reboot,
+//! so a crash loses at most the uptime and counts of the last period.
+//! `/api/stats` reports them, and resets them before evaluating a new version.
+//! Firmware is flashed over USB, there are no OTA updates to count.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use embassy_time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::wifimanager::{Nvs, Record};
+
+/// Time between saves, each one wears the flash sector
+const SAVE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Totals of the previous boots
+static SAVED: BlockingMutex<CriticalSectionRawMutex, Cell<Stats>> =
+    BlockingMutex::new(Cell::new(Stats::ZERO));
+/// Counts of this boot, its uptime aside
+static COUNTS: BlockingMutex<CriticalSectionRawMutex, Cell<Stats>> =
+    BlockingMutex::new(Cell::new(Stats::ZERO));
+/// Uptime of this boot before the last reset, not counted
+static RESET_AT: BlockingMutex<CriticalSectionRawMutex, Cell<u64>> =
+    BlockingMutex::new(Cell::new(0));
+/// Uptime at the last save
+static LAST_SAVE: BlockingMutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    BlockingMutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    WifiReconnect,
+    NtpSync,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(default)]
+    pub uptime_s: u64,
+    #[serde(default)]
+    pub boots: u32,
+    #[serde(default)]
+    pub wifi_reconnects: u32,
+    #[serde(default)]
+    pub ntp_syncs: u32,
+}
+
+impl Stats {
+    const ZERO: Stats = Stats {
+        uptime_s: 0,
+        boots: 0,
+        wifi_reconnects: 0,
+        ntp_syncs: 0,
+    };
+
+    fn plus(self, other: Stats) -> Stats {
+        Stats {
+            uptime_s: self.uptime_s.saturating_add(other.uptime_s),
+            boots: self.boots.saturating_add(other.boots),
+            wifi_reconnects: self.wifi_reconnects.saturating_add(other.wifi_reconnects),
+            ntp_syncs: self.ntp_syncs.saturating_add(other.ntp_syncs),
+        }
+    }
+}
+
+/// Count one `counter`
+pub fn count(counter: Counter) {
+    COUNTS.lock(|counts| {
+        let mut stats = counts.get();
+        match counter {
+            Counter::WifiReconnect => {
+                stats.wifi_reconnects = stats.wifi_reconnects.saturating_add(1)
+            }
+            Counter::NtpSync => stats.ntp_syncs = stats.ntp_syncs.saturating_add(1),
+        }
+        counts.set(stats);
+    });
+}
+
+/// Totals, this boot included
+pub fn report() -> Stats {
+    let this_boot = Stats {
+        uptime_s: Instant::now().as_secs() - RESET_AT.lock(|reset_at| reset_at.get()),
+        ..COUNTS.lock(|counts| counts.get())
+    };
+    SAVED.lock(|saved| saved.get()).plus(this_boot)
+}
+
+/// Read the totals saved in NVS, and save this boot
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage.lock().await.read_json::<Stats>(Record::Stats);
+    match saved {
+        Some(Ok(stats)) => SAVED.lock(|saved| saved.set(stats)),
+        Some(Err(_)) => crate::log!("Invalid saved stats, ignored"),
+        None => {}
+    }
+
+    COUNTS.lock(|counts| {
+        counts.set(Stats {
+            boots: 1,
+            ..counts.get()
+        })
+    });
+    save(storage).await;
+}
+
+/// Save the totals
+pub async fn save(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let stats = report();
+    if let Err(e) = storage.lock().await.write_json(Record::Stats, &stats) {
+        crate::log!("Stats not saved: {e:?}");
+    }
+    LAST_SAVE.lock(|last| last.set(Some(Instant::now())));
+}
+
+/// Save the totals once `SAVE_PERIOD` passed since the last save
+pub async fn save_if_due(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let due = LAST_SAVE.lock(|last| last.get().is_none_or(|last| last.elapsed() >= SAVE_PERIOD));
+    if due {
+        save(storage).await;
+    }
+}
+
+/// Start the totals again from this boot
+pub async fn reset(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    RESET_AT.lock(|reset_at| reset_at.set(Instant::now().as_secs()));
+    SAVED.lock(|saved| saved.set(Stats::ZERO));
+    COUNTS.lock(|counts| {
+        counts.set(Stats {
+            boots: 1,
+            ..Stats::ZERO
+        })
+    });
+    save(storage).await;
+}
@@ -0,0 +1,72 @@
+//! Device state on a status LED, so the matrix only shows the clock
+//!
+//! The state comes from the startup stages and the task heartbeats: a quick
+//! blink while starting, a double blink when a stage failed or a task stalls,
+//! a short heartbeat otherwise. The `status-led` feature drives an LED on
+//! GPIO16, one matrix pixel can show the same pattern at the frame rate.
+//! Morse transmissions take over the LED until they end.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::select;
+use embassy_time::Timer;
+use esp_hal::gpio::Output;
+
+use crate::{morse, startup, watchdog};
+
+/// Sequence of (on, off) durations in ms, repeated
+pub type Pattern = &'static [(u16, u16)];
+
+pub const HEARTBEAT: Pattern = &[(60, 1940)];
+pub const STARTING: Pattern = &[(250, 250)];
+pub const ERROR: Pattern = &[(150, 150), (150, 1550)];
+pub const SOLID: Pattern = &[(1000, 0)];
+
+/// Whether the LED is lit now
+static LIT: AtomicBool = AtomicBool::new(false);
+
+/// Pattern of the current state
+pub fn pattern() -> Pattern {
+    if startup::has_failed() || !watchdog::all_healthy() {
+        ERROR
+    } else if !startup::is_done() {
+        STARTING
+    } else {
+        HEARTBEAT
+    }
+}
+
+pub fn is_lit() -> bool {
+    LIT.load(Ordering::Relaxed)
+}
+
+fn show(led: &mut Option<Output<'static>>, lit: bool) {
+    LIT.store(lit, Ordering::Relaxed);
+    if let Some(led) = led.as_mut() {
+        led.set_level(lit.into());
+    }
+}
+
+/// Play the pattern of the current state, on `led` when there is one
+#[embassy_executor::task]
+pub async fn status_task(mut led: Option<Output<'static>>) {
+    loop {
+        while let Some(lit) = morse::lit() {
+            show(&mut led, lit);
+            morse::next_unit().await;
+        }
+
+        let play = async {
+            for &(on, off) in pattern() {
+                for (lit, ms) in [(true, on), (false, off)] {
+                    if ms == 0 {
+                        continue;
+                    }
+                    show(&mut led, lit);
+                    Timer::after_millis(ms as u64).await;
+                }
+            }
+        };
+        select(play, morse::started()).await;
+    }
+}
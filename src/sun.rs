@@ -0,0 +1,54 @@
+//! Sunrise and sunset of the day at the location, from the weather service
+//!
+//! Set with each weather reading, in the time of the clock. Nothing is shown
+//! without a location, or for a day without a reading.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use jiff::civil::{Date, DateTime, Time};
+
+use crate::{
+    display::Canvas,
+    face::{ClockFace, Granularity},
+};
+
+static TIMES: Mutex<CriticalSectionRawMutex, Cell<Option<SunTimes>>> = Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SunTimes {
+    pub date: Date,
+    pub sunrise: Time,
+    pub sunset: Time,
+}
+
+pub fn set(times: SunTimes) {
+    TIMES.lock(|current| current.set(Some(times)));
+}
+
+/// Times of `date`, `None` when the last reading is of another day
+pub fn times(date: Date) -> Option<SunTimes> {
+    TIMES
+        .lock(|current| current.get())
+        .filter(|times| times.date == date)
+}
+
+/// Sunrise above the sunset
+pub struct SunFace;
+
+impl<const W: usize, const H: usize> ClockFace<W, H> for SunFace {
+    fn draw(&self, canvas: &mut Canvas<W, H>, now: DateTime) {
+        let format = |time: Option<Time>| match time {
+            Some(time) => alloc::format!("{:02}:{:02}", time.hour(), time.minute()),
+            None => "--:--".into(),
+        };
+        let times = times(now.date());
+        canvas.clear();
+        canvas.print_5x7(1, 0, &format(times.map(|times| times.sunrise)));
+        canvas.print_5x7(1, 8, &format(times.map(|times| times.sunset)));
+    }
+
+    fn granularity(&self) -> Granularity {
+        Granularity::Minute
+    }
+}
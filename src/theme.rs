@@ -0,0 +1,185 @@
+//! Themes bundling the face, time font, brightness and transition, switched on
+//! a weekly schedule
+//!
+//! Settings are JSON, read and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"themes":[{"face":"Clock","font":"Big","brightness":2,"transition":"None"}],
+//!  "schedule":[{"days":"Workdays","start":420,"theme":0}]}
+//! ```
+//!
+//! `start` is in minutes from midnight, the active theme is the one of the
+//! latest slot started, looking back to the previous day.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    face::Face,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+pub use b_intime_logic::schedule::Days;
+
+pub const MAX_THEMES: usize = 4;
+pub const MAX_SLOTS: usize = 8;
+
+/// Highest brightness, the MAX7219 has 16 intensity steps
+pub const MAX_BRIGHTNESS: u8 = 15;
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<ThemeSettings>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeFont {
+    /// 8x8 digits
+    Big,
+    /// 5x7 digits
+    Normal,
+}
+
+/// Played when the minute changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transition {
+    None,
+    FallingBlocks,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Theme {
+    pub face: Face,
+    pub font: TimeFont,
+    /// From 0 to `MAX_BRIGHTNESS`
+    pub brightness: u8,
+    pub transition: Transition,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub days: Days,
+    /// Minutes from midnight
+    pub start: u16,
+    /// Index in `ThemeSettings::themes`
+    pub theme: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThemeSettings {
+    pub themes: Vec<Theme>,
+    pub schedule: Vec<Slot>,
+}
+
+impl Default for ThemeSettings {
+    /// Dimmed at night
+    fn default() -> Self {
+        let day = Theme {
+            face: Face::Clock,
+            font: TimeFont::Big,
+            brightness: 2,
+            transition: Transition::None,
+        };
+        let night = Theme {
+            brightness: 0,
+            ..day
+        };
+        Self {
+            themes: alloc::vec![day, night],
+            schedule: alloc::vec![
+                Slot {
+                    days: Days::Every,
+                    start: 7 * 60,
+                    theme: 0,
+                },
+                Slot {
+                    days: Days::Every,
+                    start: 22 * 60,
+                    theme: 1,
+                },
+            ],
+        }
+    }
+}
+
+impl ThemeSettings {
+    pub fn is_valid(&self) -> bool {
+        !self.themes.is_empty()
+            && self.themes.len() <= MAX_THEMES
+            && self.schedule.len() <= MAX_SLOTS
+            && self
+                .themes
+                .iter()
+                .all(|theme| theme.brightness <= MAX_BRIGHTNESS)
+            && self
+                .schedule
+                .iter()
+                .all(|slot| slot.start < 24 * 60 && (slot.theme as usize) < self.themes.len())
+    }
+
+    /// Index of the theme active on `weekday` at `minute` from midnight
+    pub fn active(&self, weekday: Weekday, minute: u16) -> usize {
+        let latest = |weekday: Weekday, before: u16| {
+            self.schedule
+                .iter()
+                .filter(|slot| slot.days.contains(weekday) && slot.start <= before)
+                .max_by_key(|slot| slot.start)
+        };
+
+        latest(weekday, minute)
+            .or_else(|| latest(weekday.previous(), u16::MAX))
+            .map_or(0, |slot| slot.theme as usize)
+    }
+}
+
+/// Current settings
+pub fn settings() -> ThemeSettings {
+    SETTINGS.lock(|settings| settings.borrow().clone().unwrap_or_default())
+}
+
+/// Theme active on `weekday` at `minute` from midnight, with its index
+pub fn active(weekday: Weekday, minute: u16) -> (usize, Theme) {
+    SETTINGS.lock(|settings| {
+        let settings = settings.borrow();
+        let default;
+        let settings = match settings.as_ref() {
+            Some(settings) => settings,
+            None => {
+                default = ThemeSettings::default();
+                &default
+            }
+        };
+        let idx = settings.active(weekday, minute);
+        (idx, settings.themes[idx])
+    })
+}
+
+/// Read the settings saved in NVS, defaults otherwise
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<ThemeSettings>(Record::Themes);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => {
+            SETTINGS.lock(|current| *current.borrow_mut() = Some(settings));
+        }
+        Some(_) => crate::log!("Invalid saved themes, using defaults"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: ThemeSettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Themes, &settings)?;
+    SETTINGS.lock(|current| *current.borrow_mut() = Some(settings));
+    Ok(())
+}
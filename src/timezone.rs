@@ -0,0 +1,128 @@
+//! Timezone rules from a POSIX TZ string, for zones the built-in rules do not
+//! fit, without the whole tz database
+//!
+//! Unset by default. Settings are JSON, read and written through the HTTP API
+//! and kept in NVS:
+//!
+//! ```json
+//! {"posix":"CET-1CEST,M3.5.0,M10.5.0/3"}
+//! ```
+//!
+//! A fixed offset set in the button menu still wins over the string.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::{tz::TimeZone, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::wifimanager::{Nvs, Record, RecordError};
+
+/// Transitions listed by a preview
+const PREVIEW_TRANSITIONS: usize = 2;
+
+/// Settings and the rules parsed from them
+static ZONE: BlockingMutex<CriticalSectionRawMutex, RefCell<(TimezoneSettings, Option<TimeZone>)>> =
+    BlockingMutex::new(RefCell::new((TimezoneSettings { posix: None }, None)));
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimezoneSettings {
+    #[serde(default)]
+    pub posix: Option<String>,
+}
+
+impl TimezoneSettings {
+    /// Rules of the string, `None` when unset, the parse error when invalid
+    pub fn parse(&self) -> Result<Option<TimeZone>, jiff::Error> {
+        self.posix.as_deref().map(TimeZone::posix).transpose()
+    }
+}
+
+/// Current settings
+pub fn settings() -> TimezoneSettings {
+    ZONE.lock(|zone| zone.borrow().0.clone())
+}
+
+/// Rules of the POSIX TZ string set, `None` when unset
+pub fn get() -> Option<TimeZone> {
+    ZONE.lock(|zone| zone.borrow().1.clone())
+}
+
+fn set(settings: TimezoneSettings, timezone: Option<TimeZone>) {
+    ZONE.lock(|zone| *zone.borrow_mut() = (settings, timezone));
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<TimezoneSettings>(Record::Timezone);
+    match saved.map(|settings| settings.map(|settings| (settings.parse(), settings))) {
+        Some(Ok((Ok(timezone), settings))) => set(settings, timezone),
+        Some(_) => crate::log!("Invalid saved timezone, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must parse
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: TimezoneSettings,
+) -> Result<(), RecordError> {
+    storage
+        .lock()
+        .await
+        .write_json(Record::Timezone, &settings)?;
+    let timezone = settings.parse().ok().flatten();
+    set(settings, timezone);
+    Ok(())
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Transition {
+    /// Unix time
+    pub at: i64,
+    /// From UTC, after the transition
+    pub offset_s: i32,
+    pub abbreviation: String,
+    pub dst: bool,
+}
+
+/// Time at `at` under some rules, to check them before saving
+#[derive(Clone, Debug, Serialize)]
+pub struct Preview {
+    /// Local time, ISO 8601
+    pub local: String,
+    pub offset_s: i32,
+    pub abbreviation: String,
+    pub dst: bool,
+    /// Next changes of offset
+    pub transitions: Vec<Transition>,
+}
+
+/// `timezone` at `at`
+pub fn preview(timezone: &TimeZone, at: Timestamp) -> Preview {
+    let info = timezone.to_offset_info(at);
+    let transitions = timezone
+        .following(at)
+        .take(PREVIEW_TRANSITIONS)
+        .map(|transition| Transition {
+            at: transition.timestamp().as_second(),
+            offset_s: transition.offset().seconds(),
+            abbreviation: transition.abbreviation().into(),
+            dst: transition.dst().is_dst(),
+        })
+        .collect();
+    Preview {
+        local: alloc::format!("{}", timezone.to_datetime(at)),
+        offset_s: info.offset().seconds(),
+        abbreviation: info.abbreviation().into(),
+        dst: info.dst().is_dst(),
+        transitions,
+    }
+}
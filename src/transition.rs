@@ -0,0 +1,83 @@
+//! Transitions between two frames, rendered as animations
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{animation::Builder, display::Canvas};
+
+/// Delay between two simulation steps
+const STEP_MS: u16 = 40;
+
+/// Fastest fall, in pixels per step
+const MAX_SPEED: i32 = 3;
+
+/// Steps between two consecutive rows starting to fall
+const ROW_STAGGER: i32 = 2;
+
+struct Block {
+    x: usize,
+    y: i32,
+    target_y: i32,
+    speed: i32,
+    /// Steps left before it starts falling
+    wait: i32,
+}
+
+/// "Falling blocks" transition towards `target`
+///
+/// The pixels of `rows` drop from above the matrix and pile up into place,
+/// bottom rows first, accelerating like under gravity. Pixels out of `rows`
+/// stay still. Returns an animation in the `animation` format.
+pub fn falling_blocks<const W: usize, const H: usize>(
+    target: &Canvas<W, H>,
+    rows: Range<usize>,
+) -> Vec<u8> {
+    let rows = rows.start.min(H)..rows.end.min(H);
+
+    let mut blocks = Vec::new();
+    for y in rows.clone() {
+        for x in 0..W {
+            if target.get(x, y) {
+                let from_bottom = (rows.end - 1 - y) as i32;
+                blocks.push(Block {
+                    x,
+                    y: -1,
+                    target_y: y as i32,
+                    speed: 0,
+                    // Columns in a slightly shuffled order, as pieces would
+                    wait: from_bottom * ROW_STAGGER + ((x * 7) % 5) as i32,
+                });
+            }
+        }
+    }
+
+    let mut base = *target;
+    base.clear_area(0, rows.start, W, rows.len());
+
+    let mut builder = Builder::new(W as u8, H as u8);
+    loop {
+        let mut falling = false;
+        for block in blocks.iter_mut() {
+            if block.wait > 0 {
+                block.wait -= 1;
+                falling = true;
+            } else if block.y < block.target_y {
+                block.speed = (block.speed + 1).min(MAX_SPEED);
+                block.y = (block.y + block.speed).min(block.target_y);
+                falling = true;
+            }
+        }
+
+        let mut frame = base;
+        for block in blocks.iter().filter(|block| block.wait == 0 && block.y >= 0) {
+            frame.on(block.x, block.y as usize);
+        }
+        builder.push(&frame, STEP_MS);
+
+        if !falling {
+            break;
+        }
+    }
+
+    builder.finish()
+}
@@ -0,0 +1,86 @@
+//! Maximum wifi TX power
+//!
+//! Lower it for a clock sitting next to its access point, less interference
+//! and a little less current, or keep the driver default of 20 dBm when it is
+//! far away. Settings are JSON, read and written through the HTTP API and
+//! kept in NVS:
+//!
+//! ```json
+//! {"dbm":8}
+//! ```
+//!
+//! Given to the wifimanager at boot, which sets it each time the radio
+//! starts, and changed at once from the API.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::wifimanager::{self, Nvs, Record, RecordError, TX_POWER_RANGE};
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<TxPowerSettings>> =
+    BlockingMutex::new(Cell::new(TxPowerSettings { dbm: None }));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxPowerSettings {
+    /// `None` for the driver default
+    #[serde(default)]
+    pub dbm: Option<u8>,
+}
+
+#[derive(Debug)]
+pub enum TxPowerError {
+    /// Outside of `TX_POWER_RANGE`
+    OutOfRange,
+    Record(RecordError),
+}
+
+impl TxPowerSettings {
+    pub fn validate(&self) -> Result<(), TxPowerError> {
+        match self.dbm {
+            Some(dbm) if !TX_POWER_RANGE.contains(&dbm) => Err(TxPowerError::OutOfRange),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Current settings
+pub fn settings() -> TxPowerSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<TxPowerSettings>(Record::TxPower);
+    match saved {
+        Some(Ok(settings)) if settings.validate().is_ok() => {
+            SETTINGS.lock(|current| current.set(settings))
+        }
+        Some(_) => crate::log!("Invalid saved TX power, ignored"),
+        None => {}
+    }
+}
+
+/// Check, save and apply `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: TxPowerSettings,
+) -> Result<(), TxPowerError> {
+    settings.validate()?;
+
+    storage
+        .lock()
+        .await
+        .write_json(Record::TxPower, &settings)
+        .map_err(TxPowerError::Record)?;
+    SETTINGS.lock(|current| current.set(settings));
+    wifimanager::set_tx_power(settings.dbm);
+    Ok(())
+}
@@ -0,0 +1,69 @@
+//! Units and number formats of what the faces and widgets show
+//!
+//! Metric, day first and a decimal point by default. Settings are JSON, read
+//! and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! {"temperature":"Fahrenheit","date_order":"Mdy","decimal":"Point"}
+//! ```
+//!
+//! Only the matrix follows them, the HTTP API and MQTT stay in °C with ISO
+//! dates. The 12 hour clock is set in the button menu. Formatting is in
+//! `b_intime_logic::units`.
+
+use alloc::string::String;
+use core::cell::Cell;
+
+use b_intime_logic::units;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use jiff::civil::Date;
+
+use crate::wifimanager::{Nvs, Record, RecordError};
+
+pub use b_intime_logic::units::{DateOrder, DecimalMark, TemperatureUnit, Units};
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<Units>> =
+    BlockingMutex::new(Cell::new(Units {
+        temperature: TemperatureUnit::Celsius,
+        date_order: DateOrder::Dmy,
+        decimal: DecimalMark::Point,
+    }));
+
+/// Current settings
+pub fn settings() -> Units {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Temperature from °C with `decimals` digits and the unit, dashes when
+/// unknown
+pub fn temperature(celsius: Option<f32>, decimals: usize) -> String {
+    units::temperature(settings(), celsius, decimals)
+}
+
+/// Day and month of `date`, in the date order
+pub fn short_date(date: Date) -> String {
+    units::short_date(settings().date_order, date)
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage.lock().await.read_json::<Units>(Record::Units);
+    match saved {
+        Some(Ok(settings)) => SETTINGS.lock(|current| current.set(settings)),
+        Some(Err(_)) => crate::log!("Invalid saved units, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: Units,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Units, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Audio-reactive VU meter face, from an I2S MEMS microphone (INMP441 like)
+//!
+//! Enabled with the `microphone` feature. Samples are split in bands by a
+//! cascade of fixed-point one-pole low-pass filters, and the peak of each band
+//! over a block gives the bar height, on a log scale.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use esp_hal::{
+    dma_circular_buffers,
+    gpio::AnyPin,
+    i2s::master::{Channels, Config, DataFormat, I2s},
+    peripherals::{DMA_CH0, I2S0},
+    time::Rate,
+};
+
+use crate::display::Canvas;
+
+pub const BANDS: usize = 4;
+
+const SAMPLE_RATE: u32 = 16_000;
+
+/// Samples per level update, 32 ms
+const BLOCK_LEN: usize = 512;
+
+/// One-pole low-pass coefficients (Q15) for cutoffs at 250 Hz, 1 kHz and 4 kHz,
+/// `1 - exp(-2 pi fc / fs)`
+const LOW_PASS_ALPHA: [i64; BANDS - 1] = [3070, 10650, 25950];
+
+/// log2 of the peak under which a band is silent (microphone noise floor)
+const NOISE_FLOOR_BITS: u32 = 7;
+
+static LEVELS: Mutex<CriticalSectionRawMutex, Cell<[u8; BANDS]>> =
+    Mutex::new(Cell::new([0; BANDS]));
+
+/// Last bands level, from 0 (silence) to 16, low frequencies first
+pub fn levels() -> [u8; BANDS] {
+    LEVELS.lock(|levels| levels.get())
+}
+
+/// Bars from the bottom of `canvas`, one per band
+pub fn draw<const W: usize, const H: usize>(canvas: &mut Canvas<W, H>, levels: &[u8; BANDS]) {
+    canvas.clear();
+    let bar_width = W / BANDS;
+    for (band, &level) in levels.iter().enumerate() {
+        let height = (level as usize * H / 16).min(H);
+        // One pixel gap between bars
+        for x in band * bar_width..(band + 1) * bar_width - 1 {
+            for y in H - height..H {
+                canvas.on(x, y);
+            }
+        }
+    }
+}
+
+struct Analyzer {
+    low_pass: [i64; BANDS - 1],
+    peaks: [i64; BANDS],
+    count: usize,
+}
+
+impl Analyzer {
+    fn new() -> Self {
+        Self {
+            low_pass: [0; BANDS - 1],
+            peaks: [0; BANDS],
+            count: 0,
+        }
+    }
+
+    /// Feed one sample, returns the levels at the end of each block
+    fn push(&mut self, sample: i64) -> Option<[u8; BANDS]> {
+        for (state, alpha) in self.low_pass.iter_mut().zip(LOW_PASS_ALPHA) {
+            *state += ((sample - *state) * alpha) >> 15;
+        }
+
+        // Band n is what passes the n-th low-pass and not the previous one
+        let mut lower = 0;
+        for (band, peak) in self.peaks.iter_mut().enumerate() {
+            let upper = self.low_pass.get(band).copied().unwrap_or(sample);
+            *peak = (*peak).max((upper - lower).abs());
+            lower = upper;
+        }
+
+        self.count += 1;
+        if self.count < BLOCK_LEN {
+            return None;
+        }
+
+        let mut levels = [0u8; BANDS];
+        for (level, peak) in levels.iter_mut().zip(self.peaks.iter_mut()) {
+            let bits = 64 - (*peak as u64).leading_zeros();
+            *level = bits.saturating_sub(NOISE_FLOOR_BITS).min(16) as u8;
+            *peak = 0;
+        }
+        self.count = 0;
+        Some(levels)
+    }
+}
+
+/// Read the microphone and keep `levels` up to date
+#[embassy_executor::task]
+pub async fn mic_task(
+    i2s: I2S0<'static>,
+    dma: DMA_CH0<'static>,
+    bclk: AnyPin<'static>,
+    ws: AnyPin<'static>,
+    din: AnyPin<'static>,
+) {
+    let (rx_buffer, rx_descriptors, _, _) = dma_circular_buffers!(4 * BLOCK_LEN * 4, 0);
+
+    let config = Config::new_tdm_philips()
+        .with_sample_rate(Rate::from_hz(SAMPLE_RATE))
+        .with_data_format(DataFormat::Data32Channel32)
+        .with_channels(Channels::STEREO);
+    let i2s = match I2s::new(i2s, dma, config) {
+        Ok(i2s) => i2s.into_async(),
+        Err(e) => {
+            crate::log!("I2S config error: {e:?}");
+            return;
+        }
+    };
+
+    let i2s_rx = i2s
+        .i2s_rx
+        .with_bclk(bclk)
+        .with_ws(ws)
+        .with_din(din)
+        .build(rx_descriptors);
+    let mut transfer = match i2s_rx.read_dma_circular_async(rx_buffer) {
+        Ok(transfer) => transfer,
+        Err(e) => {
+            crate::log!("I2S read error: {e:?}");
+            return;
+        }
+    };
+
+    let mut analyzer = Analyzer::new();
+    let mut data = [0u8; 4 * 2 * 64];
+    // Bytes of an incomplete frame left by the previous read
+    let mut pending = 0;
+    loop {
+        let len = match transfer.pop(&mut data[pending..]).await {
+            Ok(len) => pending + len,
+            Err(e) => {
+                crate::log!("I2S read error: {e:?}");
+                return;
+            }
+        };
+
+        // Left slot only, 24 bits left-justified in 32
+        let frames = data[..len].chunks_exact(8);
+        let rest = frames.remainder().len();
+        for frame in frames {
+            let sample = i32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]]) >> 8;
+            if let Some(levels) = analyzer.push(sample as i64) {
+                LEVELS.lock(|current| current.set(levels));
+            }
+        }
+
+        data.copy_within(len - rest..len, 0);
+        pending = rest;
+    }
+}
@@ -0,0 +1,117 @@
+//! Wake alarm, with a sunrise on the matrix before it rings
+//!
+//! Disabled by default. Settings are JSON, read and written through the HTTP
+//! API and kept in NVS:
+//!
+//! ```json
+//! {"alarm":{"days":"Workdays","minute":420,"sunrise":true}}
+//! ```
+//!
+//! `minute` is from midnight, local time. With `sunrise`, the brightness and a
+//! horizon line grow over the `SUNRISE` before the alarm. The alarm rings like
+//! the kitchen timer, so it snoozes and stops the same way, and do not disturb
+//! silences both.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use embassy_time::Duration;
+use jiff::civil::Weekday;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    countdown, dnd,
+    theme::Days,
+    wifimanager::{Nvs, Record, RecordError},
+};
+
+/// Ramp before the alarm
+pub const SUNRISE: Duration = Duration::from_secs(10 * 60);
+
+static SETTINGS: BlockingMutex<CriticalSectionRawMutex, Cell<WakeSettings>> =
+    BlockingMutex::new(Cell::new(WakeSettings { alarm: None }));
+/// Day and minute of the last ring, so it rings once
+static RUNG: BlockingMutex<CriticalSectionRawMutex, Cell<Option<(Weekday, u16)>>> =
+    BlockingMutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WakeAlarm {
+    pub days: Days,
+    /// From midnight
+    pub minute: u16,
+    #[serde(default)]
+    pub sunrise: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WakeSettings {
+    #[serde(default)]
+    pub alarm: Option<WakeAlarm>,
+}
+
+impl WakeSettings {
+    pub fn is_valid(&self) -> bool {
+        self.alarm.is_none_or(|alarm| alarm.minute < 24 * 60)
+    }
+}
+
+/// Current settings
+pub fn settings() -> WakeSettings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Read the settings saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage.lock().await.read_json::<WakeSettings>(Record::Wake);
+    match saved {
+        Some(Ok(settings)) if settings.is_valid() => SETTINGS.lock(|current| current.set(settings)),
+        Some(_) => crate::log!("Invalid saved wake alarm, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `settings`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    settings: WakeSettings,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Wake, &settings)?;
+    SETTINGS.lock(|current| current.set(settings));
+    Ok(())
+}
+
+/// Ring the alarm when its time comes, called with the time each second
+pub fn tick(weekday: Weekday, minute: u16) {
+    let Some(alarm) = settings().alarm else {
+        return;
+    };
+
+    if minute == alarm.minute && alarm.days.contains(weekday) {
+        let rung = RUNG.lock(|rung| rung.replace(Some((weekday, minute))));
+        if rung != Some((weekday, minute)) && dnd::allows(dnd::Kind::Alarm) {
+            crate::log!("Wake alarm");
+            countdown::ring();
+        }
+    }
+}
+
+/// Sunrise progress, from 0 to 1, while it is on
+pub fn sunrise(weekday: Weekday, minute: u16, second: u8) -> Option<f32> {
+    let alarm = settings().alarm?;
+    if !alarm.sunrise || !dnd::allows(dnd::Kind::Alarm) {
+        return None;
+    }
+    // The sunrise may start the day before
+    let now = minute as u64 * 60 + second as u64;
+    let start = alarm.minute as u64 * 60;
+    let (day, left) = if start > now {
+        (weekday, start - now)
+    } else {
+        (weekday.next(), start + 24 * 60 * 60 - now)
+    };
+    (alarm.days.contains(day) && left <= SUNRISE.as_secs())
+        .then(|| 1.0 - left as f32 / SUNRISE.as_secs() as f32)
+}
@@ -0,0 +1,173 @@
+//! Task heartbeats, to tell which subsystem stalls before it causes a reset
+//!
+//! Long running loops call `beat` once per iteration and `error` when one
+//! fails. `supervisor_task` logs the tasks whose last beat is older than their
+//! limit, and `/api/tasks` reports ages, iterations and last errors.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+use serde::Serialize;
+
+/// Longest error kept, the report must fit in an API response
+const MAX_ERROR_LEN: usize = 32;
+const CHECK_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Task {
+    Display,
+    Ntp,
+    Weather,
+    Maintenance,
+    Discovery,
+    Mqtt,
+    Api,
+    Webhooks,
+}
+
+const TASKS: [Task; 8] = [
+    Task::Display,
+    Task::Ntp,
+    Task::Weather,
+    Task::Maintenance,
+    Task::Discovery,
+    Task::Mqtt,
+    Task::Api,
+    Task::Webhooks,
+];
+
+impl Task {
+    /// Longest expected time between two beats, `None` for tasks waiting on
+    /// the network or on requests
+    fn limit(self) -> Option<Duration> {
+        match self {
+            // Animations and games hold the loop for a while
+            Task::Display => Some(Duration::from_secs(5 * 60)),
+            Task::Ntp => Some(Duration::from_secs(5 * 60)),
+            Task::Weather => Some(Duration::from_secs(15 * 60)),
+            Task::Maintenance => Some(Duration::from_secs(2 * 60)),
+            Task::Discovery | Task::Mqtt | Task::Api | Task::Webhooks => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        TASKS.iter().position(|&task| task == self).unwrap_or(0)
+    }
+}
+
+#[derive(Clone)]
+struct Stats {
+    last_beat: Option<Instant>,
+    iterations: u32,
+    last_error: Option<String>,
+    healthy: bool,
+}
+
+impl Stats {
+    fn age(&self) -> Option<Duration> {
+        self.last_beat.map(|beat| beat.elapsed())
+    }
+
+    /// Never beating counts from boot
+    fn is_healthy(&self, task: Task) -> bool {
+        let age = self
+            .age()
+            .unwrap_or(Duration::from_ticks(Instant::now().as_ticks()));
+        task.limit().is_none_or(|limit| age <= limit)
+    }
+}
+
+static STATS: Mutex<CriticalSectionRawMutex, RefCell<[Stats; TASKS.len()]>> =
+    Mutex::new(RefCell::new(
+        [const {
+            Stats {
+                last_beat: None,
+                iterations: 0,
+                last_error: None,
+                healthy: true,
+            }
+        }; TASKS.len()],
+    ));
+
+/// `task` went through one more iteration
+pub fn beat(task: Task) {
+    STATS.lock(|stats| {
+        let stats = &mut stats.borrow_mut()[task.index()];
+        stats.last_beat = Some(Instant::now());
+        stats.iterations = stats.iterations.wrapping_add(1);
+    });
+}
+
+/// An iteration of `task` failed with `error`, shortened
+pub fn error(task: Task, error: &str) {
+    let end = (0..=error.len().min(MAX_ERROR_LEN))
+        .rev()
+        .find(|&end| error.is_char_boundary(end))
+        .unwrap_or(0);
+    STATS.lock(|stats| stats.borrow_mut()[task.index()].last_error = Some(error[..end].into()));
+}
+
+/// Whether every task beats within its limit
+pub fn all_healthy() -> bool {
+    STATS.lock(|stats| {
+        TASKS
+            .iter()
+            .zip(stats.borrow().iter())
+            .all(|(&task, stats)| stats.is_healthy(task))
+    })
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskReport {
+    pub task: Task,
+    /// Since the last beat, `None` before the first one
+    pub age_ms: Option<u64>,
+    pub iterations: u32,
+    pub error: Option<String>,
+    pub healthy: bool,
+}
+
+/// State of every task
+pub fn report() -> [TaskReport; TASKS.len()] {
+    STATS.lock(|stats| {
+        let stats = stats.borrow();
+        core::array::from_fn(|idx| TaskReport {
+            task: TASKS[idx],
+            age_ms: stats[idx].age().map(|age| age.as_millis()),
+            iterations: stats[idx].iterations,
+            error: stats[idx].last_error.clone(),
+            healthy: stats[idx].is_healthy(TASKS[idx]),
+        })
+    })
+}
+
+/// Log the tasks becoming unhealthy, and recovering
+#[embassy_executor::task]
+pub async fn supervisor_task() {
+    loop {
+        Timer::after(CHECK_PERIOD).await;
+        // Logged out of the lock
+        let changes: Vec<(Task, bool, Option<String>)> = STATS.lock(|stats| {
+            TASKS
+                .iter()
+                .zip(stats.borrow_mut().iter_mut())
+                .filter_map(|(&task, stats)| {
+                    let healthy = stats.is_healthy(task);
+                    (healthy != stats.healthy).then(|| {
+                        stats.healthy = healthy;
+                        (task, healthy, stats.last_error.clone())
+                    })
+                })
+                .collect()
+        });
+        for (task, healthy, last_error) in changes {
+            if healthy {
+                crate::log!("Task {task:?} recovered");
+            } else {
+                crate::log!("Task {task:?} stalled, last error: {last_error:?}");
+            }
+        }
+    }
+}
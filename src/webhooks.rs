@@ -0,0 +1,219 @@
+//! Outbound webhooks, for integrations without an MQTT broker
+//!
+//! Hooks are JSON, read and written through the HTTP API and kept in NVS:
+//!
+//! ```json
+//! [{"url":"http://192.168.1.2:1880/clock","events":["Alarm","ButtonPressed"],
+//!   "template":"{\"text\":\"{device}: {event}\"}"}]
+//! ```
+//!
+//! Each event is POSTed to the hooks listening to it, with the template as
+//! the body once `{event}`, `{device}` and `{uptime}` (seconds) are replaced.
+//! Only plain HTTP URLs are supported.
+
+use alloc::{string::String, vec::Vec};
+use core::cell::RefCell;
+
+use embassy_net::{
+    tcp::client::{TcpClient, TcpClientState},
+    Stack,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    channel::Channel,
+    mutex::Mutex,
+};
+use embassy_time::Instant;
+use reqwless::{
+    client::HttpClient,
+    headers::ContentType,
+    request::{Method, RequestBuilder},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    capabilities::{self, Capability},
+    device,
+    dns::CachedDns,
+    sockets::{self, HTTP_CLIENT_BUFFER},
+    watchdog::{self, Task},
+    wifimanager::{NetEvent, NetEventSubscriber, Nvs, Record, RecordError},
+};
+
+pub const MAX_HOOKS: usize = 4;
+const MAX_URL_LEN: usize = 96;
+const MAX_TEMPLATE_LEN: usize = 128;
+/// Body of the hooks without a template
+const DEFAULT_TEMPLATE: &str = r#"{"device":"{device}","event":"{event}","uptime":{uptime}}"#;
+
+static HOOKS: BlockingMutex<CriticalSectionRawMutex, RefCell<Vec<Hook>>> =
+    BlockingMutex::new(RefCell::new(Vec::new()));
+static EVENTS: Channel<CriticalSectionRawMutex, Event, 4> = Channel::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    /// The kitchen timer started ringing
+    Alarm,
+    /// No NTP sync for too long
+    NtpDesync,
+    /// The station got an address back after losing it
+    WifiReconnected,
+    ButtonPressed,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Alarm => "alarm",
+            Event::NtpDesync => "ntp_desync",
+            Event::WifiReconnected => "wifi_reconnected",
+            Event::ButtonPressed => "button_pressed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hook {
+    /// "http://host[:port]/path"
+    pub url: String,
+    pub events: Vec<Event>,
+    /// Request body, `DEFAULT_TEMPLATE` when missing
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl Hook {
+    fn is_valid(&self) -> bool {
+        self.url.starts_with("http://")
+            && self.url.len() <= MAX_URL_LEN
+            && !self.events.is_empty()
+            && self
+                .template
+                .as_ref()
+                .is_none_or(|template| template.len() <= MAX_TEMPLATE_LEN)
+    }
+
+    fn body(&self, event: Event, device: &str) -> String {
+        let uptime = alloc::format!("{}", Instant::now().as_secs());
+        self.template
+            .as_deref()
+            .unwrap_or(DEFAULT_TEMPLATE)
+            .replace("{event}", event.name())
+            .replace("{device}", device)
+            .replace("{uptime}", &uptime)
+    }
+}
+
+pub fn is_valid(hooks: &[Hook]) -> bool {
+    hooks.len() <= MAX_HOOKS && hooks.iter().all(Hook::is_valid)
+}
+
+/// Current hooks
+pub fn hooks() -> Vec<Hook> {
+    HOOKS.lock(|hooks| hooks.borrow().clone())
+}
+
+fn set_hooks(hooks: Vec<Hook>) {
+    HOOKS.lock(|current| *current.borrow_mut() = hooks);
+}
+
+/// Call the hooks listening to `event`, dropped when too many are pending
+pub fn fire(event: Event) {
+    if EVENTS.try_send(event).is_err() {
+        crate::log!("Webhook {event:?} dropped");
+    }
+}
+
+/// Read the hooks saved in NVS
+pub async fn load(storage: &Mutex<CriticalSectionRawMutex, Nvs>) {
+    let saved = storage
+        .lock()
+        .await
+        .read_json::<Vec<Hook>>(Record::Webhooks);
+    match saved {
+        Some(Ok(hooks)) if is_valid(&hooks) => set_hooks(hooks),
+        Some(_) => crate::log!("Invalid saved webhooks, ignored"),
+        None => {}
+    }
+}
+
+/// Apply and save `hooks`, they must be valid
+pub async fn save(
+    storage: &Mutex<CriticalSectionRawMutex, Nvs>,
+    hooks: Vec<Hook>,
+) -> Result<(), RecordError> {
+    storage.lock().await.write_json(Record::Webhooks, &hooks)?;
+    set_hooks(hooks);
+    Ok(())
+}
+
+/// Host of `url` for the log, paths and user info of webhooks often hold tokens
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+}
+
+async fn post(stack: Stack<'_>, url: &str, body: &str) {
+    let _lease = sockets::lease(sockets::Use::Webhook);
+    let dns = CachedDns::new(stack);
+    let tcp_state = TcpClientState::<1, HTTP_CLIENT_BUFFER, HTTP_CLIENT_BUFFER>::new();
+    let tcp = TcpClient::new(stack, &tcp_state);
+    let mut client = HttpClient::new(&tcp, &dns);
+    let mut buffer = [0u8; 1024];
+
+    let mut request = match client.request(Method::POST, url).await {
+        Ok(request) => request
+            .content_type(ContentType::ApplicationJson)
+            .body(body.as_bytes()),
+        Err(e) => {
+            crate::log!("Webhook {} connection error: {e:?}", host(url));
+            watchdog::error(Task::Webhooks, &alloc::format!("{e:?}"));
+            return;
+        }
+    };
+    match request.send(&mut buffer).await {
+        Ok(response) if response.status.is_successful() => {}
+        Ok(response) => {
+            crate::log!("Webhook {} answered {:?}", host(url), response.status);
+            watchdog::error(Task::Webhooks, &alloc::format!("{:?}", response.status));
+        }
+        Err(e) => {
+            crate::log!("Webhook {} request error: {e:?}", host(url));
+            watchdog::error(Task::Webhooks, &alloc::format!("{e:?}"));
+        }
+    }
+}
+
+/// Send the fired events, and `WifiReconnected` from `net_events`
+#[embassy_executor::task]
+pub async fn webhook_task(stack: Stack<'static>, mut net_events: NetEventSubscriber) {
+    let mut lost_ip = false;
+    loop {
+        let event =
+            match embassy_futures::select::select(EVENTS.receive(), net_events.next_message_pure())
+                .await
+            {
+                embassy_futures::select::Either::First(event) => event,
+                embassy_futures::select::Either::Second(NetEvent::LostIp) => {
+                    lost_ip = true;
+                    continue;
+                }
+                embassy_futures::select::Either::Second(NetEvent::GotIp) if lost_ip => {
+                    lost_ip = false;
+                    Event::WifiReconnected
+                }
+                embassy_futures::select::Either::Second(_) => continue,
+            };
+
+        watchdog::beat(Task::Webhooks);
+        if !capabilities::is_enabled(Capability::Webhooks) {
+            continue;
+        }
+        for hook in hooks().iter().filter(|hook| hook.events.contains(&event)) {
+            post(stack, &hook.url, &hook.body(event, &device::name())).await;
+        }
+    }
+}
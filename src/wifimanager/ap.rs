@@ -1,36 +1,224 @@
 use alloc::rc::Rc;
-use embassy_net::{Runner, Stack};
-use embassy_time::Duration;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Runner, Stack,
+};
+use embassy_time::{Duration, Instant};
+use esp_hal_dhcp_server::structs::{DhcpLease, DhcpLeaser};
+use esp_hal_dhcp_server::Ipv4Addr;
 use esp_radio::wifi::WifiDevice;
 
-use crate::wifimanager::structs::WmInnerSignals;
+use b_intime_logic::wifimanager::captive;
+
+use crate::wifimanager::clients;
+use crate::wifimanager::structs::{WmInnerSignals, WmSettings};
+
+/// Most addresses leased by the setup AP
+const MAX_LEASES: usize = 16;
+
+/// DHCP settings of the setup AP, out of `WmSettings`
+#[derive(Clone, Copy, Debug)]
+pub struct DhcpPool {
+    pub ip: Ipv4Addr,
+    pub prefix_len: u8,
+    pub size: u8,
+    pub lease_time: Duration,
+}
+
+impl DhcpPool {
+    pub fn new(settings: &WmSettings) -> Self {
+        Self {
+            ip: settings.ap_ip,
+            prefix_len: settings.ap_prefix_len.clamp(8, 30),
+            size: settings.dhcp_pool_size.min(MAX_LEASES as u8),
+            lease_time: Duration::from_secs(settings.dhcp_lease_time),
+        }
+    }
+
+    fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX << (32 - self.prefix_len))
+    }
+
+    /// First and last leased addresses, from the one after the AP and before
+    /// the broadcast address
+    fn range(&self) -> (u32, u32) {
+        let ip = u32::from(self.ip);
+        let broadcast = ip | !u32::from(self.netmask());
+        let start = ip.saturating_add(1);
+        let end = (ip.saturating_add(self.size as u32)).min(broadcast.saturating_sub(1));
+        (start, end)
+    }
+}
 
 #[embassy_executor::task]
-pub async fn run_dhcp_server(ap_stack: Stack<'static>) {
-    let mut leaser = esp_hal_dhcp_server::simple_leaser::SingleDhcpLeaser::new(
-        esp_hal_dhcp_server::Ipv4Addr::new(192, 168, 4, 100),
-    );
+pub async fn run_dhcp_server(
+    ap_stack: Stack<'static>,
+    pool: DhcpPool,
+    signals: Rc<WmInnerSignals>,
+) {
+    let (start, end) = pool.range();
+    let mut leaser = TrackingLeaser(PoolLeaser {
+        start,
+        end,
+        leases: heapless::Vec::new(),
+    });
 
-    let ip = esp_hal_dhcp_server::Ipv4Addr::new(192, 168, 4, 1);
-    let res = esp_hal_dhcp_server::run_dhcp_server(
+    let ap_ips = [pool.ip];
+    let server = esp_hal_dhcp_server::run_dhcp_server(
         ap_stack,
         esp_hal_dhcp_server::structs::DhcpServerConfig {
-            ip,
-            lease_time: Duration::from_secs(3600),
-            gateways: &[ip],
-            subnet: None,
-            dns: &[ip],
+            ip: pool.ip,
+            lease_time: pool.lease_time,
+            gateways: &ap_ips,
+            subnet: Some(pool.netmask()),
+            dns: &ap_ips,
             use_captive_portal: true,
         },
         &mut leaser,
-    )
-    .await;
+    );
 
-    if let Err(e) = res {
+    // `dhcp_close` stops it first, the end signal covers a server still binding
+    let res = embassy_futures::select::select(server, signals.end_signalled()).await;
+    if let embassy_futures::select::Either::First(Err(e)) = res {
         esp_println::println!("run_dhcp_server failed! ({e:?})");
     }
 }
 
+const DNS_PORT: u16 = 53;
+/// Largest DNS message over UDP
+const DNS_MESSAGE_LEN: usize = 512;
+
+/// Answer every name with the AP address, the DNS server the DHCP one
+/// advertises, so OS connectivity probes reach the portal
+#[embassy_executor::task]
+pub async fn run_dns_server(ap_stack: Stack<'static>, ip: Ipv4Addr, signals: Rc<WmInnerSignals>) {
+    let server = async {
+        let mut rx_meta = [PacketMetadata::EMPTY; 4];
+        let mut rx_buffer = [0; 2 * DNS_MESSAGE_LEN];
+        let mut tx_meta = [PacketMetadata::EMPTY; 4];
+        let mut tx_buffer = [0; 2 * DNS_MESSAGE_LEN];
+        let mut socket = UdpSocket::new(
+            ap_stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        if let Err(e) = socket.bind(DNS_PORT) {
+            esp_println::println!("run_dns_server bind failed! ({e:?})");
+            return;
+        }
+
+        let mut query = [0; DNS_MESSAGE_LEN];
+        let mut answer = [0; DNS_MESSAGE_LEN];
+        loop {
+            let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+                continue;
+            };
+            if let Some(len) = captive::answer(&query[..len], ip.octets(), &mut answer) {
+                _ = socket.send_to(&answer[..len], meta.endpoint).await;
+            }
+        }
+    };
+
+    embassy_futures::select::select(server, signals.end_signalled()).await;
+}
+
+/// Leases of the pool, remembered for the whole setup session
+///
+/// A released or expired lease keeps its address for the same phone, another
+/// one only gets it once the rest of the pool is taken, so a phone coming
+/// back to the portal keeps its IP.
+struct PoolLeaser {
+    start: u32,
+    end: u32,
+    leases: heapless::Vec<DhcpLease, MAX_LEASES>,
+}
+
+impl PoolLeaser {
+    fn contains(&self, ip: Ipv4Addr) -> bool {
+        (self.start..=self.end).contains(&u32::from(ip))
+    }
+}
+
+impl DhcpLeaser for PoolLeaser {
+    fn get_lease(&mut self, mac: [u8; 16]) -> Option<DhcpLease> {
+        self.leases.iter().find(|lease| lease.mac == mac).cloned()
+    }
+
+    fn next_lease(&mut self) -> Option<Ipv4Addr> {
+        let now = Instant::now();
+        (self.start..=self.end)
+            .map(Ipv4Addr::from)
+            .find(|&ip| self.leases.iter().all(|lease| lease.ip != ip))
+            .or_else(|| {
+                self.leases
+                    .iter()
+                    .filter(|lease| lease.expires <= now)
+                    .min_by_key(|lease| lease.expires)
+                    .map(|lease| lease.ip)
+            })
+    }
+
+    fn add_lease(&mut self, ip: Ipv4Addr, mac: [u8; 16], expires: Instant) -> bool {
+        let now = Instant::now();
+        let taken = self
+            .leases
+            .iter()
+            .any(|lease| lease.ip == ip && lease.mac != mac && lease.expires > now);
+        if !self.contains(ip) || taken {
+            return false;
+        }
+
+        self.leases.retain(|lease| lease.ip != ip && lease.mac != mac);
+        self.leases.push(DhcpLease { ip, mac, expires }).is_ok()
+    }
+
+    fn remove_lease(&mut self, mac: [u8; 16]) -> bool {
+        // Ends the lease but keeps the address for this phone
+        match self.leases.iter_mut().find(|lease| lease.mac == mac) {
+            Some(lease) => {
+                lease.expires = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Leaser recording the addresses it hands out in `clients`
+struct TrackingLeaser<L>(L);
+
+impl<L: DhcpLeaser> DhcpLeaser for TrackingLeaser<L> {
+    fn get_lease(&mut self, mac: [u8; 16]) -> Option<DhcpLease> {
+        self.0.get_lease(mac)
+    }
+
+    fn next_lease(&mut self) -> Option<Ipv4Addr> {
+        self.0.next_lease()
+    }
+
+    fn add_lease(
+        &mut self,
+        ip: Ipv4Addr,
+        mac: [u8; 16],
+        expires: Instant,
+    ) -> bool {
+        let added = self.0.add_lease(ip, mac, expires);
+        if let (true, Some(mac)) = (added, clients::mac_of(&mac)) {
+            clients::leased(mac, ip.octets());
+        }
+        added
+    }
+
+    fn remove_lease(&mut self, mac: [u8; 16]) -> bool {
+        if let Some(mac) = clients::mac_of(&mac) {
+            clients::released(mac);
+        }
+        self.0.remove_lease(mac)
+    }
+}
+
 #[embassy_executor::task]
 pub async fn ap_task(
     mut runner: Runner<'static, WifiDevice<'static>>,
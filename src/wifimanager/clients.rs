@@ -0,0 +1,98 @@
+//! Stations associated to the setup AP, with the address the DHCP server
+//! leased them
+//!
+//! The wifi driver reports associations and departures, the DHCP leaser the
+//! addresses. The display and the portal show the count, so users know their
+//! phone actually joined the setup network.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use esp_radio::wifi::event::{ApStaConnected, ApStaDisconnected, EventExt};
+
+/// Stations tracked, more are counted by the AP but not listed
+const MAX_CLIENTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApClient {
+    pub mac: [u8; 6],
+    /// Leased address, `None` until the station asks for one
+    pub ip: Option<[u8; 4]>,
+}
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<ApClient, MAX_CLIENTS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Stations associated to the setup AP
+pub fn ap_clients() -> heapless::Vec<ApClient, MAX_CLIENTS> {
+    CLIENTS.lock(|clients| clients.borrow().clone())
+}
+
+/// Follow the associations to the AP, before it starts
+pub(crate) fn watch() {
+    ApStaConnected::update_handler(|event| {
+        if let Some(mac) = mac_of(event.mac()) {
+            associated(mac);
+        }
+    });
+    ApStaDisconnected::update_handler(|event| {
+        if let Some(mac) = mac_of(event.mac()) {
+            left(mac);
+        }
+    });
+}
+
+/// Stop following the associations and forget the stations, once the AP
+/// stopped
+pub(crate) fn unwatch() {
+    drop(ApStaConnected::take_handler());
+    drop(ApStaDisconnected::take_handler());
+    CLIENTS.lock(|clients| clients.borrow_mut().clear());
+}
+
+/// `ip` leased to the station `mac`
+pub(crate) fn leased(mac: [u8; 6], ip: [u8; 4]) {
+    CLIENTS.lock(|clients| {
+        let mut clients = clients.borrow_mut();
+        match clients.iter_mut().find(|client| client.mac == mac) {
+            Some(client) => client.ip = Some(ip),
+            // Leased before the association event ran
+            None => _ = clients.push(ApClient { mac, ip: Some(ip) }),
+        }
+    });
+}
+
+/// Lease of the station `mac` released
+pub(crate) fn released(mac: [u8; 6]) {
+    CLIENTS.lock(|clients| {
+        if let Some(client) = clients.borrow_mut().iter_mut().find(|client| client.mac == mac) {
+            client.ip = None;
+        }
+    });
+}
+
+fn associated(mac: [u8; 6]) {
+    crate::log!("Setup AP: {} joined", format_mac(mac));
+    CLIENTS.lock(|clients| {
+        let mut clients = clients.borrow_mut();
+        if !clients.iter().any(|client| client.mac == mac) {
+            _ = clients.push(ApClient { mac, ip: None });
+        }
+    });
+}
+
+fn left(mac: [u8; 6]) {
+    crate::log!("Setup AP: {} left", format_mac(mac));
+    CLIENTS.lock(|clients| clients.borrow_mut().retain(|client| client.mac != mac));
+}
+
+/// Station address out of the first bytes of `bytes`, DHCP hardware
+/// addresses are 16 bytes long
+pub(crate) fn mac_of(bytes: &[u8]) -> Option<[u8; 6]> {
+    bytes.get(..6)?.try_into().ok()
+}
+
+pub(crate) fn format_mac(mac: [u8; 6]) -> alloc::string::String {
+    let [a, b, c, d, e, f] = mac;
+    alloc::format!("{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}")
+}
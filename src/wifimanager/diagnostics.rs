@@ -0,0 +1,129 @@
+//! Last disconnect reasons of the station, from the driver events
+//!
+//! A failed connection ends with a disconnect event too, its reason tells a
+//! wrong password from an access point out of reach.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+use esp_radio::wifi::event::{EventExt, StaDisconnected};
+use serde::Serialize;
+
+use super::LinkQuality;
+
+/// Disconnects kept, the oldest ones are dropped
+const MAX_DISCONNECTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Disconnect {
+    pub uptime_s: u64,
+    /// `wifi_err_reason_t` of the driver
+    pub reason: u8,
+    pub name: &'static str,
+    /// Signal of the access point when the link dropped
+    pub rssi: i8,
+}
+
+static DISCONNECTS: Mutex<
+    CriticalSectionRawMutex,
+    RefCell<heapless::Deque<Disconnect, MAX_DISCONNECTS>>,
+> = Mutex::new(RefCell::new(heapless::Deque::new()));
+
+#[derive(Debug, Serialize)]
+pub struct Diagnostics {
+    /// Uptime now, to date the disconnects
+    pub uptime_s: u64,
+    pub connected: bool,
+    /// Smoothed signal, while connected
+    pub link: Option<LinkQuality>,
+    /// The oldest first
+    pub disconnects: Vec<Disconnect>,
+}
+
+/// State of the station and its last disconnects
+pub fn diagnostics() -> Diagnostics {
+    Diagnostics {
+        uptime_s: Instant::now().as_secs(),
+        connected: esp_radio::wifi::sta_state() == esp_radio::wifi::WifiStaState::Connected,
+        link: super::link_quality(),
+        disconnects: DISCONNECTS
+            .lock(|disconnects| disconnects.borrow().iter().copied().collect()),
+    }
+}
+
+/// Record the disconnects, before the first connection
+pub(crate) fn watch() {
+    StaDisconnected::update_handler(|event| {
+        let disconnect = Disconnect {
+            uptime_s: Instant::now().as_secs(),
+            reason: event.reason(),
+            name: reason_name(event.reason()),
+            rssi: event.rssi(),
+        };
+        DISCONNECTS.lock(|disconnects| {
+            let mut disconnects = disconnects.borrow_mut();
+            if disconnects.is_full() {
+                disconnects.pop_front();
+            }
+            _ = disconnects.push_back(disconnect);
+        });
+    });
+}
+
+/// Name of a driver disconnect reason, like `esp_wifi_types.h` without the
+/// `WIFI_REASON_` prefix
+pub fn reason_name(reason: u8) -> &'static str {
+    match reason {
+        1 => "UNSPECIFIED",
+        2 => "AUTH_EXPIRE",
+        3 => "AUTH_LEAVE",
+        4 => "DISASSOC_DUE_TO_INACTIVITY",
+        5 => "ASSOC_TOOMANY",
+        6 => "CLASS2_FRAME_FROM_NONAUTH_STA",
+        7 => "CLASS3_FRAME_FROM_NONASSOC_STA",
+        8 => "ASSOC_LEAVE",
+        9 => "ASSOC_NOT_AUTHED",
+        10 => "DISASSOC_PWRCAP_BAD",
+        11 => "DISASSOC_SUPCHAN_BAD",
+        12 => "BSS_TRANSITION_DISASSOC",
+        13 => "IE_INVALID",
+        14 => "MIC_FAILURE",
+        15 => "4WAY_HANDSHAKE_TIMEOUT",
+        16 => "GROUP_KEY_UPDATE_TIMEOUT",
+        17 => "IE_IN_4WAY_DIFFERS",
+        18 => "GROUP_CIPHER_INVALID",
+        19 => "PAIRWISE_CIPHER_INVALID",
+        20 => "AKMP_INVALID",
+        21 => "UNSUPP_RSN_IE_VERSION",
+        22 => "INVALID_RSN_IE_CAP",
+        23 => "802_1X_AUTH_FAILED",
+        24 => "CIPHER_SUITE_REJECTED",
+        46 => "PEER_INITIATED",
+        47 => "AP_INITIATED",
+        49 => "INVALID_PMKID",
+        200 => "BEACON_TIMEOUT",
+        201 => "NO_AP_FOUND",
+        202 => "AUTH_FAIL",
+        203 => "ASSOC_FAIL",
+        204 => "HANDSHAKE_TIMEOUT",
+        205 => "CONNECTION_FAIL",
+        206 => "AP_TSF_RESET",
+        207 => "ROAMING",
+        208 => "ASSOC_COMEBACK_TIME_TOO_LONG",
+        209 => "SA_QUERY_TIMEOUT",
+        210 => "NO_AP_FOUND_W_COMPATIBLE_SECURITY",
+        211 => "NO_AP_FOUND_IN_AUTHMODE_THRESHOLD",
+        212 => "NO_AP_FOUND_IN_RSSI_THRESHOLD",
+        _ => "OTHER",
+    }
+}
+
+/// Reason of the latest disconnect, for the log
+pub(crate) fn last_reason() -> alloc::string::String {
+    match DISCONNECTS.lock(|disconnects| disconnects.borrow().back().copied()) {
+        Some(disconnect) => alloc::format!("{} ({})", disconnect.name, disconnect.reason),
+        None => alloc::string::String::from("no reason reported"),
+    }
+}
@@ -1,81 +1,366 @@
 use crate::wifimanager::structs::AutoSetupSettings;
 
 use super::structs::WmInnerSignals;
-use alloc::{format, rc::Rc, vec::Vec};
+use alloc::{format, rc::Rc};
+use core::fmt::{self, Write};
 use embassy_executor::Spawner;
-use embassy_net::{tcp::TcpSocket, Stack};
+use embassy_net::{
+    tcp::{self, TcpSocket},
+    Ipv4Address, Stack,
+};
 use embassy_time::{Duration, Timer};
+use serde::Serialize;
 
 const WEB_TASK_POOL_SIZE: usize = 2;
 const HTTP_BUFFER_SIZE: usize = 2048;
+const RESPONSE_BUFFER_SIZE: usize = 1024;
+/// Longest scan list, it is sent from the response buffer
+pub(crate) const SCAN_LIST_LEN: usize = RESPONSE_BUFFER_SIZE;
 
-struct HttpRequest<'a> {
-    method: &'a str,
-    path: &'a str,
-    body: &'a [u8],
+pub(crate) struct HttpRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// Header lines, without the request line
+    pub headers: &'a str,
+    pub body: &'a [u8],
 }
 
-fn parse_http_request(buffer: &[u8]) -> Option<HttpRequest<'_>> {
-    let request = core::str::from_utf8(buffer).ok()?;
-    let mut lines = request.lines();
+impl<'a> HttpRequest<'a> {
+    /// Value of the first `name` header, case insensitive
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+}
+
+pub(crate) fn parse_http_request(buffer: &[u8]) -> Option<HttpRequest<'_>> {
+    // body (after \r\n\r\n) may be binary, only the headers must be text
+    let body_start = headers_end(buffer).unwrap_or(buffer.len());
+    let request = core::str::from_utf8(&buffer[..body_start]).ok()?;
+    let (first_line, headers) = request.split_once("\r\n").unwrap_or((request, ""));
 
-    let first_line = lines.next()?;
     let mut parts = first_line.split_whitespace();
     let method = parts.next()?;
     let path = parts.next()?;
 
-    // find body (after \r\n\r\n)
-    let body_start = request
-        .find("\r\n\r\n")
-        .map(|i| i + 4)
-        .unwrap_or(request.len());
     let body = &buffer[body_start..];
 
-    Some(HttpRequest { method, path, body })
+    Some(HttpRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// Extra header lines of a response, the longest are the CORS preflight ones
+const HEADERS_LEN: usize = 320;
+/// Status line and headers
+const HEAD_LEN: usize = HEADERS_LEN + 192;
+
+/// Writes part `idx` of a body too large for the response buffer, returns
+/// whether more parts follow
+///
+/// A part is written again when it did not fit after the previous ones.
+pub(crate) type WritePart = fn(usize, &mut dyn Write) -> Result<bool, fmt::Error>;
+
+enum Body {
+    /// First bytes of the buffer
+    Buffer(usize),
+    Static(&'static str),
+    Parts(WritePart),
+}
+
+/// Answer to a request, written into the buffer of its connection
+///
+/// Nothing is allocated: JSON is serialized and text formatted into the
+/// buffer, pages are sent from flash, and documents larger than the buffer
+/// are formatted part by part and sent in chunks. A body or headers not
+/// fitting are answered 500 "too large".
+pub(crate) struct Response<'b> {
+    buf: &'b mut [u8],
+    status: &'static str,
+    content_type: &'static str,
+    /// Extra header lines, each ending with `\r\n`
+    headers: heapless::String<HEADERS_LEN>,
+    body: Body,
+    overflow: bool,
+}
+
+impl<'b> Response<'b> {
+    /// 404 until the body is set
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            status: "404 Not Found",
+            content_type: "text/plain",
+            headers: heapless::String::new(),
+            body: Body::Static("Not Found"),
+            overflow: false,
+        }
+    }
+
+    pub fn send(&mut self, status: &'static str, content_type: &'static str, body: &'static str) {
+        self.status = status;
+        self.content_type = content_type;
+        self.body = Body::Static(body);
+    }
+
+    pub fn text(&mut self, status: &'static str, body: &'static str) {
+        self.send(status, "text/plain", body);
+    }
+
+    /// Plain text formatted into the buffer
+    pub fn text_fmt(&mut self, status: &'static str, body: fmt::Arguments<'_>) {
+        self.status = status;
+        self.content_type = "text/plain";
+        let mut writer = SliceWriter::new(self.buf);
+        self.overflow |= writer.write_fmt(body).is_err();
+        self.body = Body::Buffer(writer.len);
+    }
+
+    pub fn json(&mut self, value: &impl Serialize) {
+        self.json_with_status("200 OK", value);
+    }
+
+    /// `value` serialized into the buffer
+    pub fn json_with_status(&mut self, status: &'static str, value: &impl Serialize) {
+        self.status = status;
+        self.content_type = "application/json";
+        match serde_json_core::to_slice(value, self.buf) {
+            Ok(len) => self.body = Body::Buffer(len),
+            Err(_) => self.overflow = true,
+        }
+    }
+
+    /// Body of the parts of `write_part`
+    pub fn parts(&mut self, content_type: &'static str, write_part: WritePart) {
+        self.status = "200 OK";
+        self.content_type = content_type;
+        self.body = Body::Parts(write_part);
+    }
+
+    pub fn redirect(&mut self, location: &str) {
+        self.header(format_args!("Location: {location}"));
+        self.send("302 Found", "text/plain", "");
+    }
+
+    /// Add a header line, without its `\r\n`
+    pub fn header(&mut self, line: fmt::Arguments<'_>) {
+        self.overflow |= write!(self.headers, "{line}\r\n").is_err();
+    }
+
+    /// Send the response, its parts in chunks
+    pub async fn write(mut self, socket: &mut TcpSocket<'_>) -> Result<(), tcp::Error> {
+        if self.overflow {
+            self.headers.clear();
+            self.text("500 Internal Server Error", "too large");
+        }
+
+        let mut head = heapless::String::<HEAD_LEN>::new();
+        _ = write!(
+            head,
+            "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\n",
+            self.status, self.content_type
+        );
+        _ = match self.body {
+            Body::Buffer(len) => write!(head, "Content-Length: {len}\r\n"),
+            Body::Static(body) => write!(head, "Content-Length: {}\r\n", body.len()),
+            Body::Parts(_) => write!(head, "Transfer-Encoding: chunked\r\n"),
+        };
+        _ = write!(head, "{}Connection: close\r\n\r\n", self.headers);
+
+        write_all(socket, head.as_bytes()).await?;
+        match self.body {
+            Body::Buffer(len) => write_all(socket, &self.buf[..len]).await?,
+            Body::Static(body) => write_all(socket, body.as_bytes()).await?,
+            Body::Parts(write_part) => write_chunks(socket, self.buf, write_part).await?,
+        }
+        _ = socket.flush().await;
+        Ok(())
+    }
 }
 
-fn create_http_response(status: &str, content_type: &str, body: &str) -> Vec<u8> {
-    let body_bytes = body.as_bytes();
-    let header = format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
-        status,
-        content_type,
-        body_bytes.len()
-    );
+/// `fmt::Write` into a slice, failing once it is full
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
 
-    let mut response = Vec::with_capacity(header.len() + body_bytes.len());
-    response.extend_from_slice(header.as_bytes());
-    response.extend_from_slice(body_bytes);
-    response
+impl<'a> SliceWriter<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+}
+
+impl Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buf.len() {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+async fn write_all(socket: &mut TcpSocket<'_>, mut data: &[u8]) -> Result<(), tcp::Error> {
+    while !data.is_empty() {
+        let written = socket.write(data).await?;
+        data = &data[written..];
+    }
+    Ok(())
+}
+
+/// Fill `buf` with as many parts as fit and send it as a chunk, until the last
+/// part
+async fn write_chunks(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    write_part: WritePart,
+) -> Result<(), tcp::Error> {
+    let mut idx = 0;
+    let mut len = 0;
+    let mut more = true;
+    while more {
+        let mut writer = SliceWriter::new(&mut buf[len..]);
+        match write_part(idx, &mut writer) {
+            Ok(next) => {
+                len += writer.len;
+                idx += 1;
+                more = next;
+            }
+            // Written again in an empty buffer
+            Err(_) if len > 0 => {
+                write_chunk(socket, &buf[..len]).await?;
+                len = 0;
+            }
+            // Larger than the buffer, the body ends there
+            Err(_) => break,
+        }
+    }
+    if len > 0 {
+        write_chunk(socket, &buf[..len]).await?;
+    }
+    write_all(socket, b"0\r\n\r\n").await
+}
+
+async fn write_chunk(socket: &mut TcpSocket<'_>, chunk: &[u8]) -> Result<(), tcp::Error> {
+    let mut size = heapless::String::<12>::new();
+    _ = write!(size, "{:x}\r\n", chunk.len());
+    write_all(socket, size.as_bytes()).await?;
+    write_all(socket, chunk).await?;
+    write_all(socket, b"\r\n").await
+}
+
+/// Position right after the `\r\n\r\n` ending the headers
+fn headers_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4)
+}
+
+fn content_length(headers: &[u8]) -> usize {
+    let Ok(headers) = core::str::from_utf8(headers) else {
+        return 0;
+    };
+
+    headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Request larger than the buffer it is read into, answered 413
+#[derive(Debug)]
+pub(crate) struct TooLarge;
+
+/// Read a request (headers and `Content-Length` body) into `buffer`
+///
+/// Returns the number of bytes read. A request not fitting in `buffer` is
+/// not read further, rather than handled truncated.
+pub(crate) async fn read_request(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8],
+) -> Result<usize, TooLarge> {
+    let mut total_read = 0;
+    let mut expected = None;
+
+    loop {
+        match socket.read(&mut buffer[total_read..]).await {
+            Ok(0) => break,
+            Ok(n) => {
+                total_read += n;
+
+                if expected.is_none() {
+                    expected = headers_end(&buffer[..total_read])
+                        .map(|end| end + content_length(&buffer[..end]));
+                }
+                match expected {
+                    Some(expected) if expected > buffer.len() => return Err(TooLarge),
+                    Some(expected) if total_read >= expected => break,
+                    // Headers alone filling the buffer
+                    None if total_read >= buffer.len() => return Err(TooLarge),
+                    _ => {}
+                }
+            }
+            Err(_) => break,
+        }
+    }
+
+    Ok(total_read)
 }
 
 async fn handle_request(
     request: HttpRequest<'_>,
     signals: &Rc<WmInnerSignals>,
-) -> Vec<u8> {
+    portal_url: &str,
+    out: &mut Response<'_>,
+) {
     match (request.method, request.path) {
-        ("GET", "/") => create_http_response("200 OK", "text/html", include_str!("./panel.html")),
+        ("GET", "/") => out.send("200 OK", "text/html", include_str!("./panel.html")),
         ("GET", "/list") => {
             let scan_res = signals.wifi_scan_res.try_lock();
             let resp = match scan_res {
                 Ok(ref resp) => resp.as_str(),
                 Err(_) => "",
             };
-            create_http_response("200 OK", "text/plain", resp)
+            out.text_fmt("200 OK", format_args!("{resp}"))
+        }
+        // Count of the stations associated to the AP, the page itself included
+        ("GET", "/clients") => {
+            let count = super::ap_clients().len();
+            out.text_fmt("200 OK", format_args!("{count}"))
         }
         ("POST", "/setup") => {
-            let body_vec = request.body.to_vec();
-
-            if let Ok((settings, _)) = serde_json_core::from_slice::<AutoSetupSettings>(
-                &body_vec,
-            ) {
-                signals.wifi_conn_info_sig.signal(settings);
-                create_http_response("200 OK", "text/plain", ".")
-            } else {
-                create_http_response("422 Unprocessable Entity", "text/plain", "invalid body")
+            match serde_json_core::from_slice::<AutoSetupSettings>(request.body) {
+                Ok((settings, _)) if settings.is_valid() => {
+                    signals.wifi_conn_info_sig.signal(settings);
+                    out.text("200 OK", ".")
+                }
+                Ok(_) => out.text("422 Unprocessable Entity", "invalid location"),
+                Err(_) => out.text("422 Unprocessable Entity", "invalid body"),
             }
         }
-        _ => create_http_response("404 Not Found", "text/plain", "Not Found"),
+        // OS connectivity probes: redirecting them makes the phone pop the sign-in window
+        // (Android, Apple, Windows) instead of flagging the network as "no internet"
+        ("GET", "/generate_204")
+        | ("GET", "/gen_204")
+        | ("GET", "/hotspot-detect.html")
+        | ("GET", "/library/test/success.html")
+        | ("GET", "/ncsi.txt")
+        | ("GET", "/connecttest.txt")
+        | ("GET", "/redirect") => out.redirect(portal_url),
+        // Any other page of any host resolved by the captive DNS
+        ("GET", _) => out.redirect(portal_url),
+        _ => {}
     }
 }
 
@@ -83,12 +368,15 @@ async fn handle_request(
 async fn web_task(
     _id: usize,
     stack: Stack<'static>,
+    ap_ip: Ipv4Address,
     signals: Rc<WmInnerSignals>,
 ) {
     let fut = async {
         let mut rx_buffer = [0; 1024];
         let mut tx_buffer = [0; 1024];
         let mut http_buffer = alloc::vec![0; HTTP_BUFFER_SIZE];
+        let mut response_buffer = [0; RESPONSE_BUFFER_SIZE];
+        let portal_url = format!("http://{ap_ip}/");
 
         loop {
             let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
@@ -99,53 +387,32 @@ async fn web_task(
                 continue;
             }
 
-            // read req
-            let mut total_read = 0;
-            loop {
-                match socket.read(&mut http_buffer[total_read..]).await {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        total_read += n;
-                        if http_buffer[..total_read]
-                            .windows(4)
-                            .any(|w| w == b"\r\n\r\n")
-                        {
-                            break;
-                        }
-                        if total_read >= HTTP_BUFFER_SIZE {
-                            break;
-                        }
-                    }
-                    Err(_) => break,
+            let total_read = match read_request(&mut socket, &mut http_buffer).await {
+                Ok(0) => {
+                    socket.close();
+                    continue;
                 }
-            }
-
-            if total_read == 0 {
-                let _ = socket.close();
-                continue;
-            }
+                Ok(total_read) => total_read,
+                Err(TooLarge) => {
+                    let mut out = Response::new(&mut response_buffer);
+                    out.text("413 Payload Too Large", "too large");
+                    _ = out.write(&mut socket).await;
+                    socket.close();
+                    continue;
+                }
+            };
 
             // parse and handle request
             if let Some(req) = parse_http_request(&http_buffer[..total_read]) {
-                let resp = handle_request(req, &signals).await;
-                let mut i = 0;
-
-                while i < resp.len() {
-                    match socket.write(&resp[i..]).await {
-                        Ok(n) => {
-                            i += n;
-                        }
-                        Err(e) => {
-                            esp_println::println!("Http wifimanager write error: {e:?}");
-                            break;
-                        }
-                    }
-
-                    _ = socket.flush().await;
+                let mut out = Response::new(&mut response_buffer);
+                handle_request(req, &signals, &portal_url, &mut out).await;
+
+                if let Err(e) = out.write(&mut socket).await {
+                    esp_println::println!("Http wifimanager write error: {e:?}");
                 }
             }
 
-            let _ = socket.close();
+            socket.close();
         }
     };
 
@@ -155,9 +422,10 @@ async fn web_task(
 pub async fn run_http_server(
     spawner: &Spawner,
     ap_stack: Stack<'static>,
+    ap_ip: Ipv4Address,
     signals: Rc<WmInnerSignals>,
 ) {
     for id in 0..WEB_TASK_POOL_SIZE {
-        spawner.must_spawn(web_task(id, ap_stack, signals.clone()));
+        spawner.must_spawn(web_task(id, ap_stack, ap_ip, signals.clone()));
     }
 }
@@ -1,102 +1,209 @@
 use alloc::rc::Rc;
 use esp_radio::Controller;
-use core::ops::DerefMut;
+use core::cell::Cell;
 use embassy_executor::Spawner;
-use embassy_net::{Config, Runner, StackResources};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_net::{Config, Runner, Stack, StackResources};
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex};
+use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Instant, Timer};
+use embassy_time::{with_timeout, Duration, Timer};
 use esp_hal::{peripherals::WIFI, rng::Rng};
-use esp_radio::{
-    wifi::{WifiController, WifiDevice, WifiEvent, WifiStaState},
+use esp_radio::wifi::WifiDevice;
+use machine::{LinkCommand, LinkInput, Reconnect};
+use radio::{AccessPointConfig, EmbassyClock, PowerSave, Radio, RadioConfig, RadioControl};
+use radio::ScanResult;
+use setup::{ConnectError, SetupPortal, SetupSettings};
+use structs::{WmInnerSignals, WmReturn};
+
+pub use clients::{ap_clients, ApClient};
+pub use diagnostics::{diagnostics, reason_name, Diagnostics, Disconnect};
+pub use nvs::{Nvs, Record, RecordError};
+pub use b_intime_logic::wifimanager::{machine, setup};
+pub use quality::{link_quality, LinkQuality};
+pub use structs::{
+    AutoSetupSettings, Location, NetEvent, NetEventChannel, NetEventSubscriber, WmError,
+    WmSettings,
 };
-use structs::{AutoSetupSettings, WmInnerSignals, WmReturn};
-
-pub use nvs::Nvs;
-pub use structs::{WmError, WmSettings};
 pub use utils::get_efuse_mac;
 
 use crate::wifimanager::nvs::SavedSettings;
 
-mod http;
+/// Time for the setup AP tasks to end once signalled
+const AP_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) mod http;
 mod ap;
+mod clients;
+mod diagnostics;
 mod nvs;
+mod quality;
+pub mod radio;
 mod structs;
 mod utils;
 
+/// NVS partition, shared by the wifi settings and application data
+pub fn init_storage(
+    flash: esp_hal::peripherals::FLASH<'static>,
+) -> crate::wifimanager::structs::Result<&'static Mutex<CriticalSectionRawMutex, Nvs>> {
+    let nvs = Nvs::new(flash, 1024)?;
+    Ok(crate::mk_static!(Mutex<CriticalSectionRawMutex, Nvs>, Mutex::new(nvs)))
+}
+
+/// Modem sleep asked for the station, set by the connection task
+static POWER_SAVE: Signal<CriticalSectionRawMutex, PowerSave> = Signal::new();
+
+/// Change the modem sleep of the station, applied while it is connected
+pub fn set_power_save(mode: PowerSave) {
+    POWER_SAVE.signal(mode);
+}
+
+/// TX power range of the driver (in dBm), the maximum is its default
+pub const TX_POWER_RANGE: core::ops::RangeInclusive<u8> = 2..=20;
+
+/// TX power asked for, set again each time the radio starts
+static TX_POWER: BlockingMutex<CriticalSectionRawMutex, Cell<Option<u8>>> =
+    BlockingMutex::new(Cell::new(None));
+
+/// Change the maximum TX power (in dBm), `None` for the driver default
+pub fn set_tx_power(dbm: Option<u8>) {
+    TX_POWER.lock(|tx_power| tx_power.set(dbm));
+    apply_tx_power();
+}
+
+fn apply_tx_power() {
+    let dbm = TX_POWER
+        .lock(|tx_power| tx_power.get())
+        .unwrap_or(*TX_POWER_RANGE.end())
+        .clamp(*TX_POWER_RANGE.start(), *TX_POWER_RANGE.end());
+    // In quarters of dBm, only once the radio started
+    let res = unsafe { esp_wifi_sys::include::esp_wifi_set_max_tx_power((dbm * 4) as i8) };
+    if res != esp_wifi_sys::include::ESP_OK as i32 {
+        crate::log!("WIFI TX power {dbm} dBm not set: {res}");
+    }
+}
+
+/// Replace the location kept with the wifi settings, `Ok(false)` when it is
+/// not valid: both coordinates or none, in range
+pub async fn save_location(
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+    latitude: Option<f32>,
+    longitude: Option<f32>,
+) -> crate::wifimanager::structs::Result<bool> {
+    let mut saved = SavedSettings::new(storage);
+    let mut setup = saved.load().await?.ok_or(WmError::Other)?;
+    setup.latitude = latitude;
+    setup.longitude = longitude;
+    if !setup.is_valid() {
+        return Ok(false);
+    }
+    saved.save(&setup).await?;
+    Ok(true)
+}
+
+/// Forget the saved wifi settings, the setup AP starts at the next boot
+pub async fn forget_wifi(
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
+) -> crate::wifimanager::structs::Result<()> {
+    SavedSettings::new(storage).clear().await
+}
+
 #[allow(clippy::too_many_arguments)]
 pub async fn init_wm(
     settings: WmSettings,
     spawner: &Spawner,
-    flash: esp_hal::peripherals::FLASH<'static>,
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
     mut rng: Rng,
     wifi: WIFI<'static>,
 ) -> crate::wifimanager::structs::Result<WmReturn> {
     let generated_ssid = settings.ssid.clone();
+    TX_POWER.lock(|tx_power| tx_power.set(settings.tx_power));
+    diagnostics::watch();
 
     let init = crate::mk_static!(Controller<'static>, esp_radio::init()?);
 
-    let (mut controller, interfaces) = esp_radio::wifi::new(init, wifi, Default::default())?;
-    controller.set_power_saving(esp_radio::wifi::PowerSaveMode::None)?;
+    let (controller, interfaces) = esp_radio::wifi::new(init, wifi, Default::default())?;
+    let mut controller = Radio(controller);
+    controller.set_power_saving(PowerSave::None)?;
+
+    let mut storage = SavedSettings::new(storage);
 
-    let mut storage = SavedSettings::new(flash)?;
+    let mut saved_setup = storage.load().await?;
 
-    let wifi_connected = if let Some(wifi_setup) = storage.load()? {
+    let wifi_connected = if let Some(ref wifi_setup) = saved_setup {
         esp_println::println!("Read wifi_setup from flash: {wifi_setup:?}");
-        controller.set_config(&wifi_setup.to_configuration()?)?;
-        controller.start_async().await?;
+        controller.configure(&wifi_setup.to_configuration())?;
+        controller.start().await?;
+        apply_tx_power();
 
         utils::try_to_wifi_connect(&mut controller, settings.wifi_conn_timeout).await
     } else { false };
 
     if !wifi_connected {
-        esp_println::println!("Starting wifimanager with ssid: {generated_ssid}");
+        crate::log!("Starting wifimanager with ssid: {generated_ssid}");
 
         let wm_signals = Rc::new(WmInnerSignals::new());
 
-        let configuration = esp_radio::wifi::ModeConfig::ApSta(
+        let access_point = AccessPointConfig {
+            ssid: generated_ssid.clone(),
+        };
+        controller.configure(&RadioConfig::AccessPointStation(
             Default::default(),
-            esp_radio::wifi::AccessPointConfig::default().with_ssid(generated_ssid.clone()),
-        );
-
-        controller.set_config(&configuration)?;
+            access_point.clone(),
+        ))?;
+        clients::watch();
 
         utils::spawn_ap(
             &mut rng,
             spawner,
+            &settings,
             wm_signals.clone(),
             interfaces.ap,
         )
         .await?;
 
-        controller.start_async().await?;
-
-        let wifi_setup = wifi_connection_worker(
-            settings.clone(),
-            wm_signals,
+        controller.start().await?;
+        apply_tx_power();
+
+        let setup_settings = SetupSettings {
+            access_point,
+            scan_interval: settings.wifi_scan_interval,
+            reset_timeout: settings.esp_reset_timeout,
+            connect_timeout: settings.wifi_conn_timeout,
+            scan_filter: settings.scan_filter(),
+        };
+        let wifi_setup = setup::wifi_connection_worker(
             &mut controller,
-            configuration,
+            &mut Portal(wm_signals),
+            &EmbassyClock,
+            &setup_settings,
         )
         .await?;
 
-        controller.set_config(&wifi_setup.to_configuration()?)?;
+        controller.configure(&wifi_setup.to_configuration())?;
         if settings.esp_restart_after_connection {
-            esp_println::println!("Wifimanager reset after succesfull first connection...");
+            crate::log!("Wifimanager reset after succesfull first connection...");
             Timer::after_millis(1000).await;
             esp_hal::system::software_reset();
         }
 
-        storage.save(&wifi_setup)?;
+        storage.save(&wifi_setup).await?;
+        saved_setup = Some(wifi_setup);
     };
 
-    let sta_config = Config::dhcpv4(Default::default());
+    let mut dhcp_config = embassy_net::DhcpConfig::default();
+    dhcp_config.hostname = settings
+        .hostname
+        .as_deref()
+        .and_then(|hostname| heapless::String::try_from(hostname).ok());
+    let sta_config = Config::dhcpv4(dhcp_config);
     let (sta_stack, runner) = embassy_net::new(
         interfaces.sta,
         sta_config,
         {
-            static STATIC_CELL: static_cell::StaticCell<StackResources<5>> =
+            // Budget and usage in `sockets`
+            static STATIC_CELL: static_cell::StaticCell<StackResources<{ crate::sockets::STA_SOCKETS }>> =
                 static_cell::StaticCell::new();
-            STATIC_CELL.uninit().write(StackResources::<5>::new())
+            STATIC_CELL.uninit().write(StackResources::<{ crate::sockets::STA_SOCKETS }>::new())
         },
         rng.random() as u64,
     );
@@ -109,130 +216,282 @@ pub async fn init_wm(
     ))?;
     spawner.spawn(sta_task(runner))?;
 
+    let net_events = crate::mk_static!(NetEventChannel, NetEventChannel::new());
+    let mut events = net_events.subscriber().map_err(|_| WmError::Other)?;
+    spawner.spawn(net_state_task(sta_stack, net_events))?;
+    let ip_address = utils::wifi_wait_for_ip(sta_stack, &mut events).await;
+    drop(events);
+
     Ok(WmReturn {
         wifi_init: init,
         sta_stack,
-        ip_address: utils::wifi_wait_for_ip(&sta_stack).await,
+        ip_address,
+        net_events,
+        setup: saved_setup.ok_or(WmError::Other)?,
+        #[cfg(feature = "espnow")]
+        esp_now: interfaces.esp_now,
 
         stop_signal,
     })
 }
 
-async fn wifi_connection_worker(
-    settings: WmSettings,
-    wm_signals: Rc<WmInnerSignals>,
-    controller: &mut WifiController<'static>,
-    mut configuration: esp_radio::wifi::ModeConfig,
-) -> crate::wifimanager::structs::Result<AutoSetupSettings> {
-    let start_time = Instant::now();
-    let mut last_scan = Instant::MIN;
-    loop {
-        if wm_signals.wifi_conn_info_sig.signaled() {
-            let setup_info = wm_signals.wifi_conn_info_sig.wait().await;
+/// Setup AP tasks, through their signals
+struct Portal(Rc<WmInnerSignals>);
 
-            esp_println::println!("trying to connect to: {:?}", setup_info);
-            let esp_radio::wifi::ModeConfig::ApSta(ref mut client_conf, _) = configuration
-            else {
-                return Err(WmError::Other);
-            };
+impl SetupPortal for Portal {
+    type Credentials = AutoSetupSettings;
 
-            *client_conf = setup_info.to_client_conf()?;
-
-            controller.set_config(&configuration)?;
+    fn pending(&self) -> bool {
+        self.0.wifi_conn_info_sig.signaled()
+    }
 
-            let wifi_connected =
-                utils::try_to_wifi_connect(controller, settings.wifi_conn_timeout).await;
+    async fn credentials(&mut self) -> AutoSetupSettings {
+        let info = self.0.wifi_conn_info_sig.wait().await;
+        crate::log!("trying to connect to: {}", info.ssid);
+        info
+    }
 
-            if wifi_connected {
-                esp_hal_dhcp_server::dhcp_close();
+    fn station(&self, credentials: &AutoSetupSettings) -> radio::StationConfig {
+        credentials.station()
+    }
 
-                Timer::after_millis(1000).await;
-                wm_signals.signal_end();
-                return Ok(setup_info);
+    async fn show_scan(&mut self, aps: &[ScanResult]) {
+        let mut wifis = self.0.wifi_scan_res.lock().await;
+        wifis.clear();
+        // The driver lists the strongest first, the weakest are left out
+        let mut line = alloc::string::String::new();
+        for ap in aps {
+            line.clear();
+            _ = core::fmt::write(
+                &mut line,
+                format_args!("{}: {}\n", ap.ssid, ap.signal_strength),
+            );
+            if wifis.len() + line.len() > http::SCAN_LIST_LEN {
+                break;
             }
+            wifis.push_str(&line);
         }
+    }
 
-        if last_scan.elapsed().as_millis() >= settings.wifi_scan_interval {
-            let scan_res = controller.scan_with_config_async(Default::default()).await;
-            let mut wifis = wm_signals.wifi_scan_res.lock().await;
-            wifis.clear();
-            if let Ok(aps) = scan_res {
-                for ap in aps {
-                    _ = core::fmt::write(
-                        wifis.deref_mut(),
-                        format_args!("{}: {}\n", ap.ssid, ap.signal_strength),
-                    );
-                }
+    fn connect_failed(&mut self, error: ConnectError) {
+        match error {
+            ConnectError::Timeout => crate::log!("Setup connection timeout"),
+            ConnectError::Radio(e) => {
+                crate::log!("Setup connection failed: {e:?}, {}", diagnostics::last_reason())
             }
+        }
+    }
+
+    async fn finish(&mut self) {
+        stop_ap(&self.0).await;
+    }
 
-            last_scan = Instant::now();
+    async fn reset(&mut self) {
+        crate::log!("Wifimanager esp reset timeout reached! Resetting..");
+        Timer::after_millis(1000).await;
+        esp_hal::system::software_reset();
+    }
+}
+
+/// Stop the DHCP server, netstack and web tasks of the setup AP, and wait
+/// for them to end so their buffers go back to the heap
+async fn stop_ap(wm_signals: &Rc<WmInnerSignals>) {
+    let used = esp_alloc::HEAP.used();
+    esp_hal_dhcp_server::dhcp_close();
+
+    Timer::after_millis(1000).await;
+    wm_signals.signal_end();
+    clients::unwatch();
+
+    // Each AP task holds the signals until it ends
+    let ended = with_timeout(AP_STOP_TIMEOUT, async {
+        while Rc::strong_count(wm_signals) > 1 {
+            Timer::after_millis(50).await;
         }
+    })
+    .await;
+    match ended {
+        Ok(()) => crate::log!(
+            "Setup AP stopped, heap used {used} -> {} bytes",
+            esp_alloc::HEAP.used()
+        ),
+        Err(_) => crate::log!(
+            "Setup AP: {} tasks still running",
+            Rc::strong_count(wm_signals) - 1
+        ),
+    }
+}
 
-        if let Some(reset_timeout) = settings.esp_reset_timeout {
-            if start_time.elapsed().as_millis() >= reset_timeout {
-                esp_println::println!("Wifimanager esp reset timeout reached! Resetting..");
-                Timer::after_millis(1000).await;
-                esp_hal::system::software_reset();
-            }
+async fn connect(controller: &mut impl RadioControl) -> LinkInput {
+    match controller.connect().await {
+        Ok(_) => {
+            crate::log!("Wifi connected!");
+            LinkInput::Connected
         }
+        Err(e) => {
+            crate::log!("Failed to connect to wifi: {e:?}, {}", diagnostics::last_reason());
+            LinkInput::ConnectFailed
+        }
+    }
+}
 
-        Timer::after_millis(100).await;
+/// `true` asks to stop the radio, `false` to restart it
+fn stop_input(stop: bool) -> LinkInput {
+    if stop {
+        LinkInput::Stop
+    } else {
+        LinkInput::Restart
     }
 }
 
 #[embassy_executor::task]
 async fn connection(
     wifi_reconnect_time: u64,
-    mut controller: WifiController<'static>,
+    mut controller: Radio<'static>,
     stop_signal: Rc<Signal<CriticalSectionRawMutex, bool>>,
-    //stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
 ) {
-    esp_println::println!("WIFI Device capabilities: {:?}", controller.capabilities());
+    crate::log!("WIFI Device capabilities: {:?}", controller.0.capabilities());
+    keep_connected(&mut controller, wifi_reconnect_time, &stop_signal).await
+}
 
+/// Reconnect the station whenever it drops, until the radio is stopped
+async fn keep_connected(
+    controller: &mut impl RadioControl,
+    wifi_reconnect_time: u64,
+    stop_signal: &Signal<CriticalSectionRawMutex, bool>,
+) -> ! {
+    let reconnect_time = Duration::from_millis(wifi_reconnect_time);
+    let mut machine = Reconnect::new(controller.is_connected());
+    let mut command = machine.start();
+    // Connection lost, the next connect is a reconnect
+    let mut dropped = false;
     loop {
-        if esp_radio::wifi::sta_state() == WifiStaState::Connected {
-            // wait until we're no longer connected
-            let res = embassy_futures::select::select(
-                controller.wait_for_event(WifiEvent::StaDisconnected),
-                stop_signal.wait(),
-            )
-            .await;
-
-            match res {
-                embassy_futures::select::Either::First(_) => {}
-                embassy_futures::select::Either::Second(val) => {
-                    if val {
-                        _ = controller.disconnect_async().await;
-                        _ = controller.stop_async().await;
-                        esp_println::println!("WIFI radio stopped!");
-
-                        loop {
-                            // wait for `restart_wifi()`
-                            let val = stop_signal.wait().await;
-                            if !val {
-                                break;
-                            }
+        let input = match command {
+            LinkCommand::Connect => connect(controller).await,
+            LinkCommand::ConnectLater => {
+                Timer::after(reconnect_time).await;
+                connect(controller).await
+            }
+            LinkCommand::WaitDisconnect => loop {
+                match embassy_futures::select::select4(
+                    controller.wait_disconnected(),
+                    stop_signal.wait(),
+                    POWER_SAVE.wait(),
+                    Timer::after(quality::SAMPLE_PERIOD),
+                )
+                .await
+                {
+                    embassy_futures::select::Either4::First(_) => break LinkInput::Disconnected,
+                    embassy_futures::select::Either4::Second(stop) => break stop_input(stop),
+                    embassy_futures::select::Either4::Third(mode) => {
+                        match controller.set_power_saving(mode) {
+                            Ok(()) => crate::log!("WIFI power save {mode:?}"),
+                            Err(e) => crate::log!("WIFI power save {mode:?} not set: {e:?}"),
+                        }
+                    }
+                    embassy_futures::select::Either4::Fourth(_) => {
+                        if let Some(rssi) = controller.rssi() {
+                            quality::sample(rssi);
                         }
-
-                        _ = controller.start_async().await;
-                        esp_println::println!("WIFI radio restarted!");
-                    } else {
-                        continue;
                     }
                 }
+            },
+            LinkCommand::StopRadio => {
+                _ = controller.disconnect().await;
+                _ = controller.stop().await;
+                crate::log!("WIFI radio stopped!");
+                stop_input(stop_signal.wait().await)
             }
-
-            Timer::after(Duration::from_millis(wifi_reconnect_time)).await
+            LinkCommand::WaitRestart => stop_input(stop_signal.wait().await),
+            LinkCommand::StartRadio => {
+                _ = controller.start().await;
+                apply_tx_power();
+                crate::log!("WIFI radio restarted!");
+                Timer::after(reconnect_time).await;
+                connect(controller).await
+            }
+        };
+        if input != LinkInput::Connected {
+            quality::reset();
         }
+        match input {
+            LinkInput::Disconnected => dropped = true,
+            LinkInput::Connected if dropped => {
+                dropped = false;
+                crate::stats::count(crate::stats::Counter::WifiReconnect);
+            }
+            _ => {}
+        }
+        command = machine.next(input);
+    }
+}
+
+/// Wait for the station to be associated
+///
+/// Like the other waits, the state of `stack` is checked again at each event
+/// of `events`, events queued before it changed don't count.
+pub async fn wait_link_up(stack: Stack<'_>, events: &mut NetEventSubscriber) {
+    while !stack.is_link_up() {
+        events.next_message_pure().await;
+    }
+}
+
+/// Wait for the station to have an address
+pub async fn wait_ip(stack: Stack<'_>, events: &mut NetEventSubscriber) {
+    while !stack.is_config_up() {
+        events.next_message_pure().await;
+    }
+}
 
-        match controller.connect_async().await {
-            Ok(_) => {
-                esp_println::println!("Wifi connected!");
+/// Wait for the station to lose its address, to pause what needs it
+pub async fn wait_ip_lost(stack: Stack<'_>, events: &mut NetEventSubscriber) {
+    while stack.is_config_up() {
+        events.next_message_pure().await;
+    }
+}
+
+/// Publishes link and IP transitions of `stack` to `events`
+#[embassy_executor::task]
+async fn net_state_task(stack: Stack<'static>, events: &'static NetEventChannel) {
+    let publisher = events.immediate_publisher();
+    let mut link_up = false;
+    let mut config_up = false;
+
+    loop {
+        let link_change = async {
+            if link_up {
+                stack.wait_link_down().await
+            } else {
+                stack.wait_link_up().await
             }
-            Err(e) => {
-                esp_println::println!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(wifi_reconnect_time)).await
+        };
+        let config_change = async {
+            if config_up {
+                stack.wait_config_down().await
+            } else {
+                stack.wait_config_up().await
             }
+        };
+        embassy_futures::select::select(link_change, config_change).await;
+
+        let new_link_up = stack.is_link_up();
+        if new_link_up != link_up {
+            publisher.publish_immediate(if new_link_up {
+                NetEvent::LinkUp
+            } else {
+                NetEvent::LinkDown
+            });
+            link_up = new_link_up;
+        }
+
+        let new_config_up = stack.is_config_up();
+        if new_config_up != config_up {
+            publisher.publish_immediate(if new_config_up {
+                NetEvent::GotIp
+            } else {
+                NetEvent::LostIp
+            });
+            config_up = new_config_up;
         }
     }
 }
@@ -1,6 +1,8 @@
 use embedded_storage::{ReadStorage, Storage};
 use esp_bootloader_esp_idf::partitions;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use esp_storage::FlashStorage;
+use serde::{de::DeserializeOwned, Serialize};
 
 use super::structs::AutoSetupSettings;
 
@@ -62,26 +64,262 @@ impl Nvs {
         Ok(())
     }
 
+    /// Read application data, stored after the wifi settings
+    fn read_app(&mut self, offset: u32, buf: &mut [u8]) -> super::structs::Result<()> {
+        self.region
+            .read(self.offset + self.size as u32 + offset, buf)?;
+        Ok(())
+    }
+
+    /// Write application data, stored after the wifi settings
+    fn write_app(&mut self, offset: u32, buf: &[u8]) -> super::structs::Result<()> {
+        self.region
+            .write(self.offset + self.size as u32 + offset, buf)?;
+        Ok(())
+    }
+
+    /// Payload of `record` read into `buf`, `None` when it was never written,
+    /// cannot be read or does not fit
+    pub fn read_record<'b>(&mut self, record: Record, buf: &'b mut [u8]) -> Option<&'b [u8]> {
+        let layout = record.layout();
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        self.read_app(layout.offset, &mut header).ok()?;
+        let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+        if u16::from_le_bytes([header[0], header[1]]) != layout.magic
+            || len > layout.capacity
+            || len > buf.len()
+        {
+            return None;
+        }
+
+        let payload = &mut buf[..len];
+        self.read_app(layout.offset + RECORD_HEADER_LEN as u32, payload)
+            .ok()?;
+        Some(payload)
+    }
+
+    /// Replace the payload of `record`
+    pub fn write_record(&mut self, record: Record, payload: &[u8]) -> Result<(), RecordError> {
+        let mut buf = alloc::vec![0u8; RECORD_HEADER_LEN + payload.len()];
+        buf[RECORD_HEADER_LEN..].copy_from_slice(payload);
+        self.write_framed(record, &mut buf, payload.len())
+    }
+
+    /// Value of the JSON `record`, `None` when it was never written
+    pub fn read_json<T: DeserializeOwned>(
+        &mut self,
+        record: Record,
+    ) -> Option<Result<T, serde_json_core::de::Error>> {
+        let mut buf = alloc::vec![0u8; record.capacity()];
+        let payload = self.read_record(record, &mut buf)?;
+        Some(serde_json_core::from_slice::<T>(payload).map(|(value, _)| value))
+    }
+
+    /// Replace the JSON `record` by `value`
+    pub fn write_json(
+        &mut self,
+        record: Record,
+        value: &impl Serialize,
+    ) -> Result<(), RecordError> {
+        let mut buf = alloc::vec![0u8; RECORD_HEADER_LEN + record.capacity()];
+        let len = serde_json_core::to_slice(value, &mut buf[RECORD_HEADER_LEN..])
+            .map_err(|_| RecordError::TooLarge)?;
+        self.write_framed(record, &mut buf, len)
+    }
+
+    /// Write `buf`, a payload of `len` bytes after room for the header
+    fn write_framed(
+        &mut self,
+        record: Record,
+        buf: &mut [u8],
+        len: usize,
+    ) -> Result<(), RecordError> {
+        let layout = record.layout();
+        if len > layout.capacity {
+            return Err(RecordError::TooLarge);
+        }
+        buf[..2].copy_from_slice(&layout.magic.to_le_bytes());
+        buf[2..4].copy_from_slice(&(len as u16).to_le_bytes());
+        self.write_app(layout.offset, &buf[..RECORD_HEADER_LEN + len])
+            .map_err(|_| RecordError::Flash)
+    }
+}
+
+/// Magic and payload length, before the payload of each record
+const RECORD_HEADER_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordError {
+    /// Payload larger than the room of the record
+    TooLarge,
+    /// The flash could not be written
+    Flash,
 }
 
+/// Records of the application data, stored after the wifi settings
+///
+/// Each one is a magic, marking a written record since erased flash reads as
+/// 0xFF, the payload length and the payload, in the room given by `layout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    SnakeScore,
+    Pairing,
+    Password,
+    Snooze,
+    Battery,
+    Themes,
+    Score,
+    Climate,
+    Alerts,
+    /// NTP servers saved before the keys, read until the settings are saved
+    NtpLegacy,
+    Webhooks,
+    Maintenance,
+    Melody,
+    Wake,
+    Face,
+    Prefs,
+    Capabilities,
+    Timezone,
+    Units,
+    /// First alarm slot, the second is on another flash sector
+    Alarms0,
+    Stats,
+    PowerSave,
+    TxPower,
+    Automations,
+    Calendar,
+    Holidays,
+    BurnIn,
+    DeviceName,
+    Alarms1,
+    Ntp,
+}
+
+/// Where a record is, what marks it and its largest payload
+#[derive(Clone, Copy)]
+struct Layout {
+    offset: u32,
+    magic: u16,
+    capacity: usize,
+}
+
+impl Record {
+    /// Every record, by offset
+    const ALL: [Record; 30] = [
+        Record::SnakeScore,
+        Record::Pairing,
+        Record::Password,
+        Record::Snooze,
+        Record::Battery,
+        Record::Themes,
+        Record::Score,
+        Record::Climate,
+        Record::Alerts,
+        Record::NtpLegacy,
+        Record::Webhooks,
+        Record::Maintenance,
+        Record::Melody,
+        Record::Wake,
+        Record::Face,
+        Record::Prefs,
+        Record::Capabilities,
+        Record::Timezone,
+        Record::Units,
+        Record::Alarms0,
+        Record::Stats,
+        Record::PowerSave,
+        Record::TxPower,
+        Record::Automations,
+        Record::Calendar,
+        Record::Holidays,
+        Record::BurnIn,
+        Record::DeviceName,
+        Record::Alarms1,
+        Record::Ntp,
+    ];
+
+    /// Offsets of written records must not move, new ones go in free space
+    const fn layout(self) -> Layout {
+        let (offset, magic, capacity) = match self {
+            Record::SnakeScore => (0, 0x5A4E, 2),
+            Record::Pairing => (16, 0x5041, 21),
+            Record::Password => (48, 0x5057, 36),
+            Record::Snooze => (88, 0x535A, 2),
+            Record::Battery => (96, 0x4254, 2),
+            Record::Themes => (128, 0x5448, 1024),
+            Record::Score => (1160, 0x5343, 2),
+            Record::Climate => (1168, 0x434C, 84),
+            Record::Alerts => (1264, 0x414C, 1024),
+            Record::NtpLegacy => (2304, 0x4E54, 320),
+            Record::Webhooks => (2628, 0x5748, 1024),
+            Record::Maintenance => (3680, 0x5242, 60),
+            Record::Melody => (3744, 0x4D4C, 768),
+            Record::Wake => (4520, 0x574B, 60),
+            Record::Face => (4584, 0x4643, 160),
+            Record::Prefs => (4748, 0x5046, 3),
+            Record::Capabilities => (4760, 0x4350, 96),
+            Record::Timezone => (4860, 0x545A, 64),
+            Record::Units => (4928, 0x554E, 80),
+            Record::Alarms0 => (5012, 0x414C, 642),
+            Record::Stats => (5660, 0x5354, 96),
+            Record::PowerSave => (5760, 0x5053, 48),
+            Record::TxPower => (5812, 0x5458, 24),
+            Record::Automations => (5840, 0x4155, 1024),
+            Record::Calendar => (6868, 0x4341, 32),
+            Record::Holidays => (6904, 0x484F, 160),
+            Record::BurnIn => (7068, 0x4249, 64),
+            Record::DeviceName => (7136, 0x4E4D, 21),
+            Record::Alarms1 => (7168, 0x414C, 642),
+            Record::Ntp => (7816, 0x4E4B, 768),
+        };
+        Layout {
+            offset,
+            magic,
+            capacity,
+        }
+    }
+
+    /// Largest payload
+    pub const fn capacity(self) -> usize {
+        self.layout().capacity
+    }
+}
+
+// Every record is listed once, and ends before the next one starts
+const _: () = {
+    assert!(Record::ALL.len() == Record::Ntp as usize + 1);
+    let mut idx = 0;
+    while idx < Record::ALL.len() {
+        assert!(Record::ALL[idx] as usize == idx, "records out of order");
+        if idx > 0 {
+            let previous = Record::ALL[idx - 1].layout();
+            let end = previous.offset as usize + RECORD_HEADER_LEN + previous.capacity;
+            assert!(
+                end <= Record::ALL[idx].layout().offset as usize,
+                "records overlap"
+            );
+        }
+        idx += 1;
+    }
+};
+
 
 pub struct SavedSettings {
-    nvs: Nvs,
+    storage: &'static Mutex<CriticalSectionRawMutex, Nvs>,
     buf: [u8; 1024],
 }
 
 impl SavedSettings {
-    pub fn new(
-        flash: esp_hal::peripherals::FLASH<'static>,
-    ) -> super::structs::Result<Self> {
-        Ok(Self {
-            nvs: Nvs::new(flash, 1024)?,
+    pub fn new(storage: &'static Mutex<CriticalSectionRawMutex, Nvs>) -> Self {
+        Self {
+            storage,
             buf: [0u8; 1024],
-        })
+        }
     }
 
-    pub fn load(&mut self) -> super::structs::Result<Option<AutoSetupSettings>> {
-        let _ = self.nvs.read(&mut self.buf);
+    pub async fn load(&mut self) -> super::structs::Result<Option<AutoSetupSettings>> {
+        let _ = self.storage.lock().await.read(&mut self.buf);
 
         let end_pos = self.buf
                 .iter()
@@ -97,7 +335,7 @@ impl SavedSettings {
         }
     }
 
-    pub fn save(&mut self, settings: &AutoSetupSettings) -> super::structs::Result<()> {
+    pub async fn save(&mut self, settings: &AutoSetupSettings) -> super::structs::Result<()> {
         self.buf.fill(0u8);
 
         serde_json_core::to_slice(
@@ -106,8 +344,16 @@ impl SavedSettings {
         )?;
         esp_println::println!("write to nvs: {:?}", self.buf);
 
-        self.nvs.write(&self.buf)?;
+        self.storage.lock().await.write(&self.buf)?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Erase the settings, read back as none
+    pub async fn clear(&mut self) -> super::structs::Result<()> {
+        self.buf.fill(0u8);
+        self.storage.lock().await.write(&self.buf)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Link quality of the station, smoothed from the RSSI of its access point
+//!
+//! Readings jump by several dB from one beacon to the next. They are
+//! averaged with an exponential moving average, then mapped to a score from
+//! 0 to 100. The signal icon shows the score in bars that only change once
+//! it is clearly past a threshold, and a link poor for an hour is reported
+//! once as a notification.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use serde::Serialize;
+
+/// Time between two readings
+pub(crate) const SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+/// Weight of a new reading, the average settles within a minute
+const ALPHA: f32 = 0.2;
+/// Average RSSI of a 0 and a 100 score, in dBm
+const FLOOR_DBM: f32 = -90.0;
+const CEILING_DBM: f32 = -50.0;
+/// Lowest score of 1, 2 and 3 bars
+const BAR_THRESHOLDS: [u8; 3] = [25, 50, 75];
+/// Points past a threshold before the bars change
+const HYSTERESIS: u8 = 5;
+/// Time without a bar before the notification
+const WEAK_NOTICE: Duration = Duration::from_secs(3600);
+
+struct Link {
+    /// Average RSSI, in dBm
+    average: f32,
+    bars: u8,
+    /// Since when the link has no bar
+    poor_since: Option<Instant>,
+    notified: bool,
+}
+
+static LINK: Mutex<CriticalSectionRawMutex, RefCell<Option<Link>>> = Mutex::new(RefCell::new(None));
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LinkQuality {
+    /// Average RSSI, in dBm
+    pub rssi: i8,
+    /// From 0 to 100
+    pub score: u8,
+    /// From 0 to 3, for the signal icon
+    pub bars: u8,
+}
+
+fn score(average: f32) -> u8 {
+    let ratio = (average - FLOOR_DBM) / (CEILING_DBM - FLOOR_DBM);
+    (ratio.clamp(0.0, 1.0) * 100.0 + 0.5) as u8
+}
+
+/// Bars of `score`, moving from `bars` one threshold at a time
+fn bars(score: u8, mut bars: u8) -> u8 {
+    while let Some(&threshold) = BAR_THRESHOLDS.get(bars as usize) {
+        if score < threshold.saturating_add(HYSTERESIS) {
+            break;
+        }
+        bars += 1;
+    }
+    while bars > 0 && score + HYSTERESIS < BAR_THRESHOLDS[bars as usize - 1] {
+        bars -= 1;
+    }
+    bars
+}
+
+/// Add a reading of the connected station
+pub(crate) fn sample(rssi: i8) {
+    let now = Instant::now();
+    let notify = LINK.lock(|link| {
+        let mut link = link.borrow_mut();
+        let link = match link.as_mut() {
+            Some(link) => {
+                link.average += ALPHA * (rssi as f32 - link.average);
+                link.bars = bars(score(link.average), link.bars);
+                link
+            }
+            // The first reading sets the bars without hysteresis
+            None => link.insert(Link {
+                average: rssi as f32,
+                bars: BAR_THRESHOLDS
+                    .iter()
+                    .filter(|&&threshold| score(rssi as f32) >= threshold)
+                    .count() as u8,
+                poor_since: None,
+                notified: false,
+            }),
+        };
+
+        if link.bars > 0 {
+            link.poor_since = None;
+            link.notified = false;
+            return false;
+        }
+        let poor_since = *link.poor_since.get_or_insert(now);
+        let notify = !link.notified && now - poor_since >= WEAK_NOTICE;
+        link.notified |= notify;
+        notify
+    });
+
+    if notify {
+        crate::log!("Weak Wi-Fi for an hour, RSSI {rssi} dBm");
+        crate::alerts::notify("WEAK WIFI".into());
+    }
+}
+
+/// Forget the readings, once the station is disconnected
+pub(crate) fn reset() {
+    LINK.lock(|link| *link.borrow_mut() = None);
+}
+
+/// Quality of the link, `None` while disconnected or before a reading
+pub fn link_quality() -> Option<LinkQuality> {
+    LINK.lock(|link| {
+        link.borrow().as_ref().map(|link| LinkQuality {
+            // Rounded, the RSSI is negative
+            rssi: (link.average - 0.5) as i8,
+            score: score(link.average),
+            bars: link.bars,
+        })
+    })
+}
@@ -0,0 +1,123 @@
+//! esp-radio side of the wifimanager traits
+//!
+//! `RadioControl` and the types it takes are in `b_intime_logic`, so the
+//! setup worker runs against a mock radio on the host. `Radio` wraps the
+//! controller and converts them to and from the driver ones.
+
+use alloc::vec::Vec;
+
+use esp_radio::wifi::{self, WifiController, WifiError, WifiEvent};
+
+pub use b_intime_logic::wifimanager::radio::{
+    AccessPointConfig, PowerSave, RadioConfig, RadioControl, RadioError, ScanFilter, ScanResult,
+    StationConfig,
+};
+pub use b_intime_logic::wifimanager::setup::Clock;
+
+/// esp-radio controller, as the wifimanager drives it
+pub struct Radio<'d>(pub WifiController<'d>);
+
+/// Embassy time, from boot
+pub struct EmbassyClock;
+
+impl Clock for EmbassyClock {
+    fn now_ms(&self) -> u64 {
+        embassy_time::Instant::now().as_millis()
+    }
+
+    async fn sleep_ms(&self, ms: u64) {
+        embassy_time::Timer::after_millis(ms).await
+    }
+}
+
+fn radio_error(error: WifiError) -> RadioError {
+    match error {
+        WifiError::NotInitialized | WifiError::UnknownWifiMode => RadioError::NotStarted,
+        WifiError::Disconnected => RadioError::Disconnected,
+        WifiError::Unsupported => RadioError::Unsupported,
+        WifiError::InvalidArguments => RadioError::InvalidArguments,
+        _ => RadioError::Internal,
+    }
+}
+
+fn client_config(station: &StationConfig) -> wifi::ClientConfig {
+    wifi::ClientConfig::default()
+        .with_ssid(station.ssid.clone())
+        .with_password(station.password.clone())
+}
+
+fn mode_config(config: &RadioConfig) -> wifi::ModeConfig {
+    match config {
+        RadioConfig::Station(station) => wifi::ModeConfig::Client(client_config(station)),
+        RadioConfig::AccessPointStation(station, access_point) => wifi::ModeConfig::ApSta(
+            client_config(station),
+            wifi::AccessPointConfig::default().with_ssid(access_point.ssid.clone()),
+        ),
+    }
+}
+
+fn scan_result(ap: wifi::AccessPointInfo) -> ScanResult {
+    ScanResult {
+        open: matches!(ap.auth_method, None | Some(wifi::AuthMethod::None)),
+        ssid: ap.ssid,
+        channel: ap.channel,
+        signal_strength: ap.signal_strength,
+    }
+}
+
+fn power_save_mode(mode: PowerSave) -> wifi::PowerSaveMode {
+    match mode {
+        PowerSave::None => wifi::PowerSaveMode::None,
+        PowerSave::Minimum => wifi::PowerSaveMode::Minimum,
+        PowerSave::Maximum => wifi::PowerSaveMode::Maximum,
+    }
+}
+
+impl RadioControl for Radio<'_> {
+    fn configure(&mut self, config: &RadioConfig) -> Result<(), RadioError> {
+        self.0.set_config(&mode_config(config)).map_err(radio_error)
+    }
+
+    async fn start(&mut self) -> Result<(), RadioError> {
+        self.0.start_async().await.map_err(radio_error)
+    }
+
+    async fn stop(&mut self) -> Result<(), RadioError> {
+        self.0.stop_async().await.map_err(radio_error)
+    }
+
+    async fn connect(&mut self) -> Result<(), RadioError> {
+        self.0.connect_async().await.map_err(radio_error)
+    }
+
+    async fn disconnect(&mut self) -> Result<(), RadioError> {
+        self.0.disconnect_async().await.map_err(radio_error)
+    }
+
+    async fn scan(&mut self) -> Result<Vec<ScanResult>, RadioError> {
+        let aps = self
+            .0
+            .scan_with_config_async(Default::default())
+            .await
+            .map_err(radio_error)?;
+        Ok(aps.into_iter().map(scan_result).collect())
+    }
+
+    async fn wait_disconnected(&mut self) {
+        self.0.wait_for_event(WifiEvent::StaDisconnected).await
+    }
+
+    fn is_connected(&self) -> bool {
+        wifi::sta_state() == wifi::WifiStaState::Connected
+    }
+
+    fn rssi(&self) -> Option<i8> {
+        self.0.rssi().ok().map(|rssi| rssi as i8)
+    }
+
+    fn set_power_saving(&mut self, mode: PowerSave) -> Result<(), RadioError> {
+        self.0
+            .set_power_saving(power_save_mode(mode))
+            .map_err(radio_error)
+    }
+}
@@ -1,19 +1,19 @@
 use crate::wifimanager::utils::get_efuse_mac;
 use alloc::{rc::Rc, string::String};
 use embassy_executor::SpawnError;
-use embassy_net::Stack;
+use embassy_net::{Ipv4Address, Stack};
 use embassy_sync::{
     blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
     mutex::Mutex,
-    pubsub::PubSubChannel,
+    pubsub::{PubSubChannel, Subscriber},
     signal::Signal,
 };
-use esp_radio::{
-    wifi::{ClientConfig, ModeConfig, WifiError},
-    Controller, InitializationError,
-};
+use esp_radio::{wifi::WifiError, Controller, InitializationError};
 use serde::{Deserialize, Serialize};
 
+use crate::wifimanager::radio::{RadioConfig, RadioError, ScanFilter, StationConfig};
+use crate::wifimanager::setup::SetupError;
+
 pub type Result<T> = core::result::Result<T, WmError>;
 
 #[derive(Debug)]
@@ -23,6 +23,9 @@ pub enum WmError {
 
     WifiControllerStartError,
     WifiError(WifiError),
+    RadioError(RadioError),
+    /// The setup portal ended without working credentials
+    SetupError(SetupError),
     WifiInitalizationError(InitializationError),
     SerdeErrorDe(serde_json_core::de::Error),
     SerdeErrorSer(serde_json_core::ser::Error),
@@ -44,6 +47,18 @@ impl From<WifiError> for WmError {
     }
 }
 
+impl From<RadioError> for WmError {
+    fn from(value: RadioError) -> Self {
+        Self::RadioError(value)
+    }
+}
+
+impl From<SetupError> for WmError {
+    fn from(value: SetupError) -> Self {
+        Self::SetupError(value)
+    }
+}
+
 impl From<SpawnError> for WmError {
     fn from(_value: SpawnError) -> Self {
         Self::TaskSpawnError
@@ -85,6 +100,9 @@ pub struct WmSettings {
     /// SSID name
     pub ssid: String,
 
+    /// DHCP hostname of the station, 32 bytes at most
+    pub hostname: Option<String>,
+
     /// Max time WiFi will try to connect (in ms)
     pub wifi_conn_timeout: u64,
 
@@ -99,23 +117,114 @@ pub struct WmSettings {
 
     /// Indicates if esp should restart after succesfull first connection
     pub esp_restart_after_connection: bool,
+
+    /// APs weaker than this signal strength (in dBm) are hidden from the scan list
+    pub scan_min_rssi: Option<i8>,
+
+    /// Hide APs outside the 2.4 GHz band (channels 1-14) from the scan list
+    pub scan_only_2g4: bool,
+
+    /// Hide open networks (no authentication) from the scan list
+    pub scan_skip_open: bool,
+
+    /// Address of the setup AP, its portal and DNS server
+    pub ap_ip: Ipv4Address,
+
+    /// Prefix length of the setup AP subnet
+    pub ap_prefix_len: u8,
+
+    /// Addresses leased by the setup AP, from the one after `ap_ip` (16 at most)
+    pub dhcp_pool_size: u8,
+
+    /// DHCP lease time of the setup AP (in s)
+    pub dhcp_lease_time: u64,
+
+    /// Maximum TX power (in dBm, 2 to 20), `None` for the driver default of 20
+    pub tx_power: Option<u8>,
+}
+
+impl WmSettings {
+    /// Filters of the scan list
+    pub fn scan_filter(&self) -> ScanFilter {
+        ScanFilter {
+            min_rssi: self.scan_min_rssi,
+            only_2g4: self.scan_only_2g4,
+            skip_open: self.scan_skip_open,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct AutoSetupSettings {
     pub ssid: String,
     pub psk: String,
+
+    /// Latitude in degrees, north positive
+    pub latitude: Option<f32>,
+
+    /// Longitude in degrees, east positive
+    pub longitude: Option<f32>,
+
+    /// Web panel password, only its hash is kept by the application
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+}
+
+/// Leaves out `psk` and `password`, the settings are logged
+impl ::core::fmt::Debug for AutoSetupSettings {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        f.debug_struct("AutoSetupSettings")
+            .field("ssid", &self.ssid)
+            .field("psk", &"<redacted>")
+            .field("latitude", &self.latitude)
+            .field("longitude", &self.longitude)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl Location {
+    pub fn new(latitude: f32, longitude: f32) -> Option<Self> {
+        if (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude) {
+            Some(Self {
+                latitude,
+                longitude,
+            })
+        } else {
+            None
+        }
+    }
 }
 
 impl AutoSetupSettings {
-    pub fn to_configuration(&self) -> Result<ModeConfig> {
-        Ok(ModeConfig::Client(self.to_client_conf()?))
+    /// Checks optional fields, credentials are checked by connecting
+    pub fn is_valid(&self) -> bool {
+        match (self.latitude, self.longitude) {
+            (None, None) => true,
+            (Some(lat), Some(lon)) => Location::new(lat, lon).is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        Location::new(self.latitude?, self.longitude?)
     }
 
-    pub fn to_client_conf(&self) -> Result<ClientConfig> {
-        Ok(ClientConfig::default()
-            .with_ssid(self.ssid.clone())
-            .with_password(self.psk.clone()))
+    pub fn to_configuration(&self) -> RadioConfig {
+        RadioConfig::Station(self.station())
+    }
+
+    pub fn station(&self) -> StationConfig {
+        StationConfig {
+            ssid: self.ssid.clone(),
+            password: self.psk.clone(),
+        }
     }
 }
 
@@ -126,6 +235,7 @@ impl Default for WmSettings {
     fn default() -> Self {
         Self {
             ssid: alloc::format!("ESP-{:X}", get_efuse_mac()),
+            hostname: None,
 
             wifi_reconnect_time: 1000,
             wifi_conn_timeout: 15000,
@@ -133,19 +243,65 @@ impl Default for WmSettings {
 
             esp_reset_timeout: None,
             esp_restart_after_connection: false,
+
+            scan_min_rssi: Some(-85),
+            scan_only_2g4: true,
+            scan_skip_open: false,
+
+            ap_ip: Ipv4Address::new(192, 168, 4, 1),
+            ap_prefix_len: 24,
+            dhcp_pool_size: 8,
+            dhcp_lease_time: 3600,
+
+            tx_power: None,
         }
     }
 }
 
+/// Network state transitions of the station stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetEvent {
+    LinkUp,
+    LinkDown,
+    GotIp,
+    LostIp,
+}
+
+pub const NET_EVENTS_CAP: usize = 8;
+pub const NET_EVENTS_SUBS: usize = 6;
+
+pub type NetEventChannel =
+    PubSubChannel<CriticalSectionRawMutex, NetEvent, NET_EVENTS_CAP, NET_EVENTS_SUBS, 1>;
+pub type NetEventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, NetEvent, NET_EVENTS_CAP, NET_EVENTS_SUBS, 1>;
+
 pub struct WmReturn {
     pub wifi_init: &'static Controller<'static>,
     pub sta_stack: Stack<'static>,
     pub ip_address: [u8; 4],
 
+    /// Broadcast of `sta_stack` state transitions, see `WmReturn::subscribe`
+    pub net_events: &'static NetEventChannel,
+
+    /// Settings the station is connected with
+    pub setup: AutoSetupSettings,
+
+    /// Receives on the station channel
+    #[cfg(feature = "espnow")]
+    pub esp_now: esp_radio::esp_now::EspNow<'static>,
+
     pub(crate) stop_signal: Rc<Signal<CriticalSectionRawMutex, bool>>,
 }
 
 impl WmReturn {
+    /// Subscribe to network state transitions
+    ///
+    /// Only transitions happening after the call are received, check
+    /// `sta_stack.is_config_up()` for the current state.
+    pub fn subscribe(&self) -> Result<NetEventSubscriber> {
+        self.net_events.subscriber().map_err(|_| WmError::Other)
+    }
+
     // Disconnects from current wifi and stops wifi radio
     pub fn stop_radio(&self) {
         self.stop_signal.signal(true);
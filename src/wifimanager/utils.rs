@@ -1,23 +1,26 @@
 use alloc::rc::Rc;
 use embassy_executor::Spawner;
 use embassy_net::Stack;
-use embassy_time::{with_timeout, Duration, Timer};
-use esp_radio::wifi::{WifiController, WifiDevice};
+use esp_radio::wifi::WifiDevice;
 
 use embassy_net::{Config, Ipv4Cidr, StackResources, StaticConfigV4};
 
-use crate::wifimanager::structs::{ WmInnerSignals};
+use crate::wifimanager::radio::{EmbassyClock, RadioControl};
+use crate::wifimanager::setup::{connect_within, ConnectError};
+use crate::wifimanager::ap::DhcpPool;
+use crate::wifimanager::structs::{NetEventSubscriber, WmInnerSignals, WmSettings};
 
 pub async fn spawn_ap(
     rng: &mut esp_hal::rng::Rng,
     spawner: &Spawner,
+    settings: &WmSettings,
     wm_signals: Rc<WmInnerSignals>,
     ap_interface: WifiDevice<'static>,
 ) -> crate::wifimanager::structs::Result<()> {
-    let ap_ip = embassy_net::Ipv4Address::new(192, 168, 4, 1);
+    let pool = DhcpPool::new(settings);
     let ap_ip_config = Config::ipv4_static(StaticConfigV4 {
-        address: Ipv4Cidr::new(ap_ip, 24),
-        gateway: Some(ap_ip),
+        address: Ipv4Cidr::new(pool.ip, pool.prefix_len),
+        gateway: Some(pool.ip),
         dns_servers: Default::default(),
     });
 
@@ -33,64 +36,48 @@ pub async fn spawn_ap(
     );
 
     spawner.spawn(crate::wifimanager::ap::ap_task(ap_runner, wm_signals.clone()))?;
-    spawner.spawn(crate::wifimanager::ap::run_dhcp_server(ap_stack))?;
-    crate::wifimanager::http::run_http_server(spawner, ap_stack, wm_signals.clone()).await;
+    spawner.spawn(crate::wifimanager::ap::run_dhcp_server(ap_stack, pool, wm_signals.clone()))?;
+    spawner.spawn(crate::wifimanager::ap::run_dns_server(ap_stack, pool.ip, wm_signals.clone()))?;
+    crate::wifimanager::http::run_http_server(spawner, ap_stack, pool.ip, wm_signals.clone()).await;
 
     Ok(())
 }
 
 pub async fn try_to_wifi_connect(
-    controller: &mut WifiController<'static>,
+    controller: &mut impl RadioControl,
     wifi_conn_timeout: u64,
 ) -> bool {
-    let start_time = embassy_time::Instant::now();
-
-    loop {
-        if start_time.elapsed().as_millis() > wifi_conn_timeout {
-            esp_println::println!("Connect timeout (1)!");
-            return false;
+    match connect_within(controller, &EmbassyClock, wifi_conn_timeout).await {
+        Ok(()) => {
+            crate::log!("Wifi connected!");
+            true
         }
-
-        match with_timeout(
-            Duration::from_millis(wifi_conn_timeout),
-            controller.connect_async(),
-        )
-        .await
-        {
-            Ok(res) => match res {
-                Ok(_) => {
-                    esp_println::println!("Wifi connected!");
-                    return true;
-                }
-                Err(e) => {
-                    esp_println::println!("Failed to connect to wifi: {e:?}");
-                }
-            },
-            Err(_) => {
-                esp_println::println!("Connect timeout (0)!");
-                return false;
-            }
+        Err(ConnectError::Timeout) => {
+            crate::log!("Connect timeout!");
+            false
+        }
+        Err(ConnectError::Radio(e)) => {
+            crate::log!(
+                "Failed to connect to wifi: {e:?}, {}",
+                crate::wifimanager::diagnostics::last_reason()
+            );
+            false
         }
     }
 }
 
-pub async fn wifi_wait_for_ip(stack: &Stack<'static>) -> [u8; 4] {
-    while !stack.is_link_up() {
-        Timer::after(Duration::from_millis(50)).await;
-    }
+pub async fn wifi_wait_for_ip(stack: Stack<'static>, events: &mut NetEventSubscriber) -> [u8; 4] {
+    super::wait_link_up(stack, events).await;
 
-    esp_println::println!("Waiting to get IP address...");
-    let mut ip = [0; 4];
+    crate::log!("Waiting to get IP address...");
     loop {
+        super::wait_ip(stack, events).await;
+        // The address may be gone again by the time the event is read
         if let Some(config) = stack.config_v4() {
-            esp_println::println!("Got IP: {}", config.address);
-            ip.copy_from_slice(&config.address.address().octets());
-            break;
+            crate::log!("Got IP: {}", config.address);
+            return config.address.address().octets();
         }
-        Timer::after(Duration::from_millis(50)).await;
     }
-
-    ip
 }
 
 pub fn get_efuse_mac() -> u64 {
@@ -0,0 +1,52 @@
+//! Word clock face: "TEN PAST THREE" on two lines of the normal font
+//!
+//! The phrase comes from `b_intime_logic::wordclock`. A line too wide for the
+//! matrix scrolls.
+
+use crate::{display::Canvas, font::ALPHABET_NORMAL};
+
+pub use b_intime_logic::wordclock::phrase;
+
+/// Space between the end of a scrolling line and its next pass, in pixels
+const SCROLL_GAP: usize = 8;
+
+fn width(text: &str) -> usize {
+    ALPHABET_NORMAL.text_width(text)
+}
+
+/// Split between two words so that the widest line is as narrow as possible
+pub fn lines(phrase: &str) -> [&str; 2] {
+    phrase
+        .match_indices(' ')
+        .map(|(idx, _)| [&phrase[..idx], &phrase[idx + 1..]])
+        .min_by_key(|[top, bottom]| width(top).max(width(bottom)))
+        .unwrap_or([phrase, ""])
+}
+
+/// Whether a line needs scrolling on a `W` pixels wide canvas
+pub fn scrolls<const W: usize>(phrase: &str) -> bool {
+    lines(phrase).iter().any(|line| width(line) > W)
+}
+
+/// Lines fitting are centered, the others are shifted left by `scroll` pixels
+pub fn draw<const W: usize, const H: usize>(
+    canvas: &mut Canvas<W, H>,
+    phrase: &str,
+    scroll: usize,
+) {
+    canvas.clear();
+    for (line, y) in lines(phrase).iter().zip([0, 8]) {
+        let line_width = width(line);
+        let x = if line_width <= W {
+            ((W - line_width) / 2) as i32
+        } else {
+            let cycle = line_width + SCROLL_GAP;
+            -((scroll % cycle) as i32)
+        };
+        canvas.print_5x7_at(x, y, line);
+        if line_width > W {
+            // Start of the next pass
+            canvas.print_5x7_at(x + (line_width + SCROLL_GAP) as i32, y, line);
+        }
+    }
+}